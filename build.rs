@@ -1,46 +1,203 @@
 extern crate shaderc;
 
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
 use std::error::Error;
 use std::fs::File;
-use shaderc::{IncludeCallbackResult, OptimizationLevel, ResolvedInclude, ShaderKind, SpirvVersion, TargetEnv};
-use std::path::Path;
+use std::hash::{Hash, Hasher};
+use shaderc::{IncludeCallbackResult, ShaderKind};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 use std::io::{Read, Write};
-use shaderc::EnvVersion::Vulkan1_2;
-
-
-fn compile_shader(path: &Path, kind: ShaderKind, output: String) {
-  let compiler = shaderc::Compiler::new().unwrap();
-  let mut options = shaderc::CompileOptions::new().unwrap();
-  options.set_optimization_level(OptimizationLevel::Zero);
-  options.set_target_env(TargetEnv::Vulkan, Vulkan1_2 as u32);
-  options.set_target_spirv(SpirvVersion::V1_5);
-  options.set_include_callback(|name, _include_type, _containing, _depth| -> IncludeCallbackResult {
-    let mut out = String::new();
-    // only support includes within the same directory of the file for now.
-    let path = format!("resources/shaders/{}", name);
-    File::open(path).unwrap().read_to_string(&mut out).unwrap();
-
-    if out.is_empty() {
-      return Err("Could not read the include file".to_string());
+
+include!("src/shader_options.rs");
+
+impl ShaderOptions {
+  fn from_env() -> Self {
+    let release = std::env::var("PROFILE").map_or(false, |profile| profile == "release");
+    Self {
+      optimization: env_choice("OX_SHADER_OPT", &Optimization::ALL, Optimization::name, if release { Optimization::Performance } else { Optimization::Zero }),
+      spirv: env_choice("OX_SHADER_SPIRV", &Spirv::ALL, Spirv::name, Spirv::V1_5),
     }
-    
-    let resolved_include = ResolvedInclude { resolved_name: name.to_string(), content: out.to_string() };
-    return Ok(resolved_include);
+  }
+
+  // Recorded with each shader's dependencies, so changing the options recompiles.
+  fn key(&self) -> String {
+    format!("optimization={} spirv={}", self.optimization.name(), self.spirv.name())
+  }
+}
+
+// The choice named by `var` if there is one, otherwise `default` with a warning.
+fn env_choice<T: Copy>(var: &str, choices: &[T], name: fn(T) -> &'static str, default: T) -> T {
+  println!("cargo:rerun-if-env-changed={}", var);
+  match std::env::var(var) {
+    Ok(value) => choices.iter().copied().find(|&choice| name(choice) == value).unwrap_or_else(|| {
+      let names = choices.iter().map(|&choice| name(choice)).collect::<Vec<_>>();
+      println!("cargo:warning={}={} is not one of {}, using {}", var, value, names.join(", "), name(default));
+      default
+    }),
+    Err(_) => default,
+  }
+}
+
+// Compiles the shader at `path` and returns the include files it read.
+fn compile_shader(
+  path: &Path,
+  kind: ShaderKind,
+  output: String,
+  dump_assembly: bool,
+  include_dirs: &[PathBuf],
+  shader_options: &ShaderOptions,
+  cache_dir: &Path,
+  compiler_version: &str,
+) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+  let includes = RefCell::new(Vec::new());
+  let compiler = shaderc::Compiler::new().ok_or("failed to create the shaderc compiler")?;
+  let mut options = shaderc::CompileOptions::new().ok_or("failed to create the shaderc compile options")?;
+  shader_options.apply(&mut options);
+  options.set_include_callback(|name, _include_type, containing, _depth| -> IncludeCallbackResult {
+    let (path, resolved) = resolve_include(name, containing, include_dirs)?;
+    includes.borrow_mut().push(path);
+    Ok(resolved)
   });
-  let binary = compiler
-    .compile_into_spirv(&load_file(path), kind, path.as_os_str().to_str().unwrap(), "main", Some(&options))
-    .unwrap();
-  save_file(output, binary.as_binary_u8());
+  let source = load_file(path)?;
+  let name = path.to_string_lossy();
+
+  // The preprocessed text has every include expanded, with the options, the kind and the compiler it
+  // identifies the binary. Preprocessing is cheap next to compiling and resolves the includes either way.
+  let preprocessed = compiler.preprocess(&source, &name, "main", Some(&options))?;
+  let mut hasher = DefaultHasher::new();
+  (to_string(kind), shader_options.key(), compiler_version, without_line_files(&preprocessed.as_text())).hash(&mut hasher);
+  let cached = cache_dir.join(format!("{:016x}.spv", hasher.finish()));
+  let cached_assembly = PathBuf::from(format!("{}asm", cached.display()));
+  let assembly_output = format!("{}asm", output);
+  if cached.is_file() && (!dump_assembly || cached_assembly.is_file()) {
+    copy_file(&cached, Path::new(&output))?;
+    if dump_assembly {
+      copy_file(&cached_assembly, Path::new(&assembly_output))?;
+    }
+  } else {
+    // The original source rather than the preprocessed one, so shaderc's message has the file and
+    // line of every diagnostic.
+    let binary = compiler.compile_into_spirv(&source, kind, &name, "main", Some(&options))?;
+    for warning in binary.get_warning_messages().lines() {
+      println!("cargo:warning={}", warning);
+    }
+    save_file(output.clone(), binary.as_binary_u8())?;
+    save_to_cache(binary.as_binary_u8(), &cached)?;
+
+    // Same source and options as the binary, so the disassembly matches it exactly.
+    if dump_assembly {
+      let assembly = compiler.compile_into_spirv_assembly(&source, kind, &name, "main", Some(&options))?;
+      save_file(assembly_output.clone(), assembly.as_text().as_bytes())?;
+      save_to_cache(assembly.as_text().as_bytes(), &cached_assembly)?;
+    }
+  }
+  drop(options);
+  let mut includes = includes.into_inner();
+  includes.sort();
+  includes.dedup();
+  Ok(includes)
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+  std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+// Whether any of `outputs` is missing or older than one of `inputs`.
+fn is_stale(outputs: &[PathBuf], inputs: &[PathBuf]) -> bool {
+  let newest_input = inputs.iter().filter_map(|input| modified(input)).max();
+  outputs.iter().any(|output| match (modified(output), newest_input) {
+    (Some(built), Some(newest_input)) => built < newest_input,
+    _ => true,
+  })
+}
+
+// The includes of the shader's last compilation, one path per line after the options it was compiled
+// with. None if it hasn't been compiled yet or with other options.
+fn load_dependencies(path: &Path, options: &ShaderOptions) -> Option<Vec<PathBuf>> {
+  let deps = std::fs::read_to_string(path).ok()?;
+  let mut lines = deps.lines();
+  if lines.next()? != options.key() {
+    return None;
+  }
+  Some(lines.map(PathBuf::from).collect())
+}
+
+fn save_dependencies(path: &Path, options: &ShaderOptions, includes: &[PathBuf]) -> Result<(), Box<dyn Error>> {
+  let mut deps = format!("{}\n", options.key());
+  deps.extend(includes.iter().map(|include| format!("{}\n", include.display())));
+  save_file(path.display().to_string(), deps.as_bytes())
 }
 
-fn load_file(path: &Path) -> String {
+fn load_file(path: &Path) -> Result<String, Box<dyn Error>> {
   let mut out = String::new();
-  File::open(path).unwrap().read_to_string(&mut out).unwrap();
-  out
+  File::open(path)
+    .and_then(|mut file| file.read_to_string(&mut out))
+    .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+  Ok(out)
+}
+
+fn copy_file(from: &Path, to: &Path) -> Result<(), Box<dyn Error>> {
+  std::fs::copy(from, to).map_err(|e| format!("failed to copy {} to {}: {}", from.display(), to.display(), e))?;
+  Ok(())
+}
+
+fn save_file(path: String, binary: &[u8]) -> Result<(), Box<dyn Error>> {
+  File::create(&path)
+    .and_then(|mut file| file.write_all(binary))
+    .map_err(|e| format!("failed to write {}: {}", path, e))?;
+  Ok(())
+}
+
+// Writes a cache entry under a name of its own and renames it into place, so builds sharing
+// OX_SHADER_CACHE never read a partly written entry. The rename replaces an entry another build
+// wrote meanwhile, which has the same contents.
+fn save_to_cache(binary: &[u8], path: &Path) -> Result<(), Box<dyn Error>> {
+  let temporary = PathBuf::from(format!("{}.{}.tmp", path.display(), std::process::id()));
+  save_file(temporary.display().to_string(), binary)?;
+  std::fs::rename(&temporary, path).map_err(|e| {
+    let _ = std::fs::remove_file(&temporary);
+    format!("failed to move {} to {}: {}", temporary.display(), path.display(), e)
+  })?;
+  Ok(())
+}
+
+// The preprocessed text without the file names of its #line directives. Includes found through
+// OX_SHADER_INCLUDE_DIRS are named by their absolute paths, which would tie the cache entries to one
+// checkout. The binaries carry no debug info, so the names don't change them.
+fn without_line_files(preprocessed: &str) -> String {
+  preprocessed
+    .lines()
+    .map(|line| match line.trim_start().strip_prefix("#line") {
+      Some(directive) => directive.split('"').next().unwrap_or_default().trim(),
+      None => line,
+    })
+    .collect::<Vec<_>>()
+    .join("\n")
 }
 
-fn save_file(path: String, binary: &[u8]) {
-  File::create(path).unwrap().write_all(binary).unwrap();
+// Identifies the shaderc and glslang the script was built with, for the cache key: the versions of
+// shaderc and shaderc-sys in the Cargo.lock of the crate or its workspace, otherwise the hash of the
+// build script itself, which links them in.
+fn compiler_version() -> Result<String, Box<dyn Error>> {
+  let manifest_dir = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR")?);
+  if let Some(lock) = manifest_dir.ancestors().map(|dir| dir.join("Cargo.lock")).find(|lock| lock.is_file()) {
+    let lock = load_file(&lock)?;
+    let lines = lock.lines().collect::<Vec<_>>();
+    let versions = lines
+      .windows(2)
+      .filter(|pair| pair[0] == "name = \"shaderc\"" || pair[0] == "name = \"shaderc-sys\"")
+      .map(|pair| format!("{} {}", pair[0], pair[1]))
+      .collect::<Vec<_>>();
+    if !versions.is_empty() {
+      return Ok(versions.join(", "));
+    }
+  }
+  let script = std::fs::read(std::env::current_exe()?)?;
+  let mut hasher = DefaultHasher::new();
+  script.hash(&mut hasher);
+  Ok(format!("build script {:016x}", hasher.finish()))
 }
 
 fn to_string(kind: ShaderKind) -> &'static str {
@@ -51,37 +208,127 @@ fn to_string(kind: ShaderKind) -> &'static str {
     ShaderKind::Geometry => "geom",
     ShaderKind::RayGeneration => "rgen",
     ShaderKind::ClosestHit => "rchit",
+    ShaderKind::AnyHit => "rahit",
     ShaderKind::Miss => "rmiss",
+    ShaderKind::Intersection => "rint",
+    ShaderKind::Callable => "rcall",
+    ShaderKind::Task => "task",
+    ShaderKind::Mesh => "mesh",
     _ => "empty"
   }
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
-  println!("cargo:rerun-if-changed=resources/shaders");
+  // The shader sources and their includes are watched below, once the shader directory is known.
+  println!("cargo:rerun-if-changed=build.rs");
+  println!("cargo:rerun-if-changed=src/shader_options.rs");
+  // OX_DUMP_SPIRV_ASM=1 also writes a human-readable <name>_<kind>.spvasm next to every binary.
+  println!("cargo:rerun-if-env-changed=OX_DUMP_SPIRV_ASM");
+  let dump_assembly = std::env::var("OX_DUMP_SPIRV_ASM").map_or(false, |value| value == "1");
+  let shader_options = ShaderOptions::from_env();
+  // The hot-reload feature recompiles shaders at runtime with the same options and include
+  // directories, see hot_reload.rs. Exported before the skip so prebuilt shaders reload the same.
+  println!("cargo:rustc-env=OX_BUILD_SHADER_OPT={}", shader_options.optimization.name());
+  println!("cargo:rustc-env=OX_BUILD_SHADER_SPIRV={}", shader_options.spirv.name());
+  // The binaries go to the profile's OUT_DIR rather than next to the sources, so debug and release
+  // builds, compiled with different optimization levels, don't overwrite each other's. create_shader
+  // looks there first, see resolve_shader_path.
+  let out_dir = PathBuf::from(std::env::var("OUT_DIR")?);
+  let spirv_dir = out_dir.join("shaders");
+  println!("cargo:rustc-env=OX_BUILD_SHADER_DIR={}", spirv_dir.display());
+  println!("cargo:rerun-if-env-changed=OX_SHADER_INCLUDE_DIRS");
+  let extra_include_dirs = std::env::var_os("OX_SHADER_INCLUDE_DIRS");
+  println!("cargo:rustc-env=OX_BUILD_SHADER_INCLUDE_DIRS={}", extra_include_dirs.as_deref().map_or(String::new(), |dirs| dirs.to_string_lossy().into_owned()));
+  // OX_SKIP_SHADER_BUILD=1 uses prebuilt .spv files in resources/shaders instead, e.g. when the crate is
+  // vendored without its shader sources.
+  println!("cargo:rerun-if-env-changed=OX_SKIP_SHADER_BUILD");
+  if std::env::var("OX_SKIP_SHADER_BUILD").map_or(false, |value| value == "1") {
+    println!("cargo:warning=OX_SKIP_SHADER_BUILD=1 is set, not compiling shaders");
+    // Binaries of an earlier build would be found before the prebuilt ones.
+    let _ = std::fs::remove_dir_all(&spirv_dir);
+    return Ok(());
+  }
 
-  for entry in std::fs::read_dir("resources/shaders")? {
+  let shader_dir = Path::new("resources/shaders");
+  if !shader_dir.is_dir() {
+    let current_dir = std::env::current_dir().map_or_else(|e| format!("<unknown: {}>", e), |dir| dir.display().to_string());
+    let message = format!(
+      "shader source directory {} not found in {}, build from the crate root or set OX_SKIP_SHADER_BUILD=1 to use prebuilt shaders",
+      shader_dir.display(),
+      current_dir
+    );
+    println!("cargo:warning={}", message);
+    return Err(message.into());
+  }
+  // The directory as well as every shader in it, so a newly added shader is compiled right away.
+  println!("cargo:rerun-if-changed={}", shader_dir.display());
+
+  // OX_SHADER_INCLUDE_DIRS lists more directories to search for includes, separated like PATH, after
+  // the including file's directory and resources/shaders.
+  let mut include_dirs = vec![shader_dir.to_path_buf()];
+  if let Some(dirs) = &extra_include_dirs {
+    include_dirs.extend(std::env::split_paths(dirs));
+  }
+  std::fs::create_dir_all(&spirv_dir)?;
+  // Includes found by the last compilation of each shader, kept with the build outputs.
+  let deps_dir = out_dir.join("shader_deps");
+  std::fs::create_dir_all(&deps_dir)?;
+  // Compiled shaders by the hash of their preprocessed source, kind, options and compiler.
+  // OX_SHADER_CACHE puts the cache somewhere that outlives OUT_DIR, e.g. a directory CI keeps between runs.
+  println!("cargo:rerun-if-env-changed=OX_SHADER_CACHE");
+  let cache_dir = std::env::var_os("OX_SHADER_CACHE").map_or_else(|| out_dir.join("shader_cache"), PathBuf::from);
+  std::fs::create_dir_all(&cache_dir)?;
+  let compiler_version = compiler_version()?;
+  // Every shader is attempted, so one run reports all broken shaders.
+  let mut failed = Vec::new();
+
+  for entry in std::fs::read_dir(shader_dir)? {
     let entry = entry?;
 
     if entry.file_type()?.is_file() {
       let in_path = entry.path();
-      let shader_type = in_path
-        .extension()
-        .and_then(|ext| match ext.to_string_lossy().as_ref() {
-          "vert" => Some(ShaderKind::Vertex),
-          "geom" => Some(ShaderKind::Geometry),
-          "frag" => Some(ShaderKind::Fragment),
-          "comp" => Some(ShaderKind::Compute),
-          "rgen" => Some(ShaderKind::RayGeneration),
-          "rchit" => Some(ShaderKind::ClosestHit),
-          "rmiss" => Some(ShaderKind::Miss),
-          _ => None,
-        });
+      let shader_type = in_path.extension().and_then(|ext| shader_kind(&ext.to_string_lossy()));
 
       if let Some(shader_type) = shader_type {
-        let out_path = format!("resources/shaders/{}_{}.spv", in_path.file_stem().unwrap().to_string_lossy(), to_string(shader_type));
-        compile_shader(&in_path, shader_type, out_path);
+        println!("cargo:rerun-if-changed={}", in_path.display());
+        let name = format!("{}_{}", in_path.file_stem().unwrap().to_string_lossy(), to_string(shader_type));
+        let out_path = spirv_dir.join(format!("{}.spv", name)).display().to_string();
+        let deps_path = deps_dir.join(format!("{}.d", name));
+        let mut outputs = vec![PathBuf::from(&out_path)];
+        if dump_assembly {
+          outputs.push(PathBuf::from(format!("{}asm", out_path)));
+        }
+        // build.rs and shader_options.rs hold the compile options. Without a dependency file the
+        // includes are unknown and the shader is compiled to find them.
+        let mut inputs = vec![in_path.clone(), PathBuf::from("build.rs"), PathBuf::from("src/shader_options.rs")];
+        let dependencies = load_dependencies(&deps_path, &shader_options);
+        inputs.extend(dependencies.iter().flatten().cloned());
+        let includes = match dependencies {
+          Some(includes) if !is_stale(&outputs, &inputs) => includes,
+          _ => match compile_shader(&in_path, shader_type, out_path, dump_assembly, &include_dirs, &shader_options, &cache_dir, &compiler_version) {
+            Ok(includes) => {
+              save_dependencies(&deps_path, &shader_options, &includes)?;
+              includes
+            }
+            Err(e) => {
+              eprintln!("error: failed to compile {}:\n{}", in_path.display(), e);
+              println!("cargo:warning=failed to compile {}, see the build script output for the diagnostics", in_path.display());
+              failed.push(in_path.display().to_string());
+              // Stale dependencies would mark the broken shader as up to date once it's fixed.
+              let _ = std::fs::remove_file(&deps_path);
+              Vec::new()
+            }
+          },
+        };
+        // Editing a header reruns the script and recompiles exactly the shaders including it.
+        for include in &includes {
+          println!("cargo:rerun-if-changed={}", include.display());
+        }
       }
     }
   }
+  if !failed.is_empty() {
+    return Err(format!("failed to compile {} shader(s): {}", failed.len(), failed.join(", ")).into());
+  }
   Ok(())
 }
\ No newline at end of file
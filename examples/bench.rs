@@ -0,0 +1,20 @@
+// cargo run --release --example bench -- [frames] [report.json]
+//
+// Renders the benchmark through the library like `ox_tracer --headless --bench`. Any further
+// arguments are parsed like the tracer's own, e.g. --width 1920 --height 1080.
+use anyhow::Result;
+
+use ox_tracer::app::Runner;
+use ox_tracer::tracer::RaytracingSample;
+
+const DEFAULT_FRAMES: &str = "256";
+const DEFAULT_REPORT: &str = "bench.json";
+
+fn main() -> Result<()> {
+  let mut args = std::env::args().skip(1);
+  let frames = args.next().unwrap_or_else(|| DEFAULT_FRAMES.to_string());
+  let report = args.next().unwrap_or_else(|| DEFAULT_REPORT.to_string());
+  let tracer_args = ["bench", "--headless", "--bench", &frames, "--output", &report].into_iter().map(String::from).chain(args);
+  let config = ox_tracer::cli::parse_from(tracer_args);
+  Runner::new("ox-tracer", None, config, |settings| settings.raytracing(true).build())?.run::<RaytracingSample>(None);
+}
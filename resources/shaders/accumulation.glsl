@@ -0,0 +1,8 @@
+// Progressive accumulation into an RGBA32F image, see Accumulation in src/accumulation.rs.
+//
+// `count` is the number of samples already in `previous`, RenderParams::sample_index for the image
+// as a whole. The result is their running mean with `value` included: mix(previous, value, 1 / n)
+// with n = count + 1, so a count of 0 replaces `previous` entirely and it never needs clearing.
+vec4 accumulate(vec4 previous, vec4 value, uint count) {
+  return mix(previous, value, 1.0 / float(count + 1));
+}
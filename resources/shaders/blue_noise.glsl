@@ -0,0 +1,38 @@
+// Per-pixel sample offsets from a tiled blue-noise mask.
+//
+// The mask is a 64x64 RGBA8 texture (resources/textures/blue_noise.png) with
+// two independent void-and-cluster masks in the R and G channels. It is
+// uploaded as a storage buffer of packed texels so it can be fetched without
+// a sampler.
+//
+// Every pixel reads a 2D offset from the mask and uses it to toroidally shift
+// the R2 low-discrepancy sequence (Cranley-Patterson rotation). Neighbouring
+// pixels therefore walk the same well-stratified sequence with decorrelated
+// offsets, which pushes the remaining error into high frequencies that the
+// eye barely notices at 1-8 spp.
+//
+// When accumulating, pass the running sample index: sample n of a pixel is
+// R2(n) shifted by the pixel's fixed offset, so the accumulated estimate
+// keeps converging like a low-discrepancy sequence instead of repeating the
+// same offset every frame.
+
+layout(set = 0, binding = BLUE_NOISE_BINDING) readonly buffer BlueNoise {
+  uint texels[];
+} blue_noise;
+
+const uint BLUE_NOISE_SIZE = 64;
+
+vec2 blue_noise_offset(uvec2 pixel) {
+  uvec2 p = pixel % BLUE_NOISE_SIZE;
+  return unpackUnorm4x8(blue_noise.texels[p.y * BLUE_NOISE_SIZE + p.x]).xy;
+}
+
+// R2 sequence, see "The Unreasonable Effectiveness of Quasirandom Sequences" (Roberts, 2018).
+vec2 r2_sequence(uint n) {
+  const vec2 alpha = vec2(0.7548776662466927, 0.5698402909980532);
+  return fract(vec2(0.5) + alpha * float(n));
+}
+
+vec2 blue_noise_sample(uvec2 pixel, uint sample_index) {
+  return fract(blue_noise_offset(pixel) + r2_sequence(sample_index));
+}
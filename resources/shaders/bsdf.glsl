@@ -0,0 +1,41 @@
+// Smooth dielectric (glass) BSDF. src/bsdf.rs mirrors the Fresnel and refraction math on the CPU.
+
+// Unpolarized Fresnel reflectance of a smooth dielectric interface.
+// `cos_i` is the cosine between the normal on the incident side and the direction towards the
+// incident ray's origin, `eta` the IOR ratio eta_i / eta_t. Returns 1 on total internal reflection.
+float fresnel_dielectric(float cos_i, float eta) {
+  float sin2_t = eta * eta * max(1.0 - cos_i * cos_i, 0.0);
+  if (sin2_t >= 1.0) return 1.0;
+  float cos_t = sqrt(1.0 - sin2_t);
+  float r_s = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+  float r_p = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+  return 0.5 * (r_s * r_s + r_p * r_p);
+}
+
+struct BsdfSample {
+  vec3 direction;
+  vec3 weight;
+  // Whether the sampled direction crosses the surface.
+  bool transmitted;
+};
+
+// Picks reflection or refraction with probability given by the Fresnel term, which cancels the
+// Fresnel weight, so both lobes have a weight of one. `normal` is the geometric front side normal
+// and `front_face` tells whether the ray enters (front side hit) or leaves the medium.
+// Total internal reflection falls out of the Fresnel term being one and always reflects.
+BsdfSample sample_dielectric(vec3 direction, vec3 normal, bool front_face, float ior, float u) {
+  float eta = front_face ? 1.0 / ior : ior;
+  vec3 n = front_face ? normal : -normal;
+  float cos_i = clamp(dot(-direction, n), 0.0, 1.0);
+
+  BsdfSample s;
+  s.weight = vec3(1.0);
+  s.transmitted = u >= fresnel_dielectric(cos_i, eta);
+  s.direction = s.transmitted ? refract(direction, n, eta) : reflect(direction, n);
+  return s;
+}
+
+// Beer-Lambert transmittance after travelling `distance` through a medium.
+vec3 beer_lambert(vec3 absorption, float distance) {
+  return exp(-absorption * distance);
+}
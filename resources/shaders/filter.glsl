@@ -0,0 +1,87 @@
+// Pixel reconstruction filters, mirrors src/filter.rs. Needs params from the RenderParams block.
+
+// Values of params.filter_kind.
+const uint FILTER_BOX = 0;
+const uint FILTER_TENT = 1;
+const uint FILTER_GAUSSIAN = 2;
+const uint FILTER_MITCHELL = 3;
+
+// Mitchell-Netravali kernel over [-2, 2], "Reconstruction Filters in Computer Graphics" (1988).
+float mitchell(float x, float b, float c) {
+  x = abs(x);
+  float value = 0.0;
+  if (x < 1.0) {
+    value = (12.0 - 9.0 * b - 6.0 * c) * x * x * x + (-18.0 + 12.0 * b + 6.0 * c) * x * x + (6.0 - 2.0 * b);
+  } else if (x < 2.0) {
+    value = (-b - 6.0 * c) * x * x * x + (6.0 * b + 30.0 * c) * x * x + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c);
+  }
+  return value / 6.0;
+}
+
+// Abramowitz and Stegun 7.1.26.
+float erf_approx(float x) {
+  float t = 1.0 / (1.0 + 0.3275911 * abs(x));
+  float poly = t * (0.254829592 + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+  return sign(x) * (1.0 - poly * exp(-x * x));
+}
+
+// Giles, "Approximating the erfinv function" (2010).
+float erf_inv(float x) {
+  x = clamp(x, -0.999999, 0.999999);
+  float w = -log((1.0 - x) * (1.0 + x));
+  float p;
+  if (w < 5.0) {
+    w -= 2.5;
+    p = 2.81022636e-08;
+    p = 3.43273939e-07 + p * w;
+    p = -3.5233877e-06 + p * w;
+    p = -4.39150654e-06 + p * w;
+    p = 0.00021858087 + p * w;
+    p = -0.00125372503 + p * w;
+    p = -0.00417768164 + p * w;
+    p = 0.246640727 + p * w;
+    p = 1.50140941 + p * w;
+  } else {
+    w = sqrt(w) - 3.0;
+    p = -0.000200214257;
+    p = 0.000100950558 + p * w;
+    p = 0.00134934322 + p * w;
+    p = -0.00367342844 + p * w;
+    p = 0.00573950773 + p * w;
+    p = -0.0076224613 + p * w;
+    p = 0.00943887047 + p * w;
+    p = 1.00167406 + p * w;
+    p = 2.83297682 + p * w;
+  }
+  return p * x;
+}
+
+// Offset from the pixel center along one axis for a uniform u in [0, 1) in x, the sample's weight in y.
+vec2 sample_filter_axis(float u) {
+  float radius = params.filter_radius;
+  if (params.filter_kind == FILTER_TENT) {
+    float offset = u < 0.5 ? sqrt(2.0 * u) - 1.0 : 1.0 - sqrt(2.0 - 2.0 * u);
+    return vec2(offset * radius, 1.0);
+  }
+  if (params.filter_kind == FILTER_GAUSSIAN) {
+    float sigma = params.filter_parameters.x;
+    float edge = 0.5 * (1.0 + erf_approx(radius / (sigma * sqrt(2.0))));
+    float p = (1.0 - edge) + u * (2.0 * edge - 1.0);
+    return vec2(clamp(sigma * sqrt(2.0) * erf_inv(2.0 * p - 1.0), -radius, radius), 1.0);
+  }
+  float offset = (2.0 * u - 1.0) * radius;
+  if (params.filter_kind == FILTER_MITCHELL) {
+    // Sampled uniformly, the weight carries the kernel. The negative lobes are clamped, the positive
+    // part renormalized by filter_weight_scale.
+    float kernel = max(mitchell(2.0 * offset / radius, params.filter_parameters.x, params.filter_parameters.y), 0.0);
+    return vec2(offset, 4.0 * kernel / params.filter_weight_scale);
+  }
+  return vec2(offset, 1.0);
+}
+
+// Separable filter: offset from the pixel center in xy, the sample's weight in z.
+vec3 sample_filter(vec2 u) {
+  vec2 x = sample_filter_axis(u.x);
+  vec2 y = sample_filter_axis(u.y);
+  return vec3(x.x, y.x, x.y * y.y);
+}
@@ -5,6 +5,79 @@ layout(location = 0) out vec4 FragColor;
 
 layout(set = 0, binding = 0) uniform sampler2D tex;
 
+layout(set = 0, binding = 1) readonly buffer ExposureState {
+  float log_avg_luminance;
+  float exposure;
+}
+state;
+
+// Written by raygen.rgen with adaptive sampling, one entry per pixel of tex.
+struct PixelStats {
+  float m2;
+  uint count;
+};
+
+layout(set = 0, binding = 2) readonly buffer PixelStatsBuffer {
+  PixelStats pixel_stats[];
+};
+
+// Level 0 of the bloom chain, see src/bloom.rs. Bound to tex when bloom is off, bloom_intensity is 0 then.
+layout(set = 0, binding = 3) uniform sampler2D bloom;
+
+layout(push_constant) uniform PushConstants {
+  float exposure;
+  uint auto_exposure;
+  // Non-zero shows the per-pixel sample count, black at zero and white at max_samples.
+  uint sample_count_view;
+  uint max_samples;
+  float bloom_intensity;
+  // See src/color.rs, with the nits diffuse white is shown at on HDR targets.
+  uint color_space;
+  float paper_white;
+}
+pc;
+
+const uint COLOR_SPACE_SRGB = 0;
+const uint COLOR_SPACE_REC2020_PQ = 1;
+const uint COLOR_SPACE_SCRGB = 2;
+
+// Linear Rec.709 to Rec.2020 primaries, ITU-R BT.2087. Columns are the Rec.2020 coordinates of R, G and B.
+const mat3 REC709_TO_REC2020 = mat3(0.6274, 0.0691, 0.0164, 0.3293, 0.9195, 0.0880, 0.0433, 0.0114, 0.8956);
+
+// SMPTE ST 2084 inverse EOTF, absolute luminance in nits to the PQ signal.
+vec3 pq_encode(vec3 nits) {
+  const float m1 = 2610.0 / 16384.0;
+  const float m2 = 2523.0 / 4096.0 * 128.0;
+  const float c1 = 3424.0 / 4096.0;
+  const float c2 = 2413.0 / 4096.0 * 32.0;
+  const float c3 = 2392.0 / 4096.0 * 32.0;
+  vec3 y = pow(clamp(nits / 10000.0, 0.0, 1.0), vec3(m1));
+  return pow((c1 + c2 * y) / (1.0 + c3 * y), vec3(m2));
+}
+
+// Exposed linear Rec.709 color to what the target stores. sRGB targets encode in hardware.
+vec3 encode_output(vec3 color) {
+  if (pc.color_space == COLOR_SPACE_REC2020_PQ) return pq_encode(max(REC709_TO_REC2020 * color, 0.0) * pc.paper_white);
+  // scRGB keeps the primaries, 1.0 is 80 nits.
+  if (pc.color_space == COLOR_SPACE_SCRGB) return color * (pc.paper_white / 80.0);
+  return color;
+}
+
 void main() {
-    FragColor = vec4(texture(tex, UV).rgb, 1.0);
-}
\ No newline at end of file
+  if (pc.sample_count_view != 0) {
+    ivec2 size = textureSize(tex, 0);
+    ivec2 pixel = clamp(ivec2(UV * vec2(size)), ivec2(0), size - 1);
+    uint count = pixel_stats[pixel.y * size.x + pixel.x].count;
+    FragColor = vec4(encode_output(vec3(float(count) / float(max(pc.max_samples, 1)))), 1.0);
+    return;
+  }
+  const float exposure = pc.auto_exposure != 0 ? state.exposure : pc.exposure;
+  const vec4 texel = texture(tex, UV);
+  vec3 color = texel.rgb + pc.bloom_intensity * texture(bloom, UV).rgb;
+  // The image is premultiplied by its coverage, 1 everywhere unless the background is transparent.
+  // Targets store straight alpha like PNG does, and sRGB encoding premultiplied colors would darken
+  // antialiased edges. Filters with negative lobes can push alpha slightly out of [0, 1].
+  const float alpha = clamp(texel.a, 0.0, 1.0);
+  if (alpha > 0.0) color /= alpha;
+  FragColor = vec4(encode_output(color * exposure), alpha);
+}
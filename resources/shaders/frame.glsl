@@ -0,0 +1,9 @@
+// Per-frame timing, uploaded once per frame. Must match FrameTime in src/time.rs.
+layout(set = 0, binding = FRAME_BINDING) uniform Frame {
+  // Seconds since start, frozen while paused.
+  float time;
+  // Seconds since the previous frame, 0 while paused.
+  float dt;
+  uint frame_index;
+}
+frame;
@@ -0,0 +1,9 @@
+#version 450
+
+layout(location = 0) out vec2 UV;
+
+// One triangle covering the whole viewport, from gl_VertexIndex alone: draw 3 vertices without a vertex buffer.
+void main() {
+  UV = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+  gl_Position = vec4(UV * 2.0 - 1.0, 0.0, 1.0);
+}
@@ -0,0 +1,128 @@
+// Vertex fetch for hit shaders. Every geometry of every instance has an entry in the
+// geometry table at the same slot as its material (instance material offset + geometry
+// index), holding the device addresses of its vertex and index data.
+// Must match GeometryInfo in src/mesh.rs.
+//
+// Requires GL_EXT_buffer_reference2 and GL_EXT_shader_explicit_arithmetic_types_int64.
+
+layout(buffer_reference, std430) readonly buffer Positions {
+  float positions[];
+};
+
+// u32 indices, or pairs of u16 indices packed little endian, see MeshIndices::buffer_words.
+// Reading whole words keeps u16 meshes from needing the 16 bit storage feature.
+layout(buffer_reference, std430) readonly buffer Indices {
+  uint indices[];
+};
+
+layout(buffer_reference, std430) readonly buffer Uvs {
+  float uvs[];
+};
+
+layout(buffer_reference, std430) readonly buffer Tangents {
+  vec4 tangents[];
+};
+
+struct GeometryInfo {
+  uint64_t vertices;
+  uint64_t indices;
+  // Optional per-vertex attributes, 0 when the mesh doesn't have them. Normals are packed like
+  // positions (3 floats), uvs as 2 floats and tangents as vec4 with the bitangent sign in w.
+  uint64_t normals;
+  uint64_t uvs;
+  uint64_t tangents;
+  // First index of this geometry in the index buffer, gl_PrimitiveID is relative to it.
+  uint first_index;
+  // One of the INDEX_FORMAT_* values.
+  uint index_format;
+  // Light table index of the triangle light of the first triangle, gl_PrimitiveID is relative to it.
+  // Negative if the material isn't emissive.
+  int first_light;
+  uint _pad;
+};
+
+// Values of GeometryInfo::index_format, see IndexFormat in src/mesh.rs.
+const uint INDEX_FORMAT_U32 = 0;
+const uint INDEX_FORMAT_U16 = 1;
+
+layout(set = 0, binding = GEOMETRY_BINDING) readonly buffer Geometries {
+  GeometryInfo geometries[];
+};
+
+struct HitGeometry {
+  // World space hit position.
+  vec3 position;
+  // World space geometric normal on the triangle's front side, the side
+  // (v1 - v0) x (v2 - v0) points to, i.e. the side its vertices appear counterclockwise from.
+  vec3 normal;
+  // Whether the ray hit the front side. Use this, not the normal after any flipping,
+  // to decide whether a ray enters or leaves a closed mesh.
+  bool front_face;
+  // Interpolated vertex normal in world space, the geometric normal without vertex normals.
+  // Oriented like `normal`, not towards the ray.
+  vec3 shading_normal;
+  // Only meaningful if has_uv / has_tangent.
+  vec2 uv;
+  vec4 tangent;
+  bool has_uv;
+  bool has_tangent;
+};
+
+vec3 fetch_position(Positions positions, uint index) {
+  return vec3(positions.positions[3 * index], positions.positions[3 * index + 1], positions.positions[3 * index + 2]);
+}
+
+uint fetch_index(GeometryInfo info, uint i) {
+  Indices indices = Indices(info.indices);
+  if (info.index_format == INDEX_FORMAT_U16) {
+    uint word = indices.indices[i >> 1];
+    return (i & 1) == 0 ? word & 0xffffu : word >> 16;
+  }
+  return indices.indices[i];
+}
+
+vec3 interpolate(vec3 a, vec3 b, vec3 c, vec3 weights) {
+  return a * weights.x + b * weights.y + c * weights.z;
+}
+
+// Only valid in hit shaders, `barycentrics` are the triangle hit attributes.
+HitGeometry hit_geometry(vec2 barycentrics) {
+  GeometryInfo info = geometries[gl_InstanceCustomIndexEXT + gl_GeometryIndexEXT];
+  Positions positions = Positions(info.vertices);
+  uint base = info.first_index + 3 * gl_PrimitiveID;
+  uint i0 = fetch_index(info, base);
+  uint i1 = fetch_index(info, base + 1);
+  uint i2 = fetch_index(info, base + 2);
+  vec3 v0 = fetch_position(positions, i0);
+  vec3 v1 = fetch_position(positions, i1);
+  vec3 v2 = fetch_position(positions, i2);
+  vec3 weights = vec3(1.0 - barycentrics.x - barycentrics.y, barycentrics.x, barycentrics.y);
+
+  HitGeometry hit;
+  hit.position = gl_WorldRayOriginEXT + gl_HitTEXT * gl_WorldRayDirectionEXT;
+  // Normals transform with the inverse transpose, n * M is M^T * n.
+  hit.normal = normalize((cross(v1 - v0, v2 - v0) * gl_WorldToObjectEXT).xyz);
+  hit.front_face = dot(hit.normal, gl_WorldRayDirectionEXT) < 0.0;
+
+  hit.shading_normal = hit.normal;
+  if (info.normals != 0) {
+    Positions normals = Positions(info.normals);
+    vec3 n = interpolate(fetch_position(normals, i0), fetch_position(normals, i1), fetch_position(normals, i2), weights);
+    hit.shading_normal = normalize((n * gl_WorldToObjectEXT).xyz);
+  }
+  hit.has_uv = info.uvs != 0;
+  if (hit.has_uv) {
+    Uvs uvs = Uvs(info.uvs);
+    hit.uv = vec2(uvs.uvs[2 * i0], uvs.uvs[2 * i0 + 1]) * weights.x + vec2(uvs.uvs[2 * i1], uvs.uvs[2 * i1 + 1]) * weights.y
+           + vec2(uvs.uvs[2 * i2], uvs.uvs[2 * i2 + 1]) * weights.z;
+  }
+  hit.has_tangent = info.tangents != 0;
+  if (hit.has_tangent) {
+    Tangents tangents = Tangents(info.tangents);
+    vec4 t0 = tangents.tangents[i0];
+    // Tangents are directions on the surface and transform with the object to world matrix.
+    vec3 t = interpolate(t0.xyz, tangents.tangents[i1].xyz, tangents.tangents[i2].xyz, weights);
+    hit.tangent = vec4(normalize(gl_ObjectToWorldEXT * vec4(t, 0.0)), t0.w);
+  }
+  return hit;
+}
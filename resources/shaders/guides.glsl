@@ -0,0 +1,68 @@
+// Temporally accumulated denoiser guides of the primary hit, see src/guides.rs. Must match Guide there.
+// Written by raygen into guides_out, reprojecting last frame's guides_in through the previous
+// view-projection. The two buffers swap every frame.
+
+struct Guide {
+  // First-hit albedo, 1 for dielectrics and misses.
+  vec3 albedo;
+  // Frames accumulated into this guide, 0 for no history.
+  float history;
+  // World space shading normal of the first hit, zero on a miss.
+  vec3 normal;
+  // Hit distance of the primary ray, negative on a miss.
+  float depth;
+};
+
+layout(set = 0, binding = GUIDES_IN_BINDING) readonly buffer GuidesIn {
+  Guide guides_in[];
+};
+
+layout(set = 0, binding = GUIDES_OUT_BINDING) writeonly buffer GuidesOut {
+  Guide guides_out[];
+};
+
+// History length past which new frames keep a fixed weight, so guides still follow lighting and
+// geometry changes instead of freezing.
+const float GUIDE_MAX_HISTORY = 16.0;
+
+// Splits the albedo off the color so a denoiser only blurs the illumination, which keeps texture
+// and material detail sharp. Black albedo can't be divided out, its pixels keep the color.
+vec3 demodulate(vec3 color, vec3 albedo) {
+  return color / max(albedo, vec3(1.0e-3));
+}
+
+// Inverse of demodulate, applied to the denoised illumination.
+vec3 remodulate(vec3 illumination, vec3 albedo) {
+  return illumination * max(albedo, vec3(1.0e-3));
+}
+
+// Where `point` was on screen in the previous frame, in pixels. `point` is a position with w = 1, or
+// a direction with w = 0 for things infinitely far away like the sky. Returns false when it was
+// behind the camera.
+bool previous_screen_position(vec4 point, mat4 previous_view_projection, uvec2 size, out vec2 position) {
+  vec4 clip = previous_view_projection * point;
+  if (clip.w <= 0.0) return false;
+  position = (clip.xy / clip.w * 0.5 + 0.5) * vec2(size);
+  return true;
+}
+
+// Pixel of world position `position` in the previous frame, or a negative pixel when it was off screen.
+ivec2 reproject(vec3 position, mat4 previous_view_projection, uvec2 size) {
+  vec2 previous;
+  if (!previous_screen_position(vec4(position, 1.0), previous_view_projection, size, previous)) return ivec2(-1);
+  if (any(lessThan(previous, vec2(0.0))) || any(greaterThanEqual(previous, vec2(size)))) return ivec2(-1);
+  return ivec2(previous);
+}
+
+// Blends this frame's guide into the reprojected history, an exponential moving average once the
+// history is full.
+Guide accumulate_guide(Guide history, Guide current) {
+  float weight = 1.0 / (min(history.history, GUIDE_MAX_HISTORY - 1.0) + 1.0);
+  Guide result;
+  result.albedo = mix(history.albedo, current.albedo, weight);
+  vec3 normal = mix(history.normal, current.normal, weight);
+  result.normal = dot(normal, normal) > 0.0 ? normalize(normal) : current.normal;
+  result.depth = current.depth;
+  result.history = history.history + 1.0;
+  return result;
+}
@@ -0,0 +1,153 @@
+// Lights for next event estimation: point and area lights that only shadow rays see and the
+// emissive triangles of the scene. Must match the Light struct in src/light.rs. Needs PI and
+// basis_from_normal from sampling.glsl and texture_sample_srgb from textures.glsl.
+
+const uint LIGHT_POINT = 0;
+const uint LIGHT_AREA = 1;
+const uint LIGHT_TRIANGLE = 2;
+
+struct Light {
+  // Center of a point light, one corner of an area light, first vertex of a triangle.
+  vec3 position;
+  uint kind;
+  // Radiant intensity of a point light, emitted radiance of an area light or triangle.
+  vec3 emission;
+  // Sphere radius of a point light, 0 for a hard point light.
+  float radius;
+  // Sides of an area light or triangle, which emit towards cross(edge_u, edge_v).
+  vec3 edge_u;
+  // Chance of picking this light or any before it.
+  float cdf;
+  vec3 edge_v;
+  // Emissive texture of a triangle, negative for none.
+  int texture;
+  vec2 uv0;
+  vec2 uv1;
+  vec2 uv2;
+  uint two_sided;
+  uint _pad;
+};
+
+layout(set = 0, binding = LIGHT_BINDING) readonly buffer Lights {
+  Light lights[];
+};
+
+// Direction from a shading point towards a sampled point of a light and the distance to it.
+// `radiance` is the light arriving along the direction divided by the solid angle density of the
+// sample, zero if the sample can't light the point. `pdf` is that density for triangles, which
+// bounces can hit as well, and 0 for point and area lights, which only shadow rays see.
+struct LightSample {
+  vec3 direction;
+  float distance;
+  vec3 radiance;
+  float pdf;
+};
+
+// A uniformly sampled point on the parallelogram, converted from area to solid angle density.
+LightSample sample_area_light(Light light, vec3 position, vec2 u) {
+  LightSample s;
+  vec3 to_light = light.position + u.x * light.edge_u + u.y * light.edge_v - position;
+  float distance_squared = dot(to_light, to_light);
+  s.distance = sqrt(distance_squared);
+  s.direction = to_light / s.distance;
+  vec3 area_normal = cross(light.edge_u, light.edge_v);
+  float area = length(area_normal);
+  float cos_light = dot(-s.direction, area_normal / area);
+  s.radiance = cos_light > 0.0 ? light.emission * cos_light * area / distance_squared : vec3(0.0);
+  s.pdf = 0.0;
+  return s;
+}
+
+// A uniformly sampled point on the triangle. Its emission is looked up in the texture at that point,
+// the precomputed texture average only decides how often the triangle is picked.
+LightSample sample_triangle_light(Light light, vec3 position, vec2 u) {
+  LightSample s;
+  // Folds the unit square onto the triangle, uniform in area.
+  if (u.x + u.y > 1.0) u = 1.0 - u;
+  vec3 to_light = light.position + u.x * light.edge_u + u.y * light.edge_v - position;
+  float distance_squared = dot(to_light, to_light);
+  s.distance = sqrt(distance_squared);
+  s.direction = to_light / s.distance;
+  vec3 area_normal = cross(light.edge_u, light.edge_v);
+  float area = 0.5 * length(area_normal);
+  float cos_light = dot(-s.direction, normalize(area_normal));
+  if (light.two_sided != 0) cos_light = abs(cos_light);
+  vec3 emission = light.emission;
+  if (light.texture >= 0) {
+    vec2 uv = (1.0 - u.x - u.y) * light.uv0 + u.x * light.uv1 + u.y * light.uv2;
+    emission *= texture_sample_srgb(uint(light.texture), uv).rgb;
+  }
+  s.radiance = cos_light > 0.0 ? emission * cos_light * area / distance_squared : vec3(0.0);
+  s.pdf = cos_light > 0.0 ? distance_squared / (cos_light * area) : 0.0;
+  return s;
+}
+
+// A uniform direction within the cone the sphere subtends. The sphere emits radiance
+// intensity / (PI r^2), from outside it has the same intensity as the point light of radius 0.
+LightSample sample_point_light(Light light, vec3 position, vec2 u) {
+  LightSample s;
+  vec3 to_center = light.position - position;
+  float distance_squared = dot(to_center, to_center);
+  float center_distance = sqrt(distance_squared);
+  s.direction = to_center / center_distance;
+  s.distance = center_distance;
+  s.pdf = 0.0;
+  if (light.radius <= 0.0) {
+    s.radiance = light.emission / distance_squared;
+    return s;
+  }
+  float radius_squared = light.radius * light.radius;
+  // A point inside the sphere is not lit by it, there is no shadow to be cast there.
+  if (distance_squared <= radius_squared) {
+    s.radiance = vec3(0.0);
+    return s;
+  }
+  float sin_max_squared = radius_squared / distance_squared;
+  float cos_max = sqrt(1.0 - sin_max_squared);
+  // 1 - cos_max without the cancellation for small or distant spheres.
+  float one_minus_cos_max = sin_max_squared / (1.0 + cos_max);
+  float cos_theta = 1.0 - u.x * one_minus_cos_max;
+  float sin_theta = sqrt(max(1.0 - cos_theta * cos_theta, 0.0));
+  float phi = 2.0 * PI * u.y;
+  s.direction = normalize(basis_from_normal(s.direction) * vec3(sin_theta * cos(phi), sin_theta * sin(phi), cos_theta));
+  // Nearest intersection with the sphere.
+  float b = dot(s.direction, to_center);
+  s.distance = b - sqrt(max(b * b - distance_squared + radius_squared, 0.0));
+  float solid_angle = 2.0 * PI * one_minus_cos_max;
+  s.radiance = light.emission / (PI * radius_squared) * solid_angle;
+  return s;
+}
+
+LightSample sample_light(Light light, vec3 position, vec2 u) {
+  if (light.kind == LIGHT_AREA) return sample_area_light(light, position, u);
+  if (light.kind == LIGHT_TRIANGLE) return sample_triangle_light(light, position, u);
+  return sample_point_light(light, position, u);
+}
+
+// Index of the light whose cdf interval holds u, lights are picked in proportion to their power.
+uint pick_light(uint count, float u) {
+  uint low = 0;
+  uint high = count - 1;
+  while (low < high) {
+    uint middle = (low + high) / 2;
+    if (u < lights[middle].cdf) high = middle;
+    else low = middle + 1;
+  }
+  return low;
+}
+
+// Chance that pick_light returns `index`.
+float light_probability(uint index) {
+  return lights[index].cdf - (index > 0 ? lights[index - 1].cdf : 0.0);
+}
+
+// Solid angle density with which pick_light and sample_triangle_light together produce `direction`,
+// a ray that hits triangle light `index` at `distance`. Bounces hitting an emitter weigh their
+// emission against it.
+float triangle_light_pdf(uint index, vec3 direction, float distance) {
+  Light light = lights[index];
+  vec3 area_normal = cross(light.edge_u, light.edge_v);
+  float area = 0.5 * length(area_normal);
+  float cos_light = abs(dot(direction, normalize(area_normal)));
+  return cos_light > 0.0 ? light_probability(index) * distance * distance / (cos_light * area) : 0.0;
+}
@@ -0,0 +1,49 @@
+// Materials of all instances, indexed by instance material offset + geometry index.
+// Must match the Material struct in src/material.rs.
+struct Material {
+  vec4 base_color;
+  // Beer-Lambert absorption coefficient per unit distance inside dielectrics.
+  vec3 absorption;
+  // Index of refraction. Zero for opaque materials, anything else makes the material a dielectric.
+  float ior;
+  // Non-zero for thin surfaces that should be shaded the same from both sides.
+  uint double_sided;
+  // Index of a tangent space normal map in the texture table, negative for none.
+  int normal_map;
+  // Cauchy B coefficient of dispersive dielectrics, see spectral.glsl.
+  float dispersion;
+  // Chance of a mirror reflection tinted by the base color instead of a diffuse bounce.
+  float metallic;
+  // Color shadow rays keep when passing through, zero for opaque. See shadow.rahit.
+  vec3 transmission;
+  // In [0, 1]. Shading ignores it so far, it only steers next event estimation.
+  float roughness;
+  // Emitted radiance, multiplied by the emissive texture if there is one.
+  vec3 emission;
+  // Index of the emission color map in the texture table, negative for none.
+  int emissive_texture;
+  // Index of the color map multiplied into base_color, negative for none.
+  int base_color_texture;
+};
+
+layout(set = 0, binding = MATERIAL_BINDING) readonly buffer Materials {
+  Material materials[];
+};
+
+bool is_dielectric(Material material) {
+  return material.ior > 0.0;
+}
+
+// Shading normal for a hit. Double-sided materials always face the incoming ray.
+// Single-sided materials keep the geometric normal, so their back side is dark, which is
+// correct for closed meshes where it can't be seen anyway.
+vec3 shading_normal(Material material, vec3 normal, bool front_face) {
+  return (material.double_sided != 0 && !front_face) ? -normal : normal;
+}
+
+// What a shadow ray keeps after passing the surface. Alpha is treated as coverage: the uncovered
+// fraction passes untinted, the covered one is filtered by the transmission color.
+vec3 shadow_transmission(Material material) {
+  float coverage = clamp(material.base_color.a, 0.0, 1.0);
+  return vec3(1.0 - coverage) + coverage * material.transmission;
+}
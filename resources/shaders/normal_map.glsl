@@ -0,0 +1,19 @@
+// Tangent space normal mapping. Tangents come from the glTF file or mesh::compute_tangents, with the bitangent
+// sign in w.
+
+// Perturbs `normal` by a tangent space normal `mapped` ([-1, 1], z towards the surface).
+// Interpolated normals and tangents are no longer orthogonal, the tangent is re-orthogonalized
+// against the normal (Gram-Schmidt) before building the basis.
+vec3 perturb_normal(vec3 normal, vec4 tangent, vec3 mapped) {
+  vec3 t = tangent.xyz - normal * dot(normal, tangent.xyz);
+  // Degenerate UVs produce zero tangents, leave the normal alone then.
+  if (dot(t, t) < 1e-12) return normal;
+  t = normalize(t);
+  vec3 b = cross(normal, t) * (tangent.w < 0.0 ? -1.0 : 1.0);
+  return normalize(mat3(t, b, normal) * mapped);
+}
+
+// Decodes an RGB normal map texel.
+vec3 decode_normal(vec4 texel) {
+  return normalize(texel.xyz * 2.0 - 1.0);
+}
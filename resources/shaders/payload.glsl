@@ -0,0 +1,21 @@
+// Ray payload shared by raygen, hit and miss shaders. Hit shaders only describe the surface,
+// all shading happens in the raygen shader's path loop.
+struct Payload {
+  // World space hit position.
+  vec3 position;
+  // Hit distance along the ray, negative on a miss.
+  float t;
+  // Geometric normal on the triangle's front side, see HitGeometry.
+  vec3 normal;
+  // Index into the material table.
+  uint material;
+  // Vertex normal, normal mapped if the material has a normal map. Oriented like `normal`.
+  vec3 shading_normal;
+  bool front_face;
+  // Diffuse color at the hit, base_color times its texture.
+  vec3 albedo;
+  // Radiance the surface emits towards the ray, with the emissive texture applied.
+  vec3 emission;
+  // Light table index of the triangle light that was hit, negative if the material isn't emissive.
+  int light;
+};
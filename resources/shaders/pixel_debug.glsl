@@ -0,0 +1,19 @@
+// Debug output of a single pixel, see src/pixel_debug.rs. Must match PixelDebugInfo there.
+
+const uint MAX_DEBUG_BOUNCES = 8;
+
+struct PixelDebugInfo {
+  vec4 radiance;
+  // xyz is the first hit in world space, w its distance.
+  vec4 position;
+  vec4 normal;
+  uint material;
+  uint bounces;
+  uint hit;
+  uint _pad;
+  vec4 throughput[MAX_DEBUG_BOUNCES];
+};
+
+layout(set = 0, binding = PIXEL_DEBUG_BINDING) buffer PixelDebug {
+  PixelDebugInfo pixel_debug;
+};
@@ -0,0 +1,23 @@
+// Deterministic per-pixel random numbers. The state only depends on the pixel, the
+// sample index and a base seed, never on time, so a render is reproducible bit for bit.
+// src/rng.rs mirrors these functions on the CPU and must be kept in sync.
+
+// PCG hash, see "Hash Functions for GPU Rendering" (Jarzynski & Olano, 2020).
+uint pcg_hash(uint v) {
+  uint state = v * 747796405u + 2891336453u;
+  uint word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+  return (word >> 22u) ^ word;
+}
+
+uint rng_seed(uvec2 pixel, uint sample_index, uint base_seed) {
+  return pcg_hash(pixel.x + pcg_hash(pixel.y + pcg_hash(sample_index + pcg_hash(base_seed))));
+}
+
+float rng_next_float(inout uint state) {
+  state = pcg_hash(state);
+  return float(state) / 4294967296.0;
+}
+
+vec2 rng_next_vec2(inout uint state) {
+  return vec2(rng_next_float(state), rng_next_float(state));
+}
@@ -0,0 +1,55 @@
+// Inline ray tracing against the scene TLAS from compute and fragment shaders, e.g. ray traced
+// shadows in a raster pass. The TLAS is bound at RAY_QUERY_TLAS_BINDING of set 0, see
+// ray_query::bind_scene_tlas in src/ray_query.rs.
+//
+// Requires GL_EXT_ray_query and RunnerConfig::ray_query:
+//
+//   #extension GL_EXT_ray_query : require
+//   #define RAY_QUERY_TLAS_BINDING 0
+//   #include "ray_query.glsl"
+//
+//   float lit = ray_query_occluded(position + normal * 1e-3, sun_direction, 0.0, 10000.0) ? 0.0 : 1.0;
+//
+// All geometry is built opaque unless transmissive shadows are on, non-opaque candidates are
+// accepted as hits here, without the shadow any-hit shader's transmission.
+
+layout(set = 0, binding = RAY_QUERY_TLAS_BINDING) uniform accelerationStructureEXT ray_query_tlas;
+
+// Whether anything lies along the ray within [t_min, t_max].
+bool ray_query_occluded(vec3 origin, vec3 direction, float t_min, float t_max) {
+  rayQueryEXT query;
+  rayQueryInitializeEXT(query, ray_query_tlas, gl_RayFlagsTerminateOnFirstHitEXT, 0xFF, origin, t_min, direction, t_max);
+  while (rayQueryProceedEXT(query)) {
+    if (rayQueryGetIntersectionTypeEXT(query, false) == gl_RayQueryCandidateIntersectionTriangleEXT) {
+      rayQueryConfirmIntersectionEXT(query);
+    }
+  }
+  return rayQueryGetIntersectionTypeEXT(query, true) != gl_RayQueryCommittedIntersectionNoneEXT;
+}
+
+// The closest hit along the ray as in a closest hit shader: false on a miss, otherwise the distance,
+// the instance's custom index, the triangle and its barycentrics.
+struct RayQueryHit {
+  float t;
+  int instance;
+  int primitive;
+  vec2 barycentrics;
+  bool front_face;
+};
+
+bool ray_query_closest_hit(vec3 origin, vec3 direction, float t_min, float t_max, out RayQueryHit hit) {
+  rayQueryEXT query;
+  rayQueryInitializeEXT(query, ray_query_tlas, gl_RayFlagsNoneEXT, 0xFF, origin, t_min, direction, t_max);
+  while (rayQueryProceedEXT(query)) {
+    if (rayQueryGetIntersectionTypeEXT(query, false) == gl_RayQueryCandidateIntersectionTriangleEXT) {
+      rayQueryConfirmIntersectionEXT(query);
+    }
+  }
+  if (rayQueryGetIntersectionTypeEXT(query, true) == gl_RayQueryCommittedIntersectionNoneEXT) return false;
+  hit.t = rayQueryGetIntersectionTEXT(query, true);
+  hit.instance = rayQueryGetIntersectionInstanceCustomIndexEXT(query, true);
+  hit.primitive = rayQueryGetIntersectionPrimitiveIndexEXT(query, true);
+  hit.barycentrics = rayQueryGetIntersectionBarycentricsEXT(query, true);
+  hit.front_face = rayQueryGetIntersectionFrontFaceEXT(query, true);
+  return true;
+}
@@ -0,0 +1,28 @@
+// Direction sampling helpers.
+const float PI = 3.14159265358979323846;
+
+// Orthonormal basis around n, see "Building an Orthonormal Basis, Revisited" (Duff et al., 2017).
+mat3 basis_from_normal(vec3 n) {
+  float s = n.z >= 0.0 ? 1.0 : -1.0;
+  float a = -1.0 / (s + n.z);
+  float b = n.x * n.y * a;
+  vec3 t = vec3(1.0 + s * n.x * n.x * a, s * b, -s * n.x);
+  vec3 bt = vec3(b, s + n.y * n.y * a, -n.y);
+  return mat3(t, bt, n);
+}
+
+// Cosine-weighted direction in the hemisphere around n, pdf = cos(theta) / PI.
+vec3 cosine_sample_hemisphere(vec3 n, vec2 u) {
+  float r = sqrt(u.x);
+  float phi = 2.0 * PI * u.y;
+  vec3 local = vec3(r * cos(phi), r * sin(phi), sqrt(max(1.0 - u.x, 0.0)));
+  return normalize(basis_from_normal(n) * local);
+}
+
+// Uniform direction on the unit sphere, pdf = 1 / (4 PI).
+vec3 uniform_sample_sphere(vec2 u) {
+  float z = 1.0 - 2.0 * u.x;
+  float r = sqrt(max(1.0 - z * z, 0.0));
+  float phi = 2.0 * PI * u.y;
+  return vec3(r * cos(phi), r * sin(phi), z);
+}
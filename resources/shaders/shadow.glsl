@@ -0,0 +1,7 @@
+// Payload of transmissive shadow rays, traced with the shadow hit and miss groups.
+struct ShadowPayload {
+  // Product of the transmission of every surface passed so far.
+  vec3 transmission;
+  // Set by the miss shader once the ray leaves the scene.
+  bool visible;
+};
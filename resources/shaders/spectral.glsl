@@ -0,0 +1,43 @@
+// Wavelength dependent refraction for dispersive dielectrics. src/spectral.rs mirrors these
+// functions on the CPU and must be kept in sync.
+
+const float WAVELENGTH_MIN = 380.0;
+const float WAVELENGTH_MAX = 780.0;
+
+// Cauchy's equation n = A + B / lambda^2 with lambda in micrometers, `ior` being the index at the
+// sodium d-line (587.6 nm) and `dispersion` the B coefficient in um^2, e.g. 0.0042 for BK7 glass.
+float cauchy_ior(float ior, float dispersion, float wavelength) {
+  float lambda = wavelength * 1.0e-3;
+  const float lambda_d = 0.5876;
+  return ior + dispersion * (1.0 / (lambda * lambda) - 1.0 / (lambda_d * lambda_d));
+}
+
+float cie_lobe(float x, float mean, float sigma_low, float sigma_high) {
+  float t = (x - mean) / (x < mean ? sigma_low : sigma_high);
+  return exp(-0.5 * t * t);
+}
+
+// CIE 1931 color matching functions, multi-lobe fit from "Simple Analytic Approximations to the
+// CIE XYZ Color Matching Functions" (Wyman, Sloan & Shirley, 2013).
+vec3 cie_xyz(float wavelength) {
+  float x = 1.056 * cie_lobe(wavelength, 599.8, 37.9, 31.0) + 0.362 * cie_lobe(wavelength, 442.0, 16.0, 26.7)
+    - 0.065 * cie_lobe(wavelength, 501.1, 20.4, 26.2);
+  float y = 0.821 * cie_lobe(wavelength, 568.8, 46.9, 40.5) + 0.286 * cie_lobe(wavelength, 530.9, 16.3, 31.1);
+  float z = 1.217 * cie_lobe(wavelength, 437.0, 11.8, 36.0) + 0.681 * cie_lobe(wavelength, 459.0, 26.0, 13.8);
+  return vec3(x, y, z);
+}
+
+// Inverse of the mean linear sRGB response over [WAVELENGTH_MIN, WAVELENGTH_MAX], see
+// spectral::white_scale. Makes a uniformly sampled wavelength average to white.
+const vec3 SPECTRAL_WHITE_SCALE = vec3(3.11617, 3.93900, 4.12161);
+
+// RGB weight of a path that continues at a single, uniformly sampled wavelength. Averages to one
+// per channel, a few saturated wavelengths have slightly negative channels, which is what keeps
+// the average exact.
+vec3 wavelength_weight(float wavelength) {
+  const mat3 xyz_to_rgb = mat3(
+    3.2406, -0.9689, 0.0557,
+    -1.5372, 1.8758, -0.2040,
+    -0.4986, 0.0415, 1.0570);
+  return xyz_to_rgb * cie_xyz(wavelength) * SPECTRAL_WHITE_SCALE;
+}
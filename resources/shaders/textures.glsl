@@ -0,0 +1,54 @@
+// Texture fetch for hit shaders. Every texture of the scene is a storage buffer of packed RGBA8
+// texels, the TextureInfo table at TEXTURE_BINDING holds their addresses and sizes.
+// Must match TextureInfo in src/texture.rs.
+//
+// Requires GL_EXT_buffer_reference2 and GL_EXT_shader_explicit_arithmetic_types_int64.
+
+layout(buffer_reference, std430) readonly buffer Texels {
+  uint texels[];
+};
+
+struct TextureInfo {
+  uint64_t texels;
+  uint width;
+  uint height;
+};
+
+layout(set = 0, binding = TEXTURE_BINDING) readonly buffer Textures {
+  TextureInfo textures[];
+};
+
+vec3 srgb_to_linear(vec3 c) {
+  return mix(c / 12.92, pow((c + 0.055) / 1.055, vec3(2.4)), greaterThan(c, vec3(0.04045)));
+}
+
+// Color textures are sRGB encoded, `srgb` decodes the color channels, alpha is always linear.
+vec4 texel_fetch(TextureInfo info, ivec2 p, bool srgb) {
+  // Repeat wrapping, also for negative coordinates.
+  ivec2 size = ivec2(info.width, info.height);
+  p = ((p % size) + size) % size;
+  vec4 texel = unpackUnorm4x8(Texels(info.texels).texels[p.y * size.x + p.x]);
+  return srgb ? vec4(srgb_to_linear(texel.rgb), texel.a) : texel;
+}
+
+// Bilinear lookup with repeat wrapping, uv (0, 0) is the top left corner of the first texel. Texels
+// are decoded before filtering, like the hardware does for sRGB formats.
+vec4 texture_lookup(uint index, vec2 uv, bool srgb) {
+  TextureInfo info = textures[index];
+  vec2 p = uv * vec2(info.width, info.height) - 0.5;
+  ivec2 p0 = ivec2(floor(p));
+  vec2 f = p - vec2(p0);
+  vec4 top = mix(texel_fetch(info, p0, srgb), texel_fetch(info, p0 + ivec2(1, 0), srgb), f.x);
+  vec4 bottom = mix(texel_fetch(info, p0 + ivec2(0, 1), srgb), texel_fetch(info, p0 + ivec2(1, 1), srgb), f.x);
+  return mix(top, bottom, f.y);
+}
+
+// For data textures such as normal maps.
+vec4 texture_sample(uint index, vec2 uv) {
+  return texture_lookup(index, uv, false);
+}
+
+// For color textures (base color, emission).
+vec4 texture_sample_srgb(uint index, vec2 uv) {
+  return texture_lookup(index, uv, true);
+}
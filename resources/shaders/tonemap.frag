@@ -0,0 +1,33 @@
+#version 450
+
+layout(location = 0) in vec2 UV;
+layout(location = 0) out vec4 FragColor;
+
+layout(set = 0, binding = 0) uniform sampler2D hdr;
+
+// Mirrors TonemapPushConstants in src/tonemap.rs.
+layout(push_constant) uniform PushConstants {
+  float exposure;
+  // TonemapOperator::id.
+  uint operator;
+}
+pc;
+
+const uint OPERATOR_EXPOSURE = 0;
+const uint OPERATOR_REINHARD = 1;
+const uint OPERATOR_ACES = 2;
+
+// Narkowicz's fit of the ACES reference rendering transform, "ACES Filmic Tone Mapping Curve".
+vec3 aces(vec3 x) {
+  return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), 0.0, 1.0);
+}
+
+// Linear output, sRGB targets encode in hardware.
+void main() {
+  const vec4 texel = texture(hdr, UV);
+  const vec3 color = max(texel.rgb * pc.exposure, 0.0);
+  vec3 mapped = color;
+  if (pc.operator == OPERATOR_REINHARD) mapped = color / (1.0 + color);
+  else if (pc.operator == OPERATOR_ACES) mapped = aces(color);
+  FragColor = vec4(mapped, clamp(texel.a, 0.0, 1.0));
+}
@@ -0,0 +1,309 @@
+use anyhow::{ensure, Result};
+use ash::vk;
+use log::info;
+
+use phobos::prelude::*;
+use phobos::sync::domain::Compute;
+use phobos::util::align::align;
+
+use crate::app::Context;
+use crate::memory::Buffer;
+use crate::mesh::{GeometryGroup, IndexFormat};
+
+// Host visible buffer holding `data`, readable as acceleration structure build input.
+pub fn make_input_buffer<T: Copy>(ctx: &mut Context, data: &[T], usage: vk::BufferUsageFlags, alignment: Option<u64>) -> Result<Buffer> {
+  let buffer = match alignment {
+    None => Buffer::new(
+      ctx.device.clone(),
+      &mut ctx.allocator,
+      (data.len() * std::mem::size_of::<T>()) as u64,
+      vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR | vk::BufferUsageFlags::TRANSFER_DST | usage,
+      MemoryType::CpuToGpu,
+    )?,
+    Some(alignment) => Buffer::new_aligned(
+      ctx.device.clone(),
+      &mut ctx.allocator,
+      (data.len() * std::mem::size_of::<T>()) as u64,
+      alignment,
+      vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR | vk::BufferUsageFlags::TRANSFER_DST | usage,
+      MemoryType::CpuToGpu,
+    )?,
+  };
+  buffer.view_full().mapped_slice::<T>()?.copy_from_slice(data);
+  Ok(buffer)
+}
+
+// An acceleration structure with the buffer it lives in and the scratch buffer it was built with.
+pub struct BackedAccelerationStructure {
+  pub accel: AccelerationStructure,
+  pub buffer: Buffer,
+  // Large enough for a build and an update, see scratch_address.
+  pub scratch: Buffer,
+  pub sizes: AccelerationStructureBuildSize,
+  scratch_alignment: u64,
+}
+
+impl BackedAccelerationStructure {
+  // Allocates the structure and its scratch buffer for `build_info`, without building it.
+  pub fn new(ctx: &mut Context, build_info: &AccelerationStructureBuildInfo, prim_counts: &[u32]) -> Result<Self> {
+    let sizes = query_build_size(&ctx.device, AccelerationStructureBuildType::Device, build_info, prim_counts)?;
+    let buffer = Buffer::new_device_local(
+      ctx.device.clone(),
+      &mut ctx.allocator,
+      sizes.size,
+      vk::BufferUsageFlags::ACCELERATION_STRUCTURE_STORAGE_KHR,
+    )?;
+    // Buffer addresses only follow the allocator's alignment, the scratch address is rounded up
+    // within the buffer to minAccelerationStructureScratchOffsetAlignment.
+    let scratch_alignment = (ctx.gpu.accel_scratch_alignment as u64).max(1);
+    let scratch = Buffer::new_device_local(
+      ctx.device.clone(),
+      &mut ctx.allocator,
+      sizes.build_scratch_size.max(sizes.update_scratch_size) + scratch_alignment - 1,
+      vk::BufferUsageFlags::STORAGE_BUFFER,
+    )?;
+    let accel = AccelerationStructure::new(
+      ctx.device.clone(),
+      build_info.ty(),
+      buffer.view_full(),
+      vk::AccelerationStructureCreateFlagsKHR::default(),
+    )?;
+    Ok(Self {
+      accel,
+      buffer,
+      scratch,
+      sizes,
+      scratch_alignment,
+    })
+  }
+
+  pub fn scratch_address(&self) -> vk::DeviceAddress {
+    align(self.scratch.address(), self.scratch_alignment)
+  }
+}
+
+// Every group becomes its own geometry, so gl_GeometryIndexEXT identifies the group in hit shaders.
+// `opaque` holds one entry per group. Only non-opaque geometry invokes the shadow any-hit shader,
+// everything else keeps the fast path where any hit simply blocks.
+fn blas_build_info<'a>(
+  vertices: &Buffer,
+  indices: &Buffer,
+  index_format: IndexFormat,
+  groups: &[GeometryGroup],
+  opaque: &[bool],
+  flags: vk::BuildAccelerationStructureFlagsKHR,
+) -> AccelerationStructureBuildInfo<'a> {
+  let mut info = AccelerationStructureBuildInfo::new_build().flags(flags).set_type(AccelerationStructureType::BottomLevel);
+  // Highest vertex index any geometry may use, the whole tightly packed position buffer.
+  let max_vertex = (vertices.size() / (3 * std::mem::size_of::<f32>()) as u64).saturating_sub(1) as u32;
+  for (group, &opaque) in groups.iter().zip(opaque) {
+    let flags = if opaque {
+      vk::GeometryFlagsKHR::OPAQUE | vk::GeometryFlagsKHR::NO_DUPLICATE_ANY_HIT_INVOCATION
+    } else {
+      // Transmission is multiplied per hit, so every surface must be seen exactly once.
+      vk::GeometryFlagsKHR::NO_DUPLICATE_ANY_HIT_INVOCATION
+    };
+    info = info
+      .push_triangles(
+        AccelerationStructureGeometryTrianglesData::default()
+          .format(vk::Format::R32G32B32_SFLOAT)
+          .vertex_data(vertices.address())
+          .stride((3 * std::mem::size_of::<f32>()) as u64)
+          .max_vertex(max_vertex)
+          .index_data(index_format.index_type(), indices.address())
+          .flags(flags),
+      )
+      .push_range(group.triangle_count, group.index_offset_bytes(index_format), 0, 0);
+  }
+  info
+}
+
+// An update has to use the flags of the build it updates, so `allow_update` is set for both.
+fn tlas_build_info<'a>(instances: &Buffer, instance_count: u32, update: bool, allow_update: bool) -> AccelerationStructureBuildInfo<'a> {
+  let info = if update {
+    AccelerationStructureBuildInfo::new_update()
+  } else {
+    AccelerationStructureBuildInfo::new_build()
+  };
+  let mut flags = vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE;
+  if allow_update {
+    flags |= vk::BuildAccelerationStructureFlagsKHR::ALLOW_UPDATE;
+  }
+  info
+    .flags(flags)
+    .set_type(AccelerationStructureType::TopLevel)
+    .push_instances(AccelerationStructureGeometryInstancesData {
+      data: instances.address().into(),
+      flags: vk::GeometryFlagsKHR::OPAQUE | vk::GeometryFlagsKHR::NO_DUPLICATE_ANY_HIT_INVOCATION,
+    })
+    .push_range(instance_count, 0, 0, 0)
+}
+
+// Builds `build_info` into its destination structure on the compute domain and waits for it.
+fn submit_build(ctx: &mut Context, build_info: &AccelerationStructureBuildInfo) -> Result<()> {
+  let cmd = ctx
+    .exec
+    .on_domain::<Compute, DefaultAllocator>(None, None)?
+    .build_acceleration_structure(build_info)?
+    .memory_barrier(
+      PipelineStage::ACCELERATION_STRUCTURE_BUILD_KHR,
+      vk::AccessFlags2::ACCELERATION_STRUCTURE_WRITE_KHR,
+      PipelineStage::ALL_COMMANDS,
+      vk::AccessFlags2::ACCELERATION_STRUCTURE_READ_KHR,
+    )
+    .finish()?;
+  ctx.exec.submit(cmd)?.wait()?;
+  Ok(())
+}
+
+// Copies `source` into a new acceleration structure of `compacted_size`, as reported by a
+// compacted size query.
+fn compact(ctx: &mut Context, source: BackedAccelerationStructure, compacted_size: u64) -> Result<BackedAccelerationStructure> {
+  let compacted_size = align(compacted_size, AccelerationStructure::alignment());
+  info!("Acceleration structure size: {} bytes, {} after compacting", source.sizes.size, compacted_size);
+  let buffer = Buffer::new_device_local(
+    ctx.device.clone(),
+    &mut ctx.allocator,
+    compacted_size,
+    vk::BufferUsageFlags::ACCELERATION_STRUCTURE_STORAGE_KHR,
+  )?;
+  let accel = AccelerationStructure::new(
+    ctx.device.clone(),
+    source.accel.ty(),
+    buffer.view_full(),
+    vk::AccelerationStructureCreateFlagsKHR::default(),
+  )?;
+  let cmd = ctx
+    .exec
+    .on_domain::<Compute, DefaultAllocator>(None, None)?
+    .compact_acceleration_structure(&source.accel, &accel)?
+    .memory_barrier(
+      PipelineStage::ALL_COMMANDS,
+      vk::AccessFlags2::MEMORY_WRITE | vk::AccessFlags2::MEMORY_READ,
+      PipelineStage::ALL_COMMANDS,
+      vk::AccessFlags2::MEMORY_READ,
+    )
+    .finish()?;
+  ctx.exec.submit(cmd)?.wait()?;
+  Ok(BackedAccelerationStructure {
+    accel,
+    buffer,
+    // The scratch buffer is kept, so are its sizes.
+    sizes: AccelerationStructureBuildSize {
+      size: compacted_size,
+      ..source.sizes
+    },
+    ..source
+  })
+}
+
+// Builds the BLAS over the triangles of `groups`, one geometry per group, from tightly packed
+// R32G32B32 positions and `indices`, and waits for it. With `compact_after_build` it is copied
+// into a buffer of its compacted size afterwards, which usually saves about half the memory of a
+// static mesh.
+pub fn build_blas(
+  ctx: &mut Context,
+  vertices: &Buffer,
+  indices: &Buffer,
+  index_format: IndexFormat,
+  groups: &[GeometryGroup],
+  opaque: &[bool],
+  compact_after_build: bool,
+) -> Result<BackedAccelerationStructure> {
+  let mut flags = vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE;
+  if compact_after_build {
+    flags |= vk::BuildAccelerationStructureFlagsKHR::ALLOW_COMPACTION;
+  }
+  // The sizes only need the build mode, flags and geometry, the source, destination and scratch
+  // data are filled in once the structure exists.
+  let build_info = blas_build_info(vertices, indices, index_format, groups, opaque, flags);
+  let prim_counts = groups.iter().map(|group| group.triangle_count).collect::<Vec<_>>();
+  let blas = BackedAccelerationStructure::new(ctx, &build_info, &prim_counts)?;
+  let build_info = build_info.dst(&blas.accel).scratch_data(blas.scratch_address());
+  if !compact_after_build {
+    submit_build(ctx, &build_info)?;
+    drop(build_info);
+    return Ok(blas);
+  }
+  let mut qp = QueryPool::<AccelerationStructureCompactedSizeQuery>::new(
+    ctx.device.clone(),
+    QueryPoolCreateInfo {
+      count: 1,
+      statistic_flags: None,
+    },
+  )?;
+  let cmd = ctx
+    .exec
+    .on_domain::<Compute, DefaultAllocator>(None, None)?
+    .build_acceleration_structure(&build_info)?
+    .memory_barrier(
+      PipelineStage::ACCELERATION_STRUCTURE_BUILD_KHR,
+      vk::AccessFlags2::ACCELERATION_STRUCTURE_WRITE_KHR,
+      PipelineStage::ALL_COMMANDS,
+      vk::AccessFlags2::ACCELERATION_STRUCTURE_READ_KHR,
+    )
+    // The query type is inferred from the query pool type.
+    .write_acceleration_structure_properties(&blas.accel, &mut qp)?
+    .finish()?;
+  ctx.exec.submit(cmd)?.wait()?;
+  drop(build_info);
+  let compacted_size = qp.wait_for_single_result(0)?;
+  compact(ctx, blas, compacted_size)
+}
+
+// A TLAS with the host visible instance buffer it was built from, bind `accel` in descriptor sets.
+pub struct Tlas {
+  pub structure: BackedAccelerationStructure,
+  pub instances: Buffer,
+  pub instance_count: u32,
+  // Built with ALLOW_UPDATE, which refit_tlas needs.
+  pub allow_update: bool,
+}
+
+impl Tlas {
+  pub fn accel(&self) -> &AccelerationStructure {
+    &self.structure.accel
+  }
+}
+
+// Builds a TLAS over `instances` and waits for it. With `allow_update` it can be refit, see
+// refit_tlas, which may cost trace performance and memory, so static scenes leave it off.
+pub fn build_tlas(ctx: &mut Context, instances: &[AccelerationStructureInstance], allow_update: bool) -> Result<Tlas> {
+  // The Vulkan spec states: For any element of pInfos[i].pGeometries or pInfos[i].ppGeometries with a geometryType of VK_GEOMETRY_TYPE_INSTANCES_KHR,
+  // if geometry.arrayOfPointers is VK_FALSE, geometry.instances.data.deviceAddress must be aligned to 16 bytes
+  let instance_buffer = make_input_buffer(ctx, instances, Default::default(), Some(16))?;
+  let instance_count = instances.len() as u32;
+  let build_info = tlas_build_info(&instance_buffer, instance_count, false, allow_update);
+  let structure = BackedAccelerationStructure::new(ctx, &build_info, &[instance_count])?;
+  let build_info = build_info.dst(&structure.accel).scratch_data(structure.scratch_address());
+  submit_build(ctx, &build_info)?;
+  drop(build_info);
+  Ok(Tlas {
+    structure,
+    instances: instance_buffer,
+    instance_count,
+    allow_update,
+  })
+}
+
+// Moves the instances of `tlas` to `instances` by updating the structure in place, much cheaper
+// than build_tlas for animated transforms. The TLAS must have been built with `allow_update`, the
+// instance count and BLASes must stay the same, and
+// the trace quality degrades as instances move far from where they were built; rebuild then.
+// Waits for the update, but not for frames still tracing the TLAS: wait for the device first.
+#[allow(dead_code)]
+pub fn refit_tlas(ctx: &mut Context, tlas: &mut Tlas, instances: &[AccelerationStructureInstance]) -> Result<()> {
+  ensure!(tlas.allow_update, "the TLAS was built without allow_update, rebuild it instead of refitting");
+  ensure!(
+    instances.len() as u32 == tlas.instance_count,
+    "a refit keeps the instance count, rebuild the TLAS for {} instead of {} instances",
+    instances.len(),
+    tlas.instance_count
+  );
+  tlas.instances.view_full().mapped_slice::<AccelerationStructureInstance>()?.copy_from_slice(instances);
+  let build_info = tlas_build_info(&tlas.instances, tlas.instance_count, true, true)
+    .src(&tlas.structure.accel)
+    .dst(&tlas.structure.accel)
+    .scratch_data(tlas.structure.scratch_address());
+  submit_build(ctx, &build_info)
+}
@@ -0,0 +1,66 @@
+use anyhow::Result;
+use ash::vk;
+
+use phobos::prelude::*;
+
+use crate::app::Context;
+use crate::memory::Image;
+
+pub const ACCUMULATION_FORMAT: vk::Format = vk::Format::R32G32B32A32_SFLOAT;
+
+// Progressive accumulation target: an RGBA32F storage image holding the running mean of all samples
+// traced into it, and how many those are. Shaders blend every new sample in with
+// accumulate(previous, value, count) from accumulation.glsl, passing the count next_sample returned;
+// a count of 0 overwrites whatever the image held before, so a reset needs no clear pass.
+//
+// Reset it whenever the samples stop being comparable: the camera or scene changed, the image was
+// resized or a setting that changes the converged result was switched.
+pub struct Accumulation {
+  // Declared first so the view is dropped before the image it references.
+  pub view: ImageView,
+  pub image: Image,
+  samples: u32,
+}
+
+impl Accumulation {
+  pub fn new(ctx: &mut Context, width: u32, height: u32) -> Result<Self> {
+    let image = Image::new(
+      ctx.device.clone(),
+      &mut ctx.allocator,
+      width,
+      height,
+      vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::STORAGE,
+      ACCUMULATION_FORMAT,
+      vk::SampleCountFlags::TYPE_1,
+    )?;
+    let view = image.view(vk::ImageAspectFlags::COLOR)?;
+    Ok(Self {
+      view,
+      image,
+      samples: 0,
+    })
+  }
+
+  // Replaces the image with one of the new size, starting over.
+  pub fn resize(&mut self, ctx: &mut Context, width: u32, height: u32) -> Result<()> {
+    *self = Self::new(ctx, width, height)?;
+    Ok(())
+  }
+
+  // Samples in the image, e.g. for showing the progress.
+  pub fn sample_count(&self) -> u32 {
+    self.samples
+  }
+
+  // Discards the accumulated samples, the next one replaces the image.
+  pub fn reset(&mut self) {
+    self.samples = 0;
+  }
+
+  // Count to pass to the shader for the sample about to be recorded, and counts it.
+  pub fn next_sample(&mut self) -> u32 {
+    let index = self.samples;
+    self.samples = self.samples.wrapping_add(1);
+    index
+  }
+}
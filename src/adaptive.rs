@@ -0,0 +1,71 @@
+use anyhow::Result;
+use ash::vk;
+use bytemuck::{Pod, Zeroable};
+
+use phobos::prelude::*;
+use phobos::sync::domain::All;
+
+use crate::app::Context;
+use crate::memory::Buffer;
+
+// Per-pixel stopping rule for progressive rendering. Each pixel tracks the variance of its
+// luminance samples and stops taking samples once the relative standard error of its mean
+// drops below `threshold`, or after `max_samples`, leaving the remaining work to noisy pixels.
+//
+// This is biased: the variance estimate comes from the same samples it judges, so a pixel
+// whose rare bright paths (caustics, small lights) haven't shown up yet looks converged and
+// stops too early, coming out darker than it should. ADAPTIVE_MIN_SAMPLES in raygen.rgen
+// limits how early that can happen. An unbiased variant would have to keep sampling converged
+// pixels at a low rate, at the cost of never freeing their work completely.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AdaptiveSampling {
+  pub threshold: f32,
+  pub max_samples: u32,
+}
+
+impl AdaptiveSampling {
+  pub const DEFAULT_MAX_SAMPLES: u32 = 1024;
+}
+
+// Mirrors PixelStats in raygen.rgen (std430).
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct PixelStats {
+  // Sum of squared luminance deviations from the running mean (Welford).
+  m2: f32,
+  count: u32,
+}
+
+// GPU-only per-pixel statistics, reset by the raygen shader at sample index 0. Zeroed when created
+// as well, so a pixel read before its first sample, e.g. by the sample count view, counts none.
+pub struct PixelStatsBuffer {
+  pub buffer: Buffer,
+}
+
+impl PixelStatsBuffer {
+  pub fn new(ctx: &mut Context, width: u32, height: u32) -> Result<Self> {
+    let buffer = Buffer::new_device_local(
+      ctx.device.clone(),
+      &mut ctx.allocator,
+      (width * height) as u64 * std::mem::size_of::<PixelStats>() as u64,
+      vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+    )?;
+    let written = vk::BufferMemoryBarrier2::builder()
+      .buffer(unsafe { buffer.handle() })
+      .offset(0)
+      .size(vk::WHOLE_SIZE)
+      .src_stage_mask(PipelineStage::ALL_TRANSFER)
+      .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+      .dst_stage_mask(PipelineStage::ALL_COMMANDS)
+      .dst_access_mask(vk::AccessFlags2::SHADER_STORAGE_READ | vk::AccessFlags2::SHADER_STORAGE_WRITE)
+      .build();
+    let cmd = ctx.exec.on_domain::<All, DefaultAllocator>(None, None)?;
+    unsafe { ctx.device.cmd_fill_buffer(cmd.handle(), buffer.handle(), 0, vk::WHOLE_SIZE, 0) };
+    let cmd = cmd.pipeline_barrier(&vk::DependencyInfo::builder().buffer_memory_barriers(std::slice::from_ref(&written)).build())?.finish()?;
+    ctx.exec.submit(cmd)?.wait()?;
+    Ok(Self {
+      buffer,
+    })
+  }
+}
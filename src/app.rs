@@ -1,100 +1,792 @@
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::fs::File;
-use std::io::{Read, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use anyhow::{bail, Result};
-use futures::executor::block_on;
+use anyhow::{anyhow, bail, ensure, Context as _, Result};
+use bytemuck::Pod;
+use log::{debug, error, info, warn, LevelFilter};
 use layout::backends::svg::SVGWriter;
 use layout::gv;
 use layout::gv::GraphBuilder;
-use winit::event::{Event, WindowEvent};
+use winit::event::{DeviceEvent, ElementState, Event, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent};
 use winit::event_loop::{ControlFlow, EventLoop, EventLoopBuilder};
-use winit::window::{Window, WindowBuilder};
+use winit::platform::run_return::EventLoopExtRunReturn;
+use winit::window::{Window, WindowBuilder, WindowId};
 
 use phobos::prelude::*;
-use crate::{HEIGHT, WIDTH};
+use crate::adaptive::AdaptiveSampling;
+use crate::bloom::BloomSettings;
+use crate::blue_noise::SampleNoise;
+use crate::camera::StereoCamera;
+use crate::color::{choose_color_space, OutputColorSpace, DEFAULT_PAPER_WHITE_NITS};
+use crate::compute::ComputeKernel;
+use crate::device::{describe, initialize_ranked, log_device_ranking, probe_devices, DeviceCandidate, DeviceRequirements, DeviceSelector};
+use crate::error::{OxError, OxResult};
+use crate::executor::{CurrentThread, FrameExecutor};
+use crate::exposure::Exposure;
+use crate::features::DeviceFeatures;
+use crate::filter::ReconstructionFilter;
+use crate::frame_timing::{FrameTiming, FrameTimings, GpuTimer};
+use crate::hot_reload::{all_sources, recompile, ShaderWatcher, SHADER_DIR};
+use crate::input::InputState;
+use crate::interop::{external_memory_extensions, ExternalHandle, ExternalImage};
+use crate::memory::{AllocationTracker, Buffer, Image, MemoryBudget, MemoryReport, ScratchKind, ScratchStats, ScratchUsage, TrackingAllocator, DEFAULT_SCRATCH_RESERVE};
+use crate::mesh_shading::{mesh_shader_extensions, mesh_shader_features};
+use crate::output::{read_back, save_hdr_image, save_ldr_image, sequence_frame_path};
+use crate::pixel_debug::{PixelDebug, PixelDebugInfo};
+use crate::present::{choose_present_mode, PresentMode};
+use crate::render_mode::RenderMode;
+use crate::ray_query::{ray_query_extensions, ray_query_features};
+use crate::sampling::SamplingStrategy;
+use crate::texture::FormatSupport;
+use crate::thread::{configure_current_thread, RenderThreadPriority};
+use crate::time::{Clock, FrameTime, HEADLESS_FRAME_DT};
+use crate::validation::ValidationMessenger;
+
+// Default size of the window and the headless render target.
+pub const WIDTH: u32 = 1280;
+pub const HEIGHT: u32 = 720;
+
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+// Magic number, version, generator, bound and schema.
+const SPIRV_HEADER_WORDS: usize = 5;
 
 #[allow(dead_code)]
-pub fn load_spirv_file(path: &Path) -> Vec<u32> {
-  let mut f = File::open(&path).expect("no file found");
-  let metadata = fs::metadata(&path).expect("unable to read metadata");
-  let mut buffer = vec![0; metadata.len() as usize];
-  f.read(&mut buffer).expect("buffer overflow");
-  let (_, binary, _) = unsafe { buffer.align_to::<u32>() };
-  Vec::from(binary)
+pub fn load_spirv_file(path: &Path) -> OxResult<Vec<u32>> {
+  let load_error = |source| OxError::ShaderLoad {
+    path: path.to_path_buf(),
+    source,
+  };
+  let buffer = fs::read(path).map_err(load_error)?;
+  let invalid = |reason: &str| OxError::ShaderCompile {
+    path: path.to_path_buf(),
+    reason: reason.to_string(),
+  };
+  if buffer.len() % 4 != 0 {
+    return Err(invalid("size is not a multiple of 4 bytes"));
+  }
+  if buffer.len() < SPIRV_HEADER_WORDS * 4 {
+    return Err(invalid("truncated, shorter than the SPIR-V header"));
+  }
+  // Words are assembled from the bytes, the buffer's alignment doesn't matter. SPIR-V files may be
+  // stored in either byte order, the magic number tells which.
+  let mut binary = buffer.chunks_exact(4).map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]])).collect::<Vec<_>>();
+  if binary[0] == SPIRV_MAGIC.swap_bytes() {
+    binary.iter_mut().for_each(|word| *word = word.swap_bytes());
+  }
+  if binary[0] != SPIRV_MAGIC {
+    return Err(invalid("missing SPIR-V magic number"));
+  }
+  Ok(binary)
+}
+
+// Finds a compiled shader, so binaries work outside of `cargo run` too. Searched in order:
+// the path as given, $OX_SHADER_DIR, build.rs's output directory, the executable's directory and
+// resources/shaders. Paths in resources/shaders name shaders build.rs compiled into its output
+// directory, they are only looked up there last, for prebuilt shaders (OX_SKIP_SHADER_BUILD).
+pub fn resolve_shader_path(path: &str) -> OxResult<PathBuf> {
+  let given = PathBuf::from(path);
+  let built = given.parent() == Some(Path::new(SHADER_DIR));
+  let mut candidates = if built { Vec::new() } else { vec![given.clone()] };
+  if let Some(file_name) = given.file_name() {
+    if let Ok(dir) = std::env::var("OX_SHADER_DIR") {
+      candidates.push(Path::new(&dir).join(file_name));
+    }
+    candidates.push(Path::new(env!("OX_BUILD_SHADER_DIR")).join(file_name));
+    if let Some(exe_dir) = std::env::current_exe().ok().as_deref().and_then(Path::parent) {
+      candidates.push(exe_dir.join(file_name));
+    }
+    candidates.push(Path::new(SHADER_DIR).join(file_name));
+  }
+
+  match candidates.iter().find(|candidate| candidate.is_file()) {
+    Some(found) => Ok(found.clone()),
+    None => {
+      let searched = candidates.iter().map(|candidate| format!("  {}", candidate.display())).collect::<Vec<_>>().join("\n");
+      Err(OxError::ShaderLoad {
+        path: PathBuf::from(path),
+        source: std::io::Error::new(std::io::ErrorKind::NotFound, format!("searched:\n{}", searched)),
+      })
+    }
+  }
+}
+
+// The stage of a shader compiled by build.rs, from the kind suffix of its <name>_<kind>.spv.
+fn stage_of_spirv_file(path: &str) -> Option<vk::ShaderStageFlags> {
+  let kind = path.strip_suffix(".spv")?.rsplit('_').next()?;
+  Some(match kind {
+    "vert" => vk::ShaderStageFlags::VERTEX,
+    "geom" => vk::ShaderStageFlags::GEOMETRY,
+    "frag" => vk::ShaderStageFlags::FRAGMENT,
+    "comp" => vk::ShaderStageFlags::COMPUTE,
+    "rgen" => vk::ShaderStageFlags::RAYGEN_KHR,
+    "rchit" => vk::ShaderStageFlags::CLOSEST_HIT_KHR,
+    "rahit" => vk::ShaderStageFlags::ANY_HIT_KHR,
+    "rmiss" => vk::ShaderStageFlags::MISS_KHR,
+    "rint" => vk::ShaderStageFlags::INTERSECTION_KHR,
+    "rcall" => vk::ShaderStageFlags::CALLABLE_KHR,
+    "task" => vk::ShaderStageFlags::TASK_EXT,
+    "mesh" => vk::ShaderStageFlags::MESH_EXT,
+    _ => return None,
+  })
 }
 
+// Fails if `path` is named like a shader of another stage, which would otherwise only show up as a
+// validation error at pipeline creation.
 #[allow(dead_code)]
-pub fn create_shader(path: &str, stage: vk::ShaderStageFlags) -> ShaderCreateInfo {
-  let code = load_spirv_file(Path::new(path));
-  ShaderCreateInfo::from_spirv(stage, code)
+pub fn create_shader(path: &str, stage: vk::ShaderStageFlags) -> OxResult<ShaderCreateInfo> {
+  if let Some(expected) = stage_of_spirv_file(path).filter(|&expected| expected != stage) {
+    return Err(OxError::ShaderCompile {
+      path: PathBuf::from(path),
+      reason: format!("compiled as a {:?} shader, not {:?}", expected, stage),
+    });
+  }
+  let code = load_spirv_file(&resolve_shader_path(path)?)?;
+  Ok(ShaderCreateInfo::from_spirv(stage, code))
+}
+
+fn dot_source<G>(graph: &G) -> Result<String>
+  where
+    G: GraphViz, {
+  Ok(format!("{}", graph.dot().context("failed to export the graph as dot")?))
 }
 
+// Lays the graph out with the layout crate and returns the SVG document, for callers that embed
+// or convert it themselves.
 #[allow(dead_code)]
-pub fn save_dotfile<G>(graph: &G, path: &str)
+pub fn graph_svg<G>(graph: &G) -> Result<String>
   where
     G: GraphViz, {
-  let dot = graph.dot().unwrap();
-  let dot = format!("{}", dot);
+  let dot = dot_source(graph)?;
   let mut parser = gv::DotParser::new(&dot);
-  match parser.process() {
-    Ok(g) => {
-      let mut svg = SVGWriter::new();
-      let mut builder = GraphBuilder::new();
-      builder.visit_graph(&g);
-      let mut vg = builder.get();
-      vg.do_it(false, false, false, &mut svg);
-      let svg = svg.finalize();
-      let mut f = File::create(Path::new(path)).unwrap();
-      f.write(&svg.as_bytes()).unwrap();
+  let g = parser.process().map_err(|e| anyhow!("failed to parse the graph's dot output: {}", e))?;
+  let mut svg = SVGWriter::new();
+  let mut builder = GraphBuilder::new();
+  builder.visit_graph(&g);
+  let mut vg = builder.get();
+  vg.do_it(false, false, false, &mut svg);
+  Ok(svg.finalize())
+}
+
+#[allow(dead_code)]
+pub fn save_dotfile<G>(graph: &G, path: &str) -> Result<()>
+  where
+    G: GraphViz, {
+  let svg = graph_svg(graph).with_context(|| format!("failed to render {}", path))?;
+  std::fs::write(path, svg).map_err(|e| OxError::io(path, e))?;
+  Ok(())
+}
+
+// Rasterizes the layout of save_dotfile to a PNG `width` pixels wide, the height follows the
+// layout's aspect ratio. Labels are drawn with the system fonts. Needs the graph-png feature.
+#[allow(dead_code)]
+pub fn save_graph_png<G>(graph: &G, path: &str, width: u32) -> Result<()>
+  where
+    G: GraphViz, {
+  let svg = graph_svg(graph).with_context(|| format!("failed to render {}", path))?;
+  #[cfg(feature = "graph-png")]
+  {
+    use usvg::{FitTo, Options, Tree};
+    use usvg_text_layout::{fontdb, TreeTextToPath};
+
+    let mut tree = Tree::from_str(&svg, &Options::default())?;
+    let mut fonts = fontdb::Database::new();
+    fonts.load_system_fonts();
+    tree.convert_text(&fonts);
+    let fit = FitTo::Width(width);
+    let size = fit.fit_to(tree.size.to_screen_size()).ok_or_else(|| anyhow!("graph layout has no area"))?;
+    let mut pixmap = tiny_skia::Pixmap::new(size.width(), size.height()).ok_or_else(|| anyhow!("can't rasterize the graph at {}x{}", size.width(), size.height()))?;
+    pixmap.fill(tiny_skia::Color::WHITE);
+    resvg::render(&tree, fit, tiny_skia::Transform::default(), pixmap.as_mut()).ok_or_else(|| anyhow!("failed to rasterize the graph"))?;
+    let png = pixmap.encode_png()?;
+    std::fs::write(path, png).map_err(|e| OxError::io(path, e))?;
+    Ok(())
+  }
+  #[cfg(not(feature = "graph-png"))]
+  {
+    let _ = (svg, width);
+    bail!("can't write {}, built without the graph-png feature", path)
+  }
+}
+
+// Writes the DOT source of the graph as is, for Graphviz or an online viewer. Skips the layout
+// engine of save_dotfile, so it is fast enough to dump every graph in CI.
+#[allow(dead_code)]
+pub fn save_dot_text<G>(graph: &G, path: &str) -> Result<()>
+  where
+    G: GraphViz, {
+  let dot = dot_source(graph)?;
+  std::fs::write(path, dot).map_err(|e| OxError::io(path, e))?;
+  Ok(())
+}
+
+// Extracts the head (node id or `a -> b`) and the label of one statement of a dot graph.
+fn parse_dot_statement(line: &str) -> Option<(&str, Option<&str>)> {
+  let line = line.trim().trim_end_matches(';');
+  if line.is_empty() || line.starts_with("digraph") || line.starts_with("graph") || line == "}" || line.starts_with("//") {
+    return None;
+  }
+  let (head, attributes) = match line.find('[') {
+    Some(start) => (line[..start].trim(), Some(&line[start..])),
+    None => (line, None),
+  };
+  let label = attributes.and_then(|attributes| {
+    let start = attributes.find("label")?;
+    let rest = &attributes[start..];
+    let open = rest.find('"')? + 1;
+    let close = rest[open..].find('"')? + open;
+    Some(&rest[open..close])
+  });
+  Some((head, label))
+}
+
+// Text version of save_dotfile for terminals, CI logs and issues: every node of the graph
+// (passes, and the barriers inserted between them) in declaration order, each followed by
+// its incoming and outgoing edges, labeled with the resource they carry.
+#[allow(dead_code)]
+pub fn print_graph<G>(graph: &G) -> Result<String>
+  where
+    G: GraphViz, {
+  let dot = dot_source(graph)?;
+  let mut nodes: Vec<(&str, &str)> = Vec::new();
+  let mut edges: Vec<(&str, &str, &str)> = Vec::new();
+  for (head, label) in dot.lines().filter_map(parse_dot_statement) {
+    match head.split_once("->") {
+      Some((from, to)) => edges.push((from.trim(), to.trim(), label.unwrap_or(""))),
+      None => nodes.push((head, label.unwrap_or(head))),
+    }
+  }
+
+  let name = |id: &str| nodes.iter().find(|(node, _)| *node == id).map_or(id, |(_, label)| *label).replace("\\n", " ");
+  let mut text = String::new();
+  for (id, label) in &nodes {
+    text += &format!("{}\n", label.replace("\\n", " "));
+    for (from, _, resource) in edges.iter().filter(|(_, to, _)| to == id) {
+      text += &format!("  <- {} ({})\n", resource.replace("\\n", " "), name(from));
+    }
+    for (_, to, resource) in edges.iter().filter(|(from, _, _)| from == id) {
+      text += &format!("  -> {} ({})\n", resource.replace("\\n", " "), name(to));
+    }
+  }
+  Ok(text)
+}
+
+
+// For every resource the passes use, the passes touching it in order with the access, layout and
+// stage each one declares. These are what the graph derives its barriers from, one goes in wherever
+// they change between two passes, so this maps a sync validation error about a resource back to the
+// passes involved.
+pub fn resource_transitions<'a, 'cb: 'a, D, U: 'a, A>(passes: impl IntoIterator<Item = &'a Pass<'cb, D, U, A>>) -> String
+  where
+    D: ExecutionDomain + 'a,
+    A: Allocator + 'a, {
+  let mut resources: Vec<(String, Vec<(String, &GraphResource)>)> = Vec::new();
+  for pass in passes {
+    for usage in pass.inputs().iter().chain(pass.outputs()) {
+      // Outputs are new versions of the resource, suffixed with a +.
+      let resource = usage.resource.uid().trim_end_matches('+').to_string();
+      let entry = (pass.name().to_string(), usage);
+      match resources.iter_mut().find(|(name, _)| *name == resource) {
+        Some((_, uses)) => uses.push(entry),
+        None => resources.push((resource, vec![entry])),
+      }
     }
-    Err(e) => {
-      parser.print_error();
-      println!("dot render error: {}", e);
+  }
+
+  let mut text = String::new();
+  for (resource, uses) in &resources {
+    text += &format!("{}\n", resource);
+    let mut previous: Option<&GraphResource> = None;
+    for (pass, usage) in uses {
+      if previous.map_or(false, |previous| previous.usage != usage.usage || previous.layout != usage.layout) {
+        text += "    barrier\n";
+      }
+      text += &format!("  {}: {:?} as {:?} at {:?}\n", pass, usage.usage, usage.layout, usage.stage);
+      previous = Some(usage);
     }
   }
+  text
 }
 
+// Logs resource_transitions of the passes of a graph, see RunnerConfig::log_barriers.
+pub fn log_resource_transitions<'a, 'cb: 'a, D, U: 'a, A>(name: &str, passes: impl IntoIterator<Item = &'a Pass<'cb, D, U, A>>)
+  where
+    D: ExecutionDomain + 'a,
+    A: Allocator + 'a, {
+  debug!("Resource transitions of {}:\n{}", name, resource_transitions(passes));
+}
 
 #[derive(Debug)]
 pub struct WindowContext {
   pub event_loop: EventLoop<()>,
+  // The primary window, its size is RunnerConfig's width and height.
   pub window: Window,
+  // Windows from add_window, drawn by App::window_frame and sharing the device with the primary one.
+  pub secondary: Vec<Window>,
 }
 
 impl WindowContext {
+  // A window of the default WIDTH x HEIGHT.
   #[allow(dead_code)]
-  pub fn new(title: impl Into<String>) -> Result<Self> {
+  pub fn new(title: impl Into<String>) -> OxResult<Self> {
+    Self::with_size(title, WIDTH, HEIGHT)
+  }
+
+  // `width` and `height` are in physical pixels, the resolution of the swapchain and the render
+  // targets, so high DPI displays open at their native resolution instead of scaling the image up.
+  pub fn with_size(title: impl Into<String>, width: u32, height: u32) -> OxResult<Self> {
     let event_loop = EventLoopBuilder::new().build();
     let window = WindowBuilder::new()
       .with_title(title)
-      .with_inner_size(winit::dpi::LogicalSize::new(WIDTH, HEIGHT))
-      .build(&event_loop)?;
+      .with_inner_size(winit::dpi::PhysicalSize::new(width, height))
+      .build(&event_loop)
+      .map_err(|e| OxError::device_init("the window", e))?;
     Ok(Self {
       event_loop,
       window,
+      secondary: Vec::new(),
     })
   }
+
+  // Opens another window on the same event loop, e.g. a debug view of the G-buffer. Runner::new
+  // creates a swapchain for every window, so windows have to be added before it.
+  #[allow(dead_code)]
+  pub fn add_window(&mut self, title: impl Into<String>, width: u32, height: u32) -> OxResult<WindowId> {
+    let window = WindowBuilder::new()
+      .with_title(title)
+      .with_inner_size(winit::dpi::PhysicalSize::new(width, height))
+      .build(&self.event_loop)
+      .map_err(|e| OxError::device_init("the window", e))?;
+    let id = window.id();
+    self.secondary.push(window);
+    Ok(id)
+  }
 }
 
-pub struct VulkanContext {
+// The swapchain of a window and the surface it presents to.
+pub struct WindowSurface {
+  // None only while the swapchain is being recreated.
   pub frame: Option<FrameManager>,
+  pub surface: Surface,
+  // Size of the swapchain.
+  pub width: u32,
+  pub height: u32,
+  // Times the window's frames, with query slots of its own since its frames are in flight
+  // independently of the other windows'.
+  pub gpu_timer: GpuTimer,
+}
+
+pub struct VulkanContext {
+  // Every open window, empty when headless. Dropped before the device and the instance.
+  pub windows: HashMap<WindowId, WindowSurface>,
+  // The window RunnerConfig's width and height follow, None when headless.
+  pub primary_window: Option<WindowId>,
   pub exec: ExecutionManager,
-  pub allocator: DefaultAllocator,
+  pub allocator: TrackingAllocator,
   pub device: Device,
   pub physical_device: PhysicalDevice,
-  pub surface: Option<Surface>,
-  pub debug_messenger: DebugMessenger,
+  // phobos' messenger, None when RunnerConfig::validation is off or once validation replaced it.
+  pub debug_messenger: Option<DebugMessenger>,
+  pub validation: Option<ValidationMessenger>,
   pub instance: VkInstance,
 }
 
+// Pixel rectangle of the render target, with the origin at the top left.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Rect {
+  pub x: u32,
+  pub y: u32,
+  pub width: u32,
+  pub height: u32,
+}
+
+impl Rect {
+  pub fn full(width: u32, height: u32) -> Self {
+    Self {
+      x: 0,
+      y: 0,
+      width,
+      height,
+    }
+  }
+
+  pub fn fits_in(&self, width: u32, height: u32) -> bool {
+    self.width > 0 && self.height > 0 && self.x as u64 + self.width as u64 <= width as u64 && self.y as u64 + self.height as u64 <= height as u64
+  }
+}
+
+// Options the runner was launched with, see cli.rs for the command-line mapping.
+#[derive(Debug, Clone)]
+pub struct RunnerConfig {
+  // Render target size in physical pixels. Windowed runs open the window at this size and use the
+  // size it actually got.
+  pub width: u32,
+  pub height: u32,
+  pub scene: Option<PathBuf>,
+  // Camera file in the format of Camera::save, the default camera when None
+  pub camera: Option<PathBuf>,
+  // Samples per pixel to accumulate in a headless run
+  pub spp: Option<u32>,
+  pub headless: bool,
+  // Where a headless run writes its result
+  pub output: Option<PathBuf>,
+  // Write the output exposed and sRGB encoded like the window shows it, instead of the raw HDR values.
+  pub tone_map: bool,
+  // Color space of the window's swapchain, falls back to sRGB with a warning if the surface doesn't
+  // support it. Holds the space in use once the runner is initialized.
+  pub color_space: OutputColorSpace,
+  // Brightness of diffuse white in nits on the HDR color spaces, sRGB output ignores it.
+  pub paper_white: f32,
+  // Present mode of the window's swapchain, FIFO if the surface doesn't support it. Holds the mode
+  // in use once the runner is initialized, like color_space.
+  pub present_mode: PresentMode,
+  // Also write linear depth and world position of the primary hits next to the output, see CompositingPasses.
+  pub compositing_passes: bool,
+  // Misses get alpha 0 and hits alpha 1, with the color premultiplied by it, for compositing the
+  // render over other content. The sky still lights the scene but isn't seen directly, so this
+  // only makes sense for scenes that aren't meant to show an environment.
+  pub transparent_background: bool,
+  // Base seed of the per-pixel RNG. Renders are deterministic for a given seed.
+  pub seed: u32,
+  // Sub-pixel jitter of the samples, see SampleNoise.
+  pub sample_noise: SampleNoise,
+  // Path tracing, or ambient occlusion of the primary hit with its ray count and radius.
+  pub render_mode: RenderMode,
+  // Fixed or adaptive exposure of the displayed and tone-mapped image. The adaptive reduction runs on
+  // the compute queue after every traced sample.
+  pub exposure: Exposure,
+  // Glow around bright parts of the image, added before exposure in the display pass, None disables it.
+  // Blurred on the compute queue after every traced sample, see Bloom.
+  pub bloom: Option<BloomSettings>,
+  // Render both eyes of a stereo pair into the image, see StereoCamera. Can't be combined with crop.
+  pub stereo: Option<StereoCamera>,
+  // Per-pixel stopping rule of the accumulation, None samples every pixel equally. See AdaptiveSampling.
+  pub adaptive: Option<AdaptiveSampling>,
+  // Show the per-pixel sample counts of adaptive sampling instead of the image.
+  pub adaptive_view: bool,
+  // Ray interval [t_min, t_max] used for all trace calls. A t_min that is too small lets rays
+  // re-hit the surface they start on (shadow acne), one that is too large skips nearby
+  // geometry and makes light leak through thin walls and into corners.
+  // None derives t_min from the scene size.
+  pub t_min: Option<f32>,
+  pub t_max: f32,
+  // Per-sample radiance limit against fireflies, 0 or infinity disables it. Clamping
+  // throws away energy of exactly the rare bright paths (caustics, small bright lights)
+  // that cause fireflies, so the image converges darker than the true result there.
+  pub firefly_clamp: f32,
+  // How diffuse hits sample the sky, see SamplingStrategy.
+  pub sampling: SamplingStrategy,
+  // Next event estimation is skipped on materials smoother than this and only partly taken up to
+  // twice the value, 0 always takes it. Unbiased with MIS: bounces that hit the light make up for
+  // the skipped shadow rays, which near-mirror surfaces find with their BSDF anyway. See sampling::nee_probability.
+  pub nee_roughness_threshold: f32,
+  // Refract light through materials with a dispersion coefficient at a per-sample wavelength, see
+  // spectral.glsl. Paths through dispersive glass gain colour noise and need more samples.
+  pub spectral: bool,
+  // Let shadow rays pass through materials with a transmission colour or alpha below 1, tinted by
+  // them. Those surfaces run an any-hit shader for every shadow ray, opaque scenes should leave it off.
+  pub transmissive_shadows: bool,
+  // Add an occluder to the built-in scene with an area light and a spherical point light, each
+  // casting a soft shadow of it onto the quad. Ignored with a scene file.
+  pub light_demo: bool,
+  // Pixel filter the samples are weighted with, box with radius 0.5 averages each pixel on its own.
+  // See ReconstructionFilter for the tradeoffs.
+  pub filter: ReconstructionFilter,
+  // Only trace this part of the image, the rest is left untouched. Headless runs write just the crop.
+  pub crop: Option<Rect>,
+  // Stop rendering while the window is unfocused or occluded. The accumulated image is kept and
+  // continues to converge once the window is back.
+  pub pause_on_unfocus: bool,
+  // Closes the window like its close button, for fullscreen windows without one. None disables it.
+  pub exit_key: Option<VirtualKeyCode>,
+  // Windowed runs save the displayed image as a PNG in screenshot_dir when this key is pressed, None disables it.
+  pub screenshot_key: Option<VirtualKeyCode>,
+  pub screenshot_dir: PathBuf,
+  // Windowed runs watch resources/shaders, recompile edited shaders and pass them to App::reload_shaders
+  // before the next frame. Needs the hot-reload feature, see hot_reload.rs.
+  pub hot_reload_shaders: bool,
+  // Recompiles and reloads every shader when pressed, with or without hot_reload_shaders. None disables it.
+  pub shader_reload_key: Option<VirtualKeyCode>,
+  // Windowed runs stop tracing after this many samples and keep presenting the converged image until
+  // the view changes, None accumulates forever. Headless runs use spp instead.
+  pub max_samples: Option<u32>,
+  // How long the camera has to stay still before windowed runs switch from the direct lighting
+  // preview to full path tracing, zero disables the preview. See preview.rs.
+  pub preview_idle: Duration,
+  // Scheduling of the thread running the window's render loop, applied when Runner::run starts it.
+  // Both are best effort and need the thread-priority feature, see thread::configure_current_thread.
+  pub render_thread_priority: RenderThreadPriority,
+  // Core index to pin the render thread to, None lets the OS move it.
+  pub render_thread_core: Option<usize>,
+  // Log level when RUST_LOG isn't set, RUST_LOG filters take precedence.
+  pub log_level: LevelFilter,
+  // Enables VK_LAYER_KHRONOS_validation and logs its messages, on by default in debug builds. Turned
+  // off with a warning when the layer isn't installed. Validation slows every call down.
+  pub validation: bool,
+  // Panic after a frame (or headless run, render_to_file, render_to_external) that raised a
+  // validation error, for tests. Needs validation.
+  pub strict_validation: bool,
+  // Log the frame rate and the averaged CPU and GPU frame times once per second, see FrameStats::timing.
+  pub log_frame_times: bool,
+  // Log, at debug level, how every pass of a recorded graph uses each resource and where barriers go
+  // between them, see resource_transitions. Formats a string per graph, so it stays off normally.
+  pub log_barriers: bool,
+  // Memory and device type initialize() selects the GPU by. Lower the memory floor for small or
+  // integrated GPUs.
+  pub device: DeviceRequirements,
+  // Use this GPU instead of the best scoring one, see Runner::list_devices for the candidates.
+  pub gpu: Option<DeviceSelector>,
+  // Queues to request, with a fallback for hardware without dedicated transfer or compute families.
+  pub queues: QueueRequirements,
+  // Scratch memory (ifc.allocate_scratch_*) reserved at startup per buffer type and frame in flight.
+  // Frames never reallocate it, a frame that needs more fails. Raise it when the runner warns that
+  // the peak is close, App::on_frame_stats reports the observed peak.
+  pub scratch_reserve: u64,
+  // Enables the device extensions for importing memory from other APIs, see Runner::render_to_external.
+  // Off by default, devices without them would fail to initialize.
+  pub external_memory: bool,
+  // Enables VK_EXT_mesh_shader and its taskShader and meshShader features for task and mesh shader
+  // pipelines, see mesh_shading::MeshPipeline. Off by default like external_memory, devices without
+  // them would fail to initialize.
+  pub mesh_shaders: bool,
+  // Enables VK_KHR_ray_query and its rayQuery feature for tracing from compute and fragment shaders,
+  // see ray_query.rs. Off by default like mesh_shaders.
+  pub ray_query: bool,
+  // Device features and extensions to enable, on top of what phobos enables for ray tracing. Unknown
+  // names fail before initialization, devices lacking any of them are passed over and the error lists
+  // what is missing if none is left.
+  // The extensions and features of external_memory, mesh_shaders and ray_query are added to these.
+  pub features: DeviceFeatures,
+  // Headless runs with Some(frames) time that many samples and write a JSON report to output
+  // instead of an image, see bench.rs.
+  pub bench: Option<u32>,
+  // Headless runs with Some(frames) render that many independent frames of spp samples each, see
+  // RaytracingSample::render_sequence. Every frame starts a new accumulation with its own seed.
+  pub frames: Option<u32>,
+}
+
+impl Default for RunnerConfig {
+  fn default() -> Self {
+    Self {
+      width: WIDTH,
+      height: HEIGHT,
+      scene: None,
+      camera: None,
+      spp: None,
+      headless: false,
+      output: None,
+      tone_map: false,
+      color_space: OutputColorSpace::default(),
+      paper_white: DEFAULT_PAPER_WHITE_NITS,
+      present_mode: PresentMode::default(),
+      compositing_passes: false,
+      transparent_background: false,
+      seed: 0,
+      sample_noise: SampleNoise::default(),
+      render_mode: RenderMode::default(),
+      exposure: Exposure::default(),
+      bloom: None,
+      stereo: None,
+      adaptive: None,
+      adaptive_view: false,
+      t_min: None,
+      t_max: 10000.0,
+      firefly_clamp: 0.0,
+      sampling: SamplingStrategy::default(),
+      nee_roughness_threshold: 0.0,
+      spectral: false,
+      transmissive_shadows: false,
+      light_demo: false,
+      filter: ReconstructionFilter::default(),
+      crop: None,
+      pause_on_unfocus: true,
+      exit_key: Some(VirtualKeyCode::Escape),
+      screenshot_key: Some(VirtualKeyCode::F12),
+      screenshot_dir: PathBuf::from("screenshots"),
+      hot_reload_shaders: false,
+      shader_reload_key: Some(VirtualKeyCode::F6),
+      max_samples: None,
+      preview_idle: Duration::from_millis(300),
+      render_thread_priority: RenderThreadPriority::default(),
+      render_thread_core: None,
+      log_level: LevelFilter::Info,
+      validation: cfg!(debug_assertions),
+      strict_validation: false,
+      log_frame_times: false,
+      log_barriers: false,
+      device: DeviceRequirements::default(),
+      gpu: None,
+      queues: QueueRequirements::default(),
+      scratch_reserve: DEFAULT_SCRATCH_RESERVE,
+      external_memory: false,
+      mesh_shaders: false,
+      ray_query: false,
+      features: DeviceFeatures::default(),
+      bench: None,
+      frames: None,
+    }
+  }
+}
+
+// Shared flag to stop a long running render early, e.g. from a UI thread.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  #[allow(dead_code)]
+  pub fn cancel(&self) {
+    self.0.store(true, Ordering::Relaxed);
+  }
+
+  pub fn is_cancelled(&self) -> bool {
+    self.0.load(Ordering::Relaxed)
+  }
+}
+
 pub struct Context {
   pub device: Device,
   pub exec: ExecutionManager,
-  pub allocator: DefaultAllocator,
+  pub allocator: TrackingAllocator,
   pub pipelines: PipelineCache,
   pub descriptors: DescriptorCache,
+  pub config: RunnerConfig,
+  // Which texture formats the device can sample.
+  pub formats: FormatSupport,
+  pub memory: Arc<MemoryBudget>,
+  // Record scratch allocations here to have them show up in App::on_frame_stats.
+  pub scratch: Arc<ScratchUsage>,
+  // Allocations of `allocator` that are still alive, debug builds only.
+  pub allocations: Arc<AllocationTracker>,
+  pub pixel_debug: PixelDebug,
+  // Wrap the frame's command buffer in it for FrameStats::timing to include the GPU time. In
+  // App::window_frame it is the timer of the window being drawn.
+  pub gpu_timer: GpuTimer,
+  // The physical device initialize() picked.
+  pub gpu: DeviceCandidate,
+  // The window App::frame draws, to tell it apart in App::window_frame. None when headless.
+  pub primary_window: Option<WindowId>,
+}
+
+// maxPushConstantsSize every device supports. Push constant blocks within it need no check of the
+// device, Context::push_constants checks larger ones against DeviceCandidate::max_push_constants_size.
+pub const PUSH_CONSTANT_LIMIT: u32 = 128;
+
+// Queue family that ended up executing each domain.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct QueueFamilies {
+  pub graphics: Option<u32>,
+  pub transfer: Option<u32>,
+  pub compute: Option<u32>,
+}
+
+impl QueueFamilies {
+  // Whether work on `family` runs on a queue family no other domain uses, e.g. real async compute.
+  pub fn is_dedicated(&self, family: Option<u32>) -> bool {
+    let families = [self.graphics, self.transfer, self.compute];
+    family.is_some() && families.iter().filter(|&&other| other == family).count() == 1
+  }
+
+  #[allow(dead_code)]
+  pub fn dedicated_transfer(&self) -> bool {
+    self.is_dedicated(self.transfer)
+  }
+
+  #[allow(dead_code)]
+  pub fn dedicated_compute(&self) -> bool {
+    self.is_dedicated(self.compute)
+  }
+}
+
+fn queue_families(exec: &ExecutionManager) -> QueueFamilies {
+  QueueFamilies {
+    graphics: exec.get_queue::<domain::Graphics>().map(|queue| queue.family_index()),
+    transfer: exec.get_queue::<domain::Transfer>().map(|queue| queue.family_index()),
+    compute: exec.get_queue::<domain::Compute>().map(|queue| queue.family_index()),
+  }
+}
+
+impl Context {
+  // Base seed for the app's sampling noise, RunnerConfig::seed. Apps that derive all their random
+  // numbers from it and the frame index render the same image on every run with the same settings.
+  pub fn seed(&self) -> u32 {
+    self.config.seed
+  }
+
+  // Copies `value` into a uniform buffer from the frame's scratch memory and records it in `scratch`.
+  // The buffer is valid for this frame only, bind it with bind_uniform_buffer.
+  pub fn uniform<T: Pod>(&self, ifc: &mut InFlightContext, value: &T) -> Result<BufferView> {
+    let size = std::mem::size_of::<T>() as u64;
+    self.scratch.record(ScratchKind::Uniform, size);
+    let mut buffer = ifc.allocate_scratch_ubo(size)?;
+    buffer.mapped_slice::<T>()?.copy_from_slice(std::slice::from_ref(value));
+    Ok(buffer)
+  }
+
+  // Records `value` as push constants at `offset` for `stages`, failing if it ends past the device's
+  // maxPushConstantsSize. The pipeline layout comes from the shaders' push_constant blocks.
+  pub fn push_constants<'q, D: ExecutionDomain, T: Pod>(
+    &self,
+    cmd: IncompleteCommandBuffer<'q, D>,
+    stages: vk::ShaderStageFlags,
+    offset: u32,
+    value: &T,
+  ) -> Result<IncompleteCommandBuffer<'q, D>> {
+    let end = offset as usize + std::mem::size_of::<T>();
+    ensure!(
+      end <= self.gpu.max_push_constants_size as usize,
+      "{} push constants at offset {} end at byte {}, past the {} bytes {} supports ({} on every device), use Context::uniform instead",
+      std::any::type_name::<T>(),
+      offset,
+      end,
+      self.gpu.max_push_constants_size,
+      self.gpu.name,
+      PUSH_CONSTANT_LIMIT
+    );
+    Ok(cmd.push_constant(stages, offset, value))
+  }
+
+  // Registers the compute shader at `path` as pipeline `name`, see ComputeKernel.
+  #[allow(dead_code)]
+  pub fn compute_pipeline(&mut self, name: &str, path: &str) -> OxResult<ComputeKernel> {
+    ComputeKernel::new(self, name, path)
+  }
+
+  // Current device memory usage and budget, see MemoryBudget.
+  #[allow(dead_code)]
+  pub fn memory_report(&self) -> MemoryReport {
+    self.memory.report()
+  }
+
+  // Which queue families initialize actually assigned. Dedicated queue requests are only hints,
+  // check this before relying on async compute or transfer overlapping with graphics work.
+  #[allow(dead_code)]
+  pub fn queue_info(&self) -> QueueFamilies {
+    queue_families(&self.exec)
+  }
+
+  // Has the next frame write PixelDebugInfo for pixel (x, y) of the render target. The result is
+  // passed to App::on_pixel_debug once that frame has finished, windowed runs request it on left click.
+  pub fn debug_pixel(&self, x: u32, y: u32) {
+    self.pixel_debug.request(x, y);
+  }
+
+  // Buffer::new with the context's allocator, counted in FrameStats::live_allocations until it is
+  // dropped like everything allocated through it, see TrackingAllocator.
+  pub fn create_buffer(&mut self, size: u64, usage: vk::BufferUsageFlags, memory_type: MemoryType) -> Result<Buffer> {
+    Ok(Buffer::new(self.device.clone(), &mut self.allocator, size, usage, memory_type)?)
+  }
+
+  // A single-sampled 2D Image::new, counted like create_buffer.
+  pub fn create_image(&mut self, width: u32, height: u32, usage: vk::ImageUsageFlags, format: vk::Format) -> Result<Image> {
+    Ok(Image::new(self.device.clone(), &mut self.allocator, width, height, usage, format, vk::SampleCountFlags::TYPE_1)?)
+  }
 }
 
 pub trait App {
@@ -102,77 +794,511 @@ pub trait App {
     where
       Self: Sized;
 
-  // Implement this for a windowed application
-  fn frame(&mut self, _ctx: Context, _ifc: InFlightContext) -> Result<CommandBuffer<domain::All>> {
+  // Implement this for a windowed application. `time` is the same for everything recorded in this frame,
+  // `input` has the keyboard and mouse state and the motion since the previous frame.
+  fn frame(&mut self, _ctx: Context, _ifc: InFlightContext, _time: FrameTime, _input: &InputState) -> Result<CommandBuffer<domain::All>> {
     bail!("frame() not implemented for non-headless example app");
   }
 
+  // Draws `window`, one of the windows from WindowContext::add_window or Context::primary_window.
+  // The default draws the primary window with frame, apps with more windows match on the id. The
+  // clock ticks once per event loop iteration, so every window drawn in it gets the same `time` and
+  // `input`.
+  fn window_frame(&mut self, ctx: Context, ifc: InFlightContext, window: WindowId, time: FrameTime, input: &InputState) -> Result<CommandBuffer<domain::All>> {
+    if ctx.primary_window != Some(window) {
+      bail!("window_frame() not implemented for the app's secondary windows");
+    }
+    self.frame(ctx, ifc, time, input)
+  }
+
   // Implement this for a headless application
   fn run(&mut self, _ctx: Context, _thread: ThreadContext) -> Result<()> {
     bail!("run() not implemented for headless example app");
   }
+
+  // One frame of Runner::run_headless_frames, submitted and waited for before returning. `time`
+  // advances by a fixed step, so it is the same on every run.
+  fn headless_frame(&mut self, _ctx: Context, _thread: &mut ThreadContext, _time: FrameTime) -> Result<()> {
+    bail!("headless_frame() not implemented for this app");
+  }
+
+  // Rebuild scene-specific resources (geometry, acceleration structures, textures), called when F5 is pressed.
+  // The device is idle when this is called, so old resources can be dropped right away.
+  fn reload_scene(&mut self, _ctx: Context) -> Result<()> {
+    Ok(())
+  }
+
+  // Recreate the pipelines after Runner's shader hot reload recompiled `shaders`, the .spv files that
+  // changed. `ctx.pipelines` is a new, empty PipelineCache: register every pipeline the app binds
+  // again, e.g. with Tonemap::create_pipeline, ComputeKernel::create_pipeline or MeshPipeline::create.
+  // The runner switches to it only if this returns Ok, on an error it logs it and every pipeline keeps
+  // its previous shaders. The device is idle, like in reload_scene. The default bails, so apps that
+  // don't implement it keep their pipelines.
+  fn reload_shaders(&mut self, _ctx: Context, _shaders: &[PathBuf]) -> Result<()> {
+    bail!("reload_shaders() not implemented for this app");
+  }
+
+  // Recreate size dependent resources after the window was resized to `width` x `height` pixels, the
+  // new swapchain's extent and Context::config's size. The device is idle, like in reload_scene.
+  fn on_resize(&mut self, _ctx: Context, _width: u32, _height: u32) -> Result<()> {
+    Ok(())
+  }
+
+  // Like on_resize for a secondary window, whose size Context::config doesn't follow.
+  fn on_window_resize(&mut self, _ctx: Context, _window: WindowId, _width: u32, _height: u32) -> Result<()> {
+    Ok(())
+  }
+
+  // Move the camera so the whole scene is in view, called when F is pressed.
+  fn frame_scene(&mut self) {}
+
+  // Switch to the next sampling strategy, called when M is pressed.
+  fn cycle_sampling(&mut self) {}
+
+  // Render into `target`, a color attachment of `extent`, and wait for the result. It is left in
+  // COLOR_ATTACHMENT_OPTIMAL like the attachments of a pass graph. See Runner::render_to_external.
+  fn render_to(&mut self, _ctx: Context, _thread: ThreadContext, _target: &ImageView, _extent: vk::Extent2D) -> Result<()> {
+    bail!("render_to() not implemented for this app");
+  }
+
+  // Draws what the window currently shows into `target`, left like render_to leaves it, for
+  // screenshots and Runner::render_to_file. Only the display, without advancing anything, so the
+  // screenshot matches the window; render_to isn't a fallback, it may render a new frame.
+  fn screenshot_to(&mut self, _ctx: Context, _thread: ThreadContext, _target: &ImageView, _extent: vk::Extent2D) -> Result<()> {
+    bail!("screenshot_to() not implemented for this app");
+  }
+
+  // Like screenshot_to for `window`, into a `target` of the window's size. The default draws the
+  // primary window with screenshot_to, like window_frame.
+  fn window_screenshot_to(&mut self, ctx: Context, thread: ThreadContext, window: WindowId, target: &ImageView, extent: vk::Extent2D) -> Result<()> {
+    if ctx.primary_window != Some(window) {
+      bail!("window_screenshot_to() not implemented for the app's secondary windows");
+    }
+    self.screenshot_to(ctx, thread, target, extent)
+  }
+
+  // Samples accumulated in the current image, for display.
+  fn sample_count(&self) -> u32 {
+    0
+  }
+
+  // Whether accumulation reached its target and frames only present the finished image. The runner
+  // stops redrawing until the next event while this is set.
+  fn converged(&self) -> bool {
+    false
+  }
+
+  // Called after every windowed frame.
+  fn on_frame_stats(&mut self, _ctx: Context, _stats: FrameStats) {}
+
+  // Debug data of the pixel requested with Context::debug_pixel.
+  fn on_pixel_debug(&mut self, _ctx: Context, x: u32, y: u32, info: PixelDebugInfo) {
+    info!("Pixel ({}, {}): {:?}", x, y, info);
+  }
+}
+
+// Statistics of one windowed frame, see App::on_frame_stats.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FrameStats {
+  pub scratch: ScratchStats,
+  // Allocations of Context::allocator alive after the frame, always 0 in release builds.
+  pub live_allocations: u64,
+  // App::sample_count and App::converged after the frame.
+  pub samples: u32,
+  pub converged: bool,
+  // Rolling averages of the recent frames, see frame_timing.rs.
+  pub timing: FrameTiming,
+}
+
+// Queues requested from initialize, and whether each should get a family of its own.
+const REQUESTED_QUEUES: [(QueueType, bool); 3] = [(QueueType::Graphics, false), (QueueType::Transfer, true), (QueueType::Compute, true)];
+
+// Which queues the runner asks for. Dedicated transfer and compute families let uploads and async
+// compute overlap with rendering, but some older or mobile GPUs don't have them and initialize fails.
+#[derive(Debug, Clone)]
+pub struct QueueRequirements {
+  // Queue types and whether each should get a family of its own.
+  pub queues: Vec<(QueueType, bool)>,
+  // If initialize fails, retry with every queue allowed to share a family and then with the graphics
+  // queue alone, which phobos also uses for transfer and compute work.
+  pub fallback: bool,
+}
+
+impl Default for QueueRequirements {
+  fn default() -> Self {
+    Self {
+      queues: REQUESTED_QUEUES.to_vec(),
+      fallback: true,
+    }
+  }
+}
+
+impl QueueRequirements {
+  // Queue configurations to try in order, with a name for the log.
+  fn attempts(&self) -> Vec<(&'static str, Vec<(QueueType, bool)>)> {
+    let mut attempts = vec![("requested", self.queues.clone())];
+    if self.fallback {
+      let shared = self.queues.iter().map(|&(queue_type, _)| (queue_type, false)).collect();
+      let graphics = self.queues.iter().copied().filter(|(queue_type, _)| matches!(queue_type, QueueType::Graphics)).collect::<Vec<_>>();
+      attempts.push(("shared", shared));
+      attempts.push(("graphics only", if graphics.is_empty() { vec![(QueueType::Graphics, false)] } else { graphics }));
+    }
+    attempts
+  }
+}
+
+// A dedicated queue that collapsed into a shared family still works, just without overlap, so only warn.
+fn log_queue_assignment(requested: &[(QueueType, bool)], families: &QueueFamilies) {
+  info!("Queue families: graphics {:?}, transfer {:?}, compute {:?}", families.graphics, families.transfer, families.compute);
+  for &(queue_type, dedicated) in requested {
+    let family = match queue_type {
+      QueueType::Graphics => families.graphics,
+      QueueType::Transfer => families.transfer,
+      QueueType::Compute => families.compute,
+    };
+    if dedicated && !families.is_dedicated(family) {
+      warn!("Requested a dedicated {:?} queue but got family {:?}, which is shared with other work", queue_type, family);
+    }
+  }
+}
+
+// Some drivers (seen on Mesa) let vkAcquireNextImageKHR time out under load. The acquire fails
+// before App::frame is called, so the whole frame can simply be attempted again.
+const ACQUIRE_RETRIES: u32 = 3;
+const ACQUIRE_BACKOFF: Duration = Duration::from_millis(2);
+// Time between attempts to recreate a swapchain after a failed resize, each waits for the device.
+const RESIZE_RETRY_INTERVAL: Duration = Duration::from_millis(250);
+
+fn is_transient_acquire_error(error: &anyhow::Error) -> bool {
+  let transient = |result: &vk::Result| matches!(*result, vk::Result::TIMEOUT | vk::Result::NOT_READY);
+  error.chain().any(|cause| {
+    cause.downcast_ref::<vk::Result>().map_or(false, transient)
+      || matches!(cause.downcast_ref::<phobos::Error>(), Some(phobos::Error::VkError(result)) if transient(result))
+  })
+}
+
+// Which constraint each physical device fails, for the error when initialize() finds none.
+fn unmet_requirements(requirements: &DeviceRequirements) -> String {
+  let candidates = probe_devices();
+  if candidates.is_empty() {
+    return "no Vulkan devices found".to_string();
+  }
+  let devices = candidates
+    .iter()
+    .map(|candidate| match requirements.unmet(candidate).as_slice() {
+      [] => format!("{}: meets RunnerConfig::device, check the features, extensions and queues", candidate.name),
+      unmet => format!("{}: {}", candidate.name, unmet.join(", ")),
+    })
+    .collect::<Vec<_>>();
+  format!("devices: {}", devices.join("; "))
+}
+
+const VALIDATION_LAYER: &str = "VK_LAYER_KHRONOS_validation";
+
+// Whether the Vulkan loader can find the Khronos validation layer. Instance creation fails when
+// validation is requested without it.
+fn validation_layer_available(entry: Option<&ash::Entry>) -> bool {
+  let Some(entry) = entry else {
+    return false;
+  };
+  let layers = entry.enumerate_instance_layer_properties().unwrap_or_default();
+  layers.iter().any(|layer| unsafe { std::ffi::CStr::from_ptr(layer.layer_name.as_ptr()) }.to_str() == Ok(VALIDATION_LAYER))
+}
+
+// The app's changes to the settings phobos is initialized with, see Runner::new. Kept to create the
+// swapchains of secondary and resized windows with them too.
+type MakeSettings = Box<dyn Fn(AppBuilder<Window>) -> AppSettings<Window>>;
+
+// Passes the settings a swapchain for `window` is created with to `f`: the present mode, color space
+// and scratch size of `config`, then the app's `make_settings` like for initialization.
+fn with_swapchain_settings<T>(
+  window: &Window,
+  config: &RunnerConfig,
+  make_settings: &dyn Fn(AppBuilder<Window>) -> AppSettings<Window>,
+  f: impl FnOnce(&AppSettings<Window>) -> Result<T>,
+) -> Result<T> {
+  let mut settings = AppBuilder::new()
+    .window(window)
+    .present_mode(config.present_mode.vk())
+    .scratch_size(config.scratch_reserve);
+  if let Some(format) = config.color_space.surface_format() {
+    settings = settings.surface_format(format);
+  }
+  f(&make_settings(settings))
+}
+
+// Whether any window is focused and whether all of them are occluded, see Runner::update_paused.
+fn any_visible(visibility: &HashMap<WindowId, (bool, bool)>) -> (bool, bool) {
+  (visibility.values().any(|&(focused, _)| focused), visibility.values().all(|&(_, occluded)| occluded))
+}
+
+// The close button, or RunnerConfig::exit_key pressed in the window.
+fn closes_window(event: &WindowEvent, exit_key: Option<VirtualKeyCode>) -> bool {
+  match event {
+    WindowEvent::CloseRequested => true,
+    WindowEvent::KeyboardInput {
+      input:
+        KeyboardInput {
+          state: ElementState::Pressed,
+          virtual_keycode: Some(key),
+          ..
+        },
+      ..
+    } => exit_key == Some(*key),
+    _ => false,
+  }
+}
+
+// Logging is process wide, a second runner or a host app that set up its own logger keeps the
+// existing one.
+fn init_logger(default_level: LevelFilter) {
+  let mut builder = pretty_env_logger::formatted_builder();
+  match std::env::var("RUST_LOG") {
+    Ok(filters) => builder.parse_filters(&filters),
+    Err(_) => builder.filter_level(default_level),
+  };
+  let _ = builder.try_init();
 }
 
-pub struct Runner {
+pub struct Runner<X: FrameExecutor = CurrentThread> {
   pipelines: PipelineCache,
   descriptors: DescriptorCache,
+  // Owns a buffer, so it has to be dropped before the device in vk.
+  pixel_debug: PixelDebug,
+  // Owns a query pool, dropped before vk like pixel_debug.
+  gpu_timer: GpuTimer,
   vk: VulkanContext,
+  config: RunnerConfig,
+  formats: FormatSupport,
+  memory: Arc<MemoryBudget>,
+  scratch: Arc<ScratchUsage>,
+  // Largest scratch peak warned about so far, to warn once per new peak.
+  scratch_warned_peak: u64,
+  allocations: Arc<AllocationTracker>,
+  gpu: DeviceCandidate,
+  clock: Clock,
+  frame_timings: FrameTimings,
+  last_stats: Option<FrameStats>,
+  make_settings: MakeSettings,
+  executor: X,
 }
 
 impl Runner {
-  pub fn new(name: impl Into<String>, window: Option<&WindowContext>, make_settings: impl Fn(AppBuilder<Window>) -> AppSettings<Window>) -> Result<Self> {
-    std::env::set_var("RUST_LOG", "trace");
-    pretty_env_logger::init();
-    let mut settings = AppBuilder::new()
-      .version((1, 0, 0))
-      .name(name)
-      .validation(true)
-      .present_mode(vk::PresentModeKHR::MAILBOX)
-      .scratch_size(1 * 1024u64) // 1 KiB scratch memory per buffer type per frame
-      .gpu(GPURequirements {
-        dedicated: false,
-        min_video_memory: 1 * 1024 * 1024 * 1024, // 1 GiB.
-        min_dedicated_video_memory: 1 * 1024 * 1024 * 1024,
-        queues: vec![
-          QueueRequest {
-            dedicated: false,
-            queue_type: QueueType::Graphics,
-          },
-          QueueRequest {
-            dedicated: true,
-            queue_type: QueueType::Transfer,
-          },
-          QueueRequest {
-            dedicated: true,
-            queue_type: QueueType::Compute,
-          },
-        ],
-        ..Default::default()
-      });
+  // The GPUs, in the order RunnerConfig::gpu indexes them, without initializing the runner.
+  #[allow(dead_code)]
+  pub fn list_devices() -> Vec<DeviceCandidate> {
+    probe_devices()
+  }
 
-    match window {
-      None => {}
-      Some(window) => {
-        settings = settings.window(&window.window);
+  pub fn new(
+    name: impl Into<String>,
+    window: Option<&WindowContext>,
+    mut config: RunnerConfig,
+    make_settings: impl Fn(AppBuilder<Window>) -> AppSettings<Window> + 'static,
+  ) -> OxResult<Self> {
+    let make_settings: MakeSettings = Box::new(make_settings);
+    init_logger(config.log_level);
+    let name = name.into();
+    // The swapchain gets the window's size, which the window manager may have clamped to the screen.
+    // Render targets are created from config.width and config.height and have to match it.
+    if let Some(window) = window {
+      let size = window.window.inner_size();
+      if size.width > 0 && size.height > 0 && (size.width, size.height) != (config.width, config.height) {
+        info!("Window opened at {}x{} instead of {}x{}, rendering at the window size", size.width, size.height, config.width, config.height);
+        config.width = size.width;
+        config.height = size.height;
       }
+    }
+    // Loaded once, for the layer check and the validation messenger.
+    let entry = if config.validation { unsafe { ash::Entry::load() }.ok() } else { None };
+    if config.validation && !validation_layer_available(entry.as_ref()) {
+      warn!("{} is not installed, running without validation", VALIDATION_LAYER);
+      config.validation = false;
+    }
+    let validation = config.validation;
+    let scratch_reserve = config.scratch_reserve;
+    let mut device_extensions = if config.external_memory { external_memory_extensions() } else { Vec::new() };
+    let mut device_features = Vec::new();
+    if config.mesh_shaders {
+      device_extensions.extend(mesh_shader_extensions());
+      device_features.extend(mesh_shader_features());
+    }
+    if config.ray_query {
+      device_extensions.extend(ray_query_extensions());
+      device_features.extend(ray_query_features());
+    }
+    let features = config.features.clone().with_extensions(device_extensions).with_features(device_features);
+    let mut requested = features.requested()?;
+    // Points into `requested`, which stays put until the device is created.
+    let vulkan_13 = requested.vulkan_13_chain();
+    let device = config.device;
+    info!("Requesting device features {:?} and extensions {:?}", features.features, features.extensions);
+    let build_settings = |queues: &[(QueueType, bool)]| {
+      let mut settings = AppBuilder::new()
+        .version((1, 0, 0))
+        .name(name.clone())
+        .validation(validation)
+        .scratch_size(scratch_reserve)
+        .gpu(GPURequirements {
+          dedicated: device.discrete,
+          min_video_memory: device.min_video_memory,
+          min_dedicated_video_memory: device.min_dedicated_video_memory,
+          queues: queues
+            .iter()
+            .map(|&(queue_type, dedicated)| QueueRequest {
+              dedicated,
+              queue_type,
+            })
+            .collect(),
+          features: requested.core,
+          features_1_2: requested.vulkan_12,
+          features_1_3: vulkan_13,
+          device_extensions: features.extensions.clone(),
+          ..Default::default()
+        });
+
+      match window {
+        None => {}
+        Some(window) => {
+          settings = settings.window(&window.window);
+          // The HDR color spaces are only reported and usable with VK_EXT_swapchain_colorspace.
+          if config.color_space != OutputColorSpace::Srgb {
+            settings = settings.instance_extension(vk::ExtSwapchainColorspaceFn::name());
+          }
+        }
+      };
+      make_settings(settings)
     };
-    let settings = make_settings(settings);
 
-    let (instance, physical_device, surface, device, allocator, exec, frame, Some(debug_messenger)) = initialize(&settings, window.is_none())? else {
-      panic!("Asked for debug messenger but didnt get one")
+    let attempts = config.queues.attempts();
+    let mut initialized = None;
+    for (i, (kind, queues)) in attempts.iter().enumerate() {
+      match initialize_ranked(&build_settings(queues), window.is_none(), &features, config.gpu.as_ref(), &device) {
+        Ok(result) => {
+          info!("Initialized with {} queues {:?}", kind, queues);
+          initialized = Some((result, queues));
+          break;
+        }
+        // Missing features are missing with every queue configuration.
+        Err(e) if e.is::<OxError>() => return Err(e.downcast().expect("checked with is")),
+        Err(e) if i + 1 < attempts.len() => warn!("Initialization with {} queues {:?} failed ({}), retrying", kind, queues, e),
+        Err(e) => return Err(OxError::device_init(format!("Vulkan with {} queues {:?}, {}", kind, queues, unmet_requirements(&device)), e)),
+      }
+    }
+    let Some((result, queues)) = initialized else {
+      return Err(OxError::DeviceInit {
+        what: "Vulkan, no queue configuration to initialize with".to_string(),
+        source: None,
+      });
+    };
+    let (instance, physical_device, surface, device, allocator, exec, mut debug_messenger) = result;
+    let allocations = Arc::new(AllocationTracker::default());
+    let mut allocator = TrackingAllocator::new(allocator, allocations.clone());
+    // Ours maps severities to log levels and can be strict, phobos' messenger goes once it is set up.
+    let validation_messenger = match entry.filter(|_| debug_messenger.is_some()) {
+      None => {
+        if validation && debug_messenger.is_none() {
+          warn!("Validation was requested but no debug messenger was created, validation messages won't be logged");
+        }
+        None
+      }
+      Some(entry) => match ValidationMessenger::new(entry, &instance, config.strict_validation) {
+        Ok(messenger) => {
+          debug_messenger = None;
+          Some(messenger)
+        }
+        Err(e) => {
+          warn!("Failed to create the validation messenger ({}), keeping phobos' messenger", e);
+          None
+        }
+      },
+    };
+
+    log_device_ranking(&instance, &physical_device);
+    let gpu = describe(&instance, &physical_device).ok_or_else(|| OxError::DeviceInit {
+      what: "the device, the selected physical device is not in the device list".to_string(),
+      source: None,
+    })?;
+    log_queue_assignment(queues, &queue_families(&exec));
+    let formats = FormatSupport::query(&instance, &physical_device);
+    let memory = MemoryBudget::new(&instance, &physical_device);
+    info!("GPU memory: {}", memory.report());
+
+    let pipelines = PipelineCache::new(device.clone(), allocator.untracked()).map_err(|e| OxError::allocation("the pipeline cache", e))?;
+    let descriptors = DescriptorCache::new(device.clone()).map_err(|e| OxError::allocation("the descriptor cache", e))?;
+    let pixel_debug = PixelDebug::new(device.clone(), &mut allocator).map_err(|e| OxError::allocation("the pixel debug buffer", e))?;
+    let graphics_family = exec.get_queue::<domain::All>().map(|queue| queue.family_index()).ok_or_else(|| OxError::DeviceInit {
+      what: "the device, no graphics queue".to_string(),
+      source: None,
+    })?;
+    let gpu_timer = GpuTimer::new(device.clone(), &instance, &physical_device, graphics_family).map_err(|e| OxError::allocation("the timestamp query pool", e))?;
+
+    // Only now is the surface known to the device, the requested color space falls back to sRGB if it
+    // doesn't offer its format and the present mode to FIFO. The primary swapchain is created like the
+    // secondary ones, which take its present mode and color space.
+    let frame = match (window, &surface) {
+      (Some(window), Some(surface)) => {
+        config.color_space = choose_color_space(surface.formats(), config.color_space);
+        config.present_mode = choose_present_mode(surface.present_modes(), config.present_mode);
+        info!("Present mode: {}, output color space {}", config.present_mode, config.color_space);
+        let frame = with_swapchain_settings(&window.window, &config, &make_settings, |settings| {
+          Ok(FrameManager::new_with_swapchain(&instance, device.clone(), allocator.untracked(), settings, surface)?)
+        })
+        .map_err(|e| OxError::device_init("the swapchain", e))?;
+        Some(frame)
+      }
+      _ => None,
     };
 
-    let pipelines = PipelineCache::new(device.clone(), allocator.clone())?;
-    let descriptors = DescriptorCache::new(device.clone())?;
+    let mut windows = HashMap::new();
+    if let (Some(window), Some(surface)) = (window, surface) {
+      windows.insert(
+        window.window.id(),
+        WindowSurface {
+          frame,
+          surface,
+          width: config.width,
+          height: config.height,
+          gpu_timer: gpu_timer.clone(),
+        },
+      );
+      // initialize() only sets up the primary window, the others get their surface and swapchain here
+      // with its present mode and color space.
+      for secondary in &window.secondary {
+        let surface = with_swapchain_settings(secondary, &config, &make_settings, |settings| {
+          let mut surface = Surface::new(&instance, settings)?;
+          surface.query_details(&physical_device)?;
+          Ok(surface)
+        })
+        .map_err(|e| OxError::device_init("the surface of a secondary window", e))?;
+        let frame = with_swapchain_settings(secondary, &config, &make_settings, |settings| {
+          Ok(FrameManager::new_with_swapchain(&instance, device.clone(), allocator.untracked(), settings, &surface)?)
+        })
+          .map_err(|e| OxError::device_init("the swapchain of a secondary window", e))?;
+        let size = secondary.inner_size();
+        let gpu_timer = gpu_timer.for_window().map_err(|e| OxError::allocation("the timestamp query pool of a secondary window", e))?;
+        windows.insert(
+          secondary.id(),
+          WindowSurface {
+            frame: Some(frame),
+            surface,
+            width: size.width,
+            height: size.height,
+            gpu_timer,
+          },
+        );
+      }
+    }
 
     let vk = VulkanContext {
-      frame,
+      windows,
+      primary_window: window.map(|window| window.window.id()),
       exec,
       allocator,
       device,
       physical_device,
-      surface,
       debug_messenger,
+      validation: validation_messenger,
       instance,
     };
 
@@ -180,8 +1306,48 @@ impl Runner {
       vk,
       pipelines,
       descriptors,
+      config,
+      formats,
+      memory,
+      scratch: Arc::new(ScratchUsage::default()),
+      scratch_warned_peak: 0,
+      allocations,
+      pixel_debug,
+      gpu_timer,
+      gpu,
+      clock: Clock::new(),
+      frame_timings: FrameTimings::new(),
+      last_stats: None,
+      make_settings,
+      executor: CurrentThread,
     })
   }
+}
+
+impl<X: FrameExecutor + 'static> Runner<X> {
+  // Replaces the executor driving each frame, see FrameExecutor.
+  #[allow(dead_code)]
+  pub fn with_executor<Y: FrameExecutor>(self, executor: Y) -> Runner<Y> {
+    Runner {
+      pipelines: self.pipelines,
+      descriptors: self.descriptors,
+      vk: self.vk,
+      config: self.config,
+      formats: self.formats,
+      memory: self.memory,
+      scratch: self.scratch,
+      scratch_warned_peak: self.scratch_warned_peak,
+      allocations: self.allocations,
+      pixel_debug: self.pixel_debug,
+      gpu_timer: self.gpu_timer,
+      gpu: self.gpu,
+      clock: self.clock,
+      frame_timings: self.frame_timings,
+      last_stats: self.last_stats,
+      make_settings: self.make_settings,
+      executor,
+    }
+  }
 
   fn make_context(&self) -> Context {
     Context {
@@ -190,67 +1356,650 @@ impl Runner {
       allocator: self.vk.allocator.clone(),
       pipelines: self.pipelines.clone(),
       descriptors: self.descriptors.clone(),
+      config: self.config.clone(),
+      formats: self.formats.clone(),
+      memory: self.memory.clone(),
+      scratch: self.scratch.clone(),
+      allocations: self.allocations.clone(),
+      pixel_debug: self.pixel_debug.clone(),
+      gpu_timer: self.gpu_timer.clone(),
+      gpu: self.gpu.clone(),
+      primary_window: self.vk.primary_window,
     }
   }
 
-  fn frame<E: App + 'static>(&mut self, app: &mut E, window: &Window) -> Result<()> {
-    let ctx = self.make_context();
-    let frame = self.vk.frame.as_mut().unwrap();
-    let surface = self.vk.surface.as_ref().unwrap();
-    block_on(frame.new_frame(self.vk.exec.clone(), window, surface, |ifc| app.frame(ctx, ifc)))?;
+  // `time` is the clock's tick for this event loop iteration, shared by every window drawn in it.
+  fn frame<E: App + 'static>(&mut self, app: &mut E, window: &Window, input: &InputState, time: FrameTime) -> Result<()> {
+    let id = window.id();
+    let started = Instant::now();
+    // Only App::frame counts as CPU time, not the wait for the swapchain image and the frame's fence.
+    let mut cpu = Duration::ZERO;
+    let mut attempt = 0;
+    loop {
+      let mut ctx = self.make_context();
+      let target = self.vk.windows.get_mut(&id).ok_or_else(|| anyhow!("no swapchain for window {:?}", id))?;
+      ctx.gpu_timer = target.gpu_timer.clone();
+      let frame = target.frame.as_mut().unwrap();
+      let surface = &target.surface;
+      let record = |ifc| {
+        let record_started = Instant::now();
+        let cmd = app.window_frame(ctx, ifc, id, time, input);
+        cpu = record_started.elapsed();
+        cmd
+      };
+      match self.executor.block_on(frame.new_frame(self.vk.exec.clone(), window, surface, record)) {
+        Ok(()) => break,
+        Err(e) if is_transient_acquire_error(&e) && attempt < ACQUIRE_RETRIES => {
+          let backoff = ACQUIRE_BACKOFF * 2u32.pow(attempt);
+          attempt += 1;
+          warn!("Swapchain acquire failed ({}), retry {}/{} in {:?}", e, attempt, ACQUIRE_RETRIES, backoff);
+          std::thread::sleep(backoff);
+        }
+        Err(e) if is_transient_acquire_error(&e) => {
+          warn!("Swapchain acquire still failing after {} retries ({}), skipping the frame", ACQUIRE_RETRIES, e);
+          return Ok(());
+        }
+        Err(e) => return Err(e),
+      }
+    }
+
+    let (used, peak) = self.scratch.end_frame();
+    let scratch = ScratchStats {
+      reserve: self.config.scratch_reserve,
+      used,
+      peak,
+    };
+    if scratch.near_reserve() && peak > self.scratch_warned_peak {
+      self.scratch_warned_peak = peak;
+      warn!("Frame used {} of {} bytes of scratch memory, raise the scratch reserve", used, scratch.reserve);
+    }
+    let live_allocations = self.allocations.end_frame();
+    let gpu_ms = self.vk.windows.get(&id).and_then(|target| target.gpu_timer.last_ms());
+    let timing = self.frame_timings.record(started, cpu, gpu_ms);
+    if self.config.log_frame_times && self.frame_timings.log_due() {
+      let gpu = timing.gpu_ms.map_or("n/a".to_string(), |ms| format!("{:.2} ms", ms));
+      info!("{:.1} fps, CPU {:.2} ms, GPU {}", timing.fps, timing.cpu_ms, gpu);
+    }
+    let stats = FrameStats {
+      scratch,
+      live_allocations,
+      samples: app.sample_count(),
+      converged: app.converged(),
+      timing,
+    };
+    self.last_stats = Some(stats);
+    app.on_frame_stats(self.make_context(), stats);
 
+    // Debug readbacks are rare, waiting for the frame is simpler than tracking its fence.
+    if let Some((x, y)) = self.pixel_debug.in_flight() {
+      self.vk.device.wait_idle()?;
+      if let Some(info) = self.pixel_debug.finish()? {
+        app.on_pixel_debug(self.make_context(), x, y, info);
+      }
+    }
     Ok(())
   }
 
+  // Panics on a validation error since the last check with RunnerConfig::strict_validation.
+  fn check_validation(&self) {
+    if let Some(validation) = &self.vk.validation {
+      validation.check();
+    }
+  }
+
+  // Like check_validation for the event loop, which can't panic out of winit's callback. It shuts
+  // down instead and run_windowed panics once the loop returned.
+  fn take_validation_error(&self) -> Option<String> {
+    self.vk.validation.as_ref().and_then(ValidationMessenger::take_error)
+  }
+
+  fn reload_scene<E: App + 'static>(&mut self, app: &mut E) {
+    self.vk.device.wait_idle().unwrap();
+    match app.reload_scene(self.make_context()) {
+      Ok(()) => info!("Scene reloaded"),
+      // Keep running with the previous scene so a typo in the scene doesn't end the session.
+      Err(e) => error!("Failed to reload scene: {:?}", e),
+    }
+    self.clock.skip_stall();
+    self.frame_timings.skip_stall();
+  }
+
+  // Recompiles `sources` and lets the app register its pipelines again in a new cache, which replaces
+  // the current one only if all of them were created. Pipelines are never replaced by name, so the
+  // old ones can't outlive a reload.
+  fn reload_shaders<E: App + 'static>(&mut self, app: &mut E, sources: &[PathBuf]) {
+    let compiled = recompile(sources);
+    if compiled.is_empty() {
+      return;
+    }
+    self.vk.device.wait_idle().unwrap();
+    let pipelines = match PipelineCache::new(self.vk.device.clone(), self.vk.allocator.untracked()) {
+      Ok(pipelines) => pipelines,
+      Err(e) => {
+        error!("Failed to create the pipeline cache for the reload, keeping the previous pipelines: {:?}", e);
+        return;
+      }
+    };
+    let ctx = Context {
+      pipelines: pipelines.clone(),
+      ..self.make_context()
+    };
+    match app.reload_shaders(ctx, &compiled) {
+      Ok(()) => {
+        self.pipelines = pipelines;
+        info!("Reloaded {} shaders", compiled.len());
+      }
+      Err(e) => error!("Failed to reload shaders, keeping the previous pipelines: {:?}", e),
+    }
+    self.clock.skip_stall();
+    self.frame_timings.skip_stall();
+  }
+
+  // Replaces the swapchain with one of the window's new size and lets the app follow.
+  fn resize<E: App + 'static>(&mut self, app: &mut E, window: &Window, width: u32, height: u32) -> Result<()> {
+    let id = window.id();
+    self.vk.device.wait_idle()?;
+    let target = self.vk.windows.get_mut(&id).ok_or_else(|| anyhow!("no swapchain for window {:?}", id))?;
+    // The surface can only have one swapchain, the old one goes before the new one is created.
+    target.frame = None;
+    let (instance, device, allocator) = (&self.vk.instance, self.vk.device.clone(), self.vk.allocator.untracked());
+    let frame = with_swapchain_settings(window, &self.config, &self.make_settings, |settings| {
+      Ok(FrameManager::new_with_swapchain(instance, device, allocator, settings, &target.surface)?)
+    })?;
+    target.frame = Some(frame);
+    if self.vk.primary_window == Some(id) {
+      info!("Window resized to {}x{}", width, height);
+      self.config.width = width;
+      self.config.height = height;
+      app.on_resize(self.make_context(), width, height)?;
+    } else {
+      info!("Window {:?} resized to {}x{}", id, width, height);
+      app.on_window_resize(self.make_context(), id, width, height)?;
+    }
+    // Only once the app followed, so a failed resize is retried rather than skipped as done.
+    if let Some(target) = self.vk.windows.get_mut(&id) {
+      target.width = width;
+      target.height = height;
+    }
+    self.clock.skip_stall();
+    self.frame_timings.skip_stall();
+    Ok(())
+  }
+
+  // Destroys the swapchain and surface of a closed window, Window itself has to outlive them.
+  fn close_window(&mut self, id: WindowId) {
+    self.vk.device.wait_idle().unwrap();
+    self.vk.windows.remove(&id);
+    info!("Window {:?} closed, {} left", id, self.vk.windows.len());
+  }
+
+  // Ends the event loop, leaving the device idle and the app dropped before the runner.
+  fn shut_down<E: App>(&mut self, app: &mut Option<E>, control_flow: &mut ControlFlow) {
+    *control_flow = ControlFlow::Exit;
+    self.vk.device.wait_idle().unwrap();
+    drop(app.take());
+  }
+
+  fn update_paused(&mut self, focused: bool, occluded: bool) {
+    let pause = self.config.pause_on_unfocus && (!focused || occluded);
+    if pause && !self.clock.is_paused() {
+      debug!("Window unfocused or occluded, pausing rendering");
+      self.clock.pause();
+    } else if !pause && self.clock.is_paused() {
+      debug!("Window visible again, resuming rendering");
+      self.clock.resume();
+    }
+  }
+
   fn run_windowed<E: App + 'static>(mut self, app: E, window: WindowContext) -> ! {
-    let event_loop = window.event_loop;
-    let window = window.window;
+    let mut event_loop = window.event_loop;
+    let primary = window.window.id();
+    let mut windows = HashMap::from([(primary, window.window)]);
+    // Whether each window is focused and whether it is occluded, rendering pauses when none is visible.
+    let mut visibility = HashMap::from([(primary, (true, false))]);
+    for secondary in window.secondary {
+      visibility.insert(secondary.id(), (false, false));
+      windows.insert(secondary.id(), secondary);
+    }
     let mut app = Some(app);
-    event_loop.run(move |event, _, control_flow| {
+    let mut input = InputState::default();
+    let mut resized = HashSet::new();
+    // When each window's last resize failed, it is tried again RESIZE_RETRY_INTERVAL later.
+    let mut failed_resizes = HashMap::new();
+    // The window to capture after its next frame.
+    let mut screenshot = None;
+    // The clock's tick of this event loop iteration, taken by its first redraw.
+    let mut frame_time = None;
+    let shader_watcher = if self.config.hot_reload_shaders { ShaderWatcher::new() } else { None };
+    let mut reload_all_shaders = false;
+    let mut validation_error = None;
+    let exit_code = event_loop.run_return(|event, _, control_flow| {
       // Do not render a frame if Exit control flow is specified, to avoid
       // sync issues.
       if let ControlFlow::ExitWithCode(_) = *control_flow {
         self.vk.device.wait_idle().unwrap();
         return;
       }
-      *control_flow = ControlFlow::Poll;
+
+      let (focused, occluded) = any_visible(&visibility);
+      match &event {
+        Event::WindowEvent { event, window_id } if windows.contains_key(window_id) => input.window_event(event),
+        Event::DeviceEvent {
+          event: DeviceEvent::MouseMotion { delta },
+          ..
+        } if focused => input.mouse_motion(*delta),
+        _ => {}
+      }
 
       // Note that we want to handle events after processing our current frame, so that
       // requesting an exit doesn't attempt to render another frame, which causes
       // sync issues.
       match event {
+        // Before the other keys, so any key can be made the exit key. The event loop ends with the last window.
+        Event::WindowEvent { event, window_id } if windows.contains_key(&window_id) && closes_window(&event, self.config.exit_key) => {
+          self.close_window(window_id);
+          windows.remove(&window_id);
+          visibility.remove(&window_id);
+          resized.remove(&window_id);
+          failed_resizes.remove(&window_id);
+          if windows.is_empty() {
+            self.shut_down(&mut app, control_flow);
+          } else {
+            let (focused, occluded) = any_visible(&visibility);
+            self.update_paused(focused, occluded);
+          }
+        }
         Event::WindowEvent {
-          event: WindowEvent::CloseRequested,
+          event:
+            WindowEvent::KeyboardInput {
+              input:
+                KeyboardInput {
+                  state: ElementState::Pressed,
+                  virtual_keycode: Some(key),
+                  ..
+                },
+              ..
+            },
           window_id,
-        } if window_id == window.id() => {
-          *control_flow = ControlFlow::Exit;
-          self.vk.device.wait_idle().unwrap();
-          let app = app.take();
-          match app {
-            None => {}
-            Some(app) => {
-              drop(app);
-            }
+        } if windows.contains_key(&window_id) && self.config.screenshot_key == Some(key) => {
+          // Taken after the window's next frame, so it shows what that frame presents.
+          screenshot = Some(window_id);
+          windows[&window_id].request_redraw();
+        }
+        Event::WindowEvent {
+          event:
+            WindowEvent::KeyboardInput {
+              input:
+                KeyboardInput {
+                  state: ElementState::Pressed,
+                  virtual_keycode: Some(key),
+                  ..
+                },
+              ..
+            },
+          window_id,
+        } if windows.contains_key(&window_id) && self.config.shader_reload_key == Some(key) => {
+          // Before the next frame, like changes the watcher reports.
+          reload_all_shaders = true;
+          windows[&window_id].request_redraw();
+        }
+        Event::WindowEvent {
+          event:
+            WindowEvent::KeyboardInput {
+              input:
+                KeyboardInput {
+                  state: ElementState::Pressed,
+                  virtual_keycode: Some(VirtualKeyCode::F5),
+                  ..
+                },
+              ..
+            },
+          window_id,
+        } if windows.contains_key(&window_id) => {
+          if let Some(app) = app.as_mut() {
+            self.reload_scene(app);
+          }
+        }
+        Event::WindowEvent {
+          event:
+            WindowEvent::KeyboardInput {
+              input:
+                KeyboardInput {
+                  state: ElementState::Pressed,
+                  virtual_keycode: Some(VirtualKeyCode::F),
+                  ..
+                },
+              ..
+            },
+          window_id,
+        } if windows.contains_key(&window_id) => {
+          if let Some(app) = app.as_mut() {
+            app.frame_scene();
+          }
+        }
+        Event::WindowEvent {
+          event:
+            WindowEvent::KeyboardInput {
+              input:
+                KeyboardInput {
+                  state: ElementState::Pressed,
+                  virtual_keycode: Some(VirtualKeyCode::M),
+                  ..
+                },
+              ..
+            },
+          window_id,
+        } if windows.contains_key(&window_id) => {
+          if let Some(app) = app.as_mut() {
+            app.cycle_sampling();
+            windows[&window_id].request_redraw();
           }
         }
+        Event::WindowEvent {
+          event: WindowEvent::Resized(_),
+          window_id,
+        } if windows.contains_key(&window_id) => {
+          // Recreated before the next frame at the size the window has then, a drag sends many of these in a row.
+          resized.insert(window_id);
+        }
+        Event::WindowEvent {
+          event:
+            WindowEvent::MouseInput {
+              state: ElementState::Pressed,
+              button: MouseButton::Left,
+              ..
+            },
+          window_id,
+        } if window_id == primary && windows.contains_key(&window_id) => {
+          // The render target is stretched over the window, map the cursor to its pixels.
+          let size = windows[&window_id].inner_size();
+          if let Some(position) = input.cursor.filter(|_| size.width > 0 && size.height > 0) {
+            let x = (position.x / size.width as f64 * self.config.width as f64) as u32;
+            let y = (position.y / size.height as f64 * self.config.height as f64) as u32;
+            self.pixel_debug.request(x.min(self.config.width - 1), y.min(self.config.height - 1));
+          }
+        }
+        Event::WindowEvent {
+          event: WindowEvent::Focused(state),
+          window_id,
+        } if windows.contains_key(&window_id) => {
+          visibility.entry(window_id).or_default().0 = state;
+          let (focused, occluded) = any_visible(&visibility);
+          self.update_paused(focused, occluded);
+        }
+        Event::WindowEvent {
+          event: WindowEvent::Occluded(state),
+          window_id,
+        } if windows.contains_key(&window_id) => {
+          visibility.entry(window_id).or_default().1 = state;
+          let (focused, occluded) = any_visible(&visibility);
+          self.update_paused(focused, occluded);
+        }
         Event::MainEventsCleared => {
-          window.request_redraw();
+          // Sleep until the next window event instead of rendering, focus or visibility events wake us up again.
+          // A converged image doesn't change either, until an event such as F or F5 restarts accumulation.
+          // A minimized window has no area to present to. Held keys or mouse motion may move the camera.
+          let converged = !input.is_active() && app.as_ref().map_or(false, |app| app.converged());
+          let visible = windows.values().filter(|window| {
+            let size = window.inner_size();
+            size.width > 0 && size.height > 0
+          });
+          let mut redrawn = false;
+          for window in visible {
+            // A converged window still has to be redrawn once to apply a resize, which restarts accumulation.
+            if !self.clock.is_paused() && (!converged || resized.contains(&window.id())) {
+              window.request_redraw();
+              redrawn = true;
+            }
+          }
+          // Set here only, the control flow persists across events and the redraw events that follow
+          // would otherwise undo the Wait.
+          *control_flow = if redrawn { ControlFlow::Poll } else { ControlFlow::Wait };
         }
-        Event::RedrawRequested(_) => match app.as_mut() {
-          None => {}
-          Some(app) => {
-            self.frame(app, &window).unwrap();
+        Event::RedrawRequested(window_id) => match (app.as_mut(), windows.get(&window_id)) {
+          (Some(app), Some(window)) => {
+            let size = window.inner_size();
+            if size.width == 0 || size.height == 0 {
+              return;
+            }
+            let sources = if std::mem::take(&mut reload_all_shaders) {
+              all_sources()
+            } else {
+              shader_watcher.as_ref().map_or_else(Vec::new, ShaderWatcher::changed)
+            };
+            if !sources.is_empty() {
+              self.reload_shaders(app, &sources);
+            }
+            let target = self.vk.windows.get(&window_id);
+            // A failed resize can leave the window without a swapchain, retried at whatever size the
+            // window has by then, even the one it had before.
+            let swapchain_lost = target.map_or(false, |target| target.frame.is_none());
+            let size_changed = target.map(|target| (target.width, target.height)) != Some((size.width, size.height));
+            if swapchain_lost || (resized.contains(&window_id) && size_changed) {
+              if failed_resizes.get(&window_id).map_or(false, |failed: &Instant| failed.elapsed() < RESIZE_RETRY_INTERVAL) {
+                return;
+              }
+              // E.g. the surface went away mid-drag, the window stays marked and a later redraw tries again.
+              match self.resize(app, window, size.width, size.height) {
+                Ok(()) => {
+                  if failed_resizes.remove(&window_id).is_some() {
+                    info!("Resized window {:?} to {}x{} after failed attempts", window_id, size.width, size.height);
+                  }
+                }
+                Err(e) => {
+                  if failed_resizes.insert(window_id, Instant::now()).is_none() {
+                    warn!("Failed to resize window {:?} to {}x{} ({:?}), retrying every {:?}", window_id, size.width, size.height, e, RESIZE_RETRY_INTERVAL);
+                  } else {
+                    debug!("Failed to resize window {:?} to {}x{} again: {:?}", window_id, size.width, size.height, e);
+                  }
+                  resized.insert(window_id);
+                  return;
+                }
+              }
+            }
+            resized.remove(&window_id);
+            let time = *frame_time.get_or_insert_with(|| self.clock.tick());
+            self.frame(app, window, &input, time).unwrap();
+            if screenshot == Some(window_id) {
+              screenshot = None;
+              if let Err(e) = self.capture_screenshot(app, window_id) {
+                error!("Screenshot failed: {:?}", e);
+              }
+            }
+            if validation_error.is_none() {
+              validation_error = self.take_validation_error();
+            }
+          }
+          _ => {}
+        },
+        // Once every window was drawn, so they all saw the same input and the caches age per iteration.
+        Event::RedrawEventsCleared => {
+          if frame_time.take().is_some() {
+            input.end_frame();
             self.pipelines.next_frame();
             self.descriptors.next_frame();
           }
-        },
+        }
         _ => (),
       }
-    })
+      // After the frame that raised it, the loop ends and the panic follows outside of it.
+      if validation_error.is_some() && app.is_some() {
+        self.shut_down(&mut app, control_flow);
+      }
+    });
+    if let Some(message) = validation_error {
+      panic!("Vulkan validation error (strict validation): {}", message);
+    }
+    std::process::exit(exit_code)
+  }
+
+  fn run_headless<E: App + 'static>(self, mut app: E) -> ! {
+    let result = ThreadContext::new(self.vk.device.clone(), self.vk.allocator.untracked(), None)
+      .and_then(|thread| app.run(self.make_context(), thread));
+    self.vk.device.wait_idle().unwrap();
+    self.check_validation();
+    drop(app);
+    match result {
+      Ok(()) => std::process::exit(0),
+      Err(e) => {
+        error!("Headless run failed: {:?}", e);
+        std::process::exit(1);
+      }
+    }
+  }
+
+  // Drives `app` for exactly `count` frames of App::headless_frame, advancing the pipeline and
+  // descriptor caches after each like a windowed frame. Frame times use the fixed HEADLESS_FRAME_DT
+  // step, so with the same RunnerConfig::seed a run is reproducible, e.g. for regression tests.
+  // Write the result with render_to_file afterwards. The device is idle when this returns.
+  #[allow(dead_code)]
+  pub fn run_headless_frames<E: App>(&mut self, app: &mut E, count: u32) -> Result<()> {
+    let mut thread = ThreadContext::new(self.vk.device.clone(), self.vk.allocator.untracked(), None)?;
+    for frame_index in 0..count {
+      app.headless_frame(self.make_context(), &mut thread, FrameTime::fixed(frame_index, HEADLESS_FRAME_DT))?;
+      self.pipelines.next_frame();
+      self.descriptors.next_frame();
+      self.check_validation();
+    }
+    self.vk.device.wait_idle()?;
+    info!("Ran {} headless frames with seed {}", count, self.config.seed);
+    Ok(())
   }
 
+  // Statistics of the last windowed frame, None before the first one. Apps get the same through
+  // App::on_frame_stats while Runner::run owns the runner.
+  #[allow(dead_code)]
+  pub fn last_frame_stats(&self) -> Option<FrameStats> {
+    self.last_stats
+  }
+
+  // For embedding: creates the app without handing control to run, e.g. to drive it with render_to_external.
+  #[allow(dead_code)]
+  pub fn create_app<E: App>(&self) -> Result<E> {
+    E::new(self.make_context())
+  }
+
+  // Renders a frame of `app` into memory exported by another API, to composite it there. The image
+  // is imported for this call only and the device is idle when it returns, so the other API can use
+  // the texture right away. Requires RunnerConfig::external_memory. There is no semaphore interop
+  // yet: the other API must be done with the texture before calling this, e.g. after glFinish.
+  #[allow(dead_code)]
+  pub fn render_to_external<E: App>(&mut self, app: &mut E, handle: ExternalHandle, extent: vk::Extent2D) -> Result<()> {
+    if !self.config.external_memory {
+      bail!("render_to_external requires RunnerConfig::external_memory");
+    }
+    let target = ExternalImage::import(&self.vk.device, &self.vk.instance, unsafe { self.vk.physical_device.handle() }, handle, extent)?;
+    let thread = ThreadContext::new(self.vk.device.clone(), self.vk.allocator.untracked(), None)?;
+    let result = app.render_to(self.make_context(), thread, target.view(), extent);
+    self.vk.device.wait_idle()?;
+    self.check_validation();
+    result
+  }
+
+  // Writes the image `app` shows, through App::screenshot_to, to `path`, numbered like
+  // --frames output when `frame_index` is given. .exr files get an RGBA32F target, which keeps
+  // whatever range the app writes, other formats an 8 bit sRGB one. Works with or without a window,
+  // e.g. for reference images in CI. Returns the path written.
+  #[allow(dead_code)]
+  pub fn render_to_file<E: App>(&mut self, app: &mut E, frame_index: Option<u32>, path: &Path) -> Result<PathBuf> {
+    let path = frame_index.map_or_else(|| path.to_path_buf(), |frame| sequence_frame_path(path, frame));
+    let is_exr = path.extension().map_or(false, |ext| ext.eq_ignore_ascii_case("exr"));
+    let format = if is_exr { vk::Format::R32G32B32A32_SFLOAT } else { vk::Format::R8G8B8A8_SRGB };
+    let (width, height) = (self.config.width, self.config.height);
+    let mut ctx = self.make_context();
+    let target = ctx.create_image(width, height, vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC, format)?;
+    let view = target.view(vk::ImageAspectFlags::COLOR)?;
+    let thread = ThreadContext::new(self.vk.device.clone(), self.vk.allocator.untracked(), None)?;
+    app.screenshot_to(self.make_context(), thread, &view, vk::Extent2D { width, height })?;
+    self.check_validation();
+    let layout = vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL;
+    if is_exr {
+      save_hdr_image(&path, width, height, read_back::<f32>(&mut ctx, &target, width, height, layout)?)?;
+    } else {
+      save_ldr_image(&path, width, height, read_back::<u8>(&mut ctx, &target, width, height, layout)?)?;
+    }
+    info!("Wrote a {}x{} frame to {}", width, height, path.display());
+    Ok(path)
+  }
+
+  // Draws the image `window` displays again through App::window_screenshot_to into an sRGB image of
+  // the window's size, the same encoding the swapchain has, and writes it to a timestamped PNG in
+  // RunnerConfig::screenshot_dir. phobos doesn't hand out the presented swapchain image, so it isn't
+  // copied directly. The readback waits for the GPU once, the PNG is encoded and written on a separate
+  // thread.
+  fn capture_screenshot<E: App>(&mut self, app: &mut E, window: WindowId) -> Result<()> {
+    let target = self.vk.windows.get(&window).ok_or_else(|| anyhow!("no swapchain for window {:?}", window))?;
+    let (width, height) = (target.width, target.height);
+    let mut ctx = self.make_context();
+    let target = ctx.create_image(width, height, vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC, vk::Format::R8G8B8A8_SRGB)?;
+    let view = target.view(vk::ImageAspectFlags::COLOR)?;
+    let thread = ThreadContext::new(self.vk.device.clone(), self.vk.allocator.untracked(), None)?;
+    app.window_screenshot_to(self.make_context(), thread, window, &view, vk::Extent2D { width, height })?;
+    let texels = read_back::<u8>(&mut ctx, &target, width, height, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)?;
+
+    let dir = self.config.screenshot_dir.clone();
+    fs::create_dir_all(&dir).map_err(|e| OxError::io(&dir, e))?;
+    let millis = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+    let path = dir.join(format!("screenshot_{}.png", millis));
+    std::thread::spawn(move || match save_ldr_image(&path, width, height, texels) {
+      Ok(()) => info!("Saved a {}x{} screenshot to {}", width, height, path.display()),
+      Err(e) => error!("Failed to save screenshot {}: {:?}", path.display(), e),
+    });
+    Ok(())
+  }
+
+  // Runs headless when no window is given, calling App::run instead of App::frame.
   pub fn run<E: App + 'static>(self, window: Option<WindowContext>) -> ! {
     let app = E::new(self.make_context()).unwrap();
-    self.run_windowed(app, window.unwrap());
+    match window {
+      None => self.run_headless(app),
+      Some(window) => {
+        // The event loop thread records and submits every frame.
+        configure_current_thread(self.config.render_thread_priority, self.config.render_thread_core);
+        self.run_windowed(app, window)
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // Writes `bytes` to a file of its own in the temp directory and loads it back.
+  fn load(name: &str, bytes: &[u8]) -> OxResult<Vec<u32>> {
+    let path = std::env::temp_dir().join(format!("ox_tracer_{}_{}.spv", name, std::process::id()));
+    fs::write(&path, bytes).unwrap();
+    let result = load_spirv_file(&path);
+    fs::remove_file(&path).unwrap();
+    result
+  }
+
+  const WORDS: [u32; 6] = [SPIRV_MAGIC, 0x0001_0500, 0, 16, 0, 0x0002_0011];
+
+  #[test]
+  fn little_endian_words_load_as_is() {
+    let bytes = WORDS.iter().flat_map(|word| word.to_le_bytes()).collect::<Vec<_>>();
+    assert_eq!(load("little_endian", &bytes).unwrap(), WORDS);
+  }
+
+  #[test]
+  fn big_endian_words_are_swapped() {
+    let bytes = WORDS.iter().flat_map(|word| word.to_be_bytes()).collect::<Vec<_>>();
+    assert_eq!(load("big_endian", &bytes).unwrap(), WORDS);
+  }
+
+  #[test]
+  fn unaligned_length_is_rejected() {
+    let mut bytes = WORDS.iter().flat_map(|word| word.to_le_bytes()).collect::<Vec<_>>();
+    bytes.push(0);
+    assert!(matches!(load("unaligned", &bytes), Err(OxError::ShaderCompile { .. })));
+  }
+
+  #[test]
+  fn truncated_header_and_bad_magic_are_rejected() {
+    assert!(matches!(load("truncated", &SPIRV_MAGIC.to_le_bytes()), Err(OxError::ShaderCompile { .. })));
+    let bytes = [0u32; 5].iter().flat_map(|word| word.to_le_bytes()).collect::<Vec<_>>();
+    assert!(matches!(load("no_magic", &bytes), Err(OxError::ShaderCompile { .. })));
   }
 }
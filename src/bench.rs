@@ -0,0 +1,130 @@
+use std::path::Path;
+use std::time::Duration;
+
+use ash::vk;
+use serde::Serialize;
+
+use crate::device::DeviceCandidate;
+use crate::error::{OxError, OxResult};
+
+// Bumped whenever a field is renamed, removed or changes meaning. Adding fields keeps the version,
+// so reports of different commits can be diffed key by key.
+pub const BENCH_SCHEMA_VERSION: u32 = 1;
+
+// Samples rendered before timing starts, so pipeline creation and first-use allocations don't
+// end up in the numbers.
+pub const BENCH_WARMUP_FRAMES: u32 = 4;
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+pub struct FrameTimeStats {
+  #[serde(rename = "avg")]
+  pub avg_ms: f64,
+  #[serde(rename = "min")]
+  pub min_ms: f64,
+  #[serde(rename = "max")]
+  pub max_ms: f64,
+}
+
+impl FrameTimeStats {
+  // None without any frames.
+  pub fn from_durations(frames: &[Duration]) -> Option<Self> {
+    Self::from_ms(&frames.iter().map(|frame| frame.as_secs_f64() * 1000.0).collect::<Vec<_>>())
+  }
+
+  // None without any frames.
+  pub fn from_ms(ms: &[f64]) -> Option<Self> {
+    if ms.is_empty() {
+      return None;
+    }
+    Some(Self {
+      avg_ms: ms.iter().sum::<f64>() / ms.len() as f64,
+      min_ms: ms.iter().copied().fold(f64::INFINITY, f64::min),
+      max_ms: ms.iter().copied().fold(0.0, f64::max),
+    })
+  }
+}
+
+// Result of a --bench run. Frame times are wall clock times of one sample from recording to
+// the end of its fence wait.
+#[derive(Debug, Clone)]
+pub struct BenchReport {
+  pub gpu: DeviceCandidate,
+  pub width: u32,
+  pub height: u32,
+  pub seed: u32,
+  pub frames: Vec<Duration>,
+  // GPU time of the trace pass of every sample with a timestamp, empty on devices without timestamps.
+  pub trace_gpu_ms: Vec<f64>,
+}
+
+// The JSON document, field order is the key order.
+#[derive(Serialize)]
+struct ReportJson<'a> {
+  schema_version: u32,
+  tracer_version: &'static str,
+  device: DeviceJson<'a>,
+  resolution: ResolutionJson,
+  seed: u32,
+  warmup_frames: u32,
+  frames: usize,
+  total_ms: f64,
+  frame_ms: Option<FrameTimeStats>,
+  // GPU time per pass, null without timestamp queries.
+  gpu_passes: Option<GpuPassesJson>,
+}
+
+#[derive(Serialize)]
+struct DeviceJson<'a> {
+  name: &'a str,
+  vendor_id: u32,
+  device_id: u32,
+  driver_version: u32,
+  api_version: String,
+}
+
+#[derive(Serialize)]
+struct ResolutionJson {
+  width: u32,
+  height: u32,
+}
+
+#[derive(Serialize)]
+struct GpuPassesJson {
+  trace: FrameTimeStats,
+}
+
+impl BenchReport {
+  // Keys are always written, in this order, with null for values that aren't available.
+  pub fn to_json(&self) -> String {
+    let api = self.gpu.api_version;
+    let report = ReportJson {
+      schema_version: BENCH_SCHEMA_VERSION,
+      tracer_version: env!("CARGO_PKG_VERSION"),
+      device: DeviceJson {
+        name: &self.gpu.name,
+        vendor_id: self.gpu.vendor_id,
+        device_id: self.gpu.device_id,
+        driver_version: self.gpu.driver_version,
+        api_version: format!("{}.{}.{}", vk::api_version_major(api), vk::api_version_minor(api), vk::api_version_patch(api)),
+      },
+      resolution: ResolutionJson {
+        width: self.width,
+        height: self.height,
+      },
+      seed: self.seed,
+      warmup_frames: BENCH_WARMUP_FRAMES,
+      frames: self.frames.len(),
+      total_ms: self.frames.iter().sum::<Duration>().as_secs_f64() * 1000.0,
+      frame_ms: FrameTimeStats::from_durations(&self.frames),
+      gpu_passes: FrameTimeStats::from_ms(&self.trace_gpu_ms).map(|trace| GpuPassesJson {
+        trace,
+      }),
+    };
+    // Plain structs of numbers and strings always serialize.
+    serde_json::to_string_pretty(&report).expect("the bench report serializes") + "\n"
+  }
+
+  pub fn save(&self, path: &Path) -> OxResult<()> {
+    std::fs::write(path, self.to_json()).map_err(|e| OxError::io(path, e))
+  }
+}
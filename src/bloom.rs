@@ -0,0 +1,201 @@
+use anyhow::Result;
+use ash::vk;
+use bytemuck::{Pod, Zeroable};
+
+use phobos::prelude::*;
+
+use crate::app::{create_shader, Context};
+use crate::compute::run_on_compute_queue;
+use crate::memory::Image;
+use crate::upload::transition_to_general;
+
+// The chain stops before a level would get smaller than this in either dimension.
+const BLOOM_MIN_SIZE: u32 = 4;
+const BLOOM_MAX_LEVELS: usize = 6;
+// Matches local_size in bloom_downsample.comp and bloom_upsample.comp.
+const BLOOM_GROUP_SIZE: u32 = 8;
+
+// Glow around bright parts of the image, added to the HDR image before exposure. Everything above
+// `threshold` in luminance is blurred through a chain of half resolution images: each level is a
+// downsampled copy of the previous one, then the levels are upsampled back, every coarser level
+// added to the next finer one weighted by `radius`. `radius` in [0, 1] controls how far the glow
+// reaches, `intensity` how much of the blurred light is added back in the display pass.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BloomSettings {
+  pub threshold: f32,
+  pub intensity: f32,
+  pub radius: f32,
+}
+
+impl Default for BloomSettings {
+  fn default() -> Self {
+    Self {
+      threshold: 1.0,
+      intensity: 0.1,
+      radius: 0.7,
+    }
+  }
+}
+
+// Mirrors the push constants in bloom_downsample.comp.
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+pub struct DownsamplePushConstants {
+  pub threshold: f32,
+  // Non-zero for the first level, which reads the HDR image and applies the threshold.
+  pub prefilter: u32,
+}
+
+// Mirrors the push constants in bloom_upsample.comp.
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+pub struct UpsamplePushConstants {
+  pub radius: f32,
+}
+
+pub struct BloomLevel {
+  pub image: Image,
+  pub view: ImageView,
+  pub width: u32,
+  pub height: u32,
+}
+
+impl BloomLevel {
+  // Workgroups covering the level.
+  pub fn groups(&self) -> (u32, u32) {
+    ((self.width + BLOOM_GROUP_SIZE - 1) / BLOOM_GROUP_SIZE, (self.height + BLOOM_GROUP_SIZE - 1) / BLOOM_GROUP_SIZE)
+  }
+}
+
+// Mip chain of the bloom pass, level 0 at half the render target size. Level 0 is a graph resource
+// ("bloom_out") that the display pass samples, the coarser levels are only touched by `run` and stay
+// in GENERAL layout.
+pub struct Bloom {
+  pub settings: BloomSettings,
+  pub levels: Vec<BloomLevel>,
+  // Render target size the chain was built for.
+  extent: (u32, u32),
+}
+
+impl Bloom {
+  pub fn new(ctx: &mut Context, settings: BloomSettings, width: u32, height: u32) -> Result<Self> {
+    Self::create_pipelines(ctx)?;
+    Ok(Self {
+      settings,
+      levels: Self::make_levels(ctx, width, height)?,
+      extent: (width, height),
+    })
+  }
+
+  // Registers the downsample and upsample pipelines, again after their shaders were recompiled.
+  pub fn create_pipelines(ctx: &mut Context) -> Result<()> {
+    let downsample = create_shader("resources/shaders/bloom_downsample_comp.spv", vk::ShaderStageFlags::COMPUTE)?;
+    let pci = ComputePipelineBuilder::new("bloom_downsample").set_shader(downsample).build();
+    ctx.pipelines.create_named_compute_pipeline(pci)?;
+    let upsample = create_shader("resources/shaders/bloom_upsample_comp.spv", vk::ShaderStageFlags::COMPUTE)?;
+    let pci = ComputePipelineBuilder::new("bloom_upsample").set_shader(upsample).build();
+    ctx.pipelines.create_named_compute_pipeline(pci)?;
+    Ok(())
+  }
+
+  // Recreates the chain for a new render target size. The device has to be idle, the old levels are
+  // dropped right away.
+  pub fn resize(&mut self, ctx: &mut Context, width: u32, height: u32) -> Result<()> {
+    if self.extent == (width, height) {
+      return Ok(());
+    }
+    self.levels.clear();
+    self.levels = Self::make_levels(ctx, width, height)?;
+    self.extent = (width, height);
+    Ok(())
+  }
+
+  // Blurs the light above the threshold of `hdr`, in GENERAL layout, into level 0 on the compute
+  // queue, like the exposure reduction. Each dispatch waits for the level before it.
+  pub fn run(&self, ctx: &Context, hdr: &Image, hdr_view: &ImageView) -> Result<()> {
+    // The display pass left level 0 for sampling, the downsample overwrites all of it.
+    transition_to_general(ctx, &[&self.levels[0].image], PipelineStage::COMPUTE_SHADER)?;
+    let images = std::iter::once(hdr).chain(self.levels.iter().map(|level| &level.image)).collect::<Vec<_>>();
+    run_on_compute_queue(ctx, &images, &[], |cmd| {
+      let levels = &self.levels;
+      let mut cmd = cmd.bind_compute_pipeline("bloom_downsample")?;
+      for (i, level) in levels.iter().enumerate() {
+        let constants = DownsamplePushConstants {
+          threshold: self.settings.threshold,
+          prefilter: (i == 0) as u32,
+        };
+        let source = if i == 0 { hdr_view } else { &levels[i - 1].view };
+        let (groups_x, groups_y) = level.groups();
+        cmd = cmd
+          .bind_storage_image(0, 0, source)?
+          .bind_storage_image(0, 1, &level.view)?
+          .push_constant(vk::ShaderStageFlags::COMPUTE, 0, &constants)
+          .dispatch(groups_x, groups_y, 1)?
+          .memory_barrier(
+            PipelineStage::COMPUTE_SHADER,
+            vk::AccessFlags2::SHADER_STORAGE_WRITE,
+            PipelineStage::COMPUTE_SHADER,
+            vk::AccessFlags2::SHADER_STORAGE_READ | vk::AccessFlags2::SHADER_STORAGE_WRITE,
+          );
+      }
+      let constants = UpsamplePushConstants {
+        radius: self.settings.radius,
+      };
+      cmd = cmd.bind_compute_pipeline("bloom_upsample")?;
+      for i in (1..levels.len()).rev() {
+        let (groups_x, groups_y) = levels[i - 1].groups();
+        cmd = cmd
+          .bind_storage_image(0, 0, &levels[i].view)?
+          .bind_storage_image(0, 1, &levels[i - 1].view)?
+          .push_constant(vk::ShaderStageFlags::COMPUTE, 0, &constants)
+          .dispatch(groups_x, groups_y, 1)?
+          .memory_barrier(
+            PipelineStage::COMPUTE_SHADER,
+            vk::AccessFlags2::SHADER_STORAGE_WRITE,
+            PipelineStage::COMPUTE_SHADER,
+            vk::AccessFlags2::SHADER_STORAGE_READ | vk::AccessFlags2::SHADER_STORAGE_WRITE,
+          );
+      }
+      Ok(cmd)
+    })
+  }
+
+  // The chain sums every level weighted by radius^level, this scales the sum back so `intensity` is
+  // the fraction of the light above the threshold that is added to the image.
+  pub fn display_intensity(&self) -> f32 {
+    let total = (0..self.levels.len()).map(|level| self.settings.radius.powi(level as i32)).sum::<f32>();
+    self.settings.intensity / total.max(1.0e-6)
+  }
+
+  fn make_levels(ctx: &mut Context, width: u32, height: u32) -> Result<Vec<BloomLevel>> {
+    let mut levels = Vec::new();
+    let (mut level_width, mut level_height) = ((width / 2).max(1), (height / 2).max(1));
+    while levels.len() < BLOOM_MAX_LEVELS && (levels.is_empty() || (level_width >= BLOOM_MIN_SIZE && level_height >= BLOOM_MIN_SIZE)) {
+      let image = Image::new(
+        ctx.device.clone(),
+        &mut ctx.allocator,
+        level_width,
+        level_height,
+        vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::STORAGE,
+        // Same format as rt_out, so both shaders read their source with a single format qualifier.
+        vk::Format::R32G32B32A32_SFLOAT,
+        vk::SampleCountFlags::TYPE_1,
+      )?;
+      let view = image.view(vk::ImageAspectFlags::COLOR)?;
+      levels.push(BloomLevel {
+        image,
+        view,
+        width: level_width,
+        height: level_height,
+      });
+      level_width /= 2;
+      level_height /= 2;
+    }
+    // The graph never sees the coarser levels, they are moved to GENERAL once and stay there.
+    let coarser = levels[1..].iter().map(|level| &level.image).collect::<Vec<_>>();
+    transition_to_general(ctx, &coarser, PipelineStage::COMPUTE_SHADER)?;
+    Ok(levels)
+  }
+}
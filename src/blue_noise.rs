@@ -0,0 +1,71 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, ensure, Context as _, Result};
+use ash::vk;
+
+use phobos::prelude::*;
+use phobos::sync::domain::All;
+
+use crate::app::Context;
+use crate::memory::Buffer;
+use crate::upload::Uploader;
+
+pub const BLUE_NOISE_SIZE: u32 = 64;
+
+// Selects how the raygen shader jitters samples inside a pixel. White noise is there to compare against.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum SampleNoise {
+  White,
+  #[default]
+  BlueNoise,
+}
+
+impl FromStr for SampleNoise {
+  type Err = String;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    match value {
+      "white" => Ok(SampleNoise::White),
+      "blue" => Ok(SampleNoise::BlueNoise),
+      _ => Err("expected blue or white".to_string()),
+    }
+  }
+}
+
+impl fmt::Display for SampleNoise {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let name = match self {
+      SampleNoise::White => "white",
+      SampleNoise::BlueNoise => "blue",
+    };
+    f.write_str(name)
+  }
+}
+
+// Tiled blue-noise mask, stored as packed RGBA8 texels in a storage buffer.
+// See resources/shaders/blue_noise.glsl for how the shader consumes it.
+pub struct BlueNoise {
+  pub buffer: Buffer,
+}
+
+impl BlueNoise {
+  pub fn load(ctx: &mut Context, path: &str) -> Result<Self> {
+    let image = image::open(path).with_context(|| format!("failed to load blue noise texture {}", path))?.to_rgba8();
+    ensure!(
+      image.width() == BLUE_NOISE_SIZE && image.height() == BLUE_NOISE_SIZE,
+      "blue noise texture {} must be {}x{}, got {}x{}",
+      path,
+      BLUE_NOISE_SIZE,
+      BLUE_NOISE_SIZE,
+      image.width(),
+      image.height()
+    );
+    let mut uploader = Uploader::new(ctx);
+    let staged = uploader.stage_buffer(image.as_raw().as_slice(), vk::BufferUsageFlags::STORAGE_BUFFER)?;
+    let buffer = uploader.flush::<All>()?.take_buffer(staged).ok_or_else(|| anyhow!("blue noise buffer missing after upload"))?;
+    Ok(Self {
+      buffer,
+    })
+  }
+}
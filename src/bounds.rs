@@ -0,0 +1,67 @@
+use glam::{Mat4, Vec3};
+
+// Axis aligned bounding box. The empty box has min > max, so that any union with it is the other box.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Aabb {
+  pub min: Vec3,
+  pub max: Vec3,
+}
+
+impl Default for Aabb {
+  fn default() -> Self {
+    Self::EMPTY
+  }
+}
+
+impl Aabb {
+  pub const EMPTY: Aabb = Aabb {
+    min: Vec3::splat(f32::INFINITY),
+    max: Vec3::splat(f32::NEG_INFINITY),
+  };
+
+  // Bounds of tightly packed xyz positions, as in a vertex buffer.
+  pub fn from_positions(positions: &[f32]) -> Self {
+    positions.chunks_exact(3).fold(Self::EMPTY, |aabb, p| aabb.extend(Vec3::new(p[0], p[1], p[2])))
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.min.cmpgt(self.max).any()
+  }
+
+  pub fn extend(&self, point: Vec3) -> Self {
+    Self {
+      min: self.min.min(point),
+      max: self.max.max(point),
+    }
+  }
+
+  #[allow(dead_code)]
+  pub fn union(&self, other: &Aabb) -> Self {
+    Self {
+      min: self.min.min(other.min),
+      max: self.max.max(other.max),
+    }
+  }
+
+  // Bounds of the box after `transform`, from its eight corners. Can be larger than the bounds of
+  // the transformed geometry itself when rotated.
+  pub fn transformed(&self, transform: &Mat4) -> Self {
+    if self.is_empty() {
+      return *self;
+    }
+    (0..8).fold(Self::EMPTY, |aabb, corner| {
+      let pick = |bit: u32, min: f32, max: f32| if corner & bit == 0 { min } else { max };
+      let point = Vec3::new(pick(1, self.min.x, self.max.x), pick(2, self.min.y, self.max.y), pick(4, self.min.z, self.max.z));
+      aabb.extend(transform.transform_point3(point))
+    })
+  }
+
+  pub fn center(&self) -> Vec3 {
+    0.5 * (self.min + self.max)
+  }
+
+  // Radius of the bounding sphere around center().
+  pub fn radius(&self) -> f32 {
+    0.5 * (self.max - self.min).length()
+  }
+}
@@ -0,0 +1,94 @@
+// CPU reference of the dielectric math in resources/shaders/bsdf.glsl, keep both in sync.
+
+use glam::Vec3;
+
+// Unpolarized Fresnel reflectance of a smooth dielectric interface. `cos_i` is the cosine of the
+// incident angle, `eta` the IOR ratio eta_i / eta_t. Returns 1 on total internal reflection,
+// e.g. fresnel_dielectric(1.0, 1.0 / 1.5) is 0.04 and anything past the critical angle
+// asin(1 / 1.5) ~ 41.8 degrees is 1 when leaving glass.
+#[allow(dead_code)]
+pub fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+  let sin2_t = eta * eta * (1.0 - cos_i * cos_i).max(0.0);
+  if sin2_t >= 1.0 {
+    return 1.0;
+  }
+  let cos_t = (1.0 - sin2_t).sqrt();
+  let r_s = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+  let r_p = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+  0.5 * (r_s * r_s + r_p * r_p)
+}
+
+// Same as GLSL refract(): `direction` and `normal` are normalized and the normal faces the incident
+// side. Returns None on total internal reflection, where GLSL returns a zero vector instead.
+#[allow(dead_code)]
+pub fn refract(direction: Vec3, normal: Vec3, eta: f32) -> Option<Vec3> {
+  let cos_i = normal.dot(direction);
+  let k = 1.0 - eta * eta * (1.0 - cos_i * cos_i);
+  if k < 0.0 {
+    return None;
+  }
+  Some(eta * direction - (eta * cos_i + k.sqrt()) * normal)
+}
+
+#[allow(dead_code)]
+pub fn reflect(direction: Vec3, normal: Vec3) -> Vec3 {
+  direction - 2.0 * normal.dot(direction) * normal
+}
+
+// Beer-Lambert transmittance after travelling `distance` through a medium.
+#[allow(dead_code)]
+pub fn beer_lambert(absorption: Vec3, distance: f32) -> Vec3 {
+  (-absorption * distance).exp()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const GLASS: f32 = 1.5;
+
+  // F0 = ((n1 - n2) / (n1 + n2))^2, 0.04 for air to glass.
+  #[test]
+  fn normal_incidence_matches_f0() {
+    let f0 = ((1.0 - GLASS) / (1.0 + GLASS)).powi(2);
+    assert!((fresnel_dielectric(1.0, 1.0 / GLASS) - f0).abs() < 1.0e-6);
+    // The same from inside the glass.
+    assert!((fresnel_dielectric(1.0, GLASS) - f0).abs() < 1.0e-6);
+  }
+
+  #[test]
+  fn total_internal_reflection_past_the_critical_angle() {
+    let critical = (1.0 / GLASS).asin();
+    let beyond = (critical + 0.01).cos();
+    let before = (critical - 0.01).cos();
+    assert_eq!(fresnel_dielectric(beyond, GLASS), 1.0);
+    assert!(fresnel_dielectric(before, GLASS) < 1.0);
+
+    let normal = Vec3::Z;
+    let direction = Vec3::new((critical + 0.01).sin(), 0.0, -(critical + 0.01).cos());
+    assert!(refract(direction, normal, GLASS).is_none());
+    // Entering glass never reflects totally, even at grazing angles.
+    assert!(fresnel_dielectric(0.01, 1.0 / GLASS) < 1.0);
+  }
+
+  #[test]
+  fn refraction_follows_snells_law() {
+    let normal = Vec3::Z;
+    for degrees in [10.0f32, 30.0, 60.0, 80.0] {
+      let theta_i = degrees.to_radians();
+      let direction = Vec3::new(theta_i.sin(), 0.0, -theta_i.cos());
+      let refracted = refract(direction, normal, 1.0 / GLASS).expect("no total internal reflection entering glass");
+      assert!((refracted.length() - 1.0).abs() < 1.0e-5);
+      let sin_t = refracted.x;
+      assert!((theta_i.sin() - GLASS * sin_t).abs() < 1.0e-5, "{} degrees", degrees);
+      // The refracted ray continues into the surface, on the same side of the normal plane.
+      assert!(refracted.z < 0.0 && refracted.x > 0.0);
+    }
+  }
+
+  #[test]
+  fn reflection_mirrors_about_the_normal() {
+    let reflected = reflect(Vec3::new(1.0, 0.0, -1.0).normalize(), Vec3::Z);
+    assert!((reflected - Vec3::new(1.0, 0.0, 1.0).normalize()).length() < 1.0e-6);
+  }
+}
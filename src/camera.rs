@@ -0,0 +1,293 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat4, Vec3, Vec4};
+use log::warn;
+
+use crate::bounds::Aabb;
+use crate::error::{OxError, OxResult};
+
+const NEAR: f32 = 0.001;
+const FAR: f32 = 100.0;
+
+// Bounding sphere scale for Camera::frame_bounds, the space left around the framed scene.
+const FRAME_MARGIN: f32 = 1.1;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Projection {
+  // Vertical field of view in degrees.
+  Perspective { fov: f32 },
+  // Height of the view volume in world units, the width follows from the aspect ratio.
+  // All rays share the view direction and only their origin varies over the image plane.
+  // There is no depth of field yet, when it is added it has to stay off for orthographic
+  // cameras since there is no single center of projection to focus through.
+  Orthographic { height: f32 },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Camera {
+  pub position: Vec3,
+  pub target: Vec3,
+  pub up: Vec3,
+  pub projection: Projection,
+}
+
+impl Default for Camera {
+  fn default() -> Self {
+    Self {
+      position: Vec3::new(0.0, 0.0, -1.0),
+      target: Vec3::new(0.0, 0.0, 1.0),
+      up: Vec3::new(0.0, 1.0, 0.0),
+      projection: Projection::Perspective {
+        fov: 90.0,
+      },
+    }
+  }
+}
+
+impl Camera {
+  pub fn view(&self) -> Mat4 {
+    Mat4::look_at_rh(self.position, self.target, self.up)
+  }
+
+  pub fn projection_matrix(&self, aspect: f32) -> Mat4 {
+    match self.projection {
+      Projection::Perspective {
+        fov,
+      } => Mat4::perspective_rh(fov.to_radians(), aspect, NEAR, FAR),
+      Projection::Orthographic {
+        height,
+      } => {
+        let (half_width, half_height) = (0.5 * height * aspect, 0.5 * height);
+        Mat4::orthographic_rh(-half_width, half_width, -half_height, half_height, NEAR, FAR)
+      }
+    }
+  }
+
+  pub fn is_orthographic(&self) -> bool {
+    matches!(self.projection, Projection::Orthographic { .. })
+  }
+
+  // CPU version of the primary ray setup in raygen.rgen, returns the ray origin and direction for a
+  // point on the image, in pixels from the top left corner (pixel center plus jitter).
+  #[allow(dead_code)]
+  pub fn ray_for_pixel(&self, point: (f32, f32), size: (u32, u32)) -> (Vec3, Vec3) {
+    let ndc = (2.0 * point.0 / size.0 as f32 - 1.0, 2.0 * point.1 / size.1 as f32 - 1.0);
+    let inverse_view = self.view().inverse();
+    let inverse_projection = self.projection_matrix(size.0 as f32 / size.1 as f32).inverse();
+    if self.is_orthographic() {
+      let on_plane = inverse_projection * Vec4::new(ndc.0, ndc.1, 0.0, 1.0);
+      let origin = inverse_view * Vec4::new(on_plane.x, on_plane.y, 0.0, 1.0);
+      let direction = inverse_view * Vec4::new(0.0, 0.0, -1.0, 0.0);
+      (origin.truncate(), direction.truncate().normalize())
+    } else {
+      let origin = inverse_view * Vec4::new(0.0, 0.0, 0.0, 1.0);
+      let target = inverse_projection * Vec4::new(ndc.0, ndc.1, 1.0, 1.0);
+      let direction = inverse_view * target.truncate().normalize().extend(0.0);
+      (origin.truncate(), direction.truncate())
+    }
+  }
+
+  // Camera looking at the center of `bounds` from the current view direction, far enough away that
+  // the bounding sphere fits into the image with FRAME_MARGIN to spare. An empty scene leaves the
+  // camera as is, a single point is framed as if it were a unit sphere.
+  pub fn frame_bounds(&self, bounds: &Aabb, aspect: f32) -> Camera {
+    if bounds.is_empty() {
+      warn!("Nothing to frame, the scene is empty");
+      return *self;
+    }
+    let center = bounds.center();
+    let radius = if bounds.radius() > 0.0 { bounds.radius() * FRAME_MARGIN } else { 1.0 };
+    let direction = (self.target - self.position).try_normalize().unwrap_or(Vec3::Z);
+    let (distance, projection) = match self.projection {
+      Projection::Perspective {
+        fov,
+      } => {
+        // The narrower of the vertical and horizontal field of view has to contain the sphere.
+        let half_vertical = 0.5 * fov.to_radians();
+        let half_horizontal = (half_vertical.tan() * aspect).atan();
+        (radius / half_vertical.min(half_horizontal).sin(), self.projection)
+      }
+      // Orthographic cameras frame with their view height, stepping back only to put everything in front.
+      Projection::Orthographic {
+        ..
+      } => (
+        2.0 * radius,
+        Projection::Orthographic {
+          height: 2.0 * radius / aspect.min(1.0),
+        },
+      ),
+    };
+    Camera {
+      position: center - direction * distance,
+      target: center,
+      projection,
+      ..*self
+    }
+  }
+
+  // Plain text, one `key values...` line per field:
+  //   position 0 0 -1
+  //   target 0 0 1
+  //   up 0 1 0
+  //   projection perspective 90
+  // or `projection orthographic <height>`.
+  #[allow(dead_code)]
+  pub fn save(&self, path: &Path) -> OxResult<()> {
+    let vec = |v: Vec3| format!("{} {} {}", v.x, v.y, v.z);
+    let projection = match self.projection {
+      Projection::Perspective {
+        fov,
+      } => format!("perspective {}", fov),
+      Projection::Orthographic {
+        height,
+      } => format!("orthographic {}", height),
+    };
+    let text = format!(
+      "position {}\ntarget {}\nup {}\nprojection {}\n",
+      vec(self.position),
+      vec(self.target),
+      vec(self.up),
+      projection
+    );
+    fs::write(path, text).map_err(|e| OxError::io(path, e))
+  }
+
+  // Reads the format written by save. Missing keys keep their default value, malformed lines are
+  // reported as InvalidData.
+  #[allow(dead_code)]
+  pub fn load(path: &Path) -> OxResult<Self> {
+    let text = fs::read_to_string(path).map_err(|e| OxError::io(path, e))?;
+    Self::parse(path, &text).map_err(|message| OxError::io(path, std::io::Error::new(std::io::ErrorKind::InvalidData, message)))
+  }
+
+  fn parse(path: &Path, text: &str) -> Result<Self, String> {
+    let mut camera = Camera::default();
+    for (number, line) in text.lines().enumerate() {
+      let mut words = line.split_whitespace();
+      let Some(key) = words.next() else {
+        continue;
+      };
+      let values = words.collect::<Vec<_>>();
+      let parse = |value: &str| value.parse::<f32>().map_err(|_| format!("{}:{}: invalid number {}", path.display(), number + 1, value));
+      let vec = |values: &[&str]| -> Result<Vec3, String> {
+        match values {
+          [x, y, z] => Ok(Vec3::new(parse(x)?, parse(y)?, parse(z)?)),
+          _ => Err(format!("{}:{}: expected three numbers after {}", path.display(), number + 1, key)),
+        }
+      };
+      match (key, values.as_slice()) {
+        ("position", values) => camera.position = vec(values)?,
+        ("target", values) => camera.target = vec(values)?,
+        ("up", values) => camera.up = vec(values)?,
+        ("projection", ["perspective", fov]) => {
+          camera.projection = Projection::Perspective {
+            fov: parse(fov)?,
+          }
+        }
+        ("projection", ["orthographic", height]) => {
+          camera.projection = Projection::Orthographic {
+            height: parse(height)?,
+          }
+        }
+        _ => return Err(format!("{}:{}: unrecognized camera line: {}", path.display(), number + 1, line)),
+      }
+    }
+    Ok(camera)
+  }
+}
+
+// Mirrors View in raygen.rgen (std140), the camera and pixel rectangle of one ray launch. The
+// launches of a sample find theirs in RenderParams::views by the view_index push constant.
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+pub struct ViewParams {
+  pub view: [f32; 16],
+  pub projection: [f32; 16],
+  // x, y, width, height of the pixel rectangle the camera's image plane is mapped to.
+  pub viewport: [u32; 4],
+  // First pixel of the ray launch, the launch size is passed to trace_rays.
+  pub launch_offset: [u32; 2],
+  // Bit 0 writes red, bit 1 green and bit 2 blue.
+  pub channel_mask: u32,
+  pub _pad: u32,
+}
+
+impl ViewParams {
+  // `camera` seen through `viewport`, whose aspect ratio the projection gets.
+  pub fn new(camera: &Camera, viewport: [u32; 4], launch_offset: [u32; 2], channel_mask: u32) -> Self {
+    Self {
+      view: camera.view().to_cols_array(),
+      projection: camera.projection_matrix(viewport[2] as f32 / viewport[3] as f32).to_cols_array(),
+      viewport,
+      launch_offset,
+      channel_mask,
+      _pad: 0,
+    }
+  }
+}
+
+// Launches per sample, one per eye in stereo. Matches the size of RenderParams::views in raygen.rgen.
+pub const MAX_VIEWS: usize = 2;
+
+pub const CHANNELS_ALL: u32 = 0b111;
+pub const CHANNELS_RED: u32 = 0b001;
+pub const CHANNELS_CYAN: u32 = 0b110;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StereoMode {
+  // Left eye in the left half of the image, right eye in the right half.
+  SideBySide,
+  // Both eyes over the full image, red from the left eye and green and blue from the right.
+  Anaglyph,
+}
+
+impl FromStr for StereoMode {
+  type Err = String;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    match value {
+      "side-by-side" => Ok(StereoMode::SideBySide),
+      "anaglyph" => Ok(StereoMode::Anaglyph),
+      _ => Err("expected side-by-side or anaglyph".to_string()),
+    }
+  }
+}
+
+impl fmt::Display for StereoMode {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      StereoMode::SideBySide => "side-by-side",
+      StereoMode::Anaglyph => "anaglyph",
+    })
+  }
+}
+
+// Two eye cameras `ipd` (interpupillary distance, world units) apart, with parallel view
+// directions so that there is no vertical parallax.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct StereoCamera {
+  pub mode: StereoMode,
+  pub ipd: f32,
+}
+
+impl StereoCamera {
+  pub const DEFAULT_IPD: f32 = 0.064;
+
+  // Left and right eye cameras derived from `base`.
+  pub fn eyes(&self, base: &Camera) -> (Camera, Camera) {
+    let forward = (base.target - base.position).normalize();
+    let right = forward.cross(base.up).normalize();
+    let offset = right * (0.5 * self.ipd);
+    let eye = |offset: Vec3| Camera {
+      position: base.position + offset,
+      target: base.target + offset,
+      ..*base
+    };
+    (eye(-offset), eye(offset))
+  }
+}
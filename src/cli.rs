@@ -0,0 +1,345 @@
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser};
+use log::LevelFilter;
+
+use crate::adaptive::AdaptiveSampling;
+use crate::app::{Rect, Runner, RunnerConfig, HEIGHT, WIDTH};
+use crate::bloom::BloomSettings;
+use crate::blue_noise::SampleNoise;
+use crate::camera::{StereoCamera, StereoMode};
+use crate::color::{OutputColorSpace, DEFAULT_PAPER_WHITE_NITS};
+use crate::device::DeviceSelector;
+use crate::exposure::Exposure;
+use crate::filter::ReconstructionFilter;
+use crate::memory::DEFAULT_SCRATCH_RESERVE;
+use crate::output::is_exr;
+use crate::present::PresentMode;
+use crate::render_mode::{AoSettings, RenderMode};
+use crate::sampling::SamplingStrategy;
+use crate::thread::RenderThreadPriority;
+
+// cargo run -- --headless --spp 64 --output out.png
+#[derive(Debug, Parser)]
+#[command(name = "ox-tracer", about = "Physically based raytracer built with Vulkan")]
+pub struct Args {
+  /// Render target width in pixels
+  #[arg(long, default_value_t = WIDTH, value_parser = clap::value_parser!(u32).range(1..))]
+  width: u32,
+  /// Render target height in pixels
+  #[arg(long, default_value_t = HEIGHT, value_parser = clap::value_parser!(u32).range(1..))]
+  height: u32,
+  /// glTF scene (.gltf or .glb) to render instead of the built-in quad
+  #[arg(long)]
+  scene: Option<PathBuf>,
+  /// Camera file with position, target, up and projection
+  #[arg(long)]
+  camera: Option<PathBuf>,
+  /// Samples per pixel to accumulate before writing the output of a headless run
+  #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+  spp: Option<u32>,
+  /// Render without a window and write the result to --output
+  #[arg(long, requires = "output")]
+  headless: bool,
+  /// Output image of a headless run, .exr keeps the raw HDR values
+  #[arg(long, requires = "headless")]
+  output: Option<PathBuf>,
+  /// Write the output tone-mapped like the window shows it, as 8 bit sRGB, instead of the raw HDR values
+  #[arg(long, requires = "headless")]
+  tone_map: bool,
+  /// Fixed exposure multiplier, instead of adapting to the average luminance of the image
+  #[arg(long)]
+  exposure: Option<f32>,
+  /// Exposure compensation in stops for the adaptive exposure, positive values brighten the image
+  #[arg(long, default_value_t = 0.0, conflicts_with = "exposure")]
+  exposure_ev: f32,
+  /// Color space of the window: srgb, rec2020-pq (HDR10) or scrgb, falls back to srgb if the display doesn't support it
+  #[arg(long, default_value_t = OutputColorSpace::default())]
+  color_space: OutputColorSpace,
+  /// Brightness of diffuse white in nits on the HDR color spaces
+  #[arg(long, default_value_t = DEFAULT_PAPER_WHITE_NITS)]
+  paper_white: f32,
+  /// Vsync of the window: mailbox, fifo (saves power), fifo-relaxed or immediate (tears), falls back to fifo if unsupported
+  #[arg(long, default_value_t = PresentMode::default())]
+  present_mode: PresentMode,
+  /// Also write the linear depth and world position of the first hits as <output>_depth.exr and <output>_position.exr
+  #[arg(long, requires = "headless")]
+  compositing_passes: bool,
+  /// Leave the background transparent where camera rays miss instead of showing the sky
+  #[arg(long, requires = "headless")]
+  transparent_background: bool,
+  /// Time this many samples of the built-in scene and write a JSON report to --output instead of an image
+  #[arg(long, requires = "headless", value_parser = clap::value_parser!(u32).range(1..))]
+  bench: Option<u32>,
+  /// Stop tracing in the window once this many samples are accumulated, the converged image stays on screen
+  #[arg(long, conflicts_with = "headless", value_parser = clap::value_parser!(u32).range(1..))]
+  max_samples: Option<u32>,
+  /// Seconds the camera has to stay still before the direct lighting preview switches to path tracing, 0 disables the preview
+  #[arg(long, default_value_t = 0.3, conflicts_with = "headless")]
+  preview_idle: f32,
+  /// Render this many independent frames of --spp samples each, written as <output>_0000.<ext> and so on
+  #[arg(long, requires = "headless", conflicts_with = "bench", value_parser = clap::value_parser!(u32).range(1..))]
+  frames: Option<u32>,
+  /// Base seed of the sampling noise, renders are reproducible for a given seed
+  #[arg(long, default_value_t = 0)]
+  seed: u32,
+  /// Sub-pixel jitter of the samples: blue (the blue-noise mask) or white, for comparison
+  #[arg(long, default_value_t = SampleNoise::default())]
+  sample_noise: SampleNoise,
+  /// What to render: path (path tracing) or ao (ambient occlusion of the first hits, to check geometry and normals)
+  #[arg(long, default_value_t = RenderMode::default())]
+  render_mode: RenderMode,
+  /// Hemisphere rays per pixel and sample of --render-mode ao
+  #[arg(long, default_value_t = AoSettings::default().samples, value_parser = clap::value_parser!(u32).range(1..))]
+  ao_samples: u32,
+  /// Distance beyond which nothing occludes in --render-mode ao
+  #[arg(long, default_value_t = AoSettings::default().radius)]
+  ao_radius: f32,
+  /// Add a glow around parts of the image brighter than --bloom-threshold
+  #[arg(long)]
+  bloom: bool,
+  /// Luminance above which bloom spreads light
+  #[arg(long, default_value_t = BloomSettings::default().threshold, requires = "bloom")]
+  bloom_threshold: f32,
+  /// Fraction of the blurred light bloom adds back
+  #[arg(long, default_value_t = BloomSettings::default().intensity, requires = "bloom")]
+  bloom_intensity: f32,
+  /// How far the bloom glow reaches, between 0 and 1
+  #[arg(long, default_value_t = BloomSettings::default().radius, requires = "bloom")]
+  bloom_radius: f32,
+  /// Render a stereo pair: side-by-side puts the left eye in the left half, anaglyph renders red from the left eye and cyan from the right
+  #[arg(long, conflicts_with = "crop")]
+  stereo: Option<StereoMode>,
+  /// Distance between the eyes of --stereo in scene units
+  #[arg(long, default_value_t = StereoCamera::DEFAULT_IPD, requires = "stereo")]
+  ipd: f32,
+  /// Adaptive sampling: a pixel stops taking samples once the relative standard error of its mean luminance is below this, e.g. 0.01
+  #[arg(long)]
+  adaptive_threshold: Option<f32>,
+  /// Samples after which an adaptively sampled pixel stops regardless of its error
+  #[arg(long, default_value_t = AdaptiveSampling::DEFAULT_MAX_SAMPLES, requires = "adaptive_threshold", value_parser = clap::value_parser!(u32).range(1..))]
+  adaptive_max_samples: u32,
+  /// Show the per-pixel sample count of adaptive sampling instead of the image
+  #[arg(long, requires = "adaptive_threshold")]
+  adaptive_view: bool,
+  /// Minimum ray distance, defaults to a fraction of the scene size
+  #[arg(long)]
+  t_min: Option<f32>,
+  /// Maximum ray distance
+  #[arg(long, default_value_t = 10000.0)]
+  t_max: f32,
+  /// Clamp the radiance of every sample to this value to suppress fireflies, 0 disables it
+  #[arg(long, default_value_t = 0.0)]
+  firefly_clamp: f32,
+  /// How diffuse hits sample the sky: bsdf, light, mis or mis-balance
+  #[arg(long, default_value_t = SamplingStrategy::default())]
+  sampling: SamplingStrategy,
+  /// Skip shadow rays on materials smoother than this roughness, where bounces find the sky anyway, 0 disables it
+  #[arg(long, default_value_t = 0.0)]
+  nee_roughness_threshold: f32,
+  /// Refract light through glass with a dispersion coefficient at a per-sample wavelength, for rainbow edges
+  #[arg(long)]
+  spectral: bool,
+  /// Let shadow rays pass through transmissive and alpha-blended materials and tint them, slower
+  #[arg(long)]
+  transmissive_shadows: bool,
+  /// Add an occluder lit by an area light and a spherical point light to the built-in scene, to show soft shadows
+  #[arg(long, conflicts_with = "scene")]
+  light_demo: bool,
+  /// Pixel reconstruction filter: box, tent, gaussian or mitchell, with optional parameters like gaussian:radius=1.5,sigma=0.5
+  #[arg(long, default_value_t = ReconstructionFilter::default())]
+  filter: ReconstructionFilter,
+  /// Only render the pixel rectangle x,y,width,height
+  #[arg(long, value_parser = parse_rect)]
+  crop: Option<Rect>,
+  /// Scratch memory in bytes reserved per buffer type and frame at startup, raise it if the log says the peak is close
+  #[arg(long, default_value_t = DEFAULT_SCRATCH_RESERVE, value_parser = clap::value_parser!(u64).range(1..))]
+  scratch_reserve: u64,
+  /// Stop rendering while the window is unfocused or occluded, the accumulated image is kept
+  #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
+  pause_on_unfocus: bool,
+  /// Recompile shaders in resources/shaders when they are saved and reload them, needs the hot-reload feature
+  #[arg(long, conflicts_with = "headless")]
+  hot_reload: bool,
+  /// GPU to render on, an index or part of the name as printed by --list-gpus
+  #[arg(long)]
+  gpu: Option<DeviceSelector>,
+  /// Print the available GPUs and exit
+  #[arg(long)]
+  list_gpus: bool,
+  /// Log level when RUST_LOG is not set: off, error, warn, info, debug or trace
+  #[arg(long, default_value_t = LevelFilter::Info)]
+  log_level: LevelFilter,
+  /// Enable the Vulkan validation layer, on by default in debug builds
+  #[arg(long, default_value_t = cfg!(debug_assertions), action = clap::ArgAction::Set)]
+  validation: bool,
+  /// Panic after any frame that raised a validation error, for tests
+  #[arg(long)]
+  strict_validation: bool,
+  /// Log the frame rate and the average CPU and GPU frame times once per second
+  #[arg(long, conflicts_with = "headless")]
+  log_frame_times: bool,
+  /// Log at debug level how the passes of every recorded graph use each resource, to match sync validation errors to passes
+  #[arg(long)]
+  log_barriers: bool,
+  /// Scheduling priority of the window's render thread: normal, high or max, needs the thread-priority feature
+  #[arg(long, default_value_t = RenderThreadPriority::default())]
+  render_thread_priority: RenderThreadPriority,
+  /// Pin the window's render thread to this CPU core, needs the thread-priority feature
+  #[arg(long)]
+  render_thread_core: Option<usize>,
+}
+
+fn parse_rect(value: &str) -> Result<Rect, String> {
+  let parts = value.split(',').map(|part| part.trim().parse::<u32>()).collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())?;
+  match parts.as_slice() {
+    &[x, y, width, height] => Ok(Rect {
+      x,
+      y,
+      width,
+      height,
+    }),
+    _ => Err("expected x,y,width,height".to_string()),
+  }
+}
+
+impl From<Args> for RunnerConfig {
+  fn from(args: Args) -> Self {
+    RunnerConfig {
+      width: args.width,
+      height: args.height,
+      scene: args.scene,
+      camera: args.camera,
+      spp: args.spp,
+      headless: args.headless,
+      output: args.output,
+      tone_map: args.tone_map,
+      exposure: args.exposure.map_or(Exposure::auto(args.exposure_ev), Exposure::Manual),
+      color_space: args.color_space,
+      paper_white: args.paper_white,
+      present_mode: args.present_mode,
+      compositing_passes: args.compositing_passes,
+      transparent_background: args.transparent_background,
+      seed: args.seed,
+      sample_noise: args.sample_noise,
+      render_mode: match args.render_mode {
+        RenderMode::AmbientOcclusion(_) => RenderMode::AmbientOcclusion(AoSettings {
+          samples: args.ao_samples,
+          radius: args.ao_radius,
+        }),
+        mode => mode,
+      },
+      bloom: args.bloom.then_some(BloomSettings {
+        threshold: args.bloom_threshold,
+        intensity: args.bloom_intensity,
+        radius: args.bloom_radius,
+      }),
+      stereo: args.stereo.map(|mode| StereoCamera {
+        mode,
+        ipd: args.ipd,
+      }),
+      adaptive: args.adaptive_threshold.map(|threshold| AdaptiveSampling {
+        threshold,
+        max_samples: args.adaptive_max_samples,
+      }),
+      adaptive_view: args.adaptive_view,
+      t_min: args.t_min,
+      t_max: args.t_max,
+      firefly_clamp: args.firefly_clamp,
+      sampling: args.sampling,
+      nee_roughness_threshold: args.nee_roughness_threshold,
+      spectral: args.spectral,
+      transmissive_shadows: args.transmissive_shadows,
+      light_demo: args.light_demo,
+      filter: args.filter,
+      crop: args.crop,
+      pause_on_unfocus: args.pause_on_unfocus,
+      hot_reload_shaders: args.hot_reload,
+      log_level: args.log_level,
+      validation: args.validation,
+      strict_validation: args.strict_validation,
+      log_frame_times: args.log_frame_times,
+      log_barriers: args.log_barriers,
+      gpu: args.gpu,
+      render_thread_priority: args.render_thread_priority,
+      render_thread_core: args.render_thread_core,
+      bench: args.bench,
+      frames: args.frames,
+      max_samples: args.max_samples,
+      preview_idle: Duration::from_secs_f32(args.preview_idle),
+      scratch_reserve: args.scratch_reserve,
+      ..Default::default()
+    }
+  }
+}
+
+pub fn parse() -> RunnerConfig {
+  parse_from(std::env::args_os())
+}
+
+// Like parse with the arguments `args` instead of the process's, the first one is the program name.
+pub fn parse_from<I, T>(args: I) -> RunnerConfig
+  where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone, {
+  let args = Args::parse_from(args);
+  if args.list_gpus {
+    for (index, device) in Runner::list_devices().iter().enumerate() {
+      let memory = device.device_local_memory / (1024 * 1024);
+      println!("{}: {} ({:?}, {} MiB, raytracing {})", index, device.name, device.device_type, memory, device.raytracing);
+    }
+    std::process::exit(0);
+  }
+  if let Some(scene) = &args.scene {
+    let gltf = scene.extension().map_or(false, |extension| extension.eq_ignore_ascii_case("gltf") || extension.eq_ignore_ascii_case("glb"));
+    if !gltf {
+      Args::command().error(ErrorKind::ValueValidation, "--scene must be a .gltf or .glb file").exit();
+    }
+  }
+  if args.tone_map && args.output.as_deref().map_or(false, is_exr) {
+    Args::command().error(ErrorKind::ValueValidation, "--tone-map writes 8 bit sRGB, which .exr can't hold, use a .png --output or drop --tone-map").exit();
+  }
+  if let Some(t_min) = args.t_min {
+    if !(t_min >= 0.0 && t_min < args.t_max) {
+      Args::command().error(ErrorKind::ValueValidation, "--t-min must be non-negative and smaller than --t-max").exit();
+    }
+  }
+  if !(args.ao_radius > 0.0) {
+    Args::command().error(ErrorKind::ValueValidation, "--ao-radius must be positive").exit();
+  }
+  if !(args.preview_idle >= 0.0 && args.preview_idle.is_finite()) {
+    Args::command().error(ErrorKind::ValueValidation, "--preview-idle must be a non-negative number of seconds").exit();
+  }
+  if !(args.bloom_threshold >= 0.0 && args.bloom_intensity >= 0.0) {
+    Args::command().error(ErrorKind::ValueValidation, "--bloom-threshold and --bloom-intensity must not be negative").exit();
+  }
+  if !(args.bloom_radius >= 0.0 && args.bloom_radius <= 1.0) {
+    Args::command().error(ErrorKind::ValueValidation, "--bloom-radius must lie within [0, 1]").exit();
+  }
+  if !(args.paper_white > 0.0 && args.paper_white.is_finite()) {
+    Args::command().error(ErrorKind::ValueValidation, "--paper-white must be a positive number of nits").exit();
+  }
+  if !(args.ipd > 0.0) {
+    Args::command().error(ErrorKind::ValueValidation, "--ipd must be positive").exit();
+  }
+  if let Some(threshold) = args.adaptive_threshold {
+    if !(threshold > 0.0) {
+      Args::command().error(ErrorKind::ValueValidation, "--adaptive-threshold must be positive").exit();
+    }
+  }
+  if !(args.firefly_clamp >= 0.0) {
+    Args::command().error(ErrorKind::ValueValidation, "--firefly-clamp must not be negative").exit();
+  }
+  if !(args.nee_roughness_threshold >= 0.0 && args.nee_roughness_threshold <= 1.0) {
+    Args::command().error(ErrorKind::ValueValidation, "--nee-roughness-threshold must lie within [0, 1]").exit();
+  }
+  if let Some(crop) = args.crop {
+    if !crop.fits_in(args.width, args.height) {
+      Args::command().error(ErrorKind::ValueValidation, format!("--crop must lie within the {}x{} image", args.width, args.height)).exit();
+    }
+  }
+  args.into()
+}
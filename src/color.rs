@@ -0,0 +1,110 @@
+use std::fmt;
+use std::str::FromStr;
+
+use ash::vk;
+use log::warn;
+
+// Brightness of diffuse white on HDR outputs, the reference white of ITU-R BT.2408, see RunnerConfig::paper_white.
+pub const DEFAULT_PAPER_WHITE_NITS: f32 = 203.0;
+
+// Color space of the swapchain, and the encoding the display pass writes for it. The image is exposed
+// in linear Rec.709 / sRGB primaries either way, only the final conversion differs. Headless output
+// and render_to_external always write sRGB.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum OutputColorSpace {
+  // SDR, the sRGB transfer function is applied by the _SRGB swapchain format.
+  #[default]
+  Srgb,
+  // HDR10: Rec.2020 primaries with the SMPTE ST 2084 (PQ) transfer function in a 10 bit format.
+  Rec2020Pq,
+  // Linear sRGB primaries in half floats with 1.0 at 80 nits. Wider gamut and brighter than SDR
+  // white come out as values outside of [0, 1].
+  ScRgb,
+}
+
+impl OutputColorSpace {
+  pub const SRGB: u32 = 0;
+  pub const REC2020_PQ: u32 = 1;
+  pub const SCRGB: u32 = 2;
+
+  // Value of DisplayPushConstants::color_space.
+  pub fn id(&self) -> u32 {
+    match self {
+      OutputColorSpace::Srgb => Self::SRGB,
+      OutputColorSpace::Rec2020Pq => Self::REC2020_PQ,
+      OutputColorSpace::ScRgb => Self::SCRGB,
+    }
+  }
+
+  // Swapchain format to request, None leaves the choice of an sRGB format to phobos.
+  pub fn surface_format(&self) -> Option<vk::SurfaceFormatKHR> {
+    match self {
+      OutputColorSpace::Srgb => None,
+      OutputColorSpace::Rec2020Pq => Some(vk::SurfaceFormatKHR {
+        format: vk::Format::A2B10G10R10_UNORM_PACK32,
+        color_space: vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+      }),
+      OutputColorSpace::ScRgb => Some(vk::SurfaceFormatKHR {
+        format: vk::Format::R16G16B16A16_SFLOAT,
+        color_space: vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT,
+      }),
+    }
+  }
+}
+
+impl FromStr for OutputColorSpace {
+  type Err = String;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    match value {
+      "srgb" => Ok(OutputColorSpace::Srgb),
+      "rec2020-pq" => Ok(OutputColorSpace::Rec2020Pq),
+      "scrgb" => Ok(OutputColorSpace::ScRgb),
+      _ => Err("expected srgb, rec2020-pq or scrgb".to_string()),
+    }
+  }
+}
+
+impl fmt::Display for OutputColorSpace {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let name = match self {
+      OutputColorSpace::Srgb => "srgb",
+      OutputColorSpace::Rec2020Pq => "rec2020-pq",
+      OutputColorSpace::ScRgb => "scrgb",
+    };
+    f.write_str(name)
+  }
+}
+
+// `requested` if the surface offers its format, otherwise sRGB, which phobos picks a format for.
+pub fn choose_color_space(formats: &[vk::SurfaceFormatKHR], requested: OutputColorSpace) -> OutputColorSpace {
+  match requested.surface_format() {
+    Some(format) if !formats.contains(&format) => {
+      warn!("The surface doesn't offer the {} format {:?}, falling back to srgb", requested, format);
+      OutputColorSpace::Srgb
+    }
+    _ => requested,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const SRGB: vk::SurfaceFormatKHR = vk::SurfaceFormatKHR {
+    format: vk::Format::B8G8R8A8_SRGB,
+    color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+  };
+
+  #[test]
+  fn offered_color_space_is_kept() {
+    let formats = [SRGB, OutputColorSpace::Rec2020Pq.surface_format().unwrap()];
+    assert_eq!(choose_color_space(&formats, OutputColorSpace::Rec2020Pq), OutputColorSpace::Rec2020Pq);
+  }
+
+  #[test]
+  fn missing_format_falls_back_to_srgb() {
+    assert_eq!(choose_color_space(&[SRGB], OutputColorSpace::ScRgb), OutputColorSpace::Srgb);
+    assert_eq!(choose_color_space(&[SRGB], OutputColorSpace::Srgb), OutputColorSpace::Srgb);
+  }
+}
@@ -0,0 +1,56 @@
+use anyhow::Result;
+use ash::vk;
+
+use phobos::prelude::*;
+
+use crate::app::Context;
+use crate::memory::Image;
+use crate::upload::transition_to_general;
+
+// Depth and position of the primary hit for compositing, aligned with the beauty image and written
+// by raygen on the first sample of an accumulation. Both are RGBA32F so they go through the same
+// readback and EXR writer as the beauty image:
+// - depth holds the linear view space depth in scene units in rgb, the distance of the hit in front
+//   of the camera plane along the view direction (not along the ray), and 1 in alpha. Misses get
+//   t_max and alpha 0.
+// - position holds the world space position in rgb and 1 in alpha, misses are all zero.
+// Neither is filtered or accumulated: averaging depth across an edge would invent surfaces between
+// foreground and background, so every pixel keeps the single hit of its first sample.
+pub struct CompositingPasses {
+  pub depth: Image,
+  pub depth_view: ImageView,
+  pub position: Image,
+  pub position_view: ImageView,
+  pub enabled: bool,
+}
+
+impl CompositingPasses {
+  // Without `enabled` the images are 1x1 placeholders that keep raygen's bindings valid, and nothing writes them.
+  pub fn new(ctx: &mut Context, width: u32, height: u32, enabled: bool) -> Result<Self> {
+    let (width, height) = if enabled { (width, height) } else { (1, 1) };
+    let mut make = || -> Result<(Image, ImageView)> {
+      let image = Image::new(
+        ctx.device.clone(),
+        &mut ctx.allocator,
+        width,
+        height,
+        vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::TRANSFER_SRC,
+        vk::Format::R32G32B32A32_SFLOAT,
+        vk::SampleCountFlags::TYPE_1,
+      )?;
+      let view = image.view(vk::ImageAspectFlags::COLOR)?;
+      Ok((image, view))
+    };
+    let (depth, depth_view) = make()?;
+    let (position, position_view) = make()?;
+    // Bound directly like the coarser bloom levels, the pass graph never transitions them.
+    transition_to_general(ctx, &[&depth, &position], PipelineStage::RAY_TRACING_SHADER_KHR)?;
+    Ok(Self {
+      depth,
+      depth_view,
+      position,
+      position_view,
+      enabled,
+    })
+  }
+}
@@ -0,0 +1,185 @@
+use std::path::PathBuf;
+
+use anyhow::{anyhow, ensure, Result};
+use ash::vk;
+
+use phobos::prelude::*;
+use phobos::sync::domain::{All, Compute};
+
+use crate::app::{create_shader, Context};
+use crate::device::ComputeLimits;
+use crate::error::{OxError, OxResult};
+use crate::memory::{Buffer, Image};
+use crate::upload::{buffer_barrier, image_barrier};
+
+const SPIRV_HEADER_WORDS: usize = 5;
+const OP_EXECUTION_MODE: u32 = 16;
+const EXECUTION_MODE_LOCAL_SIZE: u32 = 17;
+
+// The local workgroup size a compute shader declares with `layout(local_size_x = ...) in`. None if it
+// uses specialization constants for it (LocalSizeId), which can't be read without specializing.
+pub fn local_size(code: &[u32]) -> Option<[u32; 3]> {
+  let mut offset = SPIRV_HEADER_WORDS;
+  while offset < code.len() {
+    let word_count = (code[offset] >> 16) as usize;
+    let opcode = code[offset] & 0xFFFF;
+    if word_count == 0 || offset + word_count > code.len() {
+      return None;
+    }
+    // OpExecutionMode %entry LocalSize x y z
+    if opcode == OP_EXECUTION_MODE && word_count == 6 && code[offset + 2] == EXECUTION_MODE_LOCAL_SIZE {
+      return Some([code[offset + 3], code[offset + 4], code[offset + 5]]);
+    }
+    offset += word_count;
+  }
+  None
+}
+
+// A compute pipeline registered in the PipelineCache, with the workgroup size it was checked against.
+// Record with bind, then the descriptors, then dispatch.
+#[derive(Debug, Clone)]
+pub struct ComputeKernel {
+  pub name: String,
+  pub path: String,
+  // None if the shader doesn't declare a literal size, see local_size.
+  pub local_size: Option<[u32; 3]>,
+  limits: ComputeLimits,
+}
+
+#[allow(dead_code)]
+impl ComputeKernel {
+  // Fails with OxError::Unsupported if the shader's workgroup is larger than the device allows.
+  pub fn new(ctx: &mut Context, name: &str, path: &str) -> OxResult<Self> {
+    let shader = create_shader(path, vk::ShaderStageFlags::COMPUTE)?;
+    let local_size = local_size(shader.code());
+    let limits = ctx.gpu.compute_limits;
+    if let Some(size) = local_size {
+      let invocations = size.iter().map(|&n| n as u64).product::<u64>();
+      let fits = size.iter().zip(limits.max_workgroup_size).all(|(&n, max)| n <= max) && invocations <= limits.max_workgroup_invocations as u64;
+      if !fits {
+        return Err(OxError::Unsupported {
+          what: format!("the workgroup size {}x{}x{} of {}", size[0], size[1], size[2], path),
+          reason: format!(
+            "{} allows at most {:?} and {} invocations per workgroup",
+            ctx.gpu.name, limits.max_workgroup_size, limits.max_workgroup_invocations
+          ),
+        });
+      }
+    }
+    let kernel = Self {
+      name: name.to_string(),
+      path: path.to_string(),
+      local_size,
+      limits,
+    };
+    kernel.register(ctx, shader)?;
+    Ok(kernel)
+  }
+
+  // Registers the pipeline again from `path`, in App::reload_shaders after the shader was recompiled.
+  // Fails if the new shader changed the workgroup size, which the dispatches were sized for.
+  pub fn create_pipeline(&self, ctx: &mut Context) -> OxResult<()> {
+    let shader = create_shader(&self.path, vk::ShaderStageFlags::COMPUTE)?;
+    let local_size = local_size(shader.code());
+    if local_size != self.local_size {
+      return Err(OxError::ShaderCompile {
+        path: PathBuf::from(&self.path),
+        reason: format!("the workgroup size changed from {:?} to {:?}, create the kernel again", self.local_size, local_size),
+      });
+    }
+    self.register(ctx, shader)
+  }
+
+  fn register(&self, ctx: &mut Context, shader: ShaderCreateInfo) -> OxResult<()> {
+    let pci = ComputePipelineBuilder::new(&self.name).set_shader(shader).build();
+    ctx.pipelines.create_named_compute_pipeline(pci).map_err(|e| OxError::allocation(format!("the compute pipeline {}", self.name), e))
+  }
+
+  // Workgroups covering `width` x `height` x `depth` invocations, rounded up.
+  pub fn groups_for(&self, width: u32, height: u32, depth: u32) -> [u32; 3] {
+    let size = self.local_size.unwrap_or([1; 3]);
+    [(width + size[0] - 1) / size[0], (height + size[1] - 1) / size[1], (depth + size[2] - 1) / size[2]]
+  }
+
+  // Binds the pipeline, bind its descriptors afterwards.
+  pub fn bind<C: ComputeCmdBuffer>(&self, cmd: C) -> Result<C> {
+    cmd.bind_compute_pipeline(&self.name)
+  }
+
+  // Records the dispatch of `groups` workgroups, checked against maxComputeWorkGroupCount.
+  pub fn dispatch<C: ComputeCmdBuffer>(&self, cmd: C, groups: [u32; 3]) -> Result<C> {
+    let max = self.limits.max_workgroup_count;
+    ensure!(
+      groups.iter().zip(max).all(|(&n, max)| n <= max),
+      "dispatching {:?} workgroups of {} exceeds the device limit of {:?}",
+      groups,
+      self.name,
+      max
+    );
+    cmd.dispatch(groups[0], groups[1], groups[2])
+  }
+}
+
+// Records `record` on the compute queue after the graphics work submitted so far, submits it and
+// waits for it. `images`, in GENERAL layout, and `buffers` are what the graphics queue wrote before
+// and reads afterwards. On a compute queue of another family their ownership is released by the
+// graphics queue, acquired for the dispatches and handed back the same way, like Uploader::flush
+// does for uploads. Without a queue of its own the work still runs in the Compute domain.
+pub fn run_on_compute_queue(
+  ctx: &Context,
+  images: &[&Image],
+  buffers: &[&Buffer],
+  record: impl for<'q> FnOnce(IncompleteCommandBuffer<'q, Compute>) -> Result<IncompleteCommandBuffer<'q, Compute>>,
+) -> Result<()> {
+  let exec = &ctx.exec;
+  let graphics = exec.get_queue::<All>().ok_or_else(|| anyhow!("no graphics queue available"))?.family_index();
+  let compute = exec.get_queue::<Compute>().ok_or_else(|| anyhow!("no compute queue available"))?.family_index();
+  let to_compute = (graphics != compute).then_some((graphics, compute));
+  let to_graphics = to_compute.map(|(graphics, compute)| (compute, graphics));
+  let barriers = |ownership: Option<(u32, u32)>, src: (PipelineStage, vk::AccessFlags2), dst: (PipelineStage, vk::AccessFlags2)| {
+    let images = images
+      .iter()
+      .map(|image| {
+        image_barrier(image, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL, ownership)
+          .src_stage_mask(src.0)
+          .src_access_mask(src.1)
+          .dst_stage_mask(dst.0)
+          .dst_access_mask(dst.1)
+          .build()
+      })
+      .collect::<Vec<_>>();
+    let buffers = buffers
+      .iter()
+      .map(|buffer| buffer_barrier(buffer, ownership).src_stage_mask(src.0).src_access_mask(src.1).dst_stage_mask(dst.0).dst_access_mask(dst.1).build())
+      .collect::<Vec<_>>();
+    (images, buffers)
+  };
+  let written = (PipelineStage::ALL_COMMANDS, vk::AccessFlags2::MEMORY_WRITE);
+  let compute_access = (PipelineStage::COMPUTE_SHADER, vk::AccessFlags2::SHADER_STORAGE_READ | vk::AccessFlags2::SHADER_STORAGE_WRITE);
+  let compute_written = (PipelineStage::COMPUTE_SHADER, vk::AccessFlags2::SHADER_STORAGE_WRITE);
+  let read = (PipelineStage::ALL_COMMANDS, vk::AccessFlags2::MEMORY_READ | vk::AccessFlags2::MEMORY_WRITE);
+  let submit_barriers = |(images, buffers): (Vec<vk::ImageMemoryBarrier2>, Vec<vk::BufferMemoryBarrier2>)| -> Result<()> {
+    let cmd = exec
+      .on_domain::<All, DefaultAllocator>(None, None)?
+      .pipeline_barrier(&vk::DependencyInfo::builder().image_memory_barriers(&images).buffer_memory_barriers(&buffers).build())?
+      .finish()?;
+    exec.submit(cmd)?.wait()?;
+    Ok(())
+  };
+
+  // The release half on the graphics queue. Waiting for it also waits for the work before it, the
+  // compute queue has no semaphore to wait on.
+  submit_barriers(barriers(to_compute, written, compute_access))?;
+  let (images_in, buffers_in) = barriers(to_compute, written, compute_access);
+  let cmd = exec
+    .on_domain::<Compute, _>(Some(ctx.pipelines.clone()), Some(ctx.descriptors.clone()))?
+    .pipeline_barrier(&vk::DependencyInfo::builder().image_memory_barriers(&images_in).buffer_memory_barriers(&buffers_in).build())?;
+  let (images_out, buffers_out) = barriers(to_graphics, compute_written, read);
+  let cmd = record(cmd)?
+    .pipeline_barrier(&vk::DependencyInfo::builder().image_memory_barriers(&images_out).buffer_memory_barriers(&buffers_out).build())?
+    .finish()?;
+  exec.submit(cmd)?.wait()?;
+  // The acquire half back on the graphics queue.
+  submit_barriers(barriers(to_graphics, compute_written, read))?;
+  Ok(())
+}
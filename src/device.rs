@@ -0,0 +1,415 @@
+use std::cmp::Reverse;
+use std::ffi::CStr;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use ash::vk;
+use log::info;
+
+use phobos::prelude::*;
+
+use crate::error::{OxError, OxResult};
+use crate::features::DeviceFeatures;
+
+// Compute dispatch limits of VkPhysicalDeviceLimits, checked by compute::ComputeKernel.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct ComputeLimits {
+  pub max_workgroup_size: [u32; 3],
+  pub max_workgroup_invocations: u32,
+  pub max_workgroup_count: [u32; 3],
+}
+
+// What device scoring looks at, gathered once per physical device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceCandidate {
+  pub name: String,
+  pub device_type: vk::PhysicalDeviceType,
+  pub vendor_id: u32,
+  pub device_id: u32,
+  pub device_local_memory: u64,
+  pub raytracing: bool,
+  // VK_EXT_mesh_shader and its taskShader and meshShader features are available, see mesh_shading.rs.
+  pub mesh_shader: bool,
+  // VK_KHR_ray_query and its rayQuery feature are available, see ray_query.rs.
+  pub ray_query: bool,
+  // minAccelerationStructureScratchOffsetAlignment, 0 without VK_KHR_acceleration_structure.
+  pub accel_scratch_alignment: u32,
+  pub compute_limits: ComputeLimits,
+  // maxPushConstantsSize, at least PUSH_CONSTANT_LIMIT. Context::push_constants checks against it.
+  pub max_push_constants_size: u32,
+  // Vendor specific encoding, reported as is.
+  pub driver_version: u32,
+  pub api_version: u32,
+}
+
+// Higher is better. Compared lexicographically: raytracing support first, then discrete over
+// integrated over virtual over CPU devices, then the amount of device local memory.
+pub fn score(candidate: &DeviceCandidate) -> (bool, u32, u64) {
+  let type_rank = match candidate.device_type {
+    vk::PhysicalDeviceType::DISCRETE_GPU => 3,
+    vk::PhysicalDeviceType::INTEGRATED_GPU => 2,
+    vk::PhysicalDeviceType::VIRTUAL_GPU => 1,
+    _ => 0,
+  };
+  (candidate.raytracing, type_rank, candidate.device_local_memory)
+}
+
+// Indices of `candidates` from best to worst. Ties are broken by name, vendor and device id
+// instead of enumeration order, which can change between boots and driver updates.
+pub fn rank(candidates: &[DeviceCandidate]) -> Vec<usize> {
+  let mut order = (0..candidates.len()).collect::<Vec<_>>();
+  order.sort_by_key(|&index| {
+    let candidate = &candidates[index];
+    (Reverse(score(candidate)), candidate.name.clone(), candidate.vendor_id, candidate.device_id)
+  });
+  order
+}
+
+// Device requirements initialize() selects physical devices by, see RunnerConfig::device.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DeviceRequirements {
+  // Only accept discrete GPUs.
+  pub discrete: bool,
+  pub min_video_memory: u64,
+  pub min_dedicated_video_memory: u64,
+}
+
+impl Default for DeviceRequirements {
+  fn default() -> Self {
+    Self {
+      discrete: false,
+      min_video_memory: 1024 * 1024 * 1024, // 1 GiB.
+      min_dedicated_video_memory: 1024 * 1024 * 1024,
+    }
+  }
+}
+
+impl DeviceRequirements {
+  // Why `candidate` doesn't meet these requirements or ray tracing, empty if it does. Extensions,
+  // features and queues aren't part of DeviceCandidate, initialize() can still reject a device for them.
+  pub fn unmet(&self, candidate: &DeviceCandidate) -> Vec<String> {
+    let mib = |bytes: u64| bytes / (1024 * 1024);
+    let mut unmet = Vec::new();
+    if !candidate.raytracing {
+      unmet.push("no VK_KHR_ray_tracing_pipeline".to_string());
+    }
+    if self.discrete && candidate.device_type != vk::PhysicalDeviceType::DISCRETE_GPU {
+      unmet.push(format!("{:?} but discrete is required", candidate.device_type));
+    }
+    for (field, minimum) in [("min_video_memory", self.min_video_memory), ("min_dedicated_video_memory", self.min_dedicated_video_memory)] {
+      if candidate.device_local_memory < minimum {
+        unmet.push(format!("{} MiB device local memory, below {} of {} MiB", mib(candidate.device_local_memory), field, mib(minimum)));
+      }
+    }
+    unmet
+  }
+}
+
+// Picks the GPU instead of the scoring, see RunnerConfig::gpu. Parsed from an index into the device
+// list or a case insensitive part of the device name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceSelector {
+  Index(usize),
+  Name(String),
+}
+
+impl DeviceSelector {
+  // The index of the selected device in `candidates`, fails listing the devices if none matches, the
+  // name is ambiguous or the device doesn't meet `requirements`.
+  pub fn resolve(&self, candidates: &[DeviceCandidate], requirements: &DeviceRequirements) -> OxResult<usize> {
+    let matches = match self {
+      DeviceSelector::Index(index) => (*index < candidates.len()).then_some(*index).into_iter().collect::<Vec<_>>(),
+      DeviceSelector::Name(name) => {
+        let name = name.to_lowercase();
+        (0..candidates.len()).filter(|&index| candidates[index].name.to_lowercase().contains(&name)).collect()
+      }
+    };
+    let unsupported = |reason: String| OxError::Unsupported {
+      what: format!("GPU {}", self),
+      reason: format!("{}, available: {}", reason, list(candidates)),
+    };
+    let index = match matches.as_slice() {
+      [] => return Err(unsupported("no such device".to_string())),
+      &[index] => index,
+      _ => return Err(unsupported(format!("matches {} devices, select one by index", matches.len()))),
+    };
+    let unmet = requirements.unmet(&candidates[index]);
+    if !unmet.is_empty() {
+      return Err(unsupported(format!("{} doesn't meet the requirements: {}", candidates[index].name, unmet.join(", "))));
+    }
+    Ok(index)
+  }
+}
+
+fn list(candidates: &[DeviceCandidate]) -> String {
+  candidates.iter().enumerate().map(|(index, candidate)| format!("{} {}", index, candidate.name)).collect::<Vec<_>>().join(", ")
+}
+
+impl FromStr for DeviceSelector {
+  type Err = String;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    match value.trim() {
+      "" => Err("expected a device index or part of a device name".to_string()),
+      value => Ok(value.parse().map_or_else(|_| DeviceSelector::Name(value.to_string()), DeviceSelector::Index)),
+    }
+  }
+}
+
+impl fmt::Display for DeviceSelector {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      DeviceSelector::Index(index) => write!(f, "#{}", index),
+      DeviceSelector::Name(name) => write!(f, "\"{}\"", name),
+    }
+  }
+}
+
+// Every physical device of the instance, in enumeration order.
+pub fn enumerate(instance: &VkInstance) -> Vec<(vk::PhysicalDevice, DeviceCandidate)> {
+  enumerate_with(instance)
+}
+
+// The physical devices as seen by a throwaway instance, to explain why initialize() found no device
+// when there is no phobos instance to ask. Empty if Vulkan can't be loaded at all.
+pub fn probe_devices() -> Vec<DeviceCandidate> {
+  let Ok(entry) = (unsafe { ash::Entry::load() }) else {
+    return Vec::new();
+  };
+  let app_info = vk::ApplicationInfo::builder().api_version(vk::API_VERSION_1_2);
+  let create_info = vk::InstanceCreateInfo::builder().application_info(&app_info);
+  let Ok(instance) = (unsafe { entry.create_instance(&create_info, None) }) else {
+    return Vec::new();
+  };
+  let candidates = enumerate_with(&instance).into_iter().map(|(_, candidate)| candidate).collect();
+  unsafe { instance.destroy_instance(None) };
+  candidates
+}
+
+fn enumerate_with(instance: &ash::Instance) -> Vec<(vk::PhysicalDevice, DeviceCandidate)> {
+  let devices = unsafe { instance.enumerate_physical_devices() }.unwrap_or_default();
+  devices
+    .into_iter()
+    .map(|device| {
+      let properties = unsafe { instance.get_physical_device_properties(device) };
+      let memory = unsafe { instance.get_physical_device_memory_properties(device) };
+      let extensions = unsafe { instance.enumerate_device_extension_properties(device) }.unwrap_or_default();
+      let has_extension = |name: &CStr| extensions.iter().any(|extension| unsafe { CStr::from_ptr(extension.extension_name.as_ptr()) } == name);
+      let raytracing = has_extension(vk::KhrRayTracingPipelineFn::name());
+      let mut mesh_shader_features = vk::PhysicalDeviceMeshShaderFeaturesEXT::default();
+      if has_extension(vk::ExtMeshShaderFn::name()) {
+        let mut features = vk::PhysicalDeviceFeatures2::builder().push_next(&mut mesh_shader_features).build();
+        unsafe { instance.get_physical_device_features2(device, &mut features) };
+      }
+      let mesh_shader = mesh_shader_features.task_shader == vk::TRUE && mesh_shader_features.mesh_shader == vk::TRUE;
+      let mut ray_query_features = vk::PhysicalDeviceRayQueryFeaturesKHR::default();
+      if has_extension(vk::KhrRayQueryFn::name()) {
+        let mut features = vk::PhysicalDeviceFeatures2::builder().push_next(&mut ray_query_features).build();
+        unsafe { instance.get_physical_device_features2(device, &mut features) };
+      }
+      let ray_query = ray_query_features.ray_query == vk::TRUE;
+      let mut accel_properties = vk::PhysicalDeviceAccelerationStructurePropertiesKHR::default();
+      if has_extension(vk::KhrAccelerationStructureFn::name()) {
+        let mut properties = vk::PhysicalDeviceProperties2::builder().push_next(&mut accel_properties).build();
+        unsafe { instance.get_physical_device_properties2(device, &mut properties) };
+      }
+      let device_local_memory = memory.memory_heaps[..memory.memory_heap_count as usize]
+        .iter()
+        .filter(|heap| heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+        .map(|heap| heap.size)
+        .sum();
+      let candidate = DeviceCandidate {
+        name: unsafe { CStr::from_ptr(properties.device_name.as_ptr()) }.to_string_lossy().into_owned(),
+        device_type: properties.device_type,
+        vendor_id: properties.vendor_id,
+        device_id: properties.device_id,
+        device_local_memory,
+        raytracing,
+        mesh_shader,
+        ray_query,
+        accel_scratch_alignment: accel_properties.min_acceleration_structure_scratch_offset_alignment,
+        compute_limits: ComputeLimits {
+          max_workgroup_size: properties.limits.max_compute_work_group_size,
+          max_workgroup_invocations: properties.limits.max_compute_work_group_invocations,
+          max_workgroup_count: properties.limits.max_compute_work_group_count,
+        },
+        max_push_constants_size: properties.limits.max_push_constants_size,
+        driver_version: properties.driver_version,
+        api_version: properties.api_version,
+      };
+      (device, candidate)
+    })
+    .collect()
+}
+
+// The candidate entry of the device initialize() picked.
+pub fn describe(instance: &VkInstance, selected: &PhysicalDevice) -> Option<DeviceCandidate> {
+  let selected = unsafe { selected.handle() };
+  enumerate(instance).into_iter().find(|(device, _)| *device == selected).map(|(_, candidate)| candidate)
+}
+
+// What initialize_ranked returns, in the order of phobos' initialize(). The swapchain is left to the
+// caller, which picks its format from the surface's formats first.
+pub type Initialized = (VkInstance, PhysicalDevice, Option<Surface>, Device, DefaultAllocator, ExecutionManager, Option<DebugMessenger>);
+
+// The best ranked of `candidates`, None if there are none.
+pub fn choose(candidates: &[DeviceCandidate]) -> Option<usize> {
+  rank(candidates).first().copied()
+}
+
+// phobos' initialize(), except for the physical device: phobos takes the first device that meets
+// `settings`, in enumeration order, this takes the one `selector` picks from the instance's device list
+// or else the best ranked of those that also support `features`. Fails with OxError::Unsupported if
+// none does or the selected one doesn't, since device creation would fail without saying why.
+pub fn initialize_ranked<W: WindowInterface>(
+  settings: &AppSettings<W>,
+  headless: bool,
+  features: &DeviceFeatures,
+  selector: Option<&DeviceSelector>,
+  requirements: &DeviceRequirements,
+) -> Result<Initialized> {
+  let instance = VkInstance::new(settings)?;
+  let debug_messenger = if settings.enable_validation { Some(DebugMessenger::new(&instance)?) } else { None };
+  let mut surface = if headless { None } else { Some(Surface::new(&instance, settings)?) };
+  // Every device meeting the requirements, with the queues phobos assigned it.
+  let mut suitable = PhysicalDevice::enumerate(&instance, surface.as_ref(), settings)?;
+  let devices = enumerate(&instance);
+  let candidates = suitable
+    .iter()
+    .map(|device| {
+      let handle = unsafe { device.handle() };
+      devices.iter().find(|(other, _)| *other == handle).map(|(_, candidate)| candidate.clone())
+    })
+    .collect::<Option<Vec<_>>>()
+    .ok_or_else(|| anyhow!("phobos reported a physical device the instance doesn't list"))?;
+  let index = match selector {
+    // Indices and names as listed by --list-gpus, the same enumeration order.
+    Some(selector) => {
+      let listed = devices.iter().map(|(_, candidate)| candidate.clone()).collect::<Vec<_>>();
+      let wanted = selector.resolve(&listed, requirements)?;
+      info!("Selecting GPU {} for {}", listed[wanted].name, selector);
+      // Not an OxError, another queue configuration may still suit the device.
+      let index = suitable
+        .iter()
+        .position(|device| unsafe { device.handle() } == devices[wanted].0)
+        .ok_or_else(|| anyhow!("the selected GPU {} doesn't offer the requested queues", listed[wanted].name))?;
+      features.check(&instance, &suitable[index], &candidates[index].name)?;
+      index
+    }
+    None => {
+      let mut supported = Vec::new();
+      let mut unsupported = None;
+      for (index, device) in suitable.iter().enumerate() {
+        match features.check(&instance, device, &candidates[index].name) {
+          Ok(()) => supported.push(index),
+          Err(e) => {
+            unsupported.get_or_insert(e);
+          }
+        }
+      }
+      let ranked = supported.iter().map(|&index| candidates[index].clone()).collect::<Vec<_>>();
+      match (choose(&ranked), unsupported) {
+        (Some(index), _) => supported[index],
+        (None, Some(e)) => return Err(e.into()),
+        (None, None) => return Err(anyhow!("no physical device meets the requirements")),
+      }
+    }
+  };
+  let physical_device = suitable.swap_remove(index);
+  if let Some(surface) = surface.as_mut() {
+    surface.query_details(&physical_device)?;
+  }
+  let device = Device::new(&instance, &physical_device, settings)?;
+  let allocator = DefaultAllocator::new(&instance, &device, &physical_device)?;
+  let exec = ExecutionManager::new(device.clone(), &physical_device)?;
+  Ok((instance, physical_device, surface, device, allocator, exec, debug_messenger))
+}
+
+// Logs the scored device list, with the device initialize_ranked picked.
+pub fn log_device_ranking(instance: &VkInstance, selected: &PhysicalDevice) {
+  let devices = enumerate(instance);
+  let candidates = devices.iter().map(|(_, candidate)| candidate.clone()).collect::<Vec<_>>();
+  let order = rank(&candidates);
+  let selected = unsafe { selected.handle() };
+  for (place, &index) in order.iter().enumerate() {
+    let candidate = &candidates[index];
+    info!(
+      "GPU #{} (index {}): {} {:?}, {} MiB device local, raytracing {}, score {:?}{}",
+      place + 1,
+      index,
+      candidate.name,
+      candidate.device_type,
+      candidate.device_local_memory / (1024 * 1024),
+      candidate.raytracing,
+      score(candidate),
+      if devices[index].0 == selected { " [selected]" } else { "" }
+    );
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn candidate(name: &str, device_type: vk::PhysicalDeviceType, memory_mib: u64, raytracing: bool) -> DeviceCandidate {
+    DeviceCandidate {
+      name: name.to_string(),
+      device_type,
+      vendor_id: 0x10de,
+      device_id: 0,
+      device_local_memory: memory_mib * 1024 * 1024,
+      raytracing,
+      mesh_shader: false,
+      ray_query: false,
+      accel_scratch_alignment: 0,
+      compute_limits: ComputeLimits::default(),
+      max_push_constants_size: 128,
+      driver_version: 0,
+      api_version: vk::API_VERSION_1_3,
+    }
+  }
+
+  #[test]
+  fn raytracing_comes_first() {
+    let candidates = [
+      candidate("discrete", vk::PhysicalDeviceType::DISCRETE_GPU, 16384, false),
+      candidate("integrated", vk::PhysicalDeviceType::INTEGRATED_GPU, 2048, true),
+    ];
+    assert_eq!(choose(&candidates), Some(1));
+  }
+
+  #[test]
+  fn discrete_beats_integrated_and_cpu() {
+    let candidates = [
+      candidate("cpu", vk::PhysicalDeviceType::CPU, 32768, true),
+      candidate("integrated", vk::PhysicalDeviceType::INTEGRATED_GPU, 8192, true),
+      candidate("discrete", vk::PhysicalDeviceType::DISCRETE_GPU, 4096, true),
+    ];
+    assert_eq!(rank(&candidates), vec![2, 1, 0]);
+  }
+
+  #[test]
+  fn more_memory_wins_between_equal_types() {
+    let candidates = [
+      candidate("small", vk::PhysicalDeviceType::DISCRETE_GPU, 8192, true),
+      candidate("large", vk::PhysicalDeviceType::DISCRETE_GPU, 24576, true),
+    ];
+    assert_eq!(choose(&candidates), Some(1));
+  }
+
+  #[test]
+  fn ties_ignore_enumeration_order() {
+    let a = candidate("GPU A", vk::PhysicalDeviceType::DISCRETE_GPU, 8192, true);
+    let b = candidate("GPU B", vk::PhysicalDeviceType::DISCRETE_GPU, 8192, true);
+    let forward = [a.clone(), b.clone()];
+    let backward = [b, a];
+    assert_eq!(forward[choose(&forward).unwrap()].name, "GPU A");
+    assert_eq!(backward[choose(&backward).unwrap()].name, "GPU A");
+  }
+
+  #[test]
+  fn no_candidates_no_choice() {
+    assert_eq!(choose(&[]), None);
+  }
+}
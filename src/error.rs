@@ -0,0 +1,93 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+pub type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+// Errors of the runner and resource constructors, for callers that need to tell failures apart,
+// e.g. to fall back to another device or report a missing shader. Internals keep using anyhow and
+// are converted here, App implementations can keep returning anyhow::Result, OxError converts with `?`.
+#[derive(Debug, Error)]
+pub enum OxError {
+  #[error("failed to load shader {}", .path.display())]
+  ShaderLoad {
+    path: PathBuf,
+    #[source]
+    source: std::io::Error,
+  },
+  // Shaders are compiled by build.rs, at runtime this means the binary isn't valid SPIR-V.
+  #[error("invalid shader {}: {reason}", .path.display())]
+  ShaderCompile { path: PathBuf, reason: String },
+  #[error("failed to initialize {what}")]
+  DeviceInit {
+    what: String,
+    #[source]
+    source: Option<BoxError>,
+  },
+  #[error("failed to allocate {what}")]
+  Allocation {
+    what: String,
+    #[source]
+    source: BoxError,
+  },
+  #[error("failed to build acceleration structure {what}")]
+  AccelBuild {
+    what: String,
+    #[source]
+    source: BoxError,
+  },
+  // An optional device feature that wasn't requested or that the device lacks.
+  #[error("{what} is not supported: {reason}")]
+  Unsupported { what: String, reason: String },
+  #[error("failed to load scene {}", .path.display())]
+  SceneLoad {
+    path: PathBuf,
+    #[source]
+    source: BoxError,
+  },
+  #[error("failed to access {}", .path.display())]
+  Io {
+    path: PathBuf,
+    #[source]
+    source: std::io::Error,
+  },
+}
+
+pub type OxResult<T> = std::result::Result<T, OxError>;
+
+impl OxError {
+  pub fn device_init(what: impl Into<String>, source: impl Into<BoxError>) -> Self {
+    OxError::DeviceInit {
+      what: what.into(),
+      source: Some(source.into()),
+    }
+  }
+
+  pub fn allocation(what: impl Into<String>, source: impl Into<BoxError>) -> Self {
+    OxError::Allocation {
+      what: what.into(),
+      source: source.into(),
+    }
+  }
+
+  pub fn accel_build(what: impl Into<String>, source: impl Into<BoxError>) -> Self {
+    OxError::AccelBuild {
+      what: what.into(),
+      source: source.into(),
+    }
+  }
+
+  pub fn scene_load(path: impl Into<PathBuf>, source: impl Into<BoxError>) -> Self {
+    OxError::SceneLoad {
+      path: path.into(),
+      source: source.into(),
+    }
+  }
+
+  pub fn io(path: impl Into<PathBuf>, source: std::io::Error) -> Self {
+    OxError::Io {
+      path: path.into(),
+      source,
+    }
+  }
+}
@@ -0,0 +1,23 @@
+use std::future::Future;
+
+// Drives the future returned by FrameManager::new_frame to completion.
+//
+// The frame future borrows the window, the surface and the app, so it cannot be moved to
+// another thread and has to be completed before the event loop continues. The default
+// CurrentThread executor polls it on the event loop thread, which is what phobos expects.
+// Apps that want CPU-side work to overlap with the GPU can plug in their own executor with
+// Runner::with_executor, e.g. one that also drains a queue of jobs for the next frame while
+// waiting on the frame fence.
+pub trait FrameExecutor {
+  fn block_on<F: Future>(&mut self, future: F) -> F::Output;
+}
+
+// Polls the frame future on the calling thread, see futures::executor::block_on.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct CurrentThread;
+
+impl FrameExecutor for CurrentThread {
+  fn block_on<F: Future>(&mut self, future: F) -> F::Output {
+    futures::executor::block_on(future)
+  }
+}
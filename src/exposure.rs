@@ -0,0 +1,168 @@
+use anyhow::Result;
+use ash::vk;
+use bytemuck::{Pod, Zeroable};
+
+use phobos::prelude::*;
+
+use crate::app::Context;
+use crate::color::{OutputColorSpace, DEFAULT_PAPER_WHITE_NITS};
+use crate::compute::run_on_compute_queue;
+use crate::memory::{Buffer, Image};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Exposure {
+  // Fixed multiplier applied before display.
+  Manual(f32),
+  // Exposure derived from the log-average luminance of the HDR image, targeting
+  // middle gray shifted by `target_ev` stops. `adaptation_speed` is in 1/seconds,
+  // higher values adapt faster and 0 snaps to the target every frame.
+  Auto {
+    target_ev: f32,
+    adaptation_speed: f32,
+  },
+}
+
+impl Default for Exposure {
+  fn default() -> Self {
+    Exposure::Auto {
+      target_ev: 0.0,
+      adaptation_speed: 1.5,
+    }
+  }
+}
+
+impl Exposure {
+  // Auto exposure with the default adaptation speed, targeting middle gray shifted by `target_ev` stops.
+  pub fn auto(target_ev: f32) -> Self {
+    match Exposure::default() {
+      Exposure::Auto {
+        adaptation_speed,
+        ..
+      } => Exposure::Auto {
+        target_ev,
+        adaptation_speed,
+      },
+      manual => manual,
+    }
+  }
+}
+
+// Mirrors the push constants in auto_exposure.comp.
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+pub struct AutoExposurePushConstants {
+  pub target_ev: f32,
+  pub adaptation_speed: f32,
+  // Seconds since the previous frame, see FrameTime::dt.
+  pub dt: f32,
+}
+
+// Mirrors the push constants in flat_color.frag.
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+pub struct DisplayPushConstants {
+  pub exposure: f32,
+  pub auto_exposure: u32,
+  pub sample_count_view: u32,
+  pub max_samples: u32,
+  // See Bloom::display_intensity, 0 without bloom.
+  pub bloom_intensity: f32,
+  // OutputColorSpace::id of the target, with the brightness of 1.0 in nits for the HDR ones.
+  pub color_space: u32,
+  pub paper_white: f32,
+}
+
+// Mirrors the ExposureState buffer shared by auto_exposure.comp and flat_color.frag.
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct ExposureState {
+  log_avg_luminance: f32,
+  exposure: f32,
+}
+
+// Persistent state of the auto-exposure reduction. The exposure lives on the GPU only,
+// so the adapted value carries over between frames without any readback.
+pub struct AutoExposure {
+  pub mode: Exposure,
+  pub state: Buffer,
+}
+
+impl AutoExposure {
+  pub fn new(ctx: &mut Context, mode: Exposure) -> Result<Self> {
+    let state = Buffer::new(
+      ctx.device.clone(),
+      &mut ctx.allocator,
+      std::mem::size_of::<ExposureState>() as u64,
+      vk::BufferUsageFlags::STORAGE_BUFFER,
+      MemoryType::CpuToGpu,
+    )?;
+    state.view_full().mapped_slice::<ExposureState>()?.copy_from_slice(&[ExposureState {
+      log_avg_luminance: 0.0,
+      exposure: 1.0,
+    }]);
+    Ok(Self {
+      mode,
+      state,
+    })
+  }
+
+  // Returns the push constants for the reduction pass, or None when using manual exposure.
+  // The reduction adapts by `dt` seconds.
+  fn reduce_constants(&self, dt: f32) -> Option<AutoExposurePushConstants> {
+    match self.mode {
+      Exposure::Manual(_) => None,
+      Exposure::Auto {
+        target_ev,
+        adaptation_speed,
+      } => Some(AutoExposurePushConstants {
+        target_ev,
+        adaptation_speed,
+        dt,
+      }),
+    }
+  }
+
+  // Reduces `image`, the HDR image the graphics queue just traced into and waited for, on the compute
+  // queue and waits for the result, see run_on_compute_queue. Does nothing with manual exposure.
+  pub fn reduce(&self, ctx: &Context, image: &Image, view: &ImageView, dt: f32) -> Result<()> {
+    let Some(constants) = self.reduce_constants(dt) else {
+      return Ok(());
+    };
+    run_on_compute_queue(ctx, &[image], &[&self.state], |cmd| {
+      cmd
+        .bind_compute_pipeline("auto_exposure")?
+        .bind_storage_image(0, 0, view)?
+        .bind_storage_buffer(0, 1, &self.state.view_full())?
+        .push_constant(vk::ShaderStageFlags::COMPUTE, 0, &constants)
+        .dispatch(1, 1, 1)
+    })
+  }
+
+  pub fn display_constants(&self) -> DisplayPushConstants {
+    match self.mode {
+      Exposure::Manual(exposure) => DisplayPushConstants {
+        exposure,
+        auto_exposure: 0,
+        sample_count_view: 0,
+        max_samples: 0,
+        bloom_intensity: 0.0,
+        color_space: OutputColorSpace::SRGB,
+        paper_white: DEFAULT_PAPER_WHITE_NITS,
+      },
+      Exposure::Auto {
+        ..
+      } => DisplayPushConstants {
+        exposure: 1.0,
+        auto_exposure: 1,
+        sample_count_view: 0,
+        max_samples: 0,
+        bloom_intensity: 0.0,
+        color_space: OutputColorSpace::SRGB,
+        paper_white: DEFAULT_PAPER_WHITE_NITS,
+      },
+    }
+  }
+}
@@ -0,0 +1,232 @@
+use std::ffi::{c_void, CStr};
+
+use ash::vk;
+
+use phobos::prelude::*;
+
+use crate::error::{OxError, OxResult};
+
+// Device features and extensions requested at device creation, see RunnerConfig::features.
+// Features are named like the fields of the Vulkan feature structs, e.g. "shader_int64" from
+// VkPhysicalDeviceFeatures or "buffer_device_address" from VkPhysicalDeviceVulkan12Features, see
+// FeatureStructs::field for the ones the runner knows. Extensions use their Vulkan name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceFeatures {
+  pub features: Vec<String>,
+  pub extensions: Vec<String>,
+}
+
+impl Default for DeviceFeatures {
+  // What the tracer's shaders use on top of the ray tracing pipeline, which phobos enables itself,
+  // buffer_device_address included.
+  fn default() -> Self {
+    Self {
+      // BCn textures (every raytracing capable GPU has them, individual formats are still checked with
+      // FormatSupport) and 64 bit integers for the texture addresses in textures.glsl.
+      features: ["texture_compression_bc", "shader_int64"].map(String::from).to_vec(),
+      extensions: Vec::new(),
+    }
+  }
+}
+
+impl DeviceFeatures {
+  pub fn with_features(mut self, features: impl IntoIterator<Item = String>) -> Self {
+    for feature in features {
+      if !self.features.contains(&feature) {
+        self.features.push(feature);
+      }
+    }
+    self
+  }
+
+  pub fn with_extensions(mut self, extensions: impl IntoIterator<Item = String>) -> Self {
+    for extension in extensions {
+      if !self.extensions.contains(&extension) {
+        self.extensions.push(extension);
+      }
+    }
+    self
+  }
+
+  // The feature structs to pass to GPURequirements, fails on names the runner doesn't know.
+  pub fn requested(&self) -> OxResult<FeatureStructs> {
+    let mut structs = FeatureStructs::default();
+    let unknown = self.features.iter().filter(|name| structs.field(name).is_none()).cloned().collect::<Vec<_>>();
+    if !unknown.is_empty() {
+      return Err(OxError::Unsupported {
+        what: format!("device features {}", unknown.join(", ")),
+        reason: format!("unknown names, expected one of {}", FeatureStructs::NAMES.join(", ")),
+      });
+    }
+    for name in &self.features {
+      *structs.field(name).unwrap() = vk::TRUE;
+    }
+    Ok(structs)
+  }
+
+  // Fails with every requested feature and extension the device lacks. phobos doesn't check them when
+  // it lists the suitable devices, initialize_ranked calls this for each of them before creating one.
+  pub fn check(&self, instance: &VkInstance, physical_device: &PhysicalDevice, device_name: &str) -> OxResult<()> {
+    let instance: &ash::Instance = instance;
+    let physical_device = unsafe { physical_device.handle() };
+    let extensions = unsafe { instance.enumerate_device_extension_properties(physical_device) }.unwrap_or_default();
+    let has_extension = |name: &str| {
+      extensions
+        .iter()
+        .any(|extension| unsafe { CStr::from_ptr(extension.extension_name.as_ptr()) }.to_string_lossy() == name)
+    };
+    let mut supported = FeatureStructs::query(instance, physical_device, |name: &CStr| has_extension(&name.to_string_lossy()));
+    let mut missing = self
+      .features
+      .iter()
+      .filter(|name| supported.field(name).map_or(true, |value| *value != vk::TRUE))
+      .cloned()
+      .collect::<Vec<_>>();
+    missing.extend(self.extensions.iter().filter(|name| !has_extension(name)).cloned());
+    if missing.is_empty() {
+      return Ok(());
+    }
+    Err(OxError::Unsupported {
+      what: format!("device features {}", missing.join(", ")),
+      reason: format!("{} doesn't support them, remove them from RunnerConfig::features", device_name),
+    })
+  }
+}
+
+// The feature structs GPURequirements takes, as requested or as a device supports them, and the
+// extension feature structs chained behind them, see vulkan_13_chain. Features of
+// VkPhysicalDeviceVulkan11Features (16 bit storage) aren't among them.
+#[derive(Default, Copy, Clone)]
+pub struct FeatureStructs {
+  pub core: vk::PhysicalDeviceFeatures,
+  pub vulkan_12: vk::PhysicalDeviceVulkan12Features,
+  pub vulkan_13: vk::PhysicalDeviceVulkan13Features,
+  // Needs VK_EXT_mesh_shader.
+  pub mesh_shader: vk::PhysicalDeviceMeshShaderFeaturesEXT,
+  // Needs VK_KHR_ray_query.
+  pub ray_query: vk::PhysicalDeviceRayQueryFeaturesKHR,
+}
+
+impl FeatureStructs {
+  const NAMES: [&'static str; 33] = [
+    "geometry_shader",
+    "tessellation_shader",
+    "multi_draw_indirect",
+    "fill_mode_non_solid",
+    "wide_lines",
+    "sampler_anisotropy",
+    "texture_compression_bc",
+    "fragment_stores_and_atomics",
+    "shader_storage_image_read_without_format",
+    "shader_storage_image_write_without_format",
+    "shader_float64",
+    "shader_int64",
+    "shader_int16",
+    "draw_indirect_count",
+    "storage_buffer8_bit_access",
+    "shader_int8",
+    "shader_float16",
+    "descriptor_indexing",
+    "shader_sampled_image_array_non_uniform_indexing",
+    "shader_storage_buffer_array_non_uniform_indexing",
+    "descriptor_binding_partially_bound",
+    "descriptor_binding_variable_descriptor_count",
+    "runtime_descriptor_array",
+    "scalar_block_layout",
+    "timeline_semaphore",
+    "buffer_device_address",
+    "vulkan_memory_model",
+    "synchronization2",
+    "dynamic_rendering",
+    "maintenance4",
+    "task_shader",
+    "mesh_shader",
+    "ray_query",
+  ];
+
+  // Extension structs are only queried if the device has the extension, otherwise their features stay off.
+  pub fn query(instance: &ash::Instance, physical_device: vk::PhysicalDevice, has_extension: impl Fn(&CStr) -> bool) -> Self {
+    let mut vulkan_12 = vk::PhysicalDeviceVulkan12Features::default();
+    let mut vulkan_13 = vk::PhysicalDeviceVulkan13Features::default();
+    let mut mesh_shader = vk::PhysicalDeviceMeshShaderFeaturesEXT::default();
+    let mut ray_query = vk::PhysicalDeviceRayQueryFeaturesKHR::default();
+    let mut features = vk::PhysicalDeviceFeatures2::builder().push_next(&mut vulkan_12).push_next(&mut vulkan_13);
+    if has_extension(vk::ExtMeshShaderFn::name()) {
+      features = features.push_next(&mut mesh_shader);
+    }
+    if has_extension(vk::KhrRayQueryFn::name()) {
+      features = features.push_next(&mut ray_query);
+    }
+    let mut features = features.build();
+    unsafe { instance.get_physical_device_features2(physical_device, &mut features) };
+    let core = features.features;
+    // The chain points into the locals, only the flags are kept.
+    vulkan_12.p_next = std::ptr::null_mut();
+    vulkan_13.p_next = std::ptr::null_mut();
+    mesh_shader.p_next = std::ptr::null_mut();
+    ray_query.p_next = std::ptr::null_mut();
+    Self {
+      core,
+      vulkan_12,
+      vulkan_13,
+      mesh_shader,
+      ray_query,
+    }
+  }
+
+  // GPURequirements::features_1_3 with the extension structs that have a feature requested chained
+  // behind it. phobos adds the struct to its VkDeviceCreateInfo with ash's push_next, which keeps a
+  // chain hanging off the pushed struct, so the extension features are enabled at device creation
+  // along with the core ones. The result points into `self`, which has to stay in place until then.
+  pub fn vulkan_13_chain(&mut self) -> vk::PhysicalDeviceVulkan13Features {
+    let mut next: *mut c_void = std::ptr::null_mut();
+    if self.mesh_shader.task_shader == vk::TRUE || self.mesh_shader.mesh_shader == vk::TRUE {
+      self.mesh_shader.p_next = next;
+      next = <*mut vk::PhysicalDeviceMeshShaderFeaturesEXT>::cast(&mut self.mesh_shader);
+    }
+    if self.ray_query.ray_query == vk::TRUE {
+      self.ray_query.p_next = next;
+      next = <*mut vk::PhysicalDeviceRayQueryFeaturesKHR>::cast(&mut self.ray_query);
+    }
+    vk::PhysicalDeviceVulkan13Features { p_next: next, ..self.vulkan_13 }
+  }
+
+  fn field(&mut self, name: &str) -> Option<&mut vk::Bool32> {
+    Some(match name {
+      "geometry_shader" => &mut self.core.geometry_shader,
+      "tessellation_shader" => &mut self.core.tessellation_shader,
+      "multi_draw_indirect" => &mut self.core.multi_draw_indirect,
+      "fill_mode_non_solid" => &mut self.core.fill_mode_non_solid,
+      "wide_lines" => &mut self.core.wide_lines,
+      "sampler_anisotropy" => &mut self.core.sampler_anisotropy,
+      "texture_compression_bc" => &mut self.core.texture_compression_bc,
+      "fragment_stores_and_atomics" => &mut self.core.fragment_stores_and_atomics,
+      "shader_storage_image_read_without_format" => &mut self.core.shader_storage_image_read_without_format,
+      "shader_storage_image_write_without_format" => &mut self.core.shader_storage_image_write_without_format,
+      "shader_float64" => &mut self.core.shader_float64,
+      "shader_int64" => &mut self.core.shader_int64,
+      "shader_int16" => &mut self.core.shader_int16,
+      "draw_indirect_count" => &mut self.vulkan_12.draw_indirect_count,
+      "storage_buffer8_bit_access" => &mut self.vulkan_12.storage_buffer8_bit_access,
+      "shader_int8" => &mut self.vulkan_12.shader_int8,
+      "shader_float16" => &mut self.vulkan_12.shader_float16,
+      "descriptor_indexing" => &mut self.vulkan_12.descriptor_indexing,
+      "shader_sampled_image_array_non_uniform_indexing" => &mut self.vulkan_12.shader_sampled_image_array_non_uniform_indexing,
+      "shader_storage_buffer_array_non_uniform_indexing" => &mut self.vulkan_12.shader_storage_buffer_array_non_uniform_indexing,
+      "descriptor_binding_partially_bound" => &mut self.vulkan_12.descriptor_binding_partially_bound,
+      "descriptor_binding_variable_descriptor_count" => &mut self.vulkan_12.descriptor_binding_variable_descriptor_count,
+      "runtime_descriptor_array" => &mut self.vulkan_12.runtime_descriptor_array,
+      "scalar_block_layout" => &mut self.vulkan_12.scalar_block_layout,
+      "timeline_semaphore" => &mut self.vulkan_12.timeline_semaphore,
+      "buffer_device_address" => &mut self.vulkan_12.buffer_device_address,
+      "vulkan_memory_model" => &mut self.vulkan_12.vulkan_memory_model,
+      "synchronization2" => &mut self.vulkan_13.synchronization2,
+      "dynamic_rendering" => &mut self.vulkan_13.dynamic_rendering,
+      "maintenance4" => &mut self.vulkan_13.maintenance4,
+      "task_shader" => &mut self.mesh_shader.task_shader,
+      "mesh_shader" => &mut self.mesh_shader.mesh_shader,
+      "ray_query" => &mut self.ray_query.ray_query,
+      _ => return None,
+    })
+  }
+}
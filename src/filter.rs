@@ -0,0 +1,182 @@
+use std::fmt;
+use std::str::FromStr;
+
+// Pixel reconstruction filter. Instead of splatting every sample into its neighbours, which needs
+// atomic float adds on the accumulation image, the raygen shader importance samples the filter:
+// the sub-pixel offset is drawn from the filter's footprint and the sample is weighted by the
+// filter value over that density. Each pixel keeps its own running mean and converges to the same
+// filtered image a splatting renderer would, see sample_filter_axis in filter.glsl.
+//
+// Wider filters are softer: box with radius 0.5 is the plain per-pixel average, aliases the most
+// and keeps the most detail. Tent and Gaussian trade sharpness for smoother edges. Mitchell-Netravali
+// has the narrowest central lobe and stays sharper than both, with more noise since its samples are
+// spread uniformly and weighted. Its negative lobes are clamped to zero and the rest renormalized:
+// with negative weights a running mean can go below zero next to bright edges, which the exposure
+// reduction and tone mapping can't handle, and a splatting renderer would need a weight sum per
+// pixel to divide by.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ReconstructionFilter {
+  Box { radius: f32 },
+  Tent { radius: f32 },
+  // Truncated at the radius.
+  Gaussian { radius: f32, sigma: f32 },
+  Mitchell { radius: f32, b: f32, c: f32 },
+}
+
+impl Default for ReconstructionFilter {
+  // Matches the jitter of a single pixel, the renderer's behaviour before filters existed.
+  fn default() -> Self {
+    ReconstructionFilter::Box { radius: 0.5 }
+  }
+}
+
+impl ReconstructionFilter {
+  pub const BOX: u32 = 0;
+  pub const TENT: u32 = 1;
+  pub const GAUSSIAN: u32 = 2;
+  pub const MITCHELL: u32 = 3;
+
+  // Value of RenderParams::filter_kind.
+  pub fn id(&self) -> u32 {
+    match self {
+      ReconstructionFilter::Box { .. } => Self::BOX,
+      ReconstructionFilter::Tent { .. } => Self::TENT,
+      ReconstructionFilter::Gaussian { .. } => Self::GAUSSIAN,
+      ReconstructionFilter::Mitchell { .. } => Self::MITCHELL,
+    }
+  }
+
+  // Half width of the footprint in pixels, samples land up to this far from the pixel center.
+  pub fn radius(&self) -> f32 {
+    match *self {
+      ReconstructionFilter::Box { radius }
+      | ReconstructionFilter::Tent { radius }
+      | ReconstructionFilter::Gaussian { radius, .. }
+      | ReconstructionFilter::Mitchell { radius, .. } => radius,
+    }
+  }
+
+  // RenderParams::filter_parameters: sigma for Gaussian, B and C for Mitchell.
+  pub fn parameters(&self) -> [f32; 2] {
+    match *self {
+      ReconstructionFilter::Box { .. } | ReconstructionFilter::Tent { .. } => [0.0, 0.0],
+      ReconstructionFilter::Gaussian { sigma, .. } => [sigma, 0.0],
+      ReconstructionFilter::Mitchell { b, c, .. } => [b, c],
+    }
+  }
+
+  // RenderParams::filter_weight_scale, what the kernel's positive part is divided by so it integrates
+  // to 1 along each axis. Only Mitchell has negative lobes, elsewhere it's 1.
+  pub fn weight_scale(&self) -> f32 {
+    match *self {
+      ReconstructionFilter::Mitchell { b, c, .. } => mitchell_positive_integral(b, c),
+      _ => 1.0,
+    }
+  }
+}
+
+// Mitchell-Netravali kernel, "Reconstruction Filters in Computer Graphics" (1988).
+fn mitchell(x: f32, b: f32, c: f32) -> f32 {
+  let x = x.abs();
+  let value = if x < 1.0 {
+    (12.0 - 9.0 * b - 6.0 * c) * x * x * x + (-18.0 + 12.0 * b + 6.0 * c) * x * x + (6.0 - 2.0 * b)
+  } else if x < 2.0 {
+    (-b - 6.0 * c) * x * x * x + (6.0 * b + 30.0 * c) * x * x + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)
+  } else {
+    0.0
+  };
+  value / 6.0
+}
+
+// Integral of max(mitchell, 0) over [-2, 2], 1 for kernels without negative lobes (C = 0 and B >= 0).
+fn mitchell_positive_integral(b: f32, c: f32) -> f32 {
+  const STEPS: usize = 4096;
+  let step = 4.0 / STEPS as f32;
+  (0..STEPS).map(|i| mitchell(-2.0 + (i as f32 + 0.5) * step, b, c).max(0.0) * step).sum()
+}
+
+// kind[:key=value,...], e.g. tent, gaussian:radius=1.5,sigma=0.5 or mitchell:b=0.333,c=0.333.
+// Missing keys take the defaults below.
+impl FromStr for ReconstructionFilter {
+  type Err = String;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    let (kind, options) = value.split_once(':').unwrap_or((value, ""));
+    let mut radius = None;
+    let mut sigma = 0.5;
+    let mut b = 1.0 / 3.0;
+    let mut c = 1.0 / 3.0;
+    for option in options.split(',').filter(|option| !option.is_empty()) {
+      let (key, number) = option.split_once('=').ok_or_else(|| format!("expected key=value, got {}", option))?;
+      let number = number.trim().parse::<f32>().map_err(|e| format!("{}: {}", key, e))?;
+      match (kind, key.trim()) {
+        (_, "radius") => radius = Some(number),
+        ("gaussian", "sigma") => sigma = number,
+        ("mitchell", "b") => b = number,
+        ("mitchell", "c") => c = number,
+        (_, key) => return Err(format!("{} has no parameter {}", kind, key)),
+      }
+    }
+    let filter = match kind {
+      "box" => ReconstructionFilter::Box { radius: radius.unwrap_or(0.5) },
+      "tent" => ReconstructionFilter::Tent { radius: radius.unwrap_or(1.0) },
+      "gaussian" => ReconstructionFilter::Gaussian {
+        radius: radius.unwrap_or(1.5),
+        sigma,
+      },
+      "mitchell" => ReconstructionFilter::Mitchell {
+        radius: radius.unwrap_or(2.0),
+        b,
+        c,
+      },
+      _ => return Err("expected box, tent, gaussian or mitchell".to_string()),
+    };
+    if !(filter.radius() > 0.0 && filter.radius().is_finite()) {
+      return Err("radius must be positive".to_string());
+    }
+    if !(sigma > 0.0) {
+      return Err("sigma must be positive".to_string());
+    }
+    Ok(filter)
+  }
+}
+
+impl fmt::Display for ReconstructionFilter {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match *self {
+      ReconstructionFilter::Box { radius } => write!(f, "box:radius={}", radius),
+      ReconstructionFilter::Tent { radius } => write!(f, "tent:radius={}", radius),
+      ReconstructionFilter::Gaussian { radius, sigma } => write!(f, "gaussian:radius={},sigma={}", radius, sigma),
+      ReconstructionFilter::Mitchell { radius, b, c } => write!(f, "mitchell:radius={},b={},c={}", radius, b, c),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn mitchell_integrates_to_one() {
+    let step = 4.0 / 4096.0;
+    let integral = (0..4096).map(|i| mitchell(-2.0 + (i as f32 + 0.5) * step, 1.0 / 3.0, 1.0 / 3.0) * step).sum::<f32>();
+    assert!((integral - 1.0).abs() < 1.0e-4, "integral {}", integral);
+  }
+
+  #[test]
+  fn clamping_the_lobes_only_adds_weight() {
+    let filter = "mitchell".parse::<ReconstructionFilter>().unwrap();
+    assert!(filter.weight_scale() > 1.0);
+    // B = 1, C = 0 is the cubic B-spline, which has no negative lobes.
+    assert!((mitchell_positive_integral(1.0, 0.0) - 1.0).abs() < 1.0e-4);
+    assert_eq!(ReconstructionFilter::default().weight_scale(), 1.0);
+  }
+
+  #[test]
+  fn names_round_trip() {
+    for name in ["box:radius=0.5", "tent:radius=1", "gaussian:radius=1.5,sigma=0.5", "mitchell:radius=2,b=0,c=0.5"] {
+      let filter = name.parse::<ReconstructionFilter>().unwrap();
+      assert_eq!(filter.to_string().parse::<ReconstructionFilter>(), Ok(filter));
+    }
+  }
+}
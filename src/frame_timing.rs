@@ -0,0 +1,249 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use ash::vk;
+
+use phobos::prelude::*;
+use phobos::sync::domain::All;
+
+// Pairs of timestamps in the query pool, at least as many as frames in flight. A slot is written
+// again only after phobos waited for the frame that used it, so its results are ready by then and
+// reading them never stalls.
+const TIMER_SLOTS: usize = 3;
+
+// Frames FrameTimings averages over.
+pub const FRAME_TIME_WINDOW: usize = 60;
+
+// How often the runner logs the averages with RunnerConfig::log_frame_times.
+pub const FRAME_TIME_LOG_INTERVAL: Duration = Duration::from_secs(1);
+
+struct TimestampPool {
+  device: Device,
+  handle: vk::QueryPool,
+}
+
+impl Drop for TimestampPool {
+  fn drop(&mut self) {
+    unsafe { self.device.destroy_query_pool(self.handle, None) };
+  }
+}
+
+#[derive(Debug, Default)]
+struct TimerState {
+  next_slot: usize,
+  // Slot of the command buffer between begin and end.
+  recording: Option<usize>,
+  // Slots with both timestamps written by a submitted frame.
+  written: [bool; TIMER_SLOTS],
+  // Slot of the command buffer ended last.
+  ended: Option<usize>,
+  last_ms: Option<f32>,
+}
+
+// GPU time of the frame's command buffer, from timestamps written at its start and end. Shared by
+// all Contexts of a Runner like PixelDebug. Apps wrap the command buffer App::frame returns:
+//
+//   let cmd = ctx.gpu_timer.begin(cmd);
+//   let cmd = graph.record(cmd, &bindings, &mut ifc, None, &mut ())?;
+//   ctx.gpu_timer.end(cmd).finish()
+//
+// Results are read when the slot comes around again, so the reported time lags a few frames.
+// Devices whose graphics queue family has no timestamps never report a time. The slots are only
+// recycled safely for one swapchain, every window has a timer of its own, see for_window.
+#[derive(Clone)]
+pub struct GpuTimer {
+  device: Device,
+  pool: Option<Arc<TimestampPool>>,
+  // Nanoseconds per timestamp tick.
+  period: f32,
+  // timestampValidBits of the graphics queue family, the bits above are undefined.
+  valid_bits: u32,
+  state: Arc<Mutex<TimerState>>,
+}
+
+impl GpuTimer {
+  // `queue_family` is the family of the queue the timed command buffers are submitted to.
+  pub fn new(device: Device, instance: &VkInstance, physical_device: &PhysicalDevice, queue_family: u32) -> Result<Self> {
+    let handle = unsafe { physical_device.handle() };
+    let limits = unsafe { instance.get_physical_device_properties(handle) }.limits;
+    let families = unsafe { instance.get_physical_device_queue_family_properties(handle) };
+    let valid_bits = families.get(queue_family as usize).map_or(0, |family| family.timestamp_valid_bits);
+    Self::with_pool(device, limits.timestamp_period, valid_bits)
+  }
+
+  // A timer with slots of its own and the same properties, for another window.
+  pub fn for_window(&self) -> Result<Self> {
+    Self::with_pool(self.device.clone(), self.period, self.valid_bits)
+  }
+
+  fn with_pool(device: Device, period: f32, valid_bits: u32) -> Result<Self> {
+    let pool = if valid_bits > 0 {
+      let info = vk::QueryPoolCreateInfo::builder()
+        .query_type(vk::QueryType::TIMESTAMP)
+        .query_count(2 * TIMER_SLOTS as u32);
+      let handle = unsafe { device.create_query_pool(&info, None)? };
+      Some(Arc::new(TimestampPool {
+        device: device.clone(),
+        handle,
+      }))
+    } else {
+      None
+    };
+    Ok(Self {
+      device,
+      pool,
+      period,
+      valid_bits,
+      state: Arc::default(),
+    })
+  }
+
+  // Call on the fresh command buffer, before anything is recorded into it.
+  pub fn begin<'q>(&self, cmd: IncompleteCommandBuffer<'q, All>) -> IncompleteCommandBuffer<'q, All> {
+    let Some(pool) = &self.pool else {
+      return cmd;
+    };
+    let mut state = self.state.lock().unwrap();
+    let slot = state.next_slot;
+    state.next_slot = (slot + 1) % TIMER_SLOTS;
+    let first = 2 * slot as u32;
+    if let Some(ms) = self.read(pool, &mut state, slot) {
+      state.last_ms = Some(ms);
+    }
+    state.recording = Some(slot);
+    unsafe {
+      pool.device.cmd_reset_query_pool(cmd.handle(), pool.handle, first, 2);
+      pool.device.cmd_write_timestamp(cmd.handle(), vk::PipelineStageFlags::TOP_OF_PIPE, pool.handle, first);
+    }
+    cmd
+  }
+
+  // Call after the last command, right before finish.
+  pub fn end<'q>(&self, cmd: IncompleteCommandBuffer<'q, All>) -> IncompleteCommandBuffer<'q, All> {
+    let Some(pool) = &self.pool else {
+      return cmd;
+    };
+    let mut state = self.state.lock().unwrap();
+    if let Some(slot) = state.recording.take() {
+      unsafe { pool.device.cmd_write_timestamp(cmd.handle(), vk::PipelineStageFlags::BOTTOM_OF_PIPE, pool.handle, 2 * slot as u32 + 1) };
+      state.written[slot] = true;
+      state.ended = Some(slot);
+    }
+    cmd
+  }
+
+  // GPU time of the command buffer ended last, for callers that wait for every frame themselves like
+  // the benchmark. None without timestamps, or if it was read already.
+  pub fn read_ended(&self) -> Option<f32> {
+    let pool = self.pool.as_ref()?;
+    let mut state = self.state.lock().unwrap();
+    let slot = state.ended.take()?;
+    let ms = self.read(pool, &mut state, slot)?;
+    state.last_ms = Some(ms);
+    Some(ms)
+  }
+
+  // Reads the timestamps of `slot` once, None if it has none or they aren't ready.
+  fn read(&self, pool: &TimestampPool, state: &mut TimerState, slot: usize) -> Option<f32> {
+    if !state.written[slot] {
+      return None;
+    }
+    state.written[slot] = false;
+    let mut ticks = [0u64; 2];
+    // Without WAIT this fails with NOT_READY instead of blocking, the previous time is kept then.
+    unsafe { pool.device.get_query_pool_results(pool.handle, 2 * slot as u32, 2, &mut ticks, vk::QueryResultFlags::TYPE_64) }.ok()?;
+    Some(elapsed_ticks(ticks, self.valid_bits) as f32 * self.period / 1.0e6)
+  }
+
+  // GPU time of the most recent frame whose timestamps were read back, in milliseconds.
+  pub fn last_ms(&self) -> Option<f32> {
+    self.state.lock().unwrap().last_ms
+  }
+}
+
+// Ticks from the first to the second timestamp, counting with only the valid bits so a counter that
+// wrapped around between them still gives the right difference.
+fn elapsed_ticks(ticks: [u64; 2], valid_bits: u32) -> u64 {
+  let mask = if valid_bits >= 64 { u64::MAX } else { (1u64 << valid_bits) - 1 };
+  (ticks[1] & mask).wrapping_sub(ticks[0] & mask) & mask
+}
+
+#[derive(Debug, Copy, Clone)]
+struct FrameSample {
+  cpu_ms: f32,
+  gpu_ms: Option<f32>,
+  // Time since the previous frame started, None for the first frame after a stall.
+  interval: Option<Duration>,
+}
+
+// Averages of the last FRAME_TIME_WINDOW frames.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct FrameTiming {
+  // Time App::frame took to record and submit the frame.
+  pub cpu_ms: f32,
+  // None until the first timestamps are read back, and on devices without timestamps.
+  pub gpu_ms: Option<f32>,
+  pub fps: f32,
+}
+
+#[derive(Debug)]
+pub struct FrameTimings {
+  samples: VecDeque<FrameSample>,
+  last_frame: Option<Instant>,
+  last_log: Instant,
+}
+
+impl FrameTimings {
+  pub fn new() -> Self {
+    Self {
+      samples: VecDeque::with_capacity(FRAME_TIME_WINDOW),
+      last_frame: None,
+      last_log: Instant::now(),
+    }
+  }
+
+  // `started` is when the frame began, `cpu` how long App::frame ran.
+  pub fn record(&mut self, started: Instant, cpu: Duration, gpu_ms: Option<f32>) -> FrameTiming {
+    if self.samples.len() == FRAME_TIME_WINDOW {
+      self.samples.pop_front();
+    }
+    self.samples.push_back(FrameSample {
+      cpu_ms: cpu.as_secs_f32() * 1000.0,
+      gpu_ms,
+      interval: self.last_frame.map(|last| started.duration_since(last)),
+    });
+    self.last_frame = Some(started);
+    self.average()
+  }
+
+  pub fn average(&self) -> FrameTiming {
+    let count = self.samples.len().max(1) as f32;
+    let cpu_ms = self.samples.iter().map(|sample| sample.cpu_ms).sum::<f32>() / count;
+    let gpu: Vec<f32> = self.samples.iter().filter_map(|sample| sample.gpu_ms).collect();
+    let gpu_ms = (!gpu.is_empty()).then(|| gpu.iter().sum::<f32>() / gpu.len() as f32);
+    let intervals: Vec<Duration> = self.samples.iter().filter_map(|sample| sample.interval).collect();
+    let total: Duration = intervals.iter().sum();
+    let fps = if total.is_zero() { 0.0 } else { intervals.len() as f32 / total.as_secs_f32() };
+    FrameTiming {
+      cpu_ms,
+      gpu_ms,
+      fps,
+    }
+  }
+
+  // The gap up to the next frame doesn't count towards the frame rate, like Clock::skip_stall.
+  pub fn skip_stall(&mut self) {
+    self.last_frame = None;
+  }
+
+  // True at most once per FRAME_TIME_LOG_INTERVAL.
+  pub fn log_due(&mut self) -> bool {
+    if self.last_log.elapsed() < FRAME_TIME_LOG_INTERVAL {
+      return false;
+    }
+    self.last_log = Instant::now();
+    true
+  }
+}
@@ -0,0 +1,87 @@
+use anyhow::Result;
+use ash::vk;
+use bytemuck::{Pod, Zeroable};
+use glam::Vec3;
+
+use phobos::prelude::*;
+
+use crate::app::Context;
+use crate::memory::Buffer;
+
+// Mirrors Guide in guides.glsl (std430), one per pixel.
+//
+// Denoisers like SVGF steer their filter with the albedo and normal of the first hit, so the guides
+// have to be noise free. A single jittered sample isn't (edges, textures, normal maps alias), so the
+// guides are accumulated over frames, reprojected with the previous frame's view-projection:
+// - Reprojection assumes a static scene, only the camera moves. Moving objects smear.
+// - It goes through the current first hit, so disocclusions pick up the history of the surface that
+//   covered them before. There is no depth or normal rejection yet, the GUIDE_MAX_HISTORY cap only
+//   limits how long such errors last.
+// - Pixels that reproject off screen start over.
+//
+// Raygen also writes the motion of every primary hit into an RG16F image (motion_out), in pixels of
+// current minus previous position, for temporal passes that need to follow pixels themselves.
+//
+// The denoiser is expected to work on demodulated illumination, color / albedo (see demodulate in
+// guides.glsl), and to multiply the albedo back in afterwards, so that it never blurs textures.
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+pub struct Guide {
+  pub albedo: [f32; 3],
+  pub history: f32,
+  pub normal: [f32; 3],
+  pub depth: f32,
+}
+
+// Ping-pong pair of guide buffers, swapped every frame.
+pub struct GuideBuffers {
+  buffers: [Buffer; 2],
+  current: usize,
+  // Whether the buffer read this frame was written by a previous frame.
+  has_history: bool,
+}
+
+impl GuideBuffers {
+  pub fn new(ctx: &mut Context, width: u32, height: u32) -> Result<Self> {
+    let size = (width * height) as u64 * std::mem::size_of::<Guide>() as u64;
+    let mut make = || Buffer::new_device_local(ctx.device.clone(), &mut ctx.allocator, size, vk::BufferUsageFlags::STORAGE_BUFFER);
+    Ok(Self {
+      buffers: [make()?, make()?],
+      current: 0,
+      has_history: false,
+    })
+  }
+
+  // Last frame's guides, read this frame.
+  pub fn history(&self) -> &Buffer {
+    &self.buffers[1 - self.current]
+  }
+
+  // Guides written this frame.
+  pub fn current(&self) -> &Buffer {
+    &self.buffers[self.current]
+  }
+
+  pub fn has_history(&self) -> bool {
+    self.has_history
+  }
+
+  // Called once per frame after recording, this frame's guides become the history of the next.
+  pub fn swap(&mut self) {
+    self.current = 1 - self.current;
+    self.has_history = true;
+  }
+}
+
+// CPU mirror of demodulate in guides.glsl.
+#[allow(dead_code)]
+pub fn demodulate(color: Vec3, albedo: Vec3) -> Vec3 {
+  color / albedo.max(Vec3::splat(1.0e-3))
+}
+
+// CPU mirror of remodulate in guides.glsl, remodulate(demodulate(c, a), a) == c.
+#[allow(dead_code)]
+pub fn remodulate(illumination: Vec3, albedo: Vec3) -> Vec3 {
+  illumination * albedo.max(Vec3::splat(1.0e-3))
+}
@@ -0,0 +1,195 @@
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "hot-reload")]
+use log::{error, info};
+use log::warn;
+
+pub const SHADER_DIR: &str = "resources/shaders";
+
+// Extensions build.rs compiles, the binary of `name.<ext>` is `name_<ext>.spv`.
+const SHADER_EXTENSIONS: [&str; 12] = ["vert", "geom", "frag", "comp", "rgen", "rchit", "rahit", "rmiss", "rint", "rcall", "task", "mesh"];
+
+// The binary build.rs writes for a shader source, in its output directory, None for files that aren't
+// shaders like includes.
+pub fn spirv_path(source: &Path) -> Option<PathBuf> {
+  let extension = source.extension()?.to_str()?;
+  if !SHADER_EXTENSIONS.contains(&extension) {
+    return None;
+  }
+  let stem = source.file_stem()?.to_string_lossy();
+  Some(Path::new(env!("OX_BUILD_SHADER_DIR")).join(format!("{}_{}.spv", stem, extension)))
+}
+
+// Every shader source in SHADER_DIR, for a forced reload.
+pub fn all_sources() -> Vec<PathBuf> {
+  let Ok(entries) = std::fs::read_dir(SHADER_DIR) else {
+    warn!("Failed to list {}, no shaders to reload", SHADER_DIR);
+    return Vec::new();
+  };
+  let mut sources = entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).filter(|path| spirv_path(path).is_some()).collect::<Vec<_>>();
+  sources.sort();
+  sources
+}
+
+// Watches SHADER_DIR for edited sources with notify, see RunnerConfig::hot_reload_shaders. Needs the
+// hot-reload feature.
+pub struct ShaderWatcher {
+  #[cfg(feature = "hot-reload")]
+  _watcher: notify::RecommendedWatcher,
+  #[cfg(feature = "hot-reload")]
+  events: std::sync::mpsc::Receiver<notify::Result<notify::Event>>,
+}
+
+impl ShaderWatcher {
+  // None without the hot-reload feature or if the directory can't be watched, with a warning.
+  pub fn new() -> Option<Self> {
+    #[cfg(feature = "hot-reload")]
+    {
+      use notify::Watcher;
+
+      let (sender, events) = std::sync::mpsc::channel();
+      let watcher = notify::recommended_watcher(sender).and_then(|mut watcher| {
+        watcher.watch(Path::new(SHADER_DIR), notify::RecursiveMode::NonRecursive)?;
+        Ok(watcher)
+      });
+      match watcher {
+        Ok(watcher) => {
+          info!("Watching {} for shader changes", SHADER_DIR);
+          Some(Self {
+            _watcher: watcher,
+            events,
+          })
+        }
+        Err(e) => {
+          warn!("Failed to watch {}, shaders won't reload: {}", SHADER_DIR, e);
+          None
+        }
+      }
+    }
+    #[cfg(not(feature = "hot-reload"))]
+    {
+      warn!("Built without the hot-reload feature, shaders won't reload when they change");
+      None
+    }
+  }
+
+  // Shader sources written since the last call. An edited .glsl include reloads every shader, which
+  // shaders include it isn't tracked at runtime.
+  pub fn changed(&self) -> Vec<PathBuf> {
+    #[cfg(feature = "hot-reload")]
+    {
+      let mut changed = Vec::new();
+      let mut all = false;
+      for event in self.events.try_iter() {
+        let event = match event {
+          Ok(event) => event,
+          Err(e) => {
+            warn!("Shader watcher error: {}", e);
+            continue;
+          }
+        };
+        if !matches!(event.kind, notify::EventKind::Create(_) | notify::EventKind::Modify(_)) {
+          continue;
+        }
+        for path in event.paths {
+          // Editors write backup and swap files next to the sources.
+          match path.extension().and_then(|extension| extension.to_str()) {
+            Some("glsl") => all = true,
+            _ if spirv_path(&path).is_some() => changed.push(path),
+            _ => {}
+          }
+        }
+      }
+      if all {
+        return all_sources();
+      }
+      changed.sort();
+      changed.dedup();
+      changed
+    }
+    #[cfg(not(feature = "hot-reload"))]
+    Vec::new()
+  }
+}
+
+// Recompiles `sources` over the binaries build.rs wrote and returns the binaries that were replaced.
+// Nothing is written unless every shader compiles, so the pipelines never mix new and old stages; the
+// diagnostics of the failed ones are logged and the list is empty.
+pub fn recompile(sources: &[PathBuf]) -> Vec<PathBuf> {
+  #[cfg(feature = "hot-reload")]
+  {
+    let mut compiled = Vec::new();
+    let mut failed = 0;
+    for source in sources {
+      let Some(output) = spirv_path(source) else {
+        continue;
+      };
+      match compile::compile(source) {
+        Ok(binary) => compiled.push((source, output, binary)),
+        Err(e) => {
+          error!("Failed to compile {}:\n{}", source.display(), e);
+          failed += 1;
+        }
+      }
+    }
+    if failed > 0 {
+      error!("{} of {} shaders failed to compile, keeping the previous versions of all of them", failed, sources.len());
+      return Vec::new();
+    }
+    // Written next to the binaries first and renamed over them once all are, so a full disk doesn't
+    // replace only some. The directory is missing if build.rs skipped the shaders and prebuilt ones
+    // are used so far.
+    let temporary = |output: &Path| PathBuf::from(format!("{}.tmp", output.display()));
+    let written = compiled.iter().try_for_each(|(_, output, binary)| {
+      std::fs::create_dir_all(output.parent().unwrap()).and_then(|()| std::fs::write(temporary(output), binary)).map_err(|e| format!("failed to write {}: {}", output.display(), e))
+    });
+    let written = written.and_then(|()| {
+      compiled.iter().try_for_each(|(_, output, _)| std::fs::rename(temporary(output), output).map_err(|e| format!("failed to replace {}: {}", output.display(), e)))
+    });
+    if let Err(e) = written {
+      for (_, output, _) in &compiled {
+        let _ = std::fs::remove_file(temporary(output));
+      }
+      error!("Failed to replace the shader binaries, not reloading them: {}", e);
+      return Vec::new();
+    }
+    for (source, _, _) in &compiled {
+      info!("Recompiled {}", source.display());
+    }
+    compiled.into_iter().map(|(_, output, _)| output).collect()
+  }
+  #[cfg(not(feature = "hot-reload"))]
+  {
+    warn!("Built without the hot-reload feature, not recompiling {} shaders", sources.len());
+    Vec::new()
+  }
+}
+
+#[cfg(feature = "hot-reload")]
+mod compile {
+  use std::path::{Path, PathBuf};
+
+  use shaderc::{CompileOptions, IncludeCallbackResult};
+
+  use super::SHADER_DIR;
+
+  include!("shader_options.rs");
+
+  // The SPIR-V of the shader at `source`, or shaderc's diagnostics. Compiled with the options build.rs
+  // used and includes resolved the same way, see shader_options.rs.
+  pub fn compile(source: &Path) -> Result<Vec<u8>, String> {
+    let kind = source.extension().and_then(|extension| shader_kind(&extension.to_string_lossy())).ok_or_else(|| format!("{} is not a shader", source.display()))?;
+    let shader_options = ShaderOptions::from_names(env!("OX_BUILD_SHADER_OPT"), env!("OX_BUILD_SHADER_SPIRV")).ok_or("build.rs exported unknown shader options")?;
+    let compiler = shaderc::Compiler::new().ok_or("failed to create the shaderc compiler")?;
+    let mut options = CompileOptions::new().ok_or("failed to create the shaderc compile options")?;
+    shader_options.apply(&mut options);
+    let mut include_dirs = vec![PathBuf::from(SHADER_DIR)];
+    include_dirs.extend(std::env::split_paths(env!("OX_BUILD_SHADER_INCLUDE_DIRS")).filter(|dir| !dir.as_os_str().is_empty()));
+    options.set_include_callback(move |name, _include_type, containing, _depth| -> IncludeCallbackResult {
+      resolve_include(name, containing, &include_dirs).map(|(_, resolved)| resolved)
+    });
+    let text = std::fs::read_to_string(source).map_err(|e| format!("failed to read {}: {}", source.display(), e))?;
+    let binary = compiler.compile_into_spirv(&text, kind, &source.to_string_lossy(), "main", Some(&options)).map_err(|e| e.to_string())?;
+    Ok(binary.as_binary_u8().to_vec())
+  }
+}
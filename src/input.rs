@@ -0,0 +1,92 @@
+use std::collections::HashSet;
+
+use glam::Vec2;
+use winit::dpi::PhysicalPosition;
+use winit::event::{ElementState, KeyboardInput, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent};
+
+// Pixel scroll deltas (touchpads) are converted to lines with this many pixels per line.
+const PIXELS_PER_LINE: f64 = 20.0;
+
+// Keyboard and mouse state of the window, gathered by the runner from winit events and passed to
+// App::frame. Held keys and buttons persist across frames, the deltas cover the time since the
+// previous frame.
+#[derive(Debug, Clone, Default)]
+pub struct InputState {
+  pub keys_held: HashSet<VirtualKeyCode>,
+  pub buttons_held: HashSet<MouseButton>,
+  // Raw mouse motion, not limited by the window border or the cursor position.
+  pub mouse_delta: Vec2,
+  // Wheel movement in lines, positive away from the user.
+  pub scroll: f32,
+  // Cursor position in window pixels, None while it is outside the window.
+  pub cursor: Option<PhysicalPosition<f64>>,
+}
+
+impl InputState {
+  #[allow(dead_code)]
+  pub fn is_key_held(&self, key: VirtualKeyCode) -> bool {
+    self.keys_held.contains(&key)
+  }
+
+  #[allow(dead_code)]
+  pub fn is_button_held(&self, button: MouseButton) -> bool {
+    self.buttons_held.contains(&button)
+  }
+
+  // Whether the app may react to input this frame, so the runner keeps rendering even when the
+  // image is converged.
+  pub fn is_active(&self) -> bool {
+    !self.keys_held.is_empty() || !self.buttons_held.is_empty() || self.mouse_delta != Vec2::ZERO || self.scroll != 0.0
+  }
+
+  // Every event of the window goes through here, including the ones the runner handles itself like F5.
+  pub fn window_event(&mut self, event: &WindowEvent) {
+    match *event {
+      WindowEvent::KeyboardInput {
+        input:
+          KeyboardInput {
+            state,
+            virtual_keycode: Some(key),
+            ..
+          },
+        ..
+      } => {
+        match state {
+          ElementState::Pressed => self.keys_held.insert(key),
+          ElementState::Released => self.keys_held.remove(&key),
+        };
+      }
+      WindowEvent::MouseInput { state, button, .. } => {
+        match state {
+          ElementState::Pressed => self.buttons_held.insert(button),
+          ElementState::Released => self.buttons_held.remove(&button),
+        };
+      }
+      WindowEvent::MouseWheel { delta, .. } => {
+        self.scroll += match delta {
+          MouseScrollDelta::LineDelta(_, lines) => lines,
+          MouseScrollDelta::PixelDelta(position) => (position.y / PIXELS_PER_LINE) as f32,
+        };
+      }
+      WindowEvent::CursorMoved { position, .. } => self.cursor = Some(position),
+      WindowEvent::CursorLeft { .. } => self.cursor = None,
+      // The window doesn't get the release events once it lost focus.
+      WindowEvent::Focused(false) => {
+        self.keys_held.clear();
+        self.buttons_held.clear();
+      }
+      _ => {}
+    }
+  }
+
+  // DeviceEvent::MouseMotion, only passed on while the window has focus.
+  pub fn mouse_motion(&mut self, delta: (f64, f64)) {
+    self.mouse_delta += Vec2::new(delta.0 as f32, delta.1 as f32);
+  }
+
+  // Called after every frame, the next one only sees motion that happened after this one.
+  pub fn end_frame(&mut self) {
+    self.mouse_delta = Vec2::ZERO;
+    self.scroll = 0.0;
+  }
+}
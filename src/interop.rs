@@ -0,0 +1,161 @@
+use anyhow::{anyhow, ensure, Result};
+use ash::vk;
+
+use phobos::prelude::*;
+
+use crate::memory::Image;
+
+// Imported images are always this format, the tone-map pass writes display-referred sRGB like it
+// does for the swapchain. Create the texture on the other side to match, e.g. GL_SRGB8_ALPHA8 or
+// DXGI_FORMAT_R8G8B8A8_UNORM_SRGB.
+pub const EXTERNAL_IMAGE_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+// Memory exported by another API, see Runner::render_to_external.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ExternalHandle {
+  // From glExportMemory / vkGetMemoryFdKHR. Ownership passes to Vulkan once the import succeeds,
+  // don't close the fd afterwards.
+  #[cfg(unix)]
+  OpaqueFd(std::os::unix::io::RawFd),
+  // A shared NT handle, e.g. from IDXGIResource1::CreateSharedHandle. Stays owned by the caller.
+  #[cfg(windows)]
+  OpaqueWin32(vk::HANDLE),
+}
+
+impl ExternalHandle {
+  fn handle_type(&self) -> vk::ExternalMemoryHandleTypeFlags {
+    match self {
+      #[cfg(unix)]
+      ExternalHandle::OpaqueFd(_) => vk::ExternalMemoryHandleTypeFlags::OPAQUE_FD,
+      #[cfg(windows)]
+      ExternalHandle::OpaqueWin32(_) => vk::ExternalMemoryHandleTypeFlags::OPAQUE_WIN32,
+    }
+  }
+}
+
+// Device extensions for importing memory on this platform, requested when RunnerConfig::external_memory is set.
+pub fn external_memory_extensions() -> Vec<String> {
+  let platform = if cfg!(windows) {
+    vk::KhrExternalMemoryWin32Fn::name()
+  } else {
+    vk::KhrExternalMemoryFdFn::name()
+  };
+  [vk::KhrExternalMemoryFn::name(), platform]
+    .iter()
+    .map(|name| name.to_string_lossy().into_owned())
+    .collect()
+}
+
+// A color attachment backed by imported memory. The Vulkan image and memory are owned here, the
+// exporting API keeps its own reference to the memory.
+pub struct ExternalImage {
+  device: Device,
+  // Options so Drop can release the view and the phobos wrapper before destroying the image.
+  view: Option<ImageView>,
+  image: Option<Image>,
+  handle: vk::Image,
+  memory: vk::DeviceMemory,
+}
+
+impl ExternalImage {
+  pub fn import(device: &Device, instance: &VkInstance, physical_device: vk::PhysicalDevice, handle: ExternalHandle, extent: vk::Extent2D) -> Result<Self> {
+    ensure!(extent.width > 0 && extent.height > 0, "external image extent must not be empty");
+    let handle_type = handle.handle_type();
+    let mut external_info = vk::ExternalMemoryImageCreateInfo::builder().handle_types(handle_type);
+    let image_info = vk::ImageCreateInfo::builder()
+      .push_next(&mut external_info)
+      .image_type(vk::ImageType::TYPE_2D)
+      .format(EXTERNAL_IMAGE_FORMAT)
+      .extent(vk::Extent3D {
+        width: extent.width,
+        height: extent.height,
+        depth: 1,
+      })
+      .mip_levels(1)
+      .array_layers(1)
+      .samples(vk::SampleCountFlags::TYPE_1)
+      .tiling(vk::ImageTiling::OPTIMAL)
+      .usage(vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED)
+      .sharing_mode(vk::SharingMode::EXCLUSIVE)
+      .initial_layout(vk::ImageLayout::UNDEFINED);
+    let image = unsafe { device.create_image(&image_info, None)? };
+
+    let memory = match Self::import_memory(device, instance, physical_device, image, handle) {
+      Ok(memory) => memory,
+      Err(e) => {
+        unsafe { device.destroy_image(image, None) };
+        return Err(e);
+      }
+    };
+
+    let wrapped = Image::new_managed(device.clone(), image, EXTERNAL_IMAGE_FORMAT, vk::Extent3D {
+      width: extent.width,
+      height: extent.height,
+      depth: 1,
+    }, 1, 1, vk::SampleCountFlags::TYPE_1);
+    let view = wrapped.view(vk::ImageAspectFlags::COLOR)?;
+    Ok(Self {
+      device: device.clone(),
+      view: Some(view),
+      image: Some(wrapped),
+      handle: image,
+      memory,
+    })
+  }
+
+  // Exported images must get a dedicated allocation, the memory belongs to exactly this image.
+  fn import_memory(device: &Device, instance: &VkInstance, physical_device: vk::PhysicalDevice, image: vk::Image, handle: ExternalHandle) -> Result<vk::DeviceMemory> {
+    let requirements = unsafe { device.get_image_memory_requirements(image) };
+    let properties = unsafe { instance.get_physical_device_memory_properties(physical_device) };
+    let memory_type = (0..properties.memory_type_count)
+      .find(|&index| {
+        requirements.memory_type_bits & (1 << index) != 0
+          && properties.memory_types[index as usize].property_flags.contains(vk::MemoryPropertyFlags::DEVICE_LOCAL)
+      })
+      .ok_or_else(|| anyhow!("no device local memory type can hold the external image"))?;
+
+    let mut dedicated = vk::MemoryDedicatedAllocateInfo::builder().image(image);
+    let memory = match handle {
+      #[cfg(unix)]
+      ExternalHandle::OpaqueFd(fd) => {
+        let mut import = vk::ImportMemoryFdInfoKHR::builder().handle_type(handle.handle_type()).fd(fd);
+        let info = vk::MemoryAllocateInfo::builder()
+          .allocation_size(requirements.size)
+          .memory_type_index(memory_type)
+          .push_next(&mut dedicated)
+          .push_next(&mut import);
+        unsafe { device.allocate_memory(&info, None)? }
+      }
+      #[cfg(windows)]
+      ExternalHandle::OpaqueWin32(win32) => {
+        let mut import = vk::ImportMemoryWin32HandleInfoKHR::builder().handle_type(handle.handle_type()).handle(win32);
+        let info = vk::MemoryAllocateInfo::builder()
+          .allocation_size(requirements.size)
+          .memory_type_index(memory_type)
+          .push_next(&mut dedicated)
+          .push_next(&mut import);
+        unsafe { device.allocate_memory(&info, None)? }
+      }
+    };
+    if let Err(e) = unsafe { device.bind_image_memory(image, memory, 0) } {
+      unsafe { device.free_memory(memory, None) };
+      return Err(e.into());
+    }
+    Ok(memory)
+  }
+
+  pub fn view(&self) -> &ImageView {
+    self.view.as_ref().unwrap()
+  }
+}
+
+impl Drop for ExternalImage {
+  fn drop(&mut self) {
+    self.view.take();
+    self.image.take();
+    unsafe {
+      self.device.destroy_image(self.handle, None);
+      self.device.free_memory(self.memory, None);
+    }
+  }
+}
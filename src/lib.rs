@@ -0,0 +1,48 @@
+pub mod accel;
+pub mod accumulation;
+pub mod adaptive;
+pub mod app;
+pub mod bench;
+pub mod bloom;
+pub mod blue_noise;
+pub mod bounds;
+pub mod bsdf;
+pub mod camera;
+#[cfg(feature = "cli")]
+pub mod cli;
+pub mod color;
+pub mod compositing;
+pub mod compute;
+pub mod device;
+pub mod error;
+pub mod executor;
+pub mod exposure;
+pub mod features;
+pub mod filter;
+pub mod frame_timing;
+pub mod guides;
+pub mod hot_reload;
+pub mod input;
+pub mod interop;
+pub mod light;
+pub mod material;
+pub mod memory;
+pub mod mesh;
+pub mod mesh_shading;
+pub mod output;
+pub mod pixel_debug;
+pub mod present;
+pub mod preview;
+pub mod ray_query;
+pub mod render_mode;
+pub mod rng;
+pub mod sampling;
+pub mod scene;
+pub mod spectral;
+pub mod texture;
+pub mod thread;
+pub mod time;
+pub mod tonemap;
+pub mod tracer;
+pub mod upload;
+pub mod validation;
@@ -0,0 +1,256 @@
+use std::f32::consts::PI;
+
+use anyhow::{anyhow, Result};
+use ash::vk;
+use bytemuck::{Pod, Zeroable};
+use glam::{Vec2, Vec3};
+
+use phobos::prelude::*;
+use phobos::sync::domain::All;
+
+use crate::app::Context;
+use crate::error::{OxError, OxResult};
+use crate::material::Material;
+use crate::memory::Buffer;
+use crate::mesh::GeometryGroup;
+use crate::upload::Uploader;
+
+// Mirrors the Light struct in light.glsl (std430).
+//
+// Point and area lights are not part of the acceleration structure, only next event estimation
+// sees them: every diffuse hit sends one shadow ray to a random point on a light picked in
+// proportion to its power. Camera and bounce rays never hit them, so they don't show up in the
+// image, in mirrors or through glass. Triangle lights are the emissive triangles of the scene's
+// meshes, see emissive_triangles. Bounces do hit those. After camera rays and specular bounces, which
+// next event estimation can't reproduce, their emission counts in full, after diffuse bounces it is
+// weighted by multiple importance sampling against the chance of a shadow ray finding the same point.
+//
+// Sampling a new point on the light for every shadow ray is what makes the shadows soft, the
+// accumulated samples blend into a penumbra. Its width grows with the size of the light and with
+// the distance from occluder to receiver relative to the distance from light to occluder, roughly
+// size * (receiver - occluder) / (occluder - light) along the ray. A point light with radius 0 is a
+// true point and casts hard shadows.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+pub struct Light {
+  // Center of a point light, one corner of an area light, the first vertex of a triangle.
+  pub position: [f32; 3],
+  pub kind: u32,
+  // Radiant intensity of a point light, emitted radiance of an area light. Triangles multiply it
+  // by their emissive texture.
+  pub emission: [f32; 3],
+  // Sphere radius of a point light, 0 for a hard point light. Area and triangle lights ignore it.
+  pub radius: f32,
+  // Sides of an area light starting at position. It emits from one side only, towards edge_u x edge_v.
+  // A triangle's other vertices are position + edge_u and position + edge_v.
+  pub edge_u: [f32; 3],
+  // Chance of picking this light or any before it, filled in by LightTable::new.
+  pub cdf: f32,
+  pub edge_v: [f32; 3],
+  // Emissive texture of a triangle, -1 for none.
+  pub texture: i32,
+  // Vertex uvs of a triangle.
+  pub uvs: [[f32; 2]; 3],
+  // Non-zero if a triangle emits from both sides.
+  pub two_sided: u32,
+  pub _pad: u32,
+}
+
+#[allow(dead_code)]
+impl Light {
+  pub const POINT: u32 = 0;
+  pub const AREA: u32 = 1;
+  pub const TRIANGLE: u32 = 2;
+
+  // A sphere of `radius` that emits `intensity` in every direction, the same as a point light
+  // seen from outside of it. Radius 0 gives a point light with hard shadows.
+  pub fn point(position: [f32; 3], intensity: [f32; 3], radius: f32) -> Self {
+    Self {
+      position,
+      kind: Self::POINT,
+      emission: intensity,
+      radius: radius.max(0.0),
+      ..Zeroable::zeroed()
+    }
+  }
+
+  // A parallelogram with corner `corner` and sides `edge_u` and `edge_v`, emitting `radiance`.
+  pub fn area(corner: [f32; 3], edge_u: [f32; 3], edge_v: [f32; 3], radiance: [f32; 3]) -> Self {
+    Self {
+      position: corner,
+      kind: Self::AREA,
+      emission: radiance,
+      edge_u,
+      edge_v,
+      ..Zeroable::zeroed()
+    }
+  }
+
+  // A triangle with vertices `p` emitting `emission`, times `texture` at the interpolated `uvs` if
+  // the texture isn't -1. Emits towards (p1 - p0) x (p2 - p0), or from both sides if `two_sided`.
+  pub fn triangle(p: [Vec3; 3], uvs: [[f32; 2]; 3], emission: [f32; 3], texture: i32, two_sided: bool) -> Self {
+    Self {
+      position: p[0].to_array(),
+      kind: Self::TRIANGLE,
+      emission,
+      edge_u: (p[1] - p[0]).to_array(),
+      edge_v: (p[2] - p[0]).to_array(),
+      texture,
+      uvs,
+      two_sided: two_sided as u32,
+      ..Zeroable::zeroed()
+    }
+  }
+
+  // Total emitted luminance, what light selection is proportional to. `average` is the mean color
+  // of a triangle's emissive texture over its uvs, see texture_average.
+  pub fn power(&self, average: [f32; 3]) -> f32 {
+    let emission = luminance(self.emission);
+    let area = |u: [f32; 3], v: [f32; 3]| Vec3::from(u).cross(Vec3::from(v)).length();
+    match self.kind {
+      Self::POINT => 4.0 * PI * emission,
+      Self::AREA => PI * emission * area(self.edge_u, self.edge_v),
+      _ => {
+        let sides = if self.two_sided != 0 { 2.0 } else { 1.0 };
+        sides * PI * luminance(std::array::from_fn(|i| self.emission[i] * average[i])) * 0.5 * area(self.edge_u, self.edge_v)
+      }
+    }
+  }
+}
+
+fn luminance(color: [f32; 3]) -> f32 {
+  0.2126 * color[0] + 0.7152 * color[1] + 0.0722 * color[2]
+}
+
+// Mirrors srgb_to_linear in textures.glsl.
+fn srgb_to_linear(c: f32) -> f32 {
+  if c > 0.04045 {
+    ((c + 0.055) / 1.055).powf(2.4)
+  } else {
+    c / 12.92
+  }
+}
+
+// Mirrors texel_fetch in textures.glsl with sRGB decoding, emissive textures are color textures.
+fn texel(image: &image::RgbaImage, x: i64, y: i64) -> Vec3 {
+  let (width, height) = (image.width() as i64, image.height() as i64);
+  let p = image.get_pixel(x.rem_euclid(width) as u32, y.rem_euclid(height) as u32);
+  Vec3::new(srgb_to_linear(p[0] as f32 / 255.0), srgb_to_linear(p[1] as f32 / 255.0), srgb_to_linear(p[2] as f32 / 255.0))
+}
+
+// Mirrors texture_sample_srgb in textures.glsl.
+fn texture_sample(image: &image::RgbaImage, uv: Vec2) -> Vec3 {
+  let p = uv * Vec2::new(image.width() as f32, image.height() as f32) - 0.5;
+  let p0 = p.floor();
+  let f = p - p0;
+  let (x, y) = (p0.x as i64, p0.y as i64);
+  let top = texel(image, x, y).lerp(texel(image, x + 1, y), f.x);
+  let bottom = texel(image, x, y + 1).lerp(texel(image, x + 1, y + 1), f.x);
+  top.lerp(bottom, f.y)
+}
+
+// Strata per side of the barycentric grid texture_average samples the triangle with.
+const AVERAGE_STRATA: u32 = 16;
+
+// Mean color of `image` over the triangle `uvs` spans, weighted by area on the triangle. Light
+// selection uses it to estimate how bright a textured emitter is overall, the shadow rays still
+// see the texture at the sampled point. Estimated from the centroids of a regular subdivision into
+// AVERAGE_STRATA^2 triangles, which is close enough for picking lights.
+pub fn texture_average(image: &image::RgbaImage, uvs: [[f32; 2]; 3]) -> [f32; 3] {
+  let [a, b, c] = uvs.map(Vec2::from);
+  let n = AVERAGE_STRATA as f32;
+  let mut sum = Vec3::ZERO;
+  for i in 0..AVERAGE_STRATA {
+    for j in 0..AVERAGE_STRATA - i {
+      let (i, j) = (i as f32, j as f32);
+      // The upright sub-triangle at (i, j) and, except on the diagonal, the inverted one next to it.
+      sum += texture_sample(image, a + (b - a) * (i + 1.0 / 3.0) / n + (c - a) * (j + 1.0 / 3.0) / n);
+      if i + j + 1.0 < n {
+        sum += texture_sample(image, a + (b - a) * (i + 2.0 / 3.0) / n + (c - a) * (j + 2.0 / 3.0) / n);
+      }
+    }
+  }
+  (sum / (n * n)).to_array()
+}
+
+// One triangle light for every triangle of `groups` whose material is emissive. `positions` and
+// `uvs` hold 3 and 2 floats per vertex, meshes without uvs ignore emissive textures like the hit
+// shader does. `materials` is indexed by group material, `textures` are the images of the scene's
+// TextureTable. Returns the lights along with their power for LightTable::new.
+pub fn emissive_triangles(
+  positions: &[f32],
+  uvs: Option<&[f32]>,
+  indices: &[u32],
+  groups: &[GeometryGroup],
+  materials: &[Material],
+  textures: &[image::RgbaImage],
+) -> Vec<(Light, f32)> {
+  let mut lights = Vec::new();
+  for group in groups {
+    let material = &materials[group.material as usize];
+    if !material.is_emissive() {
+      continue;
+    }
+    let texture = if uvs.is_some() { material.emissive_texture } else { -1 };
+    for triangle in 0..group.triangle_count as usize {
+      let first = group.first_index as usize + 3 * triangle;
+      let vertex = |k: usize| indices[first + k] as usize;
+      let p = std::array::from_fn(|k| Vec3::from_slice(&positions[3 * vertex(k)..3 * vertex(k) + 3]));
+      let triangle_uvs = uvs.map_or([[0.0; 2]; 3], |uvs| std::array::from_fn(|k| [uvs[2 * vertex(k)], uvs[2 * vertex(k) + 1]]));
+      let light = Light::triangle(p, triangle_uvs, material.emission, texture, material.double_sided != 0);
+      let average = match usize::try_from(texture).ok().and_then(|t| textures.get(t)) {
+        Some(image) => texture_average(image, triangle_uvs),
+        None => [1.0; 3],
+      };
+      lights.push((light, light.power(average)));
+    }
+  }
+  lights
+}
+
+// All lights of the scene in one storage buffer, RenderParams::light_count says how many are valid.
+// Next event estimation picks a light in proportion to its power, so a large bright panel gets most
+// of the shadow rays and a dim indicator LED few of them.
+pub struct LightTable {
+  pub buffer: Buffer,
+  pub count: u32,
+}
+
+impl LightTable {
+  // `lights` with their power, see Light::power.
+  pub fn new(ctx: &mut Context, lights: &[(Light, f32)]) -> OxResult<Self> {
+    let lights = with_cdf(lights);
+    let buffer = Self::upload(ctx, &lights).map_err(|e| OxError::allocation("the light table", e))?;
+    Ok(Self {
+      buffer,
+      count: lights.len() as u32,
+    })
+  }
+
+  fn upload(ctx: &mut Context, lights: &[Light]) -> Result<Buffer> {
+    // Storage buffers can't be empty, an unused entry keeps the binding valid for scenes without lights.
+    let placeholder = [Light::zeroed()];
+    let lights = if lights.is_empty() { &placeholder[..] } else { lights };
+    let mut uploader = Uploader::new(ctx);
+    let staged = uploader.stage_buffer(lights, vk::BufferUsageFlags::STORAGE_BUFFER)?;
+    uploader.flush::<All>()?.take_buffer(staged).ok_or_else(|| anyhow!("light buffer missing after upload"))
+  }
+}
+
+// Fills in Light::cdf from the powers. Without any power, e.g. all black, lights are picked uniformly.
+fn with_cdf(lights: &[(Light, f32)]) -> Vec<Light> {
+  let total = lights.iter().map(|(_, power)| power.max(0.0)).sum::<f32>();
+  let mut cumulative = 0.0;
+  let mut result = lights
+    .iter()
+    .map(|&(light, power)| {
+      cumulative += if total > 0.0 { power.max(0.0) / total } else { 1.0 / lights.len() as f32 };
+      Light { cdf: cumulative, ..light }
+    })
+    .collect::<Vec<_>>();
+  // Rounding must not leave a gap at the end for the shader's search to fall into.
+  if let Some(last) = result.last_mut() {
+    last.cdf = 1.0;
+  }
+  result
+}
@@ -0,0 +1,164 @@
+use anyhow::{anyhow, Result};
+use ash::vk;
+use bytemuck::{Pod, Zeroable};
+
+use phobos::prelude::*;
+use phobos::sync::domain::All;
+
+use crate::app::Context;
+use crate::error::{OxError, OxResult};
+use crate::memory::Buffer;
+use crate::upload::Uploader;
+
+// Mirrors the Material struct in material.glsl (std430).
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+pub struct Material {
+  pub base_color: [f32; 4],
+  // Beer-Lambert absorption coefficient per unit distance, only used by dielectrics.
+  // Colored glass absorbs the complement of its tint, e.g. [0.0, 0.5, 1.0] for amber.
+  pub absorption: [f32; 3],
+  // Index of refraction, 0 for opaque materials. Any other value turns the material
+  // into a smooth dielectric (1.5 for glass, 1.33 for water) that ignores base_color.
+  pub ior: f32,
+  // Thin surfaces (leaves, paper) set this so their back side is shaded like the front,
+  // by flipping the shading normal towards the incoming ray. Keep it off for closed meshes.
+  // Flipping only affects shading: whether a ray enters or leaves a refractive medium must
+  // still come from the geometric front face, or inside and outside IOR get swapped.
+  // Camera and bounce rays cull the back faces of single-sided materials, like a rasterizer
+  // would. Culling is per instance in Vulkan though, so a mesh with any double-sided or
+  // dielectric material keeps all its back faces, and those of its single-sided materials are
+  // hit but not lit. Shadow rays never cull, an open surface casts a shadow from both sides.
+  pub double_sided: u32,
+  // Tangent space normal map in the scene's TextureTable, -1 for none. Needs uvs, scene loading
+  // adds tangents to the meshes using it, from the file or mesh::compute_tangents.
+  pub normal_map: i32,
+  // Cauchy B coefficient in um^2 of dielectrics, 0 for a constant IOR. Only used with
+  // RunnerConfig::spectral, where paths through dispersive glass are traced at a single wavelength,
+  // see spectral.glsl.
+  pub dispersion: f32,
+  // Fraction of the surface that reflects like a metal in [0, 1], mirroring base_color instead of
+  // scattering it diffusely. Roughness doesn't blur the reflection yet.
+  pub metallic: f32,
+  // Color that shadow rays keep when passing through the surface, 0 for opaque. Only seen by
+  // shadow rays with --transmissive-shadows, camera and bounce rays still stop at the surface.
+  // Alpha below 1 in base_color additionally lets that fraction through untinted, for cutouts.
+  pub transmission: [f32; 3],
+  // Microfacet roughness in [0, 1], 1 for the default diffuse. Shading is Lambertian regardless for now,
+  // it only decides how often next event estimation is worth a shadow ray, see sampling::nee_probability.
+  pub roughness: f32,
+  // Emitted radiance, 0 for surfaces that don't glow. With an emissive texture this is its strength
+  // and the texture's color is multiplied in. Emitters light the scene both when a path hits them
+  // and through next event estimation, see light::emissive_triangles.
+  pub emission: [f32; 3],
+  // Emission color map in the scene's TextureTable, -1 for none. Only applied to meshes with uvs.
+  pub emissive_texture: i32,
+  // Color map multiplied into base_color in the scene's TextureTable, -1 for none. Only applied to
+  // meshes with uvs, see TextureArray for assigning the indices.
+  pub base_color_texture: i32,
+  pub _pad1: [u32; 3],
+}
+
+impl Default for Material {
+  fn default() -> Self {
+    Self {
+      base_color: [0.8, 0.8, 0.8, 1.0],
+      absorption: [0.0; 3],
+      ior: 0.0,
+      double_sided: 0,
+      normal_map: -1,
+      dispersion: 0.0,
+      metallic: 0.0,
+      transmission: [0.0; 3],
+      roughness: 1.0,
+      emission: [0.0; 3],
+      emissive_texture: -1,
+      base_color_texture: -1,
+      _pad1: [0; 3],
+    }
+  }
+}
+
+// All materials of the scene in one storage buffer.
+//
+// Hit shaders find their material at `gl_InstanceCustomIndexEXT + gl_GeometryIndexEXT`:
+// every instance stores the offset of its first material as its custom index, and
+// every geometry of a BLAS is one material group of the mesh. A mesh with N
+// materials therefore occupies N consecutive entries, in the order its geometries
+// were pushed to the BLAS build.
+pub struct MaterialTable {
+  pub buffer: Buffer,
+  #[allow(dead_code)]
+  pub count: u32,
+}
+
+#[allow(dead_code)]
+impl Material {
+  // Clear or tinted glass. Dielectrics must be closed meshes, see double_sided.
+  pub fn dielectric(ior: f32, absorption: [f32; 3]) -> Self {
+    Self {
+      absorption,
+      ior,
+      ..Default::default()
+    }
+  }
+
+  // Glass that splits light into its colors, e.g. dispersive(1.5168, 0.0042) for BK7 or
+  // dispersive(1.62, 0.0095) for dense flint.
+  pub fn dispersive(ior: f32, dispersion: f32) -> Self {
+    Self {
+      dispersion,
+      ..Self::dielectric(ior, [0.0; 3])
+    }
+  }
+
+  // A black diffuse surface glowing with `emission`, times the color of `texture` if it isn't -1,
+  // e.g. a TV screen. Emission is one-sided unless double_sided is set.
+  pub fn emissive(emission: [f32; 3], texture: i32) -> Self {
+    Self {
+      base_color: [0.0, 0.0, 0.0, 1.0],
+      emission,
+      emissive_texture: texture,
+      ..Default::default()
+    }
+  }
+}
+
+#[allow(dead_code)]
+impl Material {
+  // Shadow ray transmission, mirrors shadow_transmission in material.glsl.
+  pub fn shadow_transmission(&self) -> [f32; 3] {
+    let coverage = self.base_color[3].clamp(0.0, 1.0);
+    self.transmission.map(|t| (1.0 - coverage) + coverage * t)
+  }
+
+  // Whether shadow rays can pass through, such geometry can't be built as opaque.
+  pub fn casts_transmissive_shadow(&self) -> bool {
+    self.shadow_transmission().iter().any(|&t| t > 0.0)
+  }
+
+  // Whether the instances of a mesh with this material must keep their back faces, see double_sided.
+  pub fn needs_back_faces(&self) -> bool {
+    self.double_sided != 0 || self.ior > 0.0
+  }
+
+  pub fn is_emissive(&self) -> bool {
+    self.emission.iter().any(|&e| e > 0.0)
+  }
+}
+
+impl MaterialTable {
+  pub fn new(ctx: &mut Context, materials: &[Material]) -> OxResult<Self> {
+    let buffer = Self::upload(ctx, materials).map_err(|e| OxError::allocation("the material table", e))?;
+    Ok(Self {
+      buffer,
+      count: materials.len() as u32,
+    })
+  }
+
+  fn upload(ctx: &mut Context, materials: &[Material]) -> Result<Buffer> {
+    let mut uploader = Uploader::new(ctx);
+    let staged = uploader.stage_buffer(materials, vk::BufferUsageFlags::STORAGE_BUFFER)?;
+    uploader.flush::<All>()?.take_buffer(staged).ok_or_else(|| anyhow!("material buffer missing after upload"))
+  }
+}
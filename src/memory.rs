@@ -0,0 +1,281 @@
+use std::ffi::CStr;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use ash::vk;
+use log::warn;
+
+use phobos::prelude::*;
+
+// Fraction of the device local budget above which check() warns.
+pub const BUDGET_WARNING_THRESHOLD: f64 = 0.9;
+
+#[derive(Debug, Copy, Clone)]
+pub struct HeapReport {
+  pub size: u64,
+  // How much this process may allocate from the heap, the heap size without VK_EXT_memory_budget.
+  pub budget: u64,
+  // Current usage of this process, None without VK_EXT_memory_budget.
+  pub usage: Option<u64>,
+  pub device_local: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct MemoryReport {
+  pub heaps: Vec<HeapReport>,
+}
+
+impl MemoryReport {
+  // Summed usage and budget of all device local heaps.
+  pub fn device_local(&self) -> (Option<u64>, u64) {
+    let heaps = self.heaps.iter().filter(|heap| heap.device_local);
+    let usage = heaps.clone().map(|heap| heap.usage).sum::<Option<u64>>();
+    (usage, heaps.map(|heap| heap.budget).sum())
+  }
+
+  pub fn device_local_fraction(&self) -> Option<f64> {
+    match self.device_local() {
+      (Some(usage), budget) if budget > 0 => Some(usage as f64 / budget as f64),
+      _ => None,
+    }
+  }
+}
+
+impl fmt::Display for MemoryReport {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    const MIB: u64 = 1024 * 1024;
+    match (self.device_local(), self.device_local_fraction()) {
+      ((Some(usage), budget), Some(fraction)) => {
+        write!(f, "{} MiB of {} MiB device local budget used ({:.0}%)", usage / MIB, budget / MIB, fraction * 100.0)
+      }
+      ((_, budget), _) => write!(f, "{} MiB device local memory, usage unknown (no VK_EXT_memory_budget)", budget / MIB),
+    }
+  }
+}
+
+// Queries heap budgets through VK_EXT_memory_budget, falling back to heap sizes without it.
+pub struct MemoryBudget {
+  instance: ash::Instance,
+  physical_device: vk::PhysicalDevice,
+  supported: bool,
+}
+
+impl MemoryBudget {
+  pub fn new(instance: &VkInstance, physical_device: &PhysicalDevice) -> Arc<Self> {
+    let instance: ash::Instance = (**instance).clone();
+    let physical_device = unsafe { physical_device.handle() };
+    let supported = unsafe { instance.enumerate_device_extension_properties(physical_device) }
+      .unwrap_or_default()
+      .iter()
+      .any(|extension| unsafe { CStr::from_ptr(extension.extension_name.as_ptr()) } == vk::ExtMemoryBudgetFn::name());
+    Arc::new(Self {
+      instance,
+      physical_device,
+      supported,
+    })
+  }
+
+  pub fn report(&self) -> MemoryReport {
+    let mut budget = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+    let mut properties = vk::PhysicalDeviceMemoryProperties2::builder();
+    if self.supported {
+      properties = properties.push_next(&mut budget);
+    }
+    let mut properties = properties.build();
+    unsafe { self.instance.get_physical_device_memory_properties2(self.physical_device, &mut properties) };
+
+    let memory = properties.memory_properties;
+    let heaps = (0..memory.memory_heap_count as usize)
+      .map(|index| {
+        let heap = memory.memory_heaps[index];
+        HeapReport {
+          size: heap.size,
+          budget: if self.supported { budget.heap_budget[index] } else { heap.size },
+          usage: self.supported.then(|| budget.heap_usage[index]),
+          device_local: heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL),
+        }
+      })
+      .collect();
+    MemoryReport {
+      heaps,
+    }
+  }
+
+  // Warns when device local usage is close to the budget, returns the report for further logging.
+  pub fn check(&self) -> MemoryReport {
+    let report = self.report();
+    if report.device_local_fraction().map_or(false, |fraction| fraction > BUDGET_WARNING_THRESHOLD) {
+      warn!("GPU memory is almost exhausted: {}", report);
+    }
+    report
+  }
+}
+
+// Default scratch reserve, per buffer type (vertex, index, uniform, storage) and frame in flight.
+pub const DEFAULT_SCRATCH_RESERVE: u64 = 1024;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ScratchKind {
+  Vertex,
+  Index,
+  Uniform,
+  Storage,
+}
+
+// Scratch memory requested per frame, recorded by App code next to its ifc.allocate_scratch_* calls.
+// phobos reserves the scratch buffers once at startup, with RunnerConfig::scratch_reserve bytes per
+// buffer type, and never grows them, so steady-state frames don't allocate. A frame that needs more
+// fails its allocation instead, end_frame warns before that when usage gets close.
+#[derive(Debug, Default)]
+pub struct ScratchUsage {
+  used: [AtomicU64; 4],
+  peak: AtomicU64,
+}
+
+impl ScratchUsage {
+  // `size` as requested, the allocator pads it for alignment on top.
+  pub fn record(&self, kind: ScratchKind, size: u64) {
+    self.used[kind as usize].fetch_add(size, Ordering::Relaxed);
+  }
+
+  // Resets the per-frame counters. Returns the largest amount any one buffer type used this frame,
+  // and the peak of that since startup.
+  pub fn end_frame(&self) -> (u64, u64) {
+    let used = self.used.iter().map(|used| used.swap(0, Ordering::Relaxed)).max().unwrap_or(0);
+    let previous_peak = self.peak.fetch_max(used, Ordering::Relaxed);
+    (used, previous_peak.max(used))
+  }
+}
+
+// Scratch statistics of one frame, passed to App::on_frame_stats.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ScratchStats {
+  // Bytes reserved per buffer type.
+  pub reserve: u64,
+  // Largest amount of one buffer type used this frame, and since startup.
+  pub used: u64,
+  pub peak: u64,
+}
+
+impl ScratchStats {
+  // Alignment padding isn't recorded, so anything close to the reserve is treated as exceeding it.
+  pub fn near_reserve(&self) -> bool {
+    self.used as f64 > self.reserve as f64 * BUDGET_WARNING_THRESHOLD
+  }
+}
+
+// Frames between two leak checks, and how many checks in a row the live count has to grow for.
+pub const LEAK_CHECK_INTERVAL: u64 = 120;
+pub const LEAK_CHECK_STRIKES: u32 = 3;
+
+#[derive(Debug, Default)]
+struct LeakCheck {
+  frames: u64,
+  // Live count at the previous check, None before the first one.
+  last: Option<u64>,
+  strikes: u32,
+  // Highest count warned about, so a slow leak doesn't warn every interval.
+  warned: u64,
+}
+
+// Live allocations of a TrackingAllocator, only counted in debug builds. A transient allocated every
+// frame and kept alive somewhere by mistake makes the count climb steadily, while correct code
+// returns to the same count every frame once the scene is loaded. Release builds skip the counting,
+// live() is always 0 there.
+#[derive(Debug, Default)]
+pub struct AllocationTracker {
+  live: AtomicU64,
+  check: Mutex<LeakCheck>,
+}
+
+impl AllocationTracker {
+  pub fn live(&self) -> u64 {
+    self.live.load(Ordering::Relaxed)
+  }
+
+  fn allocated(&self) {
+    if cfg!(debug_assertions) {
+      self.live.fetch_add(1, Ordering::Relaxed);
+    }
+  }
+
+  fn freed(&self) {
+    if cfg!(debug_assertions) {
+      self.live.fetch_sub(1, Ordering::Relaxed);
+    }
+  }
+
+  // Called once per frame. Warns when the live count grew over LEAK_CHECK_STRIKES consecutive
+  // intervals of LEAK_CHECK_INTERVAL frames, the signature of a per-frame leak. Returns the live count.
+  pub fn end_frame(&self) -> u64 {
+    let live = self.live();
+    if !cfg!(debug_assertions) {
+      return live;
+    }
+    let mut check = self.check.lock().unwrap();
+    check.frames += 1;
+    if check.frames % LEAK_CHECK_INTERVAL != 0 {
+      return live;
+    }
+    check.strikes = match check.last {
+      Some(last) if live > last => check.strikes + 1,
+      _ => 0,
+    };
+    check.last = Some(live);
+    if check.strikes >= LEAK_CHECK_STRIKES && live > check.warned {
+      check.warned = live;
+      warn!(
+        "Live allocations grew for {} frames in a row and are now {}, something allocated every frame is kept alive",
+        LEAK_CHECK_INTERVAL * check.strikes as u64,
+        live
+      );
+    }
+    live
+  }
+}
+
+// The Context's allocator: phobos' DefaultAllocator, counting every allocation until it is freed in an
+// AllocationTracker. Buffers and images made with it, whether by the runner, an App or a resource
+// constructor, free their memory through it when dropped, so the count covers windowed and headless
+// runs alike. phobos' frame and thread contexts get the untracked allocator, their scratch buffers
+// are reserved once and show up in ScratchUsage instead.
+#[derive(Clone)]
+pub struct TrackingAllocator {
+  inner: DefaultAllocator,
+  tracker: Arc<AllocationTracker>,
+}
+
+impl TrackingAllocator {
+  pub fn new(inner: DefaultAllocator, tracker: Arc<AllocationTracker>) -> Self {
+    Self {
+      inner,
+      tracker,
+    }
+  }
+
+  pub fn untracked(&self) -> DefaultAllocator {
+    self.inner.clone()
+  }
+}
+
+impl Allocator for TrackingAllocator {
+  type Allocation = <DefaultAllocator as Allocator>::Allocation;
+
+  fn allocate(&mut self, name: &'static str, requirements: &vk::MemoryRequirements, ty: MemoryType) -> Result<Self::Allocation> {
+    let allocation = self.inner.allocate(name, requirements, ty)?;
+    self.tracker.allocated();
+    Ok(allocation)
+  }
+
+  fn free(&mut self, allocation: Self::Allocation) -> Result<()> {
+    self.tracker.freed();
+    self.inner.free(allocation)
+  }
+}
+
+// Buffers and images allocated through the TrackingAllocator, used everywhere instead of phobos'
+// types for the DefaultAllocator.
+pub type Buffer = phobos::prelude::Buffer<TrackingAllocator>;
+pub type Image = phobos::prelude::Image<TrackingAllocator>;
@@ -0,0 +1,269 @@
+use anyhow::{anyhow, bail, Result};
+use ash::vk;
+use bytemuck::{Pod, Zeroable};
+use glam::{Vec2, Vec3};
+
+use phobos::prelude::*;
+use phobos::sync::domain::All;
+
+use crate::app::Context;
+use crate::error::{OxError, OxResult};
+use crate::memory::Buffer;
+use crate::upload::Uploader;
+
+// A run of triangles sharing one material, built as a separate geometry of the BLAS.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct GeometryGroup {
+  pub first_index: u32,
+  pub triangle_count: u32,
+  // Index into the mesh's own material list.
+  pub material: u32,
+}
+
+impl GeometryGroup {
+  // Byte offset of the group into an index buffer of `format`, as expected by the build range.
+  pub fn index_offset_bytes(&self, format: IndexFormat) -> u32 {
+    self.first_index * format.size()
+  }
+}
+
+// Width of a mesh's indices. Meshes with at most 65536 vertices use u16, half the index memory.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IndexFormat {
+  U16,
+  U32,
+}
+
+impl IndexFormat {
+  // Values of GeometryInfo::index_format, 0 so that zeroed entries read u32 indices.
+  pub const ID_U32: u32 = 0;
+  pub const ID_U16: u32 = 1;
+
+  // The narrowest format that can address every one of `vertex_count` vertices.
+  pub fn for_vertex_count(vertex_count: usize) -> Self {
+    if vertex_count <= u16::MAX as usize + 1 {
+      IndexFormat::U16
+    } else {
+      IndexFormat::U32
+    }
+  }
+
+  pub fn size(&self) -> u32 {
+    match self {
+      IndexFormat::U16 => std::mem::size_of::<u16>() as u32,
+      IndexFormat::U32 => std::mem::size_of::<u32>() as u32,
+    }
+  }
+
+  pub fn index_type(&self) -> vk::IndexType {
+    match self {
+      IndexFormat::U16 => vk::IndexType::UINT16,
+      IndexFormat::U32 => vk::IndexType::UINT32,
+    }
+  }
+
+  pub fn id(&self) -> u32 {
+    match self {
+      IndexFormat::U16 => Self::ID_U16,
+      IndexFormat::U32 => Self::ID_U32,
+    }
+  }
+}
+
+// Index list of a mesh in the format its vertex count allows, checked against that count.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MeshIndices {
+  U16(Vec<u16>),
+  U32(Vec<u32>),
+}
+
+impl MeshIndices {
+  // Narrows `indices` to u16 when the vertex count allows it.
+  pub fn new(indices: &[u32], vertex_count: usize) -> Result<Self> {
+    check_indices(indices.iter().map(|&index| index as usize), vertex_count)?;
+    Ok(match IndexFormat::for_vertex_count(vertex_count) {
+      IndexFormat::U16 => MeshIndices::U16(indices.iter().map(|&index| index as u16).collect()),
+      IndexFormat::U32 => MeshIndices::U32(indices.to_vec()),
+    })
+  }
+
+  // Keeps u16 indices as they are, e.g. those of a glTF primitive with an UNSIGNED_SHORT accessor.
+  #[allow(dead_code)]
+  pub fn from_u16(indices: Vec<u16>, vertex_count: usize) -> Result<Self> {
+    check_indices(indices.iter().map(|&index| index as usize), vertex_count)?;
+    Ok(MeshIndices::U16(indices))
+  }
+
+  pub fn format(&self) -> IndexFormat {
+    match self {
+      MeshIndices::U16(_) => IndexFormat::U16,
+      MeshIndices::U32(_) => IndexFormat::U32,
+    }
+  }
+
+  // For CPU side processing like emissive_triangles and compute_tangents.
+  pub fn to_u32(&self) -> Vec<u32> {
+    match self {
+      MeshIndices::U16(indices) => indices.iter().map(|&index| index as u32).collect(),
+      MeshIndices::U32(indices) => indices.clone(),
+    }
+  }
+
+  // Contents of the index buffer as 32 bit words, which is how geometry.glsl reads them: u16 indices
+  // are packed in pairs, little endian, and an odd count is padded so the last word can be read whole.
+  pub fn buffer_words(&self) -> Vec<u32> {
+    match self {
+      MeshIndices::U16(indices) => indices
+        .chunks(2)
+        .map(|pair| pair[0] as u32 | (pair.get(1).copied().unwrap_or(0) as u32) << 16)
+        .collect(),
+      MeshIndices::U32(indices) => indices.clone(),
+    }
+  }
+}
+
+fn check_indices(indices: impl Iterator<Item = usize>, vertex_count: usize) -> Result<()> {
+  match indices.enumerate().find(|&(_, index)| index >= vertex_count) {
+    Some((position, index)) => bail!("index {} at position {} refers past the mesh's {} vertices", index, position, vertex_count),
+    None => Ok(()),
+  }
+}
+
+// Reorders triangles so that each material's triangles are contiguous and returns the new
+// index list with one group per used material, in ascending material order.
+// The material table entries of a mesh must follow its groups, not its material list:
+// push `materials[group.material]` for every group in order, so that `gl_GeometryIndexEXT`
+// selects the right entry even when some materials are unused.
+pub fn group_by_material(indices: &[u32], triangle_materials: &[u32]) -> (Vec<u32>, Vec<GeometryGroup>) {
+  assert_eq!(indices.len(), triangle_materials.len() * 3, "expected one material per triangle");
+  let mut order = (0..triangle_materials.len()).collect::<Vec<_>>();
+  order.sort_by_key(|&triangle| triangle_materials[triangle]);
+
+  let mut grouped = Vec::with_capacity(indices.len());
+  let mut groups: Vec<GeometryGroup> = Vec::new();
+  for triangle in order {
+    let material = triangle_materials[triangle];
+    match groups.last_mut() {
+      Some(group) if group.material == material => group.triangle_count += 1,
+      _ => groups.push(GeometryGroup {
+        first_index: grouped.len() as u32,
+        triangle_count: 1,
+        material,
+      }),
+    }
+    grouped.extend_from_slice(&indices[triangle * 3..triangle * 3 + 3]);
+  }
+  (grouped, groups)
+}
+
+// Mirrors GeometryInfo in geometry.glsl (std430). One entry per geometry, at the same slot as its material.
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+pub struct GeometryInfo {
+  pub vertices: u64,
+  pub indices: u64,
+  // Optional attributes, 0 if absent: normals as 3 floats, uvs as 2 floats and tangents as 4 floats per vertex.
+  pub normals: u64,
+  pub uvs: u64,
+  pub tangents: u64,
+  pub first_index: u32,
+  // IndexFormat::id of the index buffer.
+  pub index_format: u32,
+  // Index in the light table of the triangle light of the geometry's first triangle, the others follow
+  // in order. -1 if its material isn't emissive.
+  pub first_light: i32,
+  pub _pad: u32,
+}
+
+impl GeometryInfo {
+  pub fn new(vertices: &Buffer, indices: &Buffer, index_format: IndexFormat, group: &GeometryGroup) -> Self {
+    Self {
+      vertices: vertices.address(),
+      indices: indices.address(),
+      normals: 0,
+      uvs: 0,
+      tangents: 0,
+      first_index: group.first_index,
+      index_format: index_format.id(),
+      first_light: -1,
+      _pad: 0,
+    }
+  }
+
+  pub fn with_attributes(self, normals: Option<&Buffer>, uvs: Option<&Buffer>, tangents: Option<&Buffer>) -> Self {
+    Self {
+      normals: normals.map_or(0, |buffer| buffer.address()),
+      uvs: uvs.map_or(0, |buffer| buffer.address()),
+      tangents: tangents.map_or(0, |buffer| buffer.address()),
+      ..self
+    }
+  }
+
+  // For geometries whose triangles are lights starting at `first_light`.
+  pub fn with_first_light(self, first_light: Option<u32>) -> Self {
+    Self {
+      first_light: first_light.map_or(-1, |index| index as i32),
+      ..self
+    }
+  }
+}
+
+// Per-vertex tangents for normal mapping, from positions and uvs (Lengyel, "Computing Tangent
+// Space Basis Vectors for an Arbitrary Mesh"). The tangent is Gram-Schmidt orthogonalized
+// against the vertex normal, w holds the sign of the bitangent, i.e. whether the uv mapping is
+// mirrored. Returns None for meshes without uvs, which can't be normal mapped.
+pub fn compute_tangents(positions: &[[f32; 3]], normals: &[[f32; 3]], uvs: Option<&[[f32; 2]]>, indices: &[u32]) -> Option<Vec<[f32; 4]>> {
+  let uvs = uvs?;
+  let mut tangents = vec![Vec3::ZERO; positions.len()];
+  let mut bitangents = vec![Vec3::ZERO; positions.len()];
+  for triangle in indices.chunks_exact(3) {
+    let [i0, i1, i2] = [triangle[0] as usize, triangle[1] as usize, triangle[2] as usize];
+    let (p0, p1, p2) = (Vec3::from(positions[i0]), Vec3::from(positions[i1]), Vec3::from(positions[i2]));
+    let (w0, w1, w2) = (Vec2::from(uvs[i0]), Vec2::from(uvs[i1]), Vec2::from(uvs[i2]));
+    let (e1, e2) = (p1 - p0, p2 - p0);
+    let (d1, d2) = (w1 - w0, w2 - w0);
+    let determinant = d1.x * d2.y - d2.x * d1.y;
+    // Degenerate uvs, this triangle can't contribute a direction.
+    if determinant.abs() < f32::EPSILON {
+      continue;
+    }
+    let r = 1.0 / determinant;
+    let tangent = (e1 * d2.y - e2 * d1.y) * r;
+    let bitangent = (e2 * d1.x - e1 * d2.x) * r;
+    for i in [i0, i1, i2] {
+      tangents[i] += tangent;
+      bitangents[i] += bitangent;
+    }
+  }
+
+  let result = (0..positions.len())
+    .map(|i| {
+      let n = Vec3::from(normals[i]);
+      let t = (tangents[i] - n * n.dot(tangents[i])).normalize_or_zero();
+      let w = if n.cross(t).dot(bitangents[i]) < 0.0 { -1.0 } else { 1.0 };
+      [t.x, t.y, t.z, w]
+    })
+    .collect();
+  Some(result)
+}
+
+// Vertex fetch table for hit shaders, laid out like the MaterialTable.
+pub struct GeometryTable {
+  pub buffer: Buffer,
+}
+
+impl GeometryTable {
+  pub fn new(ctx: &mut Context, geometries: &[GeometryInfo]) -> OxResult<Self> {
+    let buffer = Self::upload(ctx, geometries).map_err(|e| OxError::allocation("the geometry table", e))?;
+    Ok(Self {
+      buffer,
+    })
+  }
+
+  fn upload(ctx: &mut Context, geometries: &[GeometryInfo]) -> Result<Buffer> {
+    let mut uploader = Uploader::new(ctx);
+    let staged = uploader.stage_buffer(geometries, vk::BufferUsageFlags::STORAGE_BUFFER)?;
+    uploader.flush::<All>()?.take_buffer(staged).ok_or_else(|| anyhow!("geometry buffer missing after upload"))
+  }
+}
@@ -0,0 +1,85 @@
+use ash::vk;
+
+use phobos::prelude::*;
+
+use crate::app::{create_shader, Context};
+use crate::error::{OxError, OxResult};
+
+// Device extensions for task and mesh shaders, requested when RunnerConfig::mesh_shaders is set.
+pub fn mesh_shader_extensions() -> Vec<String> {
+  vec![vk::ExtMeshShaderFn::name().to_string_lossy().into_owned()]
+}
+
+// The features of VkPhysicalDeviceMeshShaderFeaturesEXT requested along with the extension, see
+// FeatureStructs::vulkan_13_chain.
+pub fn mesh_shader_features() -> Vec<String> {
+  ["task_shader", "mesh_shader"].map(String::from).to_vec()
+}
+
+// Shaders of a raster pipeline that generates its geometry in mesh shaders instead of reading
+// vertex buffers, e.g. `MeshPipeline::new("meshlets", "resources/shaders/meshlets_mesh.spv",
+// "resources/shaders/meshlets_frag.spv")`. build.rs compiles `.task` and `.mesh` sources to
+// `<name>_task.spv` and `<name>_mesh.spv`.
+#[allow(dead_code)]
+pub struct MeshPipeline {
+  pub name: String,
+  // Optional amplification stage that decides how many mesh workgroups to launch.
+  pub task: Option<String>,
+  pub mesh: String,
+  pub fragment: String,
+}
+
+#[allow(dead_code)]
+impl MeshPipeline {
+  pub fn new(name: impl Into<String>, mesh: impl Into<String>, fragment: impl Into<String>) -> Self {
+    Self {
+      name: name.into(),
+      task: None,
+      mesh: mesh.into(),
+      fragment: fragment.into(),
+    }
+  }
+
+  pub fn task(mut self, task: impl Into<String>) -> Self {
+    self.task = Some(task.into());
+    self
+  }
+
+  // Creates the pipeline in the context's pipeline cache under `name`, with dynamic viewport and
+  // scissor like the display pipeline, again in App::reload_shaders after the shaders were
+  // recompiled. Fails without creating anything if the extension and its features weren't enabled at
+  // device creation or the device lacks them.
+  pub fn create(&self, ctx: &mut Context) -> OxResult<()> {
+    ensure_mesh_shading(ctx)?;
+    let mesh = create_shader(&self.mesh, vk::ShaderStageFlags::MESH_EXT)?;
+    let fragment = create_shader(&self.fragment, vk::ShaderStageFlags::FRAGMENT)?;
+    let mut builder = PipelineBuilder::new(self.name.clone())
+      .dynamic_states(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR])
+      .blend_attachment_none()
+      .cull_mask(vk::CullModeFlags::NONE);
+    if let Some(task) = &self.task {
+      builder = builder.attach_shader(create_shader(task, vk::ShaderStageFlags::TASK_EXT)?);
+    }
+    let pci = builder.attach_shader(mesh).attach_shader(fragment).build();
+    ctx
+      .pipelines
+      .create_named_pipeline(pci)
+      .map_err(|e| OxError::allocation(format!("the mesh shading pipeline {}", self.name), e))
+  }
+}
+
+// Mesh shaders need both the device support and RunnerConfig::mesh_shaders, which enables the
+// extension and the taskShader and meshShader features.
+pub fn ensure_mesh_shading(ctx: &Context) -> OxResult<()> {
+  let reason = if !ctx.gpu.mesh_shader {
+    format!("{} doesn't support VK_EXT_mesh_shader", ctx.gpu.name)
+  } else if !ctx.config.mesh_shaders {
+    "VK_EXT_mesh_shader is not enabled, set RunnerConfig::mesh_shaders".to_string()
+  } else {
+    return Ok(());
+  };
+  Err(OxError::Unsupported {
+    what: "mesh shading".to_string(),
+    reason,
+  })
+}
@@ -0,0 +1,177 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use ash::vk;
+
+use phobos::prelude::*;
+use phobos::sync::domain::All;
+
+use crate::app::{Context, Rect};
+use crate::error::{OxError, OxResult};
+use crate::memory::Image;
+
+fn image_error(path: &Path, e: image::ImageError) -> OxError {
+  match e {
+    image::ImageError::IoError(e) => OxError::io(path, e),
+    e => OxError::io(path, std::io::Error::new(std::io::ErrorKind::Other, e)),
+  }
+}
+
+fn size_mismatch(path: &Path, width: u32, height: u32) -> OxError {
+  OxError::io(path, std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("texel count does not match {}x{}", width, height)))
+}
+
+// `path` with the zero padded frame number before the extension, e.g. out.exr becomes out_0042.exr.
+pub fn sequence_frame_path(path: &Path, frame: u32) -> PathBuf {
+  let stem = path.file_stem().map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+  let name = match path.extension() {
+    Some(extension) => format!("{}_{:04}.{}", stem, frame, extension.to_string_lossy()),
+    None => format!("{}_{:04}", stem, frame),
+  };
+  path.with_file_name(name)
+}
+
+// Where a compositing pass of the image at `path` goes: out.png becomes out_depth.exr. Always EXR,
+// 8 bit formats would clamp depth and positions.
+pub fn compositing_pass_path(path: &Path, pass: &str) -> PathBuf {
+  let stem = path.file_stem().map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+  path.with_file_name(format!("{}_{}.exr", stem, pass))
+}
+
+// Copies an RGBA32F image in GENERAL layout to the host. The image is returned to GENERAL afterwards.
+pub fn read_back_hdr(ctx: &mut Context, image: &Image, width: u32, height: u32) -> Result<Vec<f32>> {
+  read_back::<f32>(ctx, image, width, height, vk::ImageLayout::GENERAL)
+}
+
+// Copies an RGBA8 image in `layout`, e.g. the target of a tone-map pass, to the host and returns it to `layout`.
+pub fn read_back_ldr(ctx: &mut Context, image: &Image, width: u32, height: u32, layout: vk::ImageLayout) -> Result<Vec<u8>> {
+  read_back::<u8>(ctx, image, width, height, layout)
+}
+
+// Copies a four channel image in `layout` to the host and returns it to `layout`. `T` is the type of
+// one channel. The buffer rows are tightly packed whatever the image's tiling, the copy repacks them.
+pub fn read_back<T: Copy>(ctx: &mut Context, image: &Image, width: u32, height: u32, layout: vk::ImageLayout) -> Result<Vec<T>> {
+  let texel_count = (width * height * 4) as usize;
+  let buffer = ctx.create_buffer((texel_count * std::mem::size_of::<T>()) as u64, vk::BufferUsageFlags::TRANSFER_DST, MemoryType::GpuToCpu)?;
+
+  let range = vk::ImageSubresourceRange {
+    aspect_mask: vk::ImageAspectFlags::COLOR,
+    base_mip_level: 0,
+    level_count: 1,
+    base_array_layer: 0,
+    layer_count: 1,
+  };
+  let to_transfer = vk::ImageMemoryBarrier2::builder()
+    .image(unsafe { image.handle() })
+    .old_layout(layout)
+    .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+    .src_stage_mask(PipelineStage::ALL_COMMANDS)
+    .src_access_mask(vk::AccessFlags2::MEMORY_WRITE)
+    .dst_stage_mask(PipelineStage::TRANSFER)
+    .dst_access_mask(vk::AccessFlags2::TRANSFER_READ)
+    .subresource_range(range)
+    .build();
+  let to_general = vk::ImageMemoryBarrier2::builder()
+    .image(unsafe { image.handle() })
+    .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+    .new_layout(layout)
+    .src_stage_mask(PipelineStage::TRANSFER)
+    .src_access_mask(vk::AccessFlags2::TRANSFER_READ)
+    .dst_stage_mask(PipelineStage::ALL_COMMANDS)
+    .dst_access_mask(vk::AccessFlags2::MEMORY_READ | vk::AccessFlags2::MEMORY_WRITE)
+    .subresource_range(range)
+    .build();
+  // Tightly packed rows, buffer_row_length 0 means the row length equals the image width.
+  let region = vk::BufferImageCopy {
+    buffer_offset: 0,
+    buffer_row_length: 0,
+    buffer_image_height: 0,
+    image_subresource: vk::ImageSubresourceLayers {
+      aspect_mask: vk::ImageAspectFlags::COLOR,
+      mip_level: 0,
+      base_array_layer: 0,
+      layer_count: 1,
+    },
+    image_offset: vk::Offset3D::default(),
+    image_extent: vk::Extent3D {
+      width,
+      height,
+      depth: 1,
+    },
+  };
+
+  let cmd = ctx
+    .exec
+    .on_domain::<All, DefaultAllocator>(None, None)?
+    .pipeline_barrier(&vk::DependencyInfo::builder().image_memory_barriers(std::slice::from_ref(&to_transfer)).build())?;
+  unsafe {
+    ctx.device.cmd_copy_image_to_buffer(
+      cmd.handle(),
+      image.handle(),
+      vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+      buffer.handle(),
+      std::slice::from_ref(&region),
+    );
+  }
+  let cmd = cmd
+    .pipeline_barrier(&vk::DependencyInfo::builder().image_memory_barriers(std::slice::from_ref(&to_general)).build())?
+    .finish()?;
+  ctx.exec.submit(cmd)?.wait()?;
+
+  Ok(buffer.view_full().mapped_slice::<T>()?.to_vec())
+}
+
+// Divides the color of premultiplied RGBA texels by their alpha. Fully transparent texels are left alone.
+fn unpremultiply(texels: &mut [f32]) {
+  for texel in texels.chunks_exact_mut(4) {
+    let alpha = texel[3].clamp(0.0, 1.0);
+    if alpha > 0.0 {
+      texel[..3].iter_mut().for_each(|channel| *channel /= alpha);
+    }
+    texel[3] = alpha;
+  }
+}
+
+// .exr keeps the raw HDR values with premultiplied alpha, as EXR expects. Any other format is clamped
+// to [0, 1] and stored as 8 bit with straight alpha, like PNG expects.
+pub fn save_hdr_image(path: &Path, width: u32, height: u32, mut texels: Vec<f32>) -> OxResult<()> {
+  let is_exr = is_exr(path);
+  if !is_exr {
+    unpremultiply(&mut texels);
+  }
+  let image = image::Rgba32FImage::from_raw(width, height, texels).ok_or_else(|| size_mismatch(path, width, height))?;
+  let image = image::DynamicImage::ImageRgba32F(image);
+  if is_exr {
+    image.save(path)
+  } else {
+    image.to_rgba8().save(path)
+  }
+  .map_err(|e| image_error(path, e))
+}
+
+pub fn is_exr(path: &Path) -> bool {
+  path.extension().map_or(false, |ext| ext.eq_ignore_ascii_case("exr"))
+}
+
+// Writes an already tone-mapped RGBA8 image, in any format of the image crate but EXR, which holds
+// linear HDR values and would get display encoded 8 bit ones.
+pub fn save_ldr_image(path: &Path, width: u32, height: u32, texels: Vec<u8>) -> OxResult<()> {
+  if is_exr(path) {
+    return Err(OxError::Unsupported {
+      what: format!("writing a tone-mapped image to {}", path.display()),
+      reason: "EXR stores linear HDR values, write a PNG or leave tone mapping off".to_string(),
+    });
+  }
+  let image = image::RgbaImage::from_raw(width, height, texels).ok_or_else(|| size_mismatch(path, width, height))?;
+  image.save(path).map_err(|e| image_error(path, e))
+}
+
+// Cuts `rect` out of an RGBA image `width` texels wide.
+pub fn crop_texels<T: Copy>(texels: &[T], width: u32, rect: Rect) -> Vec<T> {
+  let mut cropped = Vec::with_capacity((rect.width * rect.height * 4) as usize);
+  for y in rect.y..rect.y + rect.height {
+    let start = ((y * width + rect.x) * 4) as usize;
+    cropped.extend_from_slice(&texels[start..start + (rect.width * 4) as usize]);
+  }
+  cropped
+}
@@ -0,0 +1,102 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use ash::vk;
+use bytemuck::{Pod, Zeroable};
+
+use phobos::prelude::*;
+
+use crate::memory::{Buffer, TrackingAllocator};
+
+// Bounces recorded per debugged pixel, longer paths only count in PixelDebugInfo::bounces.
+pub const MAX_DEBUG_BOUNCES: usize = 8;
+
+// Value of RenderParams::debug_pixel_x/y when no pixel is debugged.
+pub const NO_DEBUG_PIXEL: u32 = u32::MAX;
+
+// Mirrors PixelDebugInfo in pixel_debug.glsl (std430). Written by raygen for one pixel of one frame,
+// in path tracing mode only.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+pub struct PixelDebugInfo {
+  // Radiance of the single sample traced this frame, after the firefly clamp. Not the accumulated mean.
+  pub radiance: [f32; 4],
+  // First hit in world space, w is the hit distance.
+  pub position: [f32; 4],
+  // Shading normal at the first hit.
+  pub normal: [f32; 4],
+  // Index into the material table, only valid if hit is set.
+  pub material: u32,
+  // Surface interactions of the path.
+  pub bounces: u32,
+  pub hit: u32,
+  pub _pad: u32,
+  // Path throughput after each interaction.
+  pub throughput: [[f32; 4]; MAX_DEBUG_BOUNCES],
+}
+
+#[derive(Debug, Default)]
+struct State {
+  requested: Option<(u32, u32)>,
+  in_flight: Option<(u32, u32)>,
+}
+
+// A pixel to trace with debug output on the next frame, and the host visible buffer it is written to.
+// Shared by all Contexts of a Runner, which reads the result back after the frame and passes it
+// to App::on_pixel_debug.
+#[derive(Clone)]
+pub struct PixelDebug {
+  state: Arc<Mutex<State>>,
+  pub buffer: Arc<Buffer>,
+}
+
+impl PixelDebug {
+  pub fn new(device: Device, allocator: &mut TrackingAllocator) -> Result<Self> {
+    let buffer = Buffer::new(
+      device,
+      allocator,
+      std::mem::size_of::<PixelDebugInfo>() as u64,
+      vk::BufferUsageFlags::STORAGE_BUFFER,
+      MemoryType::GpuToCpu,
+    )?;
+    Ok(Self {
+      state: Arc::default(),
+      buffer: Arc::new(buffer),
+    })
+  }
+
+  pub fn request(&self, x: u32, y: u32) {
+    self.state.lock().unwrap().requested = Some((x, y));
+  }
+
+  // Called by the app while recording a frame, returns the pixel it should write debug data for.
+  // Clears the buffer first, so a frame that doesn't write it reads back as no hit rather than the
+  // previous result.
+  pub fn begin_frame(&self) -> Result<Option<(u32, u32)>> {
+    let mut state = self.state.lock().unwrap();
+    state.in_flight = state.requested.take();
+    if state.in_flight.is_some() {
+      self.buffer.view_full().mapped_slice::<PixelDebugInfo>()?.fill(PixelDebugInfo::zeroed());
+    }
+    Ok(state.in_flight)
+  }
+
+  // Drops the pixel begin_frame returned, for frames that don't trace it. Nothing is passed to
+  // App::on_pixel_debug.
+  pub fn cancel(&self) {
+    self.state.lock().unwrap().in_flight = None;
+  }
+
+  pub fn in_flight(&self) -> Option<(u32, u32)> {
+    self.state.lock().unwrap().in_flight
+  }
+
+  // Reads back the result of the frame that begin_frame returned a pixel for. The frame must have
+  // finished executing.
+  pub fn finish(&self) -> Result<Option<PixelDebugInfo>> {
+    if self.state.lock().unwrap().in_flight.take().is_none() {
+      return Ok(None);
+    }
+    Ok(self.buffer.view_full().mapped_slice::<PixelDebugInfo>()?.first().copied())
+  }
+}
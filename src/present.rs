@@ -0,0 +1,68 @@
+use std::fmt;
+use std::str::FromStr;
+
+use ash::vk;
+use log::warn;
+
+// How the swapchain presents, in the terms of VkPresentModeKHR. Only windowed runs have a swapchain,
+// headless runs ignore it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum PresentMode {
+  // Vsync with a queue of frames, the display takes them in order. Every surface supports it and the
+  // GPU idles once the queue is full, which saves power.
+  Fifo,
+  // Fifo, but a frame that misses the vertical blank is shown right away and tears.
+  FifoRelaxed,
+  // Vsync without waiting: the newest frame replaces the queued one, lowest latency without tearing.
+  #[default]
+  Mailbox,
+  // No vsync, frames are shown as soon as they are done and may tear.
+  Immediate,
+}
+
+impl PresentMode {
+  pub fn vk(&self) -> vk::PresentModeKHR {
+    match self {
+      PresentMode::Fifo => vk::PresentModeKHR::FIFO,
+      PresentMode::FifoRelaxed => vk::PresentModeKHR::FIFO_RELAXED,
+      PresentMode::Mailbox => vk::PresentModeKHR::MAILBOX,
+      PresentMode::Immediate => vk::PresentModeKHR::IMMEDIATE,
+    }
+  }
+}
+
+impl FromStr for PresentMode {
+  type Err = String;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    match value {
+      "fifo" => Ok(PresentMode::Fifo),
+      "fifo-relaxed" => Ok(PresentMode::FifoRelaxed),
+      "mailbox" => Ok(PresentMode::Mailbox),
+      "immediate" => Ok(PresentMode::Immediate),
+      _ => Err("expected fifo, fifo-relaxed, mailbox or immediate".to_string()),
+    }
+  }
+}
+
+impl fmt::Display for PresentMode {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let name = match self {
+      PresentMode::Fifo => "fifo",
+      PresentMode::FifoRelaxed => "fifo-relaxed",
+      PresentMode::Mailbox => "mailbox",
+      PresentMode::Immediate => "immediate",
+    };
+    f.write_str(name)
+  }
+}
+
+// `requested` if the surface supports it, one of `supported` as queried for the selected device,
+// otherwise FIFO, which every surface has to support.
+pub fn choose_present_mode(supported: &[vk::PresentModeKHR], requested: PresentMode) -> PresentMode {
+  if requested == PresentMode::Fifo || supported.contains(&requested.vk()) {
+    return requested;
+  }
+  warn!("The surface doesn't support the {} present mode (supported: {:?}), falling back to fifo", requested, supported);
+  PresentMode::Fifo
+}
@@ -0,0 +1,53 @@
+use std::time::{Duration, Instant};
+
+use crate::sampling::SamplingStrategy;
+
+// Path length while previewing: the primary hit and its shadow rays, i.e. direct lighting only.
+// Clean enough to aim the camera, and it converges within a few frames.
+pub const PREVIEW_BOUNCES: u32 = 1;
+// Without a second bounce there is no BSDF sample to weight the sky's shadow ray against, so the
+// preview takes light samples only.
+pub const PREVIEW_SAMPLING: SamplingStrategy = SamplingStrategy::Light;
+
+// "Interactive preview, converge on idle": while the camera moves, render the cheap preview,
+// once it has been still for `idle_threshold` switch to full path tracing.
+#[derive(Debug)]
+pub struct Preview {
+  idle_threshold: Duration,
+  last_change: Option<Instant>,
+  active: bool,
+}
+
+impl Preview {
+  // A zero idle threshold disables the preview.
+  pub fn new(idle_threshold: Duration) -> Self {
+    Self {
+      idle_threshold,
+      last_change: None,
+      active: false,
+    }
+  }
+
+  // Call whenever the view changes. Accumulation has to restart either way, the old samples show the old view.
+  #[allow(dead_code)]
+  pub fn camera_changed(&mut self) {
+    if !self.idle_threshold.is_zero() {
+      self.last_change = Some(Instant::now());
+      self.active = true;
+    }
+  }
+
+  // Whether the last update returned a preview sample.
+  pub fn is_active(&self) -> bool {
+    self.active
+  }
+
+  // Returns whether the next sample is a preview sample, and whether the mode changed since the
+  // last call. Preview and full samples estimate different images, so a change must restart accumulation.
+  pub fn update(&mut self) -> (bool, bool) {
+    let active = self.last_change.map_or(false, |last_change| last_change.elapsed() < self.idle_threshold);
+    let changed = active != self.active;
+    self.active = active;
+    (active, changed)
+  }
+}
@@ -0,0 +1,127 @@
+use anyhow::Result;
+use ash::vk;
+use bytemuck::{Pod, Zeroable};
+
+use phobos::prelude::*;
+use phobos::sync::domain::All;
+
+use crate::app::Context;
+use crate::compute::{run_on_compute_queue, ComputeKernel};
+use crate::error::{OxError, OxResult};
+use crate::memory::Buffer;
+
+// Device extensions for inline ray tracing, requested when RunnerConfig::ray_query is set.
+pub fn ray_query_extensions() -> Vec<String> {
+  vec![vk::KhrRayQueryFn::name().to_string_lossy().into_owned()]
+}
+
+// The rayQuery feature of VkPhysicalDeviceRayQueryFeaturesKHR, requested along with the extension,
+// see FeatureStructs::vulkan_13_chain. Devices without it are passed over at initialization.
+pub fn ray_query_features() -> Vec<String> {
+  vec!["ray_query".to_string()]
+}
+
+// Call it before creating a compute or graphics pipeline whose shaders include ray_query.glsl. The
+// device support is checked at initialization when RunnerConfig::ray_query is set, so this only
+// fails if it wasn't.
+#[allow(dead_code)]
+pub fn ensure_ray_query(ctx: &Context) -> OxResult<()> {
+  if ctx.config.ray_query {
+    return Ok(());
+  }
+  Err(OxError::Unsupported {
+    what: "ray queries".to_string(),
+    reason: "VK_KHR_ray_query is not enabled, set RunnerConfig::ray_query".to_string(),
+  })
+}
+
+// Mirrors QueryRay in ray_query_trace.comp (std430).
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+pub struct QueryRay {
+  pub origin: [f32; 3],
+  pub t_min: f32,
+  pub direction: [f32; 3],
+  pub t_max: f32,
+}
+
+// Mirrors QueryHit in ray_query_trace.comp (std430), the fields of RayQueryHit in ray_query.glsl.
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+pub struct QueryHit {
+  // Negative if the ray missed.
+  pub t: f32,
+  pub instance: i32,
+  pub primitive: i32,
+  pub front_face: u32,
+  pub barycentrics: [f32; 2],
+  _pad: [f32; 2],
+}
+
+// Closest hits of a batch of rays for the CPU, e.g. to find what lies under the cursor, traced with
+// ray queries in a compute kernel instead of the ray tracing pipeline.
+#[allow(dead_code)]
+pub struct RayQueryTracer {
+  kernel: ComputeKernel,
+}
+
+#[allow(dead_code)]
+impl RayQueryTracer {
+  pub fn new(ctx: &mut Context) -> OxResult<Self> {
+    ensure_ray_query(ctx)?;
+    Ok(Self {
+      kernel: ctx.compute_pipeline("ray_query_trace", "resources/shaders/ray_query_trace_comp.spv")?,
+    })
+  }
+
+  // Registers the kernel's pipeline again, in App::reload_shaders after its shader was recompiled.
+  pub fn create_pipelines(&self, ctx: &mut Context) -> OxResult<()> {
+    self.kernel.create_pipeline(ctx)
+  }
+
+  // Traces `rays` against `tlas` on the compute queue and waits for the hits, None for a miss.
+  pub fn trace(&self, ctx: &mut Context, tlas: &AccelerationStructure, rays: &[QueryRay]) -> Result<Vec<Option<QueryHit>>> {
+    if rays.is_empty() {
+      return Ok(Vec::new());
+    }
+    let size = |stride: usize| (rays.len() * stride) as u64;
+    let input = Buffer::new(
+      ctx.device.clone(),
+      &mut ctx.allocator,
+      size(std::mem::size_of::<QueryRay>()),
+      vk::BufferUsageFlags::STORAGE_BUFFER,
+      MemoryType::CpuToGpu,
+    )?;
+    input.view_full().mapped_slice::<QueryRay>()?.copy_from_slice(rays);
+    let output = Buffer::new(
+      ctx.device.clone(),
+      &mut ctx.allocator,
+      size(std::mem::size_of::<QueryHit>()),
+      vk::BufferUsageFlags::STORAGE_BUFFER,
+      MemoryType::GpuToCpu,
+    )?;
+    let count = rays.len() as u32;
+    run_on_compute_queue(ctx, &[], &[&input, &output], |cmd| {
+      let cmd = self
+        .kernel
+        .bind(cmd)?
+        .bind_acceleration_structure(0, 0, tlas)?
+        .bind_storage_buffer(0, 1, &input.view_full())?
+        .bind_storage_buffer(0, 2, &output.view_full())?
+        .push_constant(vk::ShaderStageFlags::COMPUTE, 0, &count);
+      self.kernel.dispatch(cmd, self.kernel.groups_for(count, 1, 1))
+    })?;
+    let hits = output.view_full().mapped_slice::<QueryHit>()?.iter().map(|hit| (hit.t >= 0.0).then_some(*hit)).collect();
+    Ok(hits)
+  }
+}
+
+// Binds `tlas` for ray_query.glsl, at the RAY_QUERY_TLAS_BINDING the shader was compiled with. The
+// compute or graphics pipeline has to be bound first, its layout comes from the shader's reflection
+// like every other binding. The raytrace pass binds the scene TLAS at binding 0 the same way.
+#[allow(dead_code)]
+pub fn bind_scene_tlas<'q>(cmd: IncompleteCommandBuffer<'q, All>, binding: u32, tlas: &AccelerationStructure) -> Result<IncompleteCommandBuffer<'q, All>> {
+  Ok(cmd.bind_acceleration_structure(0, binding, tlas)?)
+}
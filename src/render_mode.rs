@@ -0,0 +1,109 @@
+use std::fmt;
+use std::str::FromStr;
+
+use glam::Vec3;
+
+use crate::rng::Rng;
+
+// What the raygen shader computes per pixel.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum RenderMode {
+  #[default]
+  PathTrace,
+  // Grayscale ambient occlusion of the primary hit, to sanity check geometry and normals.
+  AmbientOcclusion(AoSettings),
+}
+
+// Values of RenderParams::mode in raygen.rgen.
+pub const MODE_PATH_TRACE: u32 = 0;
+pub const MODE_AMBIENT_OCCLUSION: u32 = 1;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AoSettings {
+  // Hemisphere rays per pixel and sample.
+  pub samples: u32,
+  // Occluders further away than this don't count.
+  pub radius: f32,
+}
+
+impl Default for AoSettings {
+  fn default() -> Self {
+    Self {
+      samples: 4,
+      radius: 1.0,
+    }
+  }
+}
+
+// "ao" parses to ambient occlusion with the default AoSettings.
+impl FromStr for RenderMode {
+  type Err = String;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    match value {
+      "path" => Ok(RenderMode::PathTrace),
+      "ao" => Ok(RenderMode::AmbientOcclusion(AoSettings::default())),
+      _ => Err("expected path or ao".to_string()),
+    }
+  }
+}
+
+impl fmt::Display for RenderMode {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let name = match self {
+      RenderMode::PathTrace => "path",
+      RenderMode::AmbientOcclusion(_) => "ao",
+    };
+    f.write_str(name)
+  }
+}
+
+impl RenderMode {
+  pub fn id(&self) -> u32 {
+    match self {
+      RenderMode::PathTrace => MODE_PATH_TRACE,
+      RenderMode::AmbientOcclusion(_) => MODE_AMBIENT_OCCLUSION,
+    }
+  }
+
+  // The settings RenderParams::ao_samples and ao_radius carry, None when path tracing.
+  pub fn ao_settings(&self) -> Option<AoSettings> {
+    match self {
+      RenderMode::PathTrace => None,
+      RenderMode::AmbientOcclusion(settings) => Some(*settings),
+    }
+  }
+}
+
+// CPU reference for ambient occlusion of a point on a flat surface, with a second, parallel
+// surface `height` above it as the only occluder.
+//
+// With cosine-weighted directions a ray at angle theta from the normal reaches the occluder
+// after height / cos(theta), so it is occluded when cos(theta) > height / radius. For
+// cosine-weighted sampling P(cos(theta) > c) = 1 - c^2, which leaves a visibility of
+// (height / radius)^2, and 1 when the occluder is out of range.
+#[allow(dead_code)]
+pub fn flat_surface_visibility(height: f32, radius: f32) -> f32 {
+  if height >= radius {
+    1.0
+  } else {
+    (height / radius).powi(2)
+  }
+}
+
+// Monte Carlo estimate of flat_surface_visibility with the shader's sampling, converging to it.
+#[allow(dead_code)]
+pub fn estimate_flat_surface_visibility(height: f32, radius: f32, samples: u32, rng: &mut Rng) -> f32 {
+  let mut visible = 0;
+  for _ in 0..samples {
+    // Same mapping as cosine_sample_hemisphere, around +z.
+    let (u, v) = rng.next_vec2();
+    let r = u.sqrt();
+    let phi = 2.0 * std::f32::consts::PI * v;
+    let direction = Vec3::new(r * phi.cos(), r * phi.sin(), (1.0 - u).max(0.0).sqrt());
+    if direction.z * radius <= height {
+      visible += 1;
+    }
+  }
+  visible as f32 / samples as f32
+}
@@ -0,0 +1,88 @@
+// CPU mirror of resources/shaders/random.glsl, producing the exact same sequence as the
+// shaders for a given pixel, sample index and base seed. Useful to reproduce a single
+// pixel's samples on the host, keep both in sync when changing either.
+
+#[allow(dead_code)]
+pub fn pcg_hash(v: u32) -> u32 {
+  let state = v.wrapping_mul(747796405).wrapping_add(2891336453);
+  let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+  (word >> 22) ^ word
+}
+
+// Base seed of frame `frame` of an image sequence, so consecutive frames don't repeat the same noise
+// pattern, which would look like a static overlay in the video.
+pub fn frame_seed(base_seed: u32, frame: u32) -> u32 {
+  base_seed ^ pcg_hash(frame.wrapping_add(1))
+}
+
+#[allow(dead_code)]
+pub struct Rng {
+  state: u32,
+}
+
+#[allow(dead_code)]
+impl Rng {
+  pub fn new(pixel: (u32, u32), sample_index: u32, base_seed: u32) -> Self {
+    let seed = pcg_hash(pixel.0.wrapping_add(pcg_hash(pixel.1.wrapping_add(pcg_hash(sample_index.wrapping_add(pcg_hash(base_seed)))))));
+    Self {
+      state: seed,
+    }
+  }
+
+  pub fn next_u32(&mut self) -> u32 {
+    self.state = pcg_hash(self.state);
+    self.state
+  }
+
+  // Same conversion as rng_next_float, including the f32 rounding of the division.
+  pub fn next_f32(&mut self) -> f32 {
+    self.next_u32() as f32 / 4294967296.0
+  }
+
+  pub fn next_vec2(&mut self) -> (f32, f32) {
+    let x = self.next_f32();
+    (x, self.next_f32())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn same_seed_gives_same_sequence() {
+    let mut a = Rng::new((3, 7), 5, 42);
+    let mut b = Rng::new((3, 7), 5, 42);
+    for _ in 0..64 {
+      assert_eq!(a.next_u32(), b.next_u32());
+    }
+  }
+
+  #[test]
+  fn seed_changes_the_sequence() {
+    let first = |pixel, sample_index, base_seed| Rng::new(pixel, sample_index, base_seed).next_u32();
+    let reference = first((3, 7), 5, 42);
+    assert_ne!(first((3, 7), 5, 43), reference);
+    assert_ne!(first((3, 7), 6, 42), reference);
+    assert_ne!(first((4, 7), 5, 42), reference);
+    assert_ne!(first((3, 8), 5, 42), reference);
+  }
+
+  // Pinned values, a change here means renders with the same seed no longer match older ones.
+  #[test]
+  fn sequence_is_pinned() {
+    assert_eq!(pcg_hash(0), 129708002);
+    assert_eq!(pcg_hash(1), 2831084092);
+    let mut rng = Rng::new((3, 7), 0, 0);
+    assert_eq!([rng.next_u32(), rng.next_u32(), rng.next_u32()], [3632206384, 686419595, 70583916]);
+  }
+
+  #[test]
+  fn floats_lie_in_the_unit_interval() {
+    let mut rng = Rng::new((0, 0), 0, 0);
+    for _ in 0..1024 {
+      let x = rng.next_f32();
+      assert!((0.0..=1.0).contains(&x));
+    }
+  }
+}
@@ -0,0 +1,246 @@
+use std::f32::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+
+use glam::Vec3;
+
+use crate::rng::Rng;
+
+// How a diffuse hit gathers light from the sky, the scene's only light. All strategies converge to
+// the same image, they differ only in noise: BSDF sampling is best where the sky is seen through a
+// narrow opening, light sampling where surfaces face it but most reflected rays hit geometry.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum SamplingStrategy {
+  // Cosine-weighted bounce directions only, the sky counts when a bounce misses the scene.
+  Bsdf,
+  // A shadow ray towards a uniformly sampled sky direction at every diffuse hit, bounces that
+  // miss the scene after a diffuse hit are ignored.
+  Light,
+  // Both, combined with multiple importance sampling.
+  #[default]
+  Mis,
+  // Like Mis with the balance instead of the power heuristic.
+  MisBalance,
+}
+
+impl SamplingStrategy {
+  pub const BSDF: u32 = 0;
+  pub const LIGHT: u32 = 1;
+  pub const MIS_POWER: u32 = 2;
+  pub const MIS_BALANCE: u32 = 3;
+
+  // Value of RenderParams::sampling.
+  pub fn id(&self) -> u32 {
+    match self {
+      SamplingStrategy::Bsdf => Self::BSDF,
+      SamplingStrategy::Light => Self::LIGHT,
+      SamplingStrategy::Mis => Self::MIS_POWER,
+      SamplingStrategy::MisBalance => Self::MIS_BALANCE,
+    }
+  }
+
+  // The strategy after this one, wrapping around, for switching live.
+  pub fn next(&self) -> Self {
+    match self {
+      SamplingStrategy::Bsdf => SamplingStrategy::Light,
+      SamplingStrategy::Light => SamplingStrategy::Mis,
+      SamplingStrategy::Mis => SamplingStrategy::MisBalance,
+      SamplingStrategy::MisBalance => SamplingStrategy::Bsdf,
+    }
+  }
+
+  // Weight of a sample with density `pdf` when the other strategy would have produced it with `other_pdf`.
+  pub fn mis_weight(&self, pdf: f32, other_pdf: f32) -> f32 {
+    match self {
+      SamplingStrategy::Bsdf | SamplingStrategy::Light => 1.0,
+      SamplingStrategy::Mis => pdf * pdf / (pdf * pdf + other_pdf * other_pdf),
+      SamplingStrategy::MisBalance => pdf / (pdf + other_pdf),
+    }
+  }
+}
+
+impl FromStr for SamplingStrategy {
+  type Err = String;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    match value {
+      "bsdf" => Ok(SamplingStrategy::Bsdf),
+      "light" => Ok(SamplingStrategy::Light),
+      "mis" => Ok(SamplingStrategy::Mis),
+      "mis-balance" => Ok(SamplingStrategy::MisBalance),
+      _ => Err("expected bsdf, light, mis or mis-balance".to_string()),
+    }
+  }
+}
+
+impl fmt::Display for SamplingStrategy {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let name = match self {
+      SamplingStrategy::Bsdf => "bsdf",
+      SamplingStrategy::Light => "light",
+      SamplingStrategy::Mis => "mis",
+      SamplingStrategy::MisBalance => "mis-balance",
+    };
+    f.write_str(name)
+  }
+}
+
+// Same gradient as sky_color in raygen.rgen.
+pub fn sky_color(direction: Vec3) -> Vec3 {
+  let t = 0.5 * (direction.normalize().y + 1.0);
+  (1.0 - t) * Vec3::ONE + t * Vec3::new(0.5, 0.7, 1.0)
+}
+
+// Exact radiance reflected by an unoccluded Lambertian surface with `normal` under the sky. The sky is
+// linear in y, L = a + b y, which integrates to an irradiance of PI a + 2 PI / 3 b n.y.
+#[allow(dead_code)]
+pub fn flat_surface_radiance(albedo: Vec3, normal: Vec3) -> Vec3 {
+  let a = 0.5 * (Vec3::ONE + Vec3::new(0.5, 0.7, 1.0));
+  let b = 0.5 * (Vec3::new(0.5, 0.7, 1.0) - Vec3::ONE);
+  albedo * (a + b * (2.0 / 3.0) * normal.normalize().y)
+}
+
+// Chance that a hit on a surface with `roughness` sends a shadow ray, mirrors nee_probability in
+// raygen.rgen. On smooth surfaces the BSDF lobe is narrow, so a uniformly sampled light direction
+// almost never lies inside it and the shadow ray is wasted, while the bounce finds the light on its
+// own. Below `threshold` the shadow ray is skipped, up to twice the threshold it is taken with rising
+// probability to avoid a visible seam between materials. Skipping at random stays unbiased under MIS
+// as long as the bounce's weight uses the light pdf scaled by this probability and taken shadow rays
+// are divided by it. Light-only sampling can't skip, it ignores bounces that hit the light.
+pub fn nee_probability(strategy: SamplingStrategy, roughness: f32, threshold: f32) -> f32 {
+  if strategy == SamplingStrategy::Light || threshold <= 0.0 {
+    return 1.0;
+  }
+  (roughness / threshold - 1.0).clamp(0.0, 1.0)
+}
+
+// Monte Carlo estimate of flat_surface_radiance with the shader's sampling for `strategy`, one BSDF
+// and (except for Bsdf) one light sample per iteration, the latter taken with nee_probability for
+// `roughness` and `threshold`. Converges to the same value for every strategy and threshold, which is
+// what makes skipping shadow rays safe.
+#[allow(dead_code)]
+pub fn estimate_flat_surface_radiance(
+  strategy: SamplingStrategy,
+  albedo: Vec3,
+  normal: Vec3,
+  roughness: f32,
+  threshold: f32,
+  samples: u32,
+  rng: &mut Rng,
+) -> Vec3 {
+  let normal = normal.normalize();
+  let (tangent, bitangent) = normal.any_orthonormal_pair();
+  let nee = nee_probability(strategy, roughness, threshold);
+  let light_pdf = nee / (4.0 * PI);
+  let mut sum = Vec3::ZERO;
+  for _ in 0..samples {
+    let take_light_sample = rng.next_f32() < nee;
+    if strategy != SamplingStrategy::Bsdf && take_light_sample {
+      let direction = uniform_sample_sphere(rng.next_vec2());
+      let cos = direction.dot(normal);
+      if cos > 0.0 {
+        let bsdf_pdf = cos / PI;
+        sum += albedo / PI * cos * sky_color(direction) * (4.0 * PI) * strategy.mis_weight(light_pdf, bsdf_pdf) / nee;
+      }
+    }
+    // Cosine weighting cancels the cosine and 1 / PI, leaving the albedo.
+    let (u, v) = rng.next_vec2();
+    let (r, phi) = (u.sqrt(), 2.0 * PI * v);
+    let cos = (1.0 - u).max(0.0).sqrt();
+    let direction = tangent * (r * phi.cos()) + bitangent * (r * phi.sin()) + normal * cos;
+    if strategy != SamplingStrategy::Light {
+      sum += albedo * sky_color(direction) * strategy.mis_weight(cos / PI, light_pdf);
+    }
+  }
+  sum / samples as f32
+}
+
+// Same mapping as uniform_sample_sphere in sampling.glsl, pdf = 1 / (4 PI).
+pub fn uniform_sample_sphere((u, v): (f32, f32)) -> Vec3 {
+  let z = 1.0 - 2.0 * u;
+  let r = (1.0 - z * z).max(0.0).sqrt();
+  let phi = 2.0 * PI * v;
+  Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const ALL: [SamplingStrategy; 4] = [SamplingStrategy::Bsdf, SamplingStrategy::Light, SamplingStrategy::Mis, SamplingStrategy::MisBalance];
+  const SAMPLES: u32 = 200_000;
+
+  fn assert_close(estimate: Vec3, exact: Vec3, what: &str) {
+    let error = ((estimate - exact).abs() / exact).max_element();
+    assert!(error < 0.01, "{}: estimated {} but the exact value is {}", what, estimate, exact);
+  }
+
+  #[test]
+  fn strategies_converge_to_the_same_mean() {
+    let albedo = Vec3::new(0.8, 0.5, 0.2);
+    for normal in [Vec3::Y, Vec3::new(1.0, 1.0, 0.0), Vec3::X, Vec3::new(0.3, -1.0, 0.2)] {
+      let exact = flat_surface_radiance(albedo, normal);
+      for strategy in ALL {
+        let mut rng = Rng::new((1, 2), 0, 7);
+        let estimate = estimate_flat_surface_radiance(strategy, albedo, normal, 1.0, 0.0, SAMPLES, &mut rng);
+        assert_close(estimate, exact, &format!("{} with normal {}", strategy, normal));
+      }
+    }
+  }
+
+  // Skipped shadow rays are made up for by the bounces, whether they are skipped always (roughness
+  // below the threshold), sometimes (up to twice the threshold) or never.
+  #[test]
+  fn skipping_shadow_rays_keeps_the_mean() {
+    let albedo = Vec3::new(0.8, 0.5, 0.2);
+    let normal = Vec3::new(1.0, 1.0, 0.0);
+    let exact = flat_surface_radiance(albedo, normal);
+    let threshold = 0.2;
+    for strategy in [SamplingStrategy::Mis, SamplingStrategy::MisBalance] {
+      for roughness in [0.05, 0.25, 0.3, 0.35, 1.0] {
+        let mut rng = Rng::new((5, 3), 0, 11);
+        let estimate = estimate_flat_surface_radiance(strategy, albedo, normal, roughness, threshold, SAMPLES, &mut rng);
+        assert_close(estimate, exact, &format!("{} at roughness {}", strategy, roughness));
+      }
+    }
+  }
+
+  #[test]
+  fn nee_probability_ramps_between_the_threshold_and_twice_it() {
+    let strategy = SamplingStrategy::Mis;
+    assert_eq!(nee_probability(strategy, 0.1, 0.2), 0.0);
+    assert_eq!(nee_probability(strategy, 0.2, 0.2), 0.0);
+    assert!((nee_probability(strategy, 0.3, 0.2) - 0.5).abs() < 1.0e-6);
+    assert_eq!(nee_probability(strategy, 0.4, 0.2), 1.0);
+    // Disabled by a zero threshold, and light-only sampling has nothing else to find the sky with.
+    assert_eq!(nee_probability(strategy, 0.0, 0.0), 1.0);
+    assert_eq!(nee_probability(SamplingStrategy::Light, 0.0, 0.2), 1.0);
+  }
+
+  // Both heuristics split every direction between the two strategies without losing any of it.
+  #[test]
+  fn mis_weights_sum_to_one() {
+    for strategy in [SamplingStrategy::Mis, SamplingStrategy::MisBalance] {
+      for (pdf, other_pdf) in [(0.1f32, 0.3f32), (1.0, 1.0), (2.5, 0.01)] {
+        let sum = strategy.mis_weight(pdf, other_pdf) + strategy.mis_weight(other_pdf, pdf);
+        assert!((sum - 1.0).abs() < 1.0e-6, "{} weights of {} and {} sum to {}", strategy, pdf, other_pdf, sum);
+      }
+    }
+  }
+
+  #[test]
+  fn names_round_trip() {
+    for strategy in ALL {
+      assert_eq!(strategy.to_string().parse::<SamplingStrategy>(), Ok(strategy));
+    }
+  }
+
+  #[test]
+  fn next_visits_every_strategy() {
+    let mut strategy = SamplingStrategy::Bsdf;
+    for expected in ALL.iter().cycle().skip(1).take(ALL.len()) {
+      strategy = strategy.next();
+      assert_eq!(strategy, *expected);
+    }
+  }
+}
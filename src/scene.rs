@@ -0,0 +1,534 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context as _, Result};
+use ash::vk;
+use glam::{Mat4, Vec3};
+use log::{info, warn};
+use serde::Deserialize;
+
+use phobos::prelude::*;
+use phobos::sync::domain::All;
+
+use crate::accel::{build_blas, BackedAccelerationStructure};
+use crate::app::Context;
+use crate::bounds::Aabb;
+use crate::error::{OxError, OxResult};
+use crate::light::{emissive_triangles, Light};
+use crate::material::Material;
+use crate::memory::Buffer;
+use crate::mesh::{compute_tangents, group_by_material, GeometryGroup, GeometryInfo, IndexFormat, MeshIndices};
+use crate::texture::TextureArray;
+use crate::upload::{StagedBuffer, Uploaded, Uploader};
+
+// A mesh of a loaded scene in device local buffers, ready for build_blas and the geometry table.
+// Every primitive of the glTF mesh is one group, its `material` indexes Scene::materials.
+pub struct SceneMesh {
+  pub name: Option<String>,
+  // Tightly packed xyz positions.
+  pub vertices: Buffer,
+  pub normals: Buffer,
+  // None if no primitive of the mesh has texture coordinates.
+  pub uvs: Option<Buffer>,
+  // None if no primitive of the mesh is normal mapped.
+  pub tangents: Option<Buffer>,
+  // In MeshIndices::buffer_words layout.
+  pub indices: Buffer,
+  pub index_format: IndexFormat,
+  pub groups: Vec<GeometryGroup>,
+  // Object space bounds.
+  pub bounds: Aabb,
+}
+
+impl SceneMesh {
+  // Geometry table entries of the mesh, one per group in order, like its material table entries.
+  pub fn geometry_infos(&self) -> Vec<GeometryInfo> {
+    self
+      .groups
+      .iter()
+      .map(|group| GeometryInfo::new(&self.vertices, &self.indices, self.index_format, group).with_attributes(Some(&self.normals), self.uvs.as_ref(), self.tangents.as_ref()))
+      .collect()
+  }
+
+  // `opaque` holds one entry per group, see build_blas.
+  pub fn build_blas(&self, ctx: &mut Context, opaque: &[bool]) -> Result<BackedAccelerationStructure> {
+    build_blas(ctx, &self.vertices, &self.indices, self.index_format, &self.groups, opaque, true)
+  }
+}
+
+// A node referring to a mesh, with its world transform.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SceneInstance {
+  // Index into Scene::meshes.
+  pub mesh: usize,
+  pub transform: Mat4,
+  // Index into Scene::lights of the first triangle light of this instance. Its emissive groups follow
+  // from there in order, one light per triangle, see emissive_triangles.
+  pub first_light: u32,
+}
+
+pub struct Scene {
+  pub meshes: Vec<SceneMesh>,
+  // The glTF materials in document order, followed by a default one if some primitive has none.
+  pub materials: Vec<Material>,
+  pub instances: Vec<SceneInstance>,
+  // Images the materials' texture indices refer to, in the order they were first used.
+  pub textures: TextureArray,
+  // Every emissive triangle of every instance in world space and the point lights, with their power
+  // for LightTable::new.
+  pub lights: Vec<(Light, f32)>,
+}
+
+impl Scene {
+  // World space bounds of all instances.
+  pub fn bounds(&self) -> Aabb {
+    self
+      .instances
+      .iter()
+      .fold(Aabb::EMPTY, |bounds, instance| bounds.union(&self.meshes[instance.mesh].bounds.transformed(&instance.transform)))
+  }
+
+  // A single untransformed mesh of unshared triangles, for built-in geometry. `positions` holds 3 floats
+  // per vertex, every vertex is used once in order. `lights` join the emissive triangles.
+  pub fn from_triangles(ctx: &mut Context, positions: &[f32], groups: Vec<GeometryGroup>, materials: Vec<Material>, lights: &[Light]) -> Result<Self> {
+    let corners = positions.chunks_exact(3).map(|p| [p[0], p[1], p[2]]).collect::<Vec<_>>();
+    let (_, normals, _) = flat_normals(&corners, None, &(0..corners.len() as u32).collect::<Vec<_>>());
+    let data = MeshData {
+      positions: positions.to_vec(),
+      normals: normals.iter().flatten().copied().collect(),
+      uvs: Vec::new(),
+      has_uvs: false,
+      tangents: Vec::new(),
+      has_tangents: false,
+      indices: (0..corners.len() as u32).collect(),
+      groups,
+    };
+    let mut uploader = Uploader::new(ctx);
+    let staged = stage_mesh(&mut uploader, None, &data)?;
+    let mesh = staged.take(&mut uploader.flush::<All>()?)?;
+    let textures = TextureArray::new();
+    let mut scene_lights = lights.iter().map(|&light| (light, light.power([1.0; 3]))).collect::<Vec<_>>();
+    let first_light = scene_lights.len() as u32;
+    scene_lights.extend(mesh_lights(&data, &Mat4::IDENTITY, &materials, &textures));
+    Ok(Self {
+      meshes: vec![mesh],
+      materials,
+      instances: vec![SceneInstance {
+        mesh: 0,
+        transform: Mat4::IDENTITY,
+        first_light,
+      }],
+      textures,
+      lights: scene_lights,
+    })
+  }
+}
+
+// Vertex data of a mesh on the CPU, all primitives appended.
+#[derive(Default)]
+struct MeshData {
+  positions: Vec<f32>,
+  normals: Vec<f32>,
+  uvs: Vec<f32>,
+  has_uvs: bool,
+  // 4 floats per vertex, see compute_tangents.
+  tangents: Vec<f32>,
+  has_tangents: bool,
+  indices: Vec<u32>,
+  groups: Vec<GeometryGroup>,
+}
+
+// The device local buffers of a mesh until the upload is flushed.
+struct StagedMesh {
+  name: Option<String>,
+  vertices: StagedBuffer,
+  normals: StagedBuffer,
+  uvs: Option<StagedBuffer>,
+  tangents: Option<StagedBuffer>,
+  indices: StagedBuffer,
+  index_format: IndexFormat,
+  groups: Vec<GeometryGroup>,
+  bounds: Aabb,
+}
+
+impl StagedMesh {
+  fn take(self, uploaded: &mut Uploaded) -> Result<SceneMesh> {
+    let mut take = |staged: StagedBuffer| uploaded.take_buffer(staged).ok_or_else(|| anyhow!("mesh buffer missing after upload"));
+    Ok(SceneMesh {
+      name: self.name,
+      vertices: take(self.vertices)?,
+      normals: take(self.normals)?,
+      uvs: self.uvs.map(&mut take).transpose()?,
+      tangents: self.tangents.map(&mut take).transpose()?,
+      indices: take(self.indices)?,
+      index_format: self.index_format,
+      groups: self.groups,
+      bounds: self.bounds,
+    })
+  }
+}
+
+// The glTF images a scene's materials use, added to the TextureArray the first time a material refers to them.
+struct SceneTextures<'a> {
+  images: &'a [gltf::image::Data],
+  array: TextureArray,
+  by_image: HashMap<usize, i32>,
+}
+
+impl SceneTextures<'_> {
+  // Index of the texture in the TextureArray, -1 for none. Only the first uv set is loaded, textures
+  // reading another one are dropped.
+  fn index(&mut self, texture: Option<(gltf::Texture, u32)>) -> Result<i32> {
+    let Some((texture, tex_coord)) = texture else {
+      return Ok(-1);
+    };
+    if tex_coord != 0 {
+      warn!("Ignoring texture {} which uses uv set {}, only the first one is supported", texture.index(), tex_coord);
+      return Ok(-1);
+    }
+    let image = texture.source().index();
+    if let Some(&index) = self.by_image.get(&image) {
+      return Ok(index);
+    }
+    let data = self.images.get(image).ok_or_else(|| anyhow!("texture {} refers to the missing image {}", texture.index(), image))?;
+    let index = self.array.add(to_rgba8(data).with_context(|| format!("in image {}", image))?);
+    self.by_image.insert(image, index);
+    Ok(index)
+  }
+}
+
+// Decoded glTF image data as 8 bit RGBA, 16 bit channels are rounded to 8 bits.
+fn to_rgba8(data: &gltf::image::Data) -> Result<image::RgbaImage> {
+  use gltf::image::Format;
+  let (width, height, pixels) = (data.width, data.height, &data.pixels);
+  let wide = || pixels.chunks_exact(2).map(|bytes| u16::from_ne_bytes([bytes[0], bytes[1]])).collect::<Vec<_>>();
+  let image = match data.format {
+    Format::R8 => image::GrayImage::from_raw(width, height, pixels.clone()).map(image::DynamicImage::ImageLuma8),
+    Format::R8G8 => image::GrayAlphaImage::from_raw(width, height, pixels.clone()).map(image::DynamicImage::ImageLumaA8),
+    Format::R8G8B8 => image::RgbImage::from_raw(width, height, pixels.clone()).map(image::DynamicImage::ImageRgb8),
+    Format::R8G8B8A8 => image::RgbaImage::from_raw(width, height, pixels.clone()).map(image::DynamicImage::ImageRgba8),
+    Format::R16G16B16 => image::ImageBuffer::from_raw(width, height, wide()).map(image::DynamicImage::ImageRgb16),
+    Format::R16G16B16A16 => image::ImageBuffer::from_raw(width, height, wide()).map(image::DynamicImage::ImageRgba16),
+    format => bail!("unsupported image format {:?}", format),
+  };
+  Ok(image.ok_or_else(|| anyhow!("image data doesn't match its {}x{} size", width, height))?.to_rgba8())
+}
+
+fn convert_material(material: &gltf::Material, textures: &mut SceneTextures) -> Result<Material> {
+  let pbr = material.pbr_metallic_roughness();
+  let base_color_texture = pbr.base_color_texture().map(|info| (info.texture(), info.tex_coord()));
+  let emissive_texture = material.emissive_texture().map(|info| (info.texture(), info.tex_coord()));
+  let normal_map = material.normal_texture().map(|normal| (normal.texture(), normal.tex_coord()));
+  // Alpha is coverage for shadow rays, see Material::shadow_transmission. glTF says to ignore it for
+  // opaque materials, and masks have no alpha cutoff here, so only blended materials keep it.
+  let mut base_color = pbr.base_color_factor();
+  if material.alpha_mode() != gltf::material::AlphaMode::Blend {
+    base_color[3] = 1.0;
+  }
+  Ok(Material {
+    base_color,
+    base_color_texture: textures.index(base_color_texture)?,
+    metallic: pbr.metallic_factor(),
+    roughness: pbr.roughness_factor(),
+    emission: material.emissive_factor(),
+    emissive_texture: textures.index(emissive_texture)?,
+    normal_map: textures.index(normal_map)?,
+    double_sided: material.double_sided() as u32,
+    ..Default::default()
+  })
+}
+
+// Normal of every triangle for all three of its corners, the triangles no longer share vertices.
+fn flat_normals(positions: &[[f32; 3]], uvs: Option<&[[f32; 2]]>, indices: &[u32]) -> (Vec<[f32; 3]>, Vec<[f32; 3]>, Option<Vec<[f32; 2]>>) {
+  let mut flat_positions = Vec::with_capacity(indices.len());
+  let mut normals = Vec::with_capacity(indices.len());
+  for triangle in indices.chunks_exact(3) {
+    let [p0, p1, p2] = [0, 1, 2].map(|corner| positions[triangle[corner] as usize]);
+    let normal = (Vec3::from(p1) - Vec3::from(p0)).cross(Vec3::from(p2) - Vec3::from(p0)).normalize_or_zero();
+    flat_positions.extend_from_slice(&[p0, p1, p2]);
+    normals.extend_from_slice(&[normal.to_array(); 3]);
+  }
+  let uvs = uvs.map(|uvs| indices.iter().map(|&index| uvs[index as usize]).collect());
+  (flat_positions, normals, uvs)
+}
+
+// Appends the triangles of `primitive` to `mesh` as one group. Primitives that aren't triangle lists are skipped.
+// A `normal_mapped` primitive with uvs gets the file's tangents, or computed ones if it has none.
+fn read_primitive(mesh: &mut MeshData, primitive: &gltf::Primitive, buffers: &[gltf::buffer::Data], material: u32, normal_mapped: bool) -> Result<()> {
+  if primitive.mode() != gltf::mesh::Mode::Triangles {
+    warn!("Skipping a primitive with mode {:?}, only triangle lists are supported", primitive.mode());
+    return Ok(());
+  }
+  let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(|data| &data.0[..]));
+  let positions = reader.read_positions().ok_or_else(|| anyhow!("primitive without positions"))?.collect::<Vec<_>>();
+  let uvs = reader.read_tex_coords(0).map(|uvs| uvs.into_f32().collect::<Vec<_>>());
+  let indices = match reader.read_indices() {
+    Some(indices) => indices.into_u32().collect::<Vec<_>>(),
+    None => (0..positions.len() as u32).collect(),
+  };
+  MeshIndices::new(&indices, positions.len())?;
+
+  let (positions, normals, uvs, indices, tangents) = match reader.read_normals() {
+    Some(normals) => (positions, normals.collect::<Vec<_>>(), uvs, indices, reader.read_tangents().map(|tangents| tangents.collect::<Vec<_>>())),
+    // The file's tangents belong to the shared vertices, flat shaded ones get computed tangents.
+    None => {
+      let (positions, normals, uvs) = flat_normals(&positions, uvs.as_deref(), &indices);
+      let indices = (0..positions.len() as u32).collect();
+      (positions, normals, uvs, indices, None)
+    }
+  };
+  let tangents = match (normal_mapped && uvs.is_some(), tangents) {
+    (false, _) => None,
+    (true, Some(tangents)) => Some(tangents),
+    (true, None) => compute_tangents(&positions, &normals, uvs.as_deref(), &indices),
+  };
+
+  let first_vertex = (mesh.positions.len() / 3) as u32;
+  mesh.groups.push(GeometryGroup {
+    first_index: mesh.indices.len() as u32,
+    triangle_count: (indices.len() / 3) as u32,
+    material,
+  });
+  mesh.indices.extend(indices.iter().map(|&index| first_vertex + index));
+  mesh.positions.extend(positions.iter().flatten());
+  mesh.normals.extend(normals.iter().flatten());
+  match uvs {
+    Some(uvs) => {
+      mesh.uvs.extend(uvs.iter().flatten());
+      mesh.has_uvs = true;
+    }
+    // Zeros, in case other primitives of the mesh have uvs.
+    None => mesh.uvs.resize(mesh.positions.len() / 3 * 2, 0.0),
+  }
+  match tangents {
+    Some(tangents) => {
+      mesh.tangents.extend(tangents.iter().flatten());
+      mesh.has_tangents = true;
+    }
+    // Zero tangents leave the normal unperturbed, see normal_map.glsl.
+    None => mesh.tangents.resize(mesh.positions.len() / 3 * 4, 0.0),
+  }
+  Ok(())
+}
+
+fn stage_mesh(uploader: &mut Uploader, name: Option<&str>, mesh: &MeshData) -> Result<StagedMesh> {
+  let vertex_count = mesh.positions.len() / 3;
+  let indices = MeshIndices::new(&mesh.indices, vertex_count)?;
+  let attributes = vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS;
+  let build_input = attributes | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR;
+  Ok(StagedMesh {
+    name: name.map(str::to_string),
+    vertices: uploader.stage_buffer(&mesh.positions, build_input | vk::BufferUsageFlags::VERTEX_BUFFER)?,
+    normals: uploader.stage_buffer(&mesh.normals, attributes)?,
+    uvs: if mesh.has_uvs {
+      Some(uploader.stage_buffer(&mesh.uvs, attributes)?)
+    } else {
+      None
+    },
+    tangents: if mesh.has_tangents {
+      Some(uploader.stage_buffer(&mesh.tangents, attributes)?)
+    } else {
+      None
+    },
+    indices: uploader.stage_buffer(indices.buffer_words().as_slice(), build_input | vk::BufferUsageFlags::INDEX_BUFFER)?,
+    index_format: indices.format(),
+    groups: mesh.groups.clone(),
+    bounds: Aabb::from_positions(&mesh.positions),
+  })
+}
+
+// Triangle lights of the emissive groups of `mesh`, placed by `transform`.
+fn mesh_lights(mesh: &MeshData, transform: &Mat4, materials: &[Material], textures: &TextureArray) -> Vec<(Light, f32)> {
+  if !mesh.groups.iter().any(|group| materials[group.material as usize].is_emissive()) {
+    return Vec::new();
+  }
+  let positions = mesh
+    .positions
+    .chunks_exact(3)
+    .flat_map(|p| transform.transform_point3(Vec3::from_slice(p)).to_array())
+    .collect::<Vec<_>>();
+  let uvs = mesh.has_uvs.then_some(mesh.uvs.as_slice());
+  emissive_triangles(&positions, uvs, &mesh.indices, &mesh.groups, materials, textures.images())
+}
+
+// Optional properties of a KHR_lights_punctual light in its `extras`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct LightExtras {
+  // Sphere radius of a point light in scene units, see Light::point.
+  radius: f32,
+}
+
+// A KHR_lights_punctual point light placed by `transform`, None for directional and spot lights,
+// which the tracer has no equivalent for. The radius scales with the largest axis of the transform.
+fn convert_light(light: &gltf::khr_lights_punctual::Light, transform: &Mat4) -> Result<Option<Light>> {
+  if !matches!(light.kind(), gltf::khr_lights_punctual::Kind::Point) {
+    return Ok(None);
+  }
+  let extras = match light.extras() {
+    Some(extras) => serde_json::from_str::<LightExtras>(extras.get()).context("invalid light extras")?,
+    None => LightExtras::default(),
+  };
+  let scale = [Vec3::X, Vec3::Y, Vec3::Z].map(|axis| transform.transform_vector3(axis).length()).into_iter().fold(0.0, f32::max);
+  let position = transform.transform_point3(Vec3::ZERO);
+  let intensity = Vec3::from(light.color()) * light.intensity();
+  Ok(Some(Light::point(position.into(), intensity.into(), extras.radius * scale)))
+}
+
+// Instances of every node below `node` that has a mesh and the lights of those with a light,
+// `parent` is the world transform of its parent.
+fn collect_instances(node: &gltf::Node, parent: Mat4, mesh_indices: &[Option<usize>], instances: &mut Vec<SceneInstance>, lights: &mut Vec<Light>) -> Result<()> {
+  let transform = parent * Mat4::from_cols_array_2d(&node.transform().matrix());
+  if let Some(mesh) = node.mesh().and_then(|mesh| mesh_indices[mesh.index()]) {
+    instances.push(SceneInstance {
+      mesh,
+      transform,
+      // Assigned by load_gltf along with the triangle lights.
+      first_light: 0,
+    });
+  }
+  if let Some(light) = node.light() {
+    let name = light.name().map_or_else(|| light.index().to_string(), str::to_string);
+    match convert_light(&light, &transform).with_context(|| format!("in light {}", name))? {
+      Some(light) => lights.push(light),
+      None => warn!("Skipping light {}, only point lights are supported", name),
+    }
+  }
+  for child in node.children() {
+    collect_instances(&child, transform, mesh_indices, instances, lights)?;
+  }
+  Ok(())
+}
+
+fn load(ctx: &mut Context, path: &Path) -> Result<Scene> {
+  let (document, buffers, images) = gltf::import(path)?;
+  let mut textures = SceneTextures {
+    images: &images,
+    array: TextureArray::new(),
+    by_image: HashMap::new(),
+  };
+  let mut materials = document
+    .materials()
+    .map(|material| convert_material(&material, &mut textures).with_context(|| format!("in material {}", material.name().unwrap_or("without a name"))))
+    .collect::<Result<Vec<_>>>()?;
+  let textures = textures.array;
+  let default_material = materials.len() as u32;
+
+  let mut uploader = Uploader::new(ctx);
+  let mut staged = Vec::new();
+  // CPU copies of the staged meshes, for the triangle lights of their instances.
+  let mut datas = Vec::new();
+  // Meshes without triangles are dropped, this maps glTF mesh indices to Scene::meshes.
+  let mut mesh_indices = Vec::new();
+  for mesh in document.meshes() {
+    let mut data = MeshData::default();
+    for primitive in mesh.primitives() {
+      let material = primitive.material().index().map_or(default_material, |index| index as u32);
+      let normal_mapped = materials.get(material as usize).map_or(false, |material| material.normal_map >= 0);
+      read_primitive(&mut data, &primitive, &buffers, material, normal_mapped).with_context(|| format!("in mesh {}", mesh.name().unwrap_or(&mesh.index().to_string())))?;
+    }
+    if data.groups.is_empty() {
+      mesh_indices.push(None);
+      continue;
+    }
+    // Primitives sharing a material become a single geometry.
+    let triangle_materials = data.groups.iter().flat_map(|group| std::iter::repeat(group.material).take(group.triangle_count as usize)).collect::<Vec<_>>();
+    (data.indices, data.groups) = group_by_material(&data.indices, &triangle_materials);
+    mesh_indices.push(Some(staged.len()));
+    staged.push(stage_mesh(&mut uploader, mesh.name(), &data)?);
+    datas.push(data);
+  }
+  if staged.iter().flat_map(|mesh| &mesh.groups).any(|group| group.material == default_material) {
+    materials.push(Material::default());
+  }
+  let mut uploaded = uploader.flush::<All>()?;
+  let meshes = staged.into_iter().map(|mesh| mesh.take(&mut uploaded)).collect::<Result<Vec<_>>>()?;
+
+  let mut instances = Vec::new();
+  let mut point_lights = Vec::new();
+  match document.default_scene().or_else(|| document.scenes().next()) {
+    Some(scene) => {
+      for node in scene.nodes() {
+        collect_instances(&node, Mat4::IDENTITY, &mesh_indices, &mut instances, &mut point_lights)?;
+      }
+    }
+    // Without a scene every mesh is shown once, untransformed.
+    None => instances.extend((0..meshes.len()).map(|mesh| SceneInstance {
+      mesh,
+      transform: Mat4::IDENTITY,
+      first_light: 0,
+    })),
+  }
+  let mut lights = Vec::new();
+  for instance in &mut instances {
+    instance.first_light = lights.len() as u32;
+    lights.extend(mesh_lights(&datas[instance.mesh], &instance.transform, &materials, &textures));
+  }
+  let triangle_lights = lights.len();
+  lights.extend(point_lights.iter().map(|&light| (light, light.power([1.0; 3]))));
+  info!(
+    "Loaded {} with {} meshes, {} materials, {} textures, {} instances, {} emissive triangles and {} point lights",
+    path.display(),
+    meshes.len(),
+    materials.len(),
+    textures.images().len(),
+    instances.len(),
+    triangle_lights,
+    point_lights.len()
+  );
+  Ok(Scene {
+    meshes,
+    materials,
+    instances,
+    textures,
+    lights,
+  })
+}
+
+// Loads a .gltf or .glb file, its geometry uploaded through the transfer queue. Materials take the
+// base color, metallic, roughness and emissive factors along with the base color, emissive and
+// normal textures of the first uv set. KHR_lights_punctual point lights become Light::point, with
+// the radius from the light's extras.
+pub fn load_gltf(ctx: &mut Context, path: impl AsRef<Path>) -> OxResult<Scene> {
+  let path = path.as_ref();
+  load(ctx, path).map_err(|e| OxError::scene_load(path, e))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // Materials of a document without images, with the same base color in every alpha mode.
+  fn convert_materials() -> Vec<Material> {
+    let json = r#"{
+      "asset": { "version": "2.0" },
+      "materials": [
+        { "alphaMode": "OPAQUE", "pbrMetallicRoughness": { "baseColorFactor": [1.0, 0.5, 0.25, 0.4] } },
+        { "alphaMode": "MASK", "pbrMetallicRoughness": { "baseColorFactor": [1.0, 0.5, 0.25, 0.4] } },
+        { "alphaMode": "BLEND", "pbrMetallicRoughness": { "baseColorFactor": [1.0, 0.5, 0.25, 0.4] } }
+      ]
+    }"#;
+    let gltf = gltf::Gltf::from_slice(json.as_bytes()).unwrap();
+    let mut textures = SceneTextures {
+      images: &[],
+      array: TextureArray::new(),
+      by_image: HashMap::new(),
+    };
+    gltf.materials().map(|material| convert_material(&material, &mut textures).unwrap()).collect()
+  }
+
+  #[test]
+  fn opaque_materials_ignore_alpha() {
+    let materials = convert_materials();
+    assert_eq!(materials[0].base_color, [1.0, 0.5, 0.25, 1.0]);
+    assert_eq!(materials[1].base_color, [1.0, 0.5, 0.25, 1.0]);
+    assert!(!materials[0].casts_transmissive_shadow());
+  }
+
+  #[test]
+  fn blended_materials_keep_alpha() {
+    let materials = convert_materials();
+    assert_eq!(materials[2].base_color, [1.0, 0.5, 0.25, 0.4]);
+    assert!(materials[2].casts_transmissive_shadow());
+  }
+}
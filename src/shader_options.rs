@@ -0,0 +1,113 @@
+// Shader compile options and include resolution shared by build.rs and the runtime recompiler in
+// hot_reload.rs, which both include! this file, so shaders reload exactly as they were built. Paths
+// are spelled out, the including files have imports of their own.
+
+// OX_SHADER_OPT, by default Performance in release builds and Zero otherwise.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Optimization {
+  Zero,
+  Size,
+  Performance,
+}
+
+impl Optimization {
+  const ALL: [Self; 3] = [Self::Zero, Self::Size, Self::Performance];
+
+  fn name(self) -> &'static str {
+    match self {
+      Self::Zero => "zero",
+      Self::Size => "size",
+      Self::Performance => "performance",
+    }
+  }
+}
+
+// OX_SHADER_SPIRV, 1.5 by default. Ray tracing and mesh shaders need at least 1.4.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Spirv {
+  V1_4,
+  V1_5,
+  V1_6,
+}
+
+impl Spirv {
+  const ALL: [Self; 3] = [Self::V1_4, Self::V1_5, Self::V1_6];
+
+  fn name(self) -> &'static str {
+    match self {
+      Self::V1_4 => "1.4",
+      Self::V1_5 => "1.5",
+      Self::V1_6 => "1.6",
+    }
+  }
+}
+
+// Compile options that can be overridden at build time, by their names in the environment variables.
+#[derive(Debug, Clone, PartialEq)]
+struct ShaderOptions {
+  optimization: Optimization,
+  spirv: Spirv,
+}
+
+impl ShaderOptions {
+  // The options named like build.rs exports them in OX_BUILD_SHADER_OPT and OX_BUILD_SHADER_SPIRV.
+  #[allow(dead_code)]
+  fn from_names(optimization: &str, spirv: &str) -> Option<Self> {
+    Some(Self {
+      optimization: Optimization::ALL.into_iter().find(|choice| choice.name() == optimization)?,
+      spirv: Spirv::ALL.into_iter().find(|choice| choice.name() == spirv)?,
+    })
+  }
+
+  fn apply(&self, options: &mut shaderc::CompileOptions) {
+    options.set_optimization_level(match self.optimization {
+      Optimization::Zero => shaderc::OptimizationLevel::Zero,
+      Optimization::Size => shaderc::OptimizationLevel::Size,
+      Optimization::Performance => shaderc::OptimizationLevel::Performance,
+    });
+    // SPIR-V 1.6 is only allowed from Vulkan 1.3 on, both need shaderc 0.8 or newer.
+    let (spirv, env) = match self.spirv {
+      Spirv::V1_4 => (shaderc::SpirvVersion::V1_4, shaderc::EnvVersion::Vulkan1_2),
+      Spirv::V1_5 => (shaderc::SpirvVersion::V1_5, shaderc::EnvVersion::Vulkan1_2),
+      Spirv::V1_6 => (shaderc::SpirvVersion::V1_6, shaderc::EnvVersion::Vulkan1_3),
+    };
+    options.set_target_env(shaderc::TargetEnv::Vulkan, env as u32);
+    options.set_target_spirv(spirv);
+  }
+}
+
+// The kind of a shader source by its extension, None for files that aren't shaders like includes.
+fn shader_kind(extension: &str) -> Option<shaderc::ShaderKind> {
+  Some(match extension {
+    "vert" => shaderc::ShaderKind::Vertex,
+    "geom" => shaderc::ShaderKind::Geometry,
+    "frag" => shaderc::ShaderKind::Fragment,
+    "comp" => shaderc::ShaderKind::Compute,
+    "rgen" => shaderc::ShaderKind::RayGeneration,
+    "rchit" => shaderc::ShaderKind::ClosestHit,
+    "rahit" => shaderc::ShaderKind::AnyHit,
+    "rmiss" => shaderc::ShaderKind::Miss,
+    "rint" => shaderc::ShaderKind::Intersection,
+    "rcall" => shaderc::ShaderKind::Callable,
+    // VK_EXT_mesh_shader needs SPIR-V 1.4, every OX_SHADER_SPIRV version covers it.
+    "task" => shaderc::ShaderKind::Task,
+    "mesh" => shaderc::ShaderKind::Mesh,
+    _ => return None,
+  })
+}
+
+// Finds `name`, included from `containing`, next to the including file first, then in the include
+// directories in order. Returns its path along with what the include callback hands to shaderc.
+fn resolve_include(name: &str, containing: &str, include_dirs: &[std::path::PathBuf]) -> Result<(std::path::PathBuf, shaderc::ResolvedInclude), String> {
+  let containing_dir = std::path::Path::new(containing).parent().map(std::path::Path::to_path_buf);
+  let Some(path) = containing_dir.iter().chain(include_dirs).map(|dir| dir.join(name)).find(|path| path.is_file()) else {
+    return Err(format!("{} not found next to {} or in {:?}", name, containing, include_dirs));
+  };
+  let content = std::fs::read_to_string(&path).map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+  // The full path, so includes of the include resolve next to it.
+  let resolved = shaderc::ResolvedInclude {
+    resolved_name: path.to_string_lossy().into_owned(),
+    content,
+  };
+  Ok((path, resolved))
+}
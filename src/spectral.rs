@@ -0,0 +1,96 @@
+// CPU reference of the wavelength dependent refraction in resources/shaders/spectral.glsl, keep both in sync.
+// The tests check SPECTRAL_WHITE_SCALE against it.
+
+use glam::{Mat3, Vec3};
+
+pub const WAVELENGTH_MIN: f32 = 380.0;
+pub const WAVELENGTH_MAX: f32 = 780.0;
+
+// Wavelength of the sodium d-line in nm, at which Material::ior is given.
+const WAVELENGTH_D: f32 = 587.6;
+
+// Cauchy's equation with the B coefficient `dispersion` in um^2, `ior` is the index at 587.6 nm.
+// Shorter wavelengths bend more, e.g. BK7 (1.5168, 0.0042) gives about 1.53 at 400 nm and 1.51 at 700 nm.
+#[allow(dead_code)]
+pub fn cauchy_ior(ior: f32, dispersion: f32, wavelength: f32) -> f32 {
+  let lambda = wavelength * 1.0e-3;
+  let lambda_d = WAVELENGTH_D * 1.0e-3;
+  ior + dispersion * (1.0 / (lambda * lambda) - 1.0 / (lambda_d * lambda_d))
+}
+
+fn cie_lobe(x: f32, mean: f32, sigma_low: f32, sigma_high: f32) -> f32 {
+  let t = (x - mean) / if x < mean { sigma_low } else { sigma_high };
+  (-0.5 * t * t).exp()
+}
+
+// CIE 1931 color matching functions (Wyman, Sloan & Shirley, 2013 multi-lobe fit).
+pub fn cie_xyz(wavelength: f32) -> Vec3 {
+  let x = 1.056 * cie_lobe(wavelength, 599.8, 37.9, 31.0) + 0.362 * cie_lobe(wavelength, 442.0, 16.0, 26.7)
+    - 0.065 * cie_lobe(wavelength, 501.1, 20.4, 26.2);
+  let y = 0.821 * cie_lobe(wavelength, 568.8, 46.9, 40.5) + 0.286 * cie_lobe(wavelength, 530.9, 16.3, 31.1);
+  let z = 1.217 * cie_lobe(wavelength, 437.0, 11.8, 36.0) + 0.681 * cie_lobe(wavelength, 459.0, 26.0, 13.8);
+  Vec3::new(x, y, z)
+}
+
+// Linear sRGB (D65) of the color matching functions at `wavelength`, without white balancing.
+pub fn wavelength_rgb(wavelength: f32) -> Vec3 {
+  let xyz_to_rgb = Mat3::from_cols(
+    Vec3::new(3.2406, -0.9689, 0.0557),
+    Vec3::new(-1.5372, 1.8758, -0.2040),
+    Vec3::new(-0.4986, 0.0415, 1.0570),
+  );
+  xyz_to_rgb * cie_xyz(wavelength)
+}
+
+// Inverse of the mean of wavelength_rgb over the sampled range, SPECTRAL_WHITE_SCALE in the shader.
+// Scaling by it makes paths that sample the wavelength uniformly average to white.
+#[allow(dead_code)]
+pub fn white_scale(steps: u32) -> Vec3 {
+  let step = (WAVELENGTH_MAX - WAVELENGTH_MIN) / steps as f32;
+  let sum = (0..steps).map(|i| wavelength_rgb(WAVELENGTH_MIN + (i as f32 + 0.5) * step)).fold(Vec3::ZERO, |a, b| a + b);
+  Vec3::ONE / (sum / steps as f32)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // SPECTRAL_WHITE_SCALE as written in spectral.glsl.
+  fn shader_white_scale() -> Vec3 {
+    let source = include_str!("../resources/shaders/spectral.glsl");
+    let line = source.lines().find(|line| line.starts_with("const vec3 SPECTRAL_WHITE_SCALE")).expect("SPECTRAL_WHITE_SCALE is missing");
+    let arguments = line.split_once("vec3(").and_then(|(_, rest)| rest.split_once(')')).map(|(arguments, _)| arguments).unwrap();
+    let values = arguments.split(',').map(|value| value.trim().parse::<f32>().unwrap()).collect::<Vec<_>>();
+    Vec3::from_slice(&values)
+  }
+
+  #[test]
+  fn white_scale_matches_the_shader() {
+    let scale = white_scale(4000);
+    assert!((scale - shader_white_scale()).abs().max_element() < 1.0e-3, "{} vs {}", scale, shader_white_scale());
+  }
+
+  #[test]
+  fn weights_average_to_white() {
+    let steps = 4000;
+    let scale = shader_white_scale();
+    let step = (WAVELENGTH_MAX - WAVELENGTH_MIN) / steps as f32;
+    let mean = (0..steps).map(|i| wavelength_rgb(WAVELENGTH_MIN + (i as f32 + 0.5) * step) * scale).fold(Vec3::ZERO, |a, b| a + b) / steps as f32;
+    assert!((mean - Vec3::ONE).abs().max_element() < 1.0e-3, "{}", mean);
+  }
+
+  #[test]
+  fn cauchy_ior_is_exact_at_the_d_line() {
+    assert!((cauchy_ior(1.5168, 0.0042, WAVELENGTH_D) - 1.5168).abs() < 1.0e-6);
+    assert_eq!(cauchy_ior(1.5, 0.0, 400.0), 1.5);
+  }
+
+  #[test]
+  fn shorter_wavelengths_bend_more() {
+    let blue = cauchy_ior(1.5168, 0.0042, 400.0);
+    let red = cauchy_ior(1.5168, 0.0042, 700.0);
+    assert!((blue - 1.5309).abs() < 1.0e-3, "{}", blue);
+    assert!((red - 1.5132).abs() < 1.0e-3, "{}", red);
+    assert!(blue > red);
+  }
+}
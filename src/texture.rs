@@ -0,0 +1,584 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, ensure, Context as _, Result};
+use ash::vk;
+use bytemuck::{Pod, Zeroable};
+use log::{debug, warn};
+
+use phobos::prelude::*;
+use phobos::sync::domain::All;
+
+use crate::app::Context;
+use crate::error::{OxError, OxResult};
+use crate::memory::{Buffer, Image, TrackingAllocator};
+use crate::upload::Uploader;
+
+// Block compressed formats the loaders know about, queried once at startup.
+pub const BC_FORMATS: [vk::Format; 14] = [
+  vk::Format::BC1_RGB_UNORM_BLOCK,
+  vk::Format::BC1_RGB_SRGB_BLOCK,
+  vk::Format::BC1_RGBA_UNORM_BLOCK,
+  vk::Format::BC1_RGBA_SRGB_BLOCK,
+  vk::Format::BC2_UNORM_BLOCK,
+  vk::Format::BC2_SRGB_BLOCK,
+  vk::Format::BC3_UNORM_BLOCK,
+  vk::Format::BC3_SRGB_BLOCK,
+  vk::Format::BC4_UNORM_BLOCK,
+  vk::Format::BC4_SNORM_BLOCK,
+  vk::Format::BC5_UNORM_BLOCK,
+  vk::Format::BC5_SNORM_BLOCK,
+  vk::Format::BC7_UNORM_BLOCK,
+  vk::Format::BC7_SRGB_BLOCK,
+];
+
+// Formats of the images load_image creates: 8 bit images as sRGB or linear, linear 16 bit images as
+// 16 bit, HDR images as float.
+pub const IMAGE_FORMATS: [vk::Format; 4] = [
+  vk::Format::R8G8B8A8_SRGB,
+  vk::Format::R8G8B8A8_UNORM,
+  vk::Format::R16G16B16A16_UNORM,
+  vk::Format::R32G32B32A32_SFLOAT,
+];
+
+// Optimal tiling features of the formats in BC_FORMATS and IMAGE_FORMATS on the selected device.
+#[derive(Debug, Clone, Default)]
+pub struct FormatSupport(Arc<HashMap<vk::Format, vk::FormatFeatureFlags>>);
+
+impl FormatSupport {
+  pub fn query(instance: &VkInstance, physical_device: &PhysicalDevice) -> Self {
+    let features = BC_FORMATS
+      .iter()
+      .chain(&IMAGE_FORMATS)
+      .map(|&format| {
+        let properties = unsafe { instance.get_physical_device_format_properties(physical_device.handle(), format) };
+        (format, properties.optimal_tiling_features)
+      })
+      .collect();
+    Self(Arc::new(features))
+  }
+
+  pub fn can_sample(&self, format: vk::Format) -> bool {
+    self.0.get(&format).map_or(false, |features| features.contains(vk::FormatFeatureFlags::SAMPLED_IMAGE | vk::FormatFeatureFlags::TRANSFER_DST))
+  }
+
+  // Whether linear filtering works, which devices don't have to support for RGBA32F.
+  pub fn can_filter_linear(&self, format: vk::Format) -> bool {
+    self.0.get(&format).map_or(false, |features| features.contains(vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR))
+  }
+
+  // Whether images of `format` can be blitted into themselves, which generating mip levels needs.
+  pub fn can_blit(&self, format: vk::Format) -> bool {
+    self.0.get(&format).map_or(false, |features| features.contains(vk::FormatFeatureFlags::BLIT_SRC | vk::FormatFeatureFlags::BLIT_DST))
+  }
+}
+
+// A sampled texture with its mip chain in SHADER_READ_ONLY_OPTIMAL layout. phobos' Image::new only
+// allocates a single level, so the image is created here and its memory comes from the Context's
+// allocator, like ExternalImage wraps an image phobos doesn't own.
+pub struct GpuTexture {
+  // Options so Drop can release the view and the phobos wrapper before destroying the image.
+  view: Option<ImageView>,
+  image: Option<Image>,
+  handle: vk::Image,
+  allocation: Option<<TrackingAllocator as Allocator>::Allocation>,
+  allocator: TrackingAllocator,
+  device: Device,
+  pub format: vk::Format,
+  pub width: u32,
+  pub height: u32,
+  pub levels: u32,
+}
+
+#[allow(dead_code)]
+impl GpuTexture {
+  fn new(ctx: &mut Context, width: u32, height: u32, levels: u32, format: vk::Format, usage: vk::ImageUsageFlags) -> Result<Self> {
+    let extent = vk::Extent3D {
+      width,
+      height,
+      depth: 1,
+    };
+    let info = vk::ImageCreateInfo::builder()
+      .image_type(vk::ImageType::TYPE_2D)
+      .format(format)
+      .extent(extent)
+      .mip_levels(levels)
+      .array_layers(1)
+      .samples(vk::SampleCountFlags::TYPE_1)
+      .tiling(vk::ImageTiling::OPTIMAL)
+      .usage(usage | vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST)
+      .sharing_mode(vk::SharingMode::EXCLUSIVE)
+      .initial_layout(vk::ImageLayout::UNDEFINED);
+    let handle = unsafe { ctx.device.create_image(&info, None)? };
+    let requirements = unsafe { ctx.device.get_image_memory_requirements(handle) };
+    let allocation = match ctx.allocator.allocate("texture", &requirements, MemoryType::GpuOnly) {
+      Ok(allocation) => allocation,
+      Err(e) => {
+        unsafe { ctx.device.destroy_image(handle, None) };
+        return Err(e.into());
+      }
+    };
+    if let Err(e) = unsafe { ctx.device.bind_image_memory(handle, allocation.memory(), allocation.offset()) } {
+      unsafe { ctx.device.destroy_image(handle, None) };
+      ctx.allocator.free(allocation)?;
+      return Err(e.into());
+    }
+    let image = Image::new_managed(ctx.device.clone(), handle, format, extent, 1, levels, vk::SampleCountFlags::TYPE_1);
+    // Covers every level, for samplers with a LOD range.
+    let view = image.view(vk::ImageAspectFlags::COLOR)?;
+    Ok(Self {
+      view: Some(view),
+      image: Some(image),
+      handle,
+      allocation: Some(allocation),
+      allocator: ctx.allocator.clone(),
+      device: ctx.device.clone(),
+      format,
+      width,
+      height,
+      levels,
+    })
+  }
+
+  pub fn image(&self) -> &Image {
+    self.image.as_ref().unwrap()
+  }
+
+  pub fn view(&self) -> &ImageView {
+    self.view.as_ref().unwrap()
+  }
+
+  // Copies the levels in `regions` from `data` on the graphics queue and waits. With `generate`, only
+  // level 0 is copied and every further level is blitted from the one before it. Leaves all levels in
+  // SHADER_READ_ONLY_OPTIMAL.
+  fn upload(&self, ctx: &mut Context, data: &[u8], regions: &[vk::BufferImageCopy], generate: Option<vk::Filter>) -> Result<()> {
+    let staging = Buffer::new(ctx.device.clone(), &mut ctx.allocator, data.len() as u64, vk::BufferUsageFlags::TRANSFER_SRC, MemoryType::CpuToGpu)?;
+    staging.view_full().mapped_slice::<u8>()?.copy_from_slice(data);
+
+    let barrier = |levels: std::ops::Range<u32>, from: vk::ImageLayout, to: vk::ImageLayout, src: (PipelineStage, vk::AccessFlags2), dst: (PipelineStage, vk::AccessFlags2)| {
+      vk::ImageMemoryBarrier2::builder()
+        .image(self.handle)
+        .old_layout(from)
+        .new_layout(to)
+        .src_stage_mask(src.0)
+        .src_access_mask(src.1)
+        .dst_stage_mask(dst.0)
+        .dst_access_mask(dst.1)
+        .subresource_range(vk::ImageSubresourceRange {
+          aspect_mask: vk::ImageAspectFlags::COLOR,
+          base_mip_level: levels.start,
+          level_count: levels.end - levels.start,
+          base_array_layer: 0,
+          layer_count: 1,
+        })
+        .build()
+    };
+    let none = (PipelineStage::NONE, vk::AccessFlags2::NONE);
+    let written = (PipelineStage::ALL_TRANSFER, vk::AccessFlags2::TRANSFER_WRITE);
+    let read = (PipelineStage::ALL_TRANSFER, vk::AccessFlags2::TRANSFER_READ);
+    let sampled = (PipelineStage::ALL_COMMANDS, vk::AccessFlags2::SHADER_READ);
+    let submit = |cmd: IncompleteCommandBuffer<All>, barriers: &[vk::ImageMemoryBarrier2]| cmd.pipeline_barrier(&vk::DependencyInfo::builder().image_memory_barriers(barriers).build());
+
+    let mut cmd = submit(
+      ctx.exec.on_domain::<All, DefaultAllocator>(None, None)?,
+      &[barrier(0..self.levels, vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL, none, written)],
+    )?;
+    unsafe { ctx.device.cmd_copy_buffer_to_image(cmd.handle(), staging.handle(), self.handle, vk::ImageLayout::TRANSFER_DST_OPTIMAL, regions) };
+    // Levels that were blitted from are left in TRANSFER_SRC_OPTIMAL, the last one stays a blit destination.
+    let mut sources = 0;
+    if let Some(filter) = generate {
+      for level in 1..self.levels {
+        cmd = submit(cmd, &[barrier(level - 1..level, vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, written, read)])?;
+        let corner = |level: u32| vk::Offset3D {
+          x: (self.width >> level).max(1) as i32,
+          y: (self.height >> level).max(1) as i32,
+          z: 1,
+        };
+        let subresource = |level: u32| vk::ImageSubresourceLayers {
+          aspect_mask: vk::ImageAspectFlags::COLOR,
+          mip_level: level,
+          base_array_layer: 0,
+          layer_count: 1,
+        };
+        let blit = vk::ImageBlit {
+          src_subresource: subresource(level - 1),
+          src_offsets: [vk::Offset3D::default(), corner(level - 1)],
+          dst_subresource: subresource(level),
+          dst_offsets: [vk::Offset3D::default(), corner(level)],
+        };
+        unsafe {
+          ctx.device.cmd_blit_image(
+            cmd.handle(),
+            self.handle,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            self.handle,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            std::slice::from_ref(&blit),
+            filter,
+          )
+        };
+        sources = level;
+      }
+    }
+    let mut to_sampled = vec![barrier(sources..self.levels, vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, written, sampled)];
+    if sources > 0 {
+      to_sampled.push(barrier(0..sources, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, read, sampled));
+    }
+    let cmd = submit(cmd, &to_sampled)?.finish()?;
+    ctx.exec.submit(cmd)?.wait()?;
+    Ok(())
+  }
+}
+
+impl Drop for GpuTexture {
+  fn drop(&mut self) {
+    self.view.take();
+    self.image.take();
+    unsafe { self.device.destroy_image(self.handle, None) };
+    if let Some(allocation) = self.allocation.take() {
+      if let Err(e) = self.allocator.free(allocation) {
+        warn!("Failed to free texture memory: {}", e);
+      }
+    }
+  }
+}
+
+// Levels of a full mip chain down to 1x1.
+pub fn mip_levels(width: u32, height: u32) -> u32 {
+  32 - width.max(height).max(1).leading_zeros()
+}
+
+// The region of `level` at `offset` in the staging buffer.
+fn level_region(width: u32, height: u32, level: u32, offset: u64) -> vk::BufferImageCopy {
+  vk::BufferImageCopy {
+    buffer_offset: offset,
+    buffer_row_length: 0,
+    buffer_image_height: 0,
+    image_subresource: vk::ImageSubresourceLayers {
+      aspect_mask: vk::ImageAspectFlags::COLOR,
+      mip_level: level,
+      base_array_layer: 0,
+      layer_count: 1,
+    },
+    image_offset: vk::Offset3D::default(),
+    image_extent: vk::Extent3D {
+      width: (width >> level).max(1),
+      height: (height >> level).max(1),
+      depth: 1,
+    },
+  }
+}
+
+const KTX2_IDENTIFIER: [u8; 12] = [0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A];
+const KTX2_HEADER_SIZE: usize = 80;
+const KTX2_LEVEL_INDEX_ENTRY_SIZE: usize = 24;
+// Offset of dfdByteOffset in the header.
+const KTX2_DFD_OFFSET: usize = 48;
+// KHR_DF_MODEL_UASTC, the color model of the basic data format descriptor of UASTC files.
+const KHR_DF_MODEL_UASTC: u8 = 166;
+
+// Bytes per 4x4 block, None for formats that aren't block compressed.
+fn bc_block_size(format: vk::Format) -> Option<u64> {
+  match format {
+    vk::Format::BC1_RGB_UNORM_BLOCK
+    | vk::Format::BC1_RGB_SRGB_BLOCK
+    | vk::Format::BC1_RGBA_UNORM_BLOCK
+    | vk::Format::BC1_RGBA_SRGB_BLOCK
+    | vk::Format::BC4_UNORM_BLOCK
+    | vk::Format::BC4_SNORM_BLOCK => Some(8),
+    format if BC_FORMATS.contains(&format) => Some(16),
+    _ => None,
+  }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+  u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+  u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+// Color model of the file's data format descriptor, None if the descriptor is missing or truncated.
+fn ktx2_color_model(bytes: &[u8]) -> Option<u8> {
+  let dfd = read_u32(bytes, KTX2_DFD_OFFSET) as usize;
+  // dfdTotalSize, then the first descriptor block's vendor and type word, its version and size, then the model.
+  bytes.get(dfd + 12).copied()
+}
+
+// Loads a 2D KTX2 texture holding BCn data and uploads it with all its mip levels, without transcoding.
+//
+// Basis Universal textures, ETC1S (BasisLZ supercompression) as well as UASTC, would have to be
+// transcoded to BCn on the CPU, which needs the Basis transcoder, and are rejected with an error
+// naming the encoding. Zstandard and ZLIB supercompressed files are rejected the same way.
+#[allow(dead_code)]
+pub fn load_ktx2(ctx: &mut Context, path: impl AsRef<Path>) -> Result<GpuTexture> {
+  let path = path.as_ref();
+  let bytes = std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+  ensure!(bytes.len() >= KTX2_HEADER_SIZE && bytes[..12] == KTX2_IDENTIFIER, "{} is not a KTX2 file", path.display());
+
+  let format = vk::Format::from_raw(read_u32(&bytes, 12) as i32);
+  let width = read_u32(&bytes, 20);
+  let height = read_u32(&bytes, 24);
+  let depth = read_u32(&bytes, 28);
+  let layers = read_u32(&bytes, 32);
+  let faces = read_u32(&bytes, 36);
+  // 0 asks the loader to generate the levels, which block compressed images can't be blitted for.
+  let levels = read_u32(&bytes, 40).max(1);
+  let supercompression = read_u32(&bytes, 44);
+
+  let basis = "transcoding Basis Universal textures is not supported, encode it as BC7 or another BCn format instead";
+  match supercompression {
+    0 => {}
+    1 => bail!("{} is a Basis Universal ETC1S texture (BasisLZ supercompression), {}", path.display(), basis),
+    2 | 3 => bail!("{} is {} supercompressed, which is not supported", path.display(), if supercompression == 2 { "Zstandard" } else { "ZLIB" }),
+    scheme => bail!("{} uses unknown supercompression scheme {}", path.display(), scheme),
+  }
+  if format == vk::Format::UNDEFINED {
+    match ktx2_color_model(&bytes) {
+      Some(KHR_DF_MODEL_UASTC) => bail!("{} is a Basis Universal UASTC texture, {}", path.display(), basis),
+      _ => bail!("{} has no Vulkan format", path.display()),
+    }
+  }
+  // A height of 0 marks a 1D texture.
+  ensure!(width > 0 && height > 0 && depth <= 1 && layers <= 1 && faces == 1, "{} is not a plain 2D texture ({}x{})", path.display(), width, height);
+  let block_size = bc_block_size(format).ok_or_else(|| anyhow!("{} has format {:?}, only BCn textures are supported", path.display(), format))?;
+  ensure!(ctx.formats.can_sample(format), "{:?} in {} can't be sampled on this device", format, path.display());
+  ensure!(levels <= mip_levels(width, height), "{} has {} levels, more than a {}x{} image can have", path.display(), levels, width, height);
+
+  ensure!(
+    bytes.len() >= KTX2_HEADER_SIZE + levels as usize * KTX2_LEVEL_INDEX_ENTRY_SIZE,
+    "{} is truncated",
+    path.display()
+  );
+  // The level index lists the largest level first. The levels are staged back to back, their sizes are
+  // whole blocks, which keeps every offset a multiple of the block size as copies require.
+  let mut data = Vec::new();
+  let mut regions = Vec::with_capacity(levels as usize);
+  for level in 0..levels {
+    let entry = KTX2_HEADER_SIZE + level as usize * KTX2_LEVEL_INDEX_ENTRY_SIZE;
+    let offset = read_u64(&bytes, entry);
+    let length = read_u64(&bytes, entry + 8);
+    let (level_width, level_height) = ((width >> level).max(1), (height >> level).max(1));
+    let expected = ((level_width + 3) / 4) as u64 * ((level_height + 3) / 4) as u64 * block_size;
+    ensure!(length == expected, "{} level {} has {} bytes, expected {}", path.display(), level, length, expected);
+    // Both come from the file, a corrupt index must not overflow.
+    let level_data = offset
+      .checked_add(length)
+      .and_then(|end| bytes.get(usize::try_from(offset).ok()?..usize::try_from(end).ok()?))
+      .ok_or_else(|| anyhow!("{} level {} lies outside of the file", path.display(), level))?;
+    regions.push(level_region(width, height, level, data.len() as u64));
+    data.extend_from_slice(level_data);
+  }
+  debug!("{}: uploading {} levels of {:?}", path.display(), levels, format);
+
+  let texture = GpuTexture::new(ctx, width, height, levels, format, vk::ImageUsageFlags::empty())?;
+  texture.upload(ctx, &data, &regions, None)?;
+  Ok(texture)
+}
+
+// Loads a PNG, JPEG or other 8 bit image as RGBA8, sRGB decoded when sampled if `srgb` is set (color
+// maps, not normal maps), a 16 bit image the same way or, linear, as RGBA16, or a Radiance HDR /
+// OpenEXR image as RGBA32F, e.g. an environment map for image based lighting. The result is in
+// SHADER_READ_ONLY_OPTIMAL, bind `view()` with a sampler from a SamplerCache.
+//
+// The mip chain is generated on the GPU, with a linear filter where the format supports it and
+// nearest otherwise. Formats the device can't blit keep only the base level. Devices don't have to
+// filter RGBA32F linearly, see FormatSupport::can_filter_linear and sample HDR images with
+// Filter::NEAREST where it's missing.
+#[allow(dead_code)]
+pub fn load_image(ctx: &mut Context, path: impl AsRef<Path>, srgb: bool) -> Result<GpuTexture> {
+  let path = path.as_ref();
+  let image = image::open(path).with_context(|| format!("failed to load image {}", path.display()))?;
+  let hdr = matches!(image, image::DynamicImage::ImageRgb32F(_) | image::DynamicImage::ImageRgba32F(_));
+  let sixteen_bit = matches!(
+    image,
+    image::DynamicImage::ImageLuma16(_) | image::DynamicImage::ImageLumaA16(_) | image::DynamicImage::ImageRgb16(_) | image::DynamicImage::ImageRgba16(_)
+  );
+  let (width, height) = (image.width(), image.height());
+  ensure!(width > 0 && height > 0, "{} is empty ({}x{})", path.display(), width, height);
+  let (format, data) = if hdr {
+    (vk::Format::R32G32B32A32_SFLOAT, bytemuck::cast_slice::<f32, u8>(image.to_rgba32f().as_raw()).to_vec())
+  } else if srgb {
+    // There is no 16 bit sRGB format, 8 bits per channel are enough for sRGB encoded color.
+    (vk::Format::R8G8B8A8_SRGB, image.to_rgba8().into_raw())
+  } else if sixteen_bit {
+    (vk::Format::R16G16B16A16_UNORM, bytemuck::cast_slice::<u16, u8>(image.to_rgba16().as_raw()).to_vec())
+  } else {
+    (vk::Format::R8G8B8A8_UNORM, image.to_rgba8().into_raw())
+  };
+  ensure!(ctx.formats.can_sample(format), "{:?} for {} can't be sampled on this device", format, path.display());
+
+  let (levels, filter) = if ctx.formats.can_blit(format) {
+    let filter = if ctx.formats.can_filter_linear(format) { vk::Filter::LINEAR } else { vk::Filter::NEAREST };
+    (mip_levels(width, height), Some(filter))
+  } else {
+    debug!("{:?} can't be blitted on this device, {} gets no mip levels", format, path.display());
+    (1, None)
+  };
+  let texture = GpuTexture::new(ctx, width, height, levels, format, vk::ImageUsageFlags::TRANSFER_SRC)?;
+  texture.upload(ctx, &data, &[level_region(width, height, 0, 0)], filter)?;
+  Ok(texture)
+}
+
+// Filter and address mode of a sampler in a SamplerCache, used for both axes and minification.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct SamplerKey {
+  pub filter: vk::Filter,
+  pub address_mode: vk::SamplerAddressMode,
+}
+
+impl Default for SamplerKey {
+  fn default() -> Self {
+    Self {
+      filter: vk::Filter::LINEAR,
+      address_mode: vk::SamplerAddressMode::REPEAT,
+    }
+  }
+}
+
+// Samplers created on first use and reused for every later lookup of the same key.
+pub struct SamplerCache {
+  device: Device,
+  samplers: HashMap<SamplerKey, Sampler>,
+}
+
+#[allow(dead_code)]
+impl SamplerCache {
+  pub fn new(device: Device) -> Self {
+    Self {
+      device,
+      samplers: HashMap::new(),
+    }
+  }
+
+  pub fn get(&mut self, key: SamplerKey) -> Result<&Sampler> {
+    if !self.samplers.contains_key(&key) {
+      let mipmap_mode = if key.filter == vk::Filter::NEAREST {
+        vk::SamplerMipmapMode::NEAREST
+      } else {
+        vk::SamplerMipmapMode::LINEAR
+      };
+      let info = vk::SamplerCreateInfo::builder()
+        .mag_filter(key.filter)
+        .min_filter(key.filter)
+        .mipmap_mode(mipmap_mode)
+        .address_mode_u(key.address_mode)
+        .address_mode_v(key.address_mode)
+        .address_mode_w(key.address_mode)
+        .max_lod(vk::LOD_CLAMP_NONE)
+        .build();
+      let sampler = Sampler::new(self.device.clone(), info)?;
+      debug!("Created sampler {:?}", key);
+      self.samplers.insert(key, sampler);
+    }
+    Ok(&self.samplers[&key])
+  }
+}
+
+// Scene textures before upload, handing out the indices materials refer to them by (base_color_texture,
+// normal_map, emissive_texture). Instead of a descriptor array with VK_EXT_descriptor_indexing, the
+// whole array ends up as one storage buffer of buffer addresses in TextureTable. Hit shaders index
+// it with a plain (possibly non-uniform) integer, which is an ordinary storage buffer read and needs
+// no nonuniformEXT. The only device features involved are buffer_device_address, which phobos
+// enables for the ray tracing pipeline, and shader_int64 from the default RunnerConfig::features.
+#[derive(Default)]
+pub struct TextureArray {
+  images: Vec<image::RgbaImage>,
+  // Files already added, so materials sharing a texture share its index.
+  by_path: HashMap<PathBuf, i32>,
+}
+
+#[allow(dead_code)]
+impl TextureArray {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  // Adds `image` and returns its index.
+  pub fn add(&mut self, image: image::RgbaImage) -> i32 {
+    self.images.push(image);
+    (self.images.len() - 1) as i32
+  }
+
+  // Loads an image file once and returns its index, later calls with the same path return the same index.
+  // Texels are stored as in the file, the hit shaders decode color textures from sRGB when sampling
+  // them (texture_sample_srgb in textures.glsl) and read normal maps as they are.
+  pub fn load(&mut self, path: impl AsRef<Path>) -> Result<i32> {
+    let path = path.as_ref();
+    if let Some(&index) = self.by_path.get(path) {
+      return Ok(index);
+    }
+    let image = image::open(path).with_context(|| format!("failed to load texture {}", path.display()))?.to_rgba8();
+    let index = self.add(image);
+    self.by_path.insert(path.to_path_buf(), index);
+    Ok(index)
+  }
+
+  pub fn images(&self) -> &[image::RgbaImage] {
+    &self.images
+  }
+
+  pub fn upload(&self, ctx: &mut Context) -> OxResult<TextureTable> {
+    TextureTable::new(ctx, &self.images)
+  }
+}
+
+// Mirrors TextureInfo in textures.glsl (std430).
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+pub struct TextureInfo {
+  pub texels: u64,
+  pub width: u32,
+  pub height: u32,
+}
+
+// Textures for hit shaders, stored like the blue-noise mask as packed RGBA8 texels in storage
+// buffers, so no sampler or descriptor array is needed. Materials refer to them by their index in
+// the table, textures.glsl fetches them through the buffer device addresses in `buffer`.
+pub struct TextureTable {
+  pub buffer: Buffer,
+  pub textures: Vec<Buffer>,
+}
+
+impl TextureTable {
+  pub fn new(ctx: &mut Context, images: &[image::RgbaImage]) -> OxResult<Self> {
+    Self::upload(ctx, images).map_err(|e| OxError::allocation("the texture table", e))
+  }
+
+  fn upload(ctx: &mut Context, images: &[image::RgbaImage]) -> Result<Self> {
+    let mut uploader = Uploader::new(ctx);
+    let staged = images
+      .iter()
+      .map(|image| uploader.stage_buffer(image.as_raw().as_slice(), vk::BufferUsageFlags::STORAGE_BUFFER))
+      .collect::<Result<Vec<_>>>()?;
+    let mut uploaded = uploader.flush::<All>()?;
+    let textures = staged
+      .into_iter()
+      .map(|staged| uploaded.take_buffer(staged).ok_or_else(|| anyhow!("texture buffer missing after upload")))
+      .collect::<Result<Vec<_>>>()?;
+
+    let mut infos = images
+      .iter()
+      .zip(&textures)
+      .map(|(image, texels)| TextureInfo {
+        texels: texels.address(),
+        width: image.width(),
+        height: image.height(),
+      })
+      .collect::<Vec<_>>();
+    // Storage buffers can't be empty, an unused entry keeps the binding valid for texture-less scenes.
+    if infos.is_empty() {
+      infos.push(TextureInfo::zeroed());
+    }
+    let mut uploader = Uploader::new(ctx);
+    let staged = uploader.stage_buffer(&infos, vk::BufferUsageFlags::STORAGE_BUFFER)?;
+    let buffer = uploader.flush::<All>()?.take_buffer(staged).ok_or_else(|| anyhow!("texture table missing after upload"))?;
+    Ok(Self {
+      buffer,
+      textures,
+    })
+  }
+}
@@ -0,0 +1,97 @@
+use std::fmt;
+use std::str::FromStr;
+
+#[cfg(feature = "thread-priority")]
+use log::info;
+use log::warn;
+
+// Scheduling priority requested for the thread running the window's render loop. Raising it keeps
+// background work from preempting frame recording and submission, which shows up as hitches on
+// busy laptops. Best effort: most platforms need extra rights for priorities above normal (e.g.
+// CAP_SYS_NICE or an rtprio limit on Linux), a denied request only logs a warning.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum RenderThreadPriority {
+  // Leave the thread as the OS created it.
+  #[default]
+  Normal,
+  High,
+  // The highest priority the OS allows for a normal process.
+  Max,
+}
+
+impl FromStr for RenderThreadPriority {
+  type Err = String;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    match value {
+      "normal" => Ok(RenderThreadPriority::Normal),
+      "high" => Ok(RenderThreadPriority::High),
+      "max" => Ok(RenderThreadPriority::Max),
+      _ => Err("expected normal, high or max".to_string()),
+    }
+  }
+}
+
+impl fmt::Display for RenderThreadPriority {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let name = match self {
+      RenderThreadPriority::Normal => "normal",
+      RenderThreadPriority::High => "high",
+      RenderThreadPriority::Max => "max",
+    };
+    f.write_str(name)
+  }
+}
+
+// Applies `priority` and pins the calling thread to the CPU core with index `core` if given. The OS
+// decides which cores are performance cores, on hybrid CPUs these are usually the lowest indices.
+// Needs the thread-priority feature, without it any request is ignored with a warning.
+pub fn configure_current_thread(priority: RenderThreadPriority, core: Option<usize>) {
+  if priority == RenderThreadPriority::Normal && core.is_none() {
+    return;
+  }
+  #[cfg(feature = "thread-priority")]
+  {
+    set_priority(priority);
+    if let Some(core) = core {
+      pin_to_core(core);
+    }
+  }
+  #[cfg(not(feature = "thread-priority"))]
+  warn!("Built without the thread-priority feature, ignoring render thread priority {} and core {:?}", priority, core);
+}
+
+#[cfg(feature = "thread-priority")]
+fn set_priority(priority: RenderThreadPriority) {
+  use thread_priority::{set_current_thread_priority, ThreadPriority, ThreadPriorityValue};
+
+  let requested = match priority {
+    RenderThreadPriority::Normal => return,
+    // Three quarters of the portable range, well above normal without starving the compositor.
+    RenderThreadPriority::High => match ThreadPriorityValue::try_from(75u8) {
+      Ok(value) => ThreadPriority::Crossplatform(value),
+      Err(e) => {
+        warn!("Invalid render thread priority: {:?}", e);
+        return;
+      }
+    },
+    RenderThreadPriority::Max => ThreadPriority::Max,
+  };
+  match set_current_thread_priority(requested) {
+    Ok(()) => info!("Render thread priority set to {}", priority),
+    Err(e) => warn!("Failed to set render thread priority to {}, keeping the default: {:?}", priority, e),
+  }
+}
+
+#[cfg(feature = "thread-priority")]
+fn pin_to_core(core: usize) {
+  let Some(cores) = core_affinity::get_core_ids() else {
+    warn!("Failed to query CPU cores, not pinning the render thread");
+    return;
+  };
+  match cores.into_iter().find(|id| id.id == core) {
+    Some(id) if core_affinity::set_for_current(id) => info!("Render thread pinned to core {}", core),
+    Some(_) => warn!("Failed to pin the render thread to core {}", core),
+    None => warn!("There is no CPU core {}, not pinning the render thread", core),
+  }
+}
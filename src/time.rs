@@ -0,0 +1,97 @@
+use std::time::Instant;
+
+use bytemuck::{Pod, Zeroable};
+
+// Longest dt a frame reports, in seconds. The first frame comes after startup and a frame after a
+// stall (swapchain recreation, a long scene reload, the window waiting while converged) would
+// otherwise make animations jump by the whole gap.
+pub const MAX_FRAME_DT: f32 = 0.1;
+
+// Per-frame timing, passed to App::frame and mirrored by the Frame uniform block in frame.glsl,
+// so the CPU and the shaders of a frame agree on the time.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, Pod, Zeroable)]
+pub struct FrameTime {
+  // Seconds since the clock started, not counting time spent paused.
+  pub elapsed: f32,
+  // Seconds since the previous frame, at most MAX_FRAME_DT, 0 while paused.
+  pub dt: f32,
+  pub frame_index: u32,
+}
+
+// Time step of Runner::run_headless_frames, which doesn't use the wall clock so that every run
+// sees the same FrameTime values.
+pub const HEADLESS_FRAME_DT: f32 = 1.0 / 60.0;
+
+impl FrameTime {
+  // Frame `frame_index` of a run that advances by exactly `dt` every frame.
+  pub fn fixed(frame_index: u32, dt: f32) -> Self {
+    Self {
+      elapsed: frame_index as f32 * dt,
+      dt,
+      frame_index,
+    }
+  }
+}
+
+#[derive(Debug)]
+pub struct Clock {
+  last_tick: Instant,
+  elapsed: f32,
+  frame_index: u32,
+  paused: bool,
+}
+
+impl Default for Clock {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Clock {
+  pub fn new() -> Self {
+    Self {
+      last_tick: Instant::now(),
+      elapsed: 0.0,
+      frame_index: 0,
+      paused: false,
+    }
+  }
+
+  // Advances to the next frame.
+  pub fn tick(&mut self) -> FrameTime {
+    let now = Instant::now();
+    let dt = if self.paused { 0.0 } else { now.duration_since(self.last_tick).as_secs_f32().min(MAX_FRAME_DT) };
+    self.last_tick = now;
+    self.elapsed += dt;
+    let time = FrameTime {
+      elapsed: self.elapsed,
+      dt,
+      frame_index: self.frame_index,
+    };
+    self.frame_index = self.frame_index.wrapping_add(1);
+    time
+  }
+
+  // Freezes elapsed time until resume, e.g. while the window is minimized.
+  pub fn pause(&mut self) {
+    self.paused = true;
+  }
+
+  // The time between pause and resume is skipped, not reported as one long frame.
+  pub fn resume(&mut self) {
+    if self.paused {
+      self.paused = false;
+      self.last_tick = Instant::now();
+    }
+  }
+
+  // Starts the next frame's dt from now, after work that shouldn't count as frame time.
+  pub fn skip_stall(&mut self) {
+    self.last_tick = Instant::now();
+  }
+
+  pub fn is_paused(&self) -> bool {
+    self.paused
+  }
+}
@@ -0,0 +1,137 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::Result;
+use ash::vk;
+use bytemuck::{Pod, Zeroable};
+
+use phobos::prelude::*;
+use phobos::sync::domain::All;
+
+use crate::app::{create_shader, Context};
+
+// Name of the pipeline Tonemap::new registers in the PipelineCache.
+pub const TONEMAP_PIPELINE: &str = "tonemap";
+
+// Curve mapping exposed HDR values into [0, 1], see tonemap.frag.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum TonemapOperator {
+  // Only the exposure, values above 1 clip.
+  Exposure,
+  // x / (1 + x), never clips but desaturates and flattens highlights.
+  Reinhard,
+  // Fit of the ACES filmic curve, with a toe and a soft shoulder.
+  #[default]
+  Aces,
+}
+
+impl TonemapOperator {
+  pub fn id(&self) -> u32 {
+    match self {
+      TonemapOperator::Exposure => 0,
+      TonemapOperator::Reinhard => 1,
+      TonemapOperator::Aces => 2,
+    }
+  }
+}
+
+impl FromStr for TonemapOperator {
+  type Err = String;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    match value {
+      "exposure" => Ok(TonemapOperator::Exposure),
+      "reinhard" => Ok(TonemapOperator::Reinhard),
+      "aces" => Ok(TonemapOperator::Aces),
+      _ => Err("expected exposure, reinhard or aces".to_string()),
+    }
+  }
+}
+
+impl fmt::Display for TonemapOperator {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let name = match self {
+      TonemapOperator::Exposure => "exposure",
+      TonemapOperator::Reinhard => "reinhard",
+      TonemapOperator::Aces => "aces",
+    };
+    f.write_str(name)
+  }
+}
+
+// Mirrors the push constants in tonemap.frag.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+pub struct TonemapPushConstants {
+  pub exposure: f32,
+  pub operator: u32,
+}
+
+// Full-screen pass drawing an HDR image into an LDR color attachment, usually the swapchain, through
+// one of the TonemapOperators. The output is linear, so targets should have an sRGB format.
+pub struct Tonemap {
+  pub operator: TonemapOperator,
+  // Multiplier applied before the curve.
+  pub exposure: f32,
+  sampler: Sampler,
+}
+
+#[allow(dead_code)]
+impl Tonemap {
+  // Registers the pipeline as TONEMAP_PIPELINE, creating a second Tonemap replaces it.
+  pub fn new(ctx: &mut Context, operator: TonemapOperator, exposure: f32) -> Result<Self> {
+    Self::create_pipeline(ctx)?;
+    Ok(Self {
+      operator,
+      exposure,
+      sampler: Sampler::default(ctx.device.clone())?,
+    })
+  }
+
+  // Registers TONEMAP_PIPELINE, again in App::reload_shaders after its shaders were recompiled.
+  pub fn create_pipeline(ctx: &mut Context) -> Result<()> {
+    let vertex = create_shader("resources/shaders/fullscreen_vert.spv", vk::ShaderStageFlags::VERTEX)?;
+    let fragment = create_shader("resources/shaders/tonemap_frag.spv", vk::ShaderStageFlags::FRAGMENT)?;
+    let pci = PipelineBuilder::new(TONEMAP_PIPELINE)
+      .dynamic_states(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR])
+      .blend_attachment_none()
+      .cull_mask(vk::CullModeFlags::NONE)
+      .attach_shader(vertex)
+      .attach_shader(fragment)
+      .build();
+    ctx.pipelines.create_named_pipeline(pci)?;
+    Ok(())
+  }
+
+  pub fn push_constants(&self) -> TonemapPushConstants {
+    TonemapPushConstants {
+      exposure: self.exposure,
+      operator: self.operator.id(),
+    }
+  }
+
+  // Draws `source` (in its version `input`) into `target`. The descriptor set is resolved through
+  // the DescriptorCache when the pass runs.
+  pub fn pass<'s>(&'s self, source: &'s VirtualResource, input: &VirtualResource, target: &VirtualResource) -> Result<Pass<'s, All, (), DefaultAllocator>> {
+    let constants = self.push_constants();
+    Ok(
+      PassBuilder::render("tonemap")
+        .color_attachment(
+          target,
+          vk::AttachmentLoadOp::CLEAR,
+          Some(vk::ClearColorValue {
+            float32: [0.0, 0.0, 0.0, 0.0],
+          }),
+        )?
+        .sample_image(input, PipelineStage::FRAGMENT_SHADER)
+        .execute_fn(move |cmd, _ifc, bindings, _| {
+          cmd.full_viewport_scissor()
+            .bind_graphics_pipeline(TONEMAP_PIPELINE)?
+            .resolve_and_bind_sampled_image(0, 0, source, &self.sampler, bindings)?
+            .push_constant(vk::ShaderStageFlags::FRAGMENT, 0, &constants)
+            .draw(3, 1, 0, 0)
+        })
+        .build(),
+    )
+  }
+}
@@ -0,0 +1,1140 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+
+use anyhow::{anyhow, ensure, Result};
+use ash::vk;
+use bytemuck::{Pod, Zeroable};
+use glam::Mat4;
+use log::{info, trace, warn};
+
+use phobos::image;
+use phobos::pipeline::raytracing::RayTracingPipelineBuilder;
+use phobos::prelude::*;
+use phobos::sync::domain::All;
+
+use crate::accel::{build_tlas, BackedAccelerationStructure, Tlas};
+use crate::accumulation::Accumulation;
+use crate::adaptive::{AdaptiveSampling, PixelStatsBuffer};
+use crate::app::{log_resource_transitions, Rect, CancellationToken, Context, create_shader, App, load_spirv_file, save_dotfile};
+use crate::bench::{BenchReport, BENCH_WARMUP_FRAMES};
+use crate::bloom::Bloom;
+use crate::blue_noise::{BlueNoise, SampleNoise};
+use crate::bounds::Aabb;
+use crate::camera::{Camera, StereoCamera, StereoMode, ViewParams, CHANNELS_ALL, CHANNELS_CYAN, CHANNELS_RED, MAX_VIEWS};
+use crate::color::OutputColorSpace;
+use crate::compositing::CompositingPasses;
+use crate::error::OxError;
+use crate::exposure::{AutoExposure, DisplayPushConstants};
+use crate::filter::ReconstructionFilter;
+use crate::guides::GuideBuffers;
+use crate::input::InputState;
+use crate::light::{Light, LightTable};
+use crate::material::{Material, MaterialTable};
+use crate::memory::{Buffer, Image, ScratchKind, ScratchUsage};
+use crate::mesh::{GeometryGroup, GeometryTable};
+use crate::output::{compositing_pass_path, crop_texels, read_back_hdr, read_back_ldr, save_hdr_image, save_ldr_image, sequence_frame_path};
+use crate::pixel_debug::{PixelDebug, NO_DEBUG_PIXEL};
+use crate::preview::{Preview, PREVIEW_BOUNCES, PREVIEW_SAMPLING};
+use crate::render_mode::RenderMode;
+use crate::rng::frame_seed;
+use crate::sampling::SamplingStrategy;
+use crate::scene::{load_gltf, Scene, SceneMesh};
+use crate::texture::TextureTable;
+use crate::time::{Clock, FrameTime};
+
+// Everything that depends on the scene contents, rebuilt on App::reload_scene.
+struct SceneResources {
+  // The vertex and index buffers the acceleration structures and the geometry table refer to.
+  #[allow(dead_code)]
+  meshes: Vec<SceneMesh>,
+  // One per mesh, in the order of `meshes`.
+  #[allow(dead_code)]
+  blases: Vec<BackedAccelerationStructure>,
+  tlas: Tlas,
+  materials: MaterialTable,
+  geometries: GeometryTable,
+  textures: TextureTable,
+  lights: LightTable,
+  radius: f32,
+  // World space bounds of all instances.
+  bounds: Aabb,
+}
+
+impl SceneResources {
+  fn bounds(&self) -> Aabb {
+    self.bounds
+  }
+}
+
+pub struct RaytracingSample {
+  scene: SceneResources,
+  camera: Camera,
+  // The HDR image and its sample count, reset when the view changes.
+  accumulation: Accumulation,
+  // Screen space motion vectors of the primary hits, see motion_out in raygen.rgen. Only used through
+  // motion_view, for temporal passes.
+  #[allow(dead_code)]
+  motion: Image,
+  motion_view: ImageView,
+  sampler: Sampler,
+  blue_noise: BlueNoise,
+  noise: SampleNoise,
+  mode: RenderMode,
+  sampling: SamplingStrategy,
+  nee_roughness_threshold: f32,
+  filter: ReconstructionFilter,
+  preview: Preview,
+  adaptive: Option<AdaptiveSampling>,
+  pixel_stats: PixelStatsBuffer,
+  guides: GuideBuffers,
+  // RenderParams of the samples traced outside of window frames, see trace_sample. Each of them is
+  // waited for before the next one is recorded, so one buffer suffices.
+  sample_params: Buffer,
+  // View-projection of the previous frame, for reprojecting the guides.
+  previous_view_projection: Option<Mat4>,
+  // Shows the adaptive sample counts instead of the image.
+  sample_count_view: bool,
+  // See RunnerConfig::log_barriers.
+  log_barriers: bool,
+  // Wavelength dependent refraction through materials with a dispersion coefficient.
+  spectral: bool,
+  // Shadow rays are attenuated by transmissive materials instead of blocked, see trace_shadow in raygen.rgen
+  // and RunnerConfig::transmissive_shadows.
+  transmissive_shadows: bool,
+  // Built-in scene with the occluder and lights of add_light_demo, see RunnerConfig::light_demo.
+  light_demo: bool,
+  // Windowed accumulation stops here, see RunnerConfig::max_samples.
+  max_samples: Option<u32>,
+  seed: u32,
+  t_min: Option<f32>,
+  t_max: f32,
+  firefly_clamp: f32,
+  crop: Rect,
+  stereo: Option<StereoCamera>,
+  pixel_debug: PixelDebug,
+  scratch: Arc<ScratchUsage>,
+  exposure: AutoExposure,
+  // Glow around bright pixels, see RunnerConfig::bloom.
+  bloom: Option<Bloom>,
+  // Depth and position of the primary hit, see RunnerConfig::compositing_passes.
+  compositing: CompositingPasses,
+  // Misses are transparent instead of showing the sky, see RunnerConfig::transparent_background.
+  transparent_background: bool,
+  // Encoding of the swapchain, offscreen targets are always sRGB.
+  color_space: OutputColorSpace,
+  paper_white: f32,
+  width: u32,
+  height: u32,
+}
+
+// Mirrors the RenderParams uniform block in raygen.rgen.
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct RenderParams {
+  sample_index: u32,
+  flags: u32,
+  seed: u32,
+  t_min: f32,
+  t_max: f32,
+  mode: u32,
+  max_bounces: u32,
+  adaptive_threshold: f32,
+  adaptive_max_samples: u32,
+  firefly_clamp: f32,
+  // NO_DEBUG_PIXEL unless Context::debug_pixel was called.
+  debug_pixel_x: u32,
+  debug_pixel_y: u32,
+  sampling: u32,
+  guide_history: u32,
+  nee_roughness_threshold: f32,
+  _pad: u32,
+  // std140 aligns the matrix to 16 bytes, hence the padding.
+  previous_view_projection: [f32; 16],
+  filter_kind: u32,
+  filter_radius: f32,
+  filter_parameters: [f32; 2],
+  light_count: u32,
+  filter_weight_scale: f32,
+  // Ambient occlusion mode only.
+  ao_samples: u32,
+  ao_radius: f32,
+  // The views()'s of this sample, unused entries zeroed.
+  views: [ViewParams; MAX_VIEWS],
+}
+
+const FLAG_BLUE_NOISE: u32 = 1;
+const FLAG_ORTHOGRAPHIC: u32 = 2;
+const FLAG_ADAPTIVE: u32 = 4;
+const FLAG_SPECTRAL: u32 = 8;
+const FLAG_GUIDES: u32 = 16;
+const FLAG_TRANSMISSIVE_SHADOWS: u32 = 32;
+const FLAG_COMPOSITING: u32 = 64;
+const FLAG_TRANSPARENT_BACKGROUND: u32 = 128;
+
+// Longest path of a full sample, counted in surface interactions.
+const MAX_BOUNCES: u32 = 8;
+
+// Default t_min relative to the scene's bounding radius.
+const T_MIN_SCALE: f32 = 1.0e-4;
+
+fn quad_vertices() -> [f32; 18] {
+  [-1.0, -1.0, 1.0, 1.0, -1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, -1.0, 1.0, 1.0, -1.0, -1.0, 1.0]
+}
+
+// The quad is split into one geometry per triangle, each with its own material.
+fn quad_geometry_groups() -> [GeometryGroup; 2] {
+  [
+    GeometryGroup {
+      first_index: 0,
+      triangle_count: 1,
+      material: 0,
+    },
+    GeometryGroup {
+      first_index: 3,
+      triangle_count: 1,
+      material: 1,
+    },
+  ]
+}
+
+// The quad is seen from behind and has no volume, so both of its materials are double-sided.
+fn quad_materials() -> [Material; 2] {
+  [
+    Material {
+      base_color: [0.0, 0.0, 1.0, 1.0],
+      double_sided: 1,
+      ..Default::default()
+    },
+    Material {
+      base_color: [0.0, 1.0, 0.0, 1.0],
+      double_sided: 1,
+      ..Default::default()
+    },
+  ]
+}
+
+// RunnerConfig::light_demo adds a small triangle in front of the quad, lit by an area light above it and a
+// spherical point light below, so each casts a soft shadow of the triangle onto the quad. Both
+// lights sit between the camera and the quad, they aren't visible themselves.
+fn add_light_demo(vertices: &mut Vec<f32>, groups: &mut Vec<GeometryGroup>, materials: &mut Vec<Material>, lights: &mut Vec<Light>) {
+  let first_index = (vertices.len() / 3) as u32;
+  vertices.extend_from_slice(&[-0.15, -0.1, 0.6, 0.15, -0.1, 0.6, 0.0, 0.2, 0.6]);
+  groups.push(GeometryGroup {
+    first_index,
+    triangle_count: 1,
+    material: materials.len() as u32,
+  });
+  materials.push(Material {
+    double_sided: 1,
+    ..Default::default()
+  });
+  // 0.5 x 0.3 facing the quad, its penumbra on the quad is about 0.2 wide.
+  lights.push(Light::area([-0.25, 0.45, 0.0], [0.5, 0.0, 0.0], [0.0, 0.3, 0.0], [30.0, 28.0, 24.0]));
+  lights.push(Light::point([0.6, -0.5, 0.2], [1.0, 1.5, 3.0], 0.08));
+}
+
+// An instance of the BLAS of a mesh, `first_geometry` is the material table slot of the instance's first group.
+// Camera and bounce rays cull back faces, unless `back_faces` keeps them for the whole instance, see
+// Material::needs_back_faces.
+fn scene_instance(blas: &AccelerationStructure, first_geometry: u32, transform: &Mat4, back_faces: bool) -> Result<AccelerationStructureInstance> {
+  // Rows of the upper 3x4 part, which the instance stores row-major.
+  let rows = transform.transpose().to_cols_array();
+  let mut matrix = [0.0; 12];
+  matrix.copy_from_slice(&rows[..12]);
+  // Front faces are counterclockwise like HitGeometry::front_face, Vulkan defaults to clockwise.
+  let mut flags = vk::GeometryInstanceFlagsKHR::TRIANGLE_FRONT_COUNTERCLOCKWISE;
+  if back_faces {
+    flags |= vk::GeometryInstanceFlagsKHR::TRIANGLE_FACING_CULL_DISABLE;
+  }
+  Ok(
+    AccelerationStructureInstance::default()
+      .mask(0xFF)
+      .flags(flags)
+      .sbt_record_offset(0)?
+      // Offset of this instance's first material in the material table
+      .custom_index(first_geometry)?
+      .transform(TransformMatrix::from(vk::TransformMatrixKHR {
+        matrix,
+      }))
+      .acceleration_structure(blas, AccelerationStructureBuildType::Device)?,
+  )
+}
+
+// The quad and, with `light_demo`, the lights and occluder of add_light_demo.
+fn builtin_scene(ctx: &mut Context, light_demo: bool) -> Result<Scene> {
+  let mut vertices = quad_vertices().to_vec();
+  let mut groups = quad_geometry_groups().to_vec();
+  let mut materials = quad_materials().to_vec();
+  let mut lights = Vec::new();
+  if light_demo {
+    add_light_demo(&mut vertices, &mut groups, &mut materials, &mut lights);
+  }
+  Scene::from_triangles(ctx, &vertices, groups, materials, &lights)
+}
+
+// Loads `path` with scene::load_gltf, or builds the built-in scene without one. Every mesh gets its
+// own BLAS, the TLAS holds one instance per scene instance. With `transmissive_shadows`, geometry
+// whose material lets light through is built as non-opaque.
+fn build_scene(ctx: &mut Context, path: Option<&Path>, transmissive_shadows: bool, light_demo: bool) -> Result<SceneResources> {
+  let scene = match path {
+    Some(path) => load_gltf(ctx, path)?,
+    None => builtin_scene(ctx, light_demo)?,
+  };
+  ensure!(!scene.instances.is_empty(), "the scene has no meshes with triangles");
+  let mut blases = Vec::with_capacity(scene.meshes.len());
+  let mut back_faces = Vec::with_capacity(scene.meshes.len());
+  for mesh in &scene.meshes {
+    let opaque = mesh
+      .groups
+      .iter()
+      .map(|group| !(transmissive_shadows && scene.materials[group.material as usize].casts_transmissive_shadow()))
+      .collect::<Vec<_>>();
+    let what = format!("of mesh {}", mesh.name.as_deref().unwrap_or("without a name"));
+    blases.push(mesh.build_blas(ctx, &opaque).map_err(|e| OxError::accel_build(what, e))?);
+    back_faces.push(mesh.groups.iter().any(|group| scene.materials[group.material as usize].needs_back_faces()));
+  }
+  // Material and geometry table entries follow the groups of every instance in order, see MaterialTable.
+  // Instances of the same mesh get entries of their own, those of emissive groups differ in the lights
+  // their triangles are.
+  let mut table_materials = Vec::new();
+  let mut geometries = Vec::new();
+  let mut instances = Vec::with_capacity(scene.instances.len());
+  for instance in &scene.instances {
+    let mesh = &scene.meshes[instance.mesh];
+    let first_geometry = geometries.len() as u32;
+    let mut next_light = instance.first_light;
+    for (group, info) in mesh.groups.iter().zip(mesh.geometry_infos()) {
+      let material = scene.materials[group.material as usize];
+      let first_light = material.is_emissive().then(|| {
+        next_light += group.triangle_count;
+        next_light - group.triangle_count
+      });
+      table_materials.push(material);
+      geometries.push(info.with_first_light(first_light));
+    }
+    instances.push(scene_instance(&blases[instance.mesh].accel, first_geometry, &instance.transform, back_faces[instance.mesh])?);
+  }
+  // Nothing in the scene moves yet, see refit_tlas.
+  let tlas = build_tlas(ctx, &instances, false).map_err(|e| OxError::accel_build("of the scene", e))?;
+
+  let materials = MaterialTable::new(ctx, &table_materials)?;
+  let geometries = GeometryTable::new(ctx, &geometries)?;
+  let textures = scene.textures.upload(ctx)?;
+  // Emissive triangles join the point and area lights, weighted by their (textured) power.
+  let lights = LightTable::new(ctx, &scene.lights)?;
+  let bounds = scene.bounds();
+
+  Ok(SceneResources {
+    meshes: scene.meshes,
+    blases,
+    tlas,
+    materials,
+    geometries,
+    textures,
+    lights,
+    // Of the bounding sphere around the origin containing the whole scene.
+    radius: bounds.min.abs().max(bounds.max.abs()).length(),
+    bounds,
+  })
+}
+
+// The motion vectors of the primary hits, with their view.
+fn create_motion_target(ctx: &mut Context, width: u32, height: u32) -> Result<(Image, ImageView)> {
+  let motion = Image::new(
+    ctx.device.clone(),
+    &mut ctx.allocator,
+    width,
+    height,
+    vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::STORAGE,
+    vk::Format::R16G16_SFLOAT,
+    vk::SampleCountFlags::TYPE_1,
+  )?;
+  let motion_view = motion.view(vk::ImageAspectFlags::COLOR)?;
+  Ok((motion, motion_view))
+}
+
+// The tracer's own pipelines, created again with the recompiled shaders by App::reload_shaders.
+fn create_pipelines(ctx: &mut Context) -> Result<()> {
+  let rgen = create_shader("resources/shaders/raygen_rgen.spv", vk::ShaderStageFlags::RAYGEN_KHR)?;
+  let rchit = create_shader("resources/shaders/rayhit_rchit.spv", vk::ShaderStageFlags::CLOSEST_HIT_KHR)?;
+  let rmiss = create_shader("resources/shaders/raymiss_rmiss.spv", vk::ShaderStageFlags::MISS_KHR)?;
+  let shadow_rahit = create_shader("resources/shaders/shadow_rahit.spv", vk::ShaderStageFlags::ANY_HIT_KHR)?;
+  let shadow_rmiss = create_shader("resources/shaders/shadow_rmiss.spv", vk::ShaderStageFlags::MISS_KHR)?;
+
+  // Create the raytracing pipeline
+  let pci = RayTracingPipelineBuilder::new("rt")
+    .max_recursion_depth(1)
+    .add_ray_gen_group(rgen)
+    .add_ray_hit_group(Some(rchit), None)
+    // Transmissive shadow rays, hit group and miss shader 1 in trace_shadow.
+    .add_ray_hit_group(None, Some(shadow_rahit))
+    .add_ray_miss_group(rmiss)
+    .add_ray_miss_group(shadow_rmiss)
+    .build();
+  ctx.pipelines.create_named_raytracing_pipeline(pci)?;
+
+  let auto_exposure = create_shader("resources/shaders/auto_exposure_comp.spv", vk::ShaderStageFlags::COMPUTE)?;
+  let pci = ComputePipelineBuilder::new("auto_exposure").set_shader(auto_exposure).build();
+  ctx.pipelines.create_named_compute_pipeline(pci)?;
+
+  // Create the pipeline for drawing the raytraced result to the screen
+  let vertex = create_shader("resources/shaders/flat_color_vert.spv", vk::ShaderStageFlags::VERTEX)?;
+  let fragment = create_shader("resources/shaders/flat_color_frag.spv", vk::ShaderStageFlags::FRAGMENT)?;
+
+  let pci = PipelineBuilder::new("sample")
+    .vertex_input(0, vk::VertexInputRate::VERTEX)
+    .vertex_attribute(0, 0, vk::Format::R32G32_SFLOAT)?
+    .vertex_attribute(0, 1, vk::Format::R32G32_SFLOAT)?
+    .dynamic_states(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR])
+    .blend_attachment_none()
+    .cull_mask(vk::CullModeFlags::NONE)
+    .attach_shader(vertex.clone())
+    .attach_shader(fragment)
+    .build();
+  ctx.pipelines.create_named_pipeline(pci)?;
+  Ok(())
+}
+
+impl App for RaytracingSample {
+  fn new(mut ctx: Context) -> Result<Self> {
+    let transmissive_shadows = ctx.config.transmissive_shadows;
+    let light_demo = ctx.config.light_demo;
+    let scene_path = ctx.config.scene.clone();
+    let scene = build_scene(&mut ctx, scene_path.as_deref(), transmissive_shadows, light_demo).map_err(|e| e.context(format!("failed to build the scene, {}", ctx.memory_report())))?;
+    // Benchmarks always look at the scene from the same place, so their numbers stay comparable.
+    let camera = match &ctx.config.camera {
+      Some(path) if ctx.config.bench.is_some() => {
+        warn!("Benchmarks use the default camera, ignoring {}", path.display());
+        Camera::default()
+      }
+      Some(path) => Camera::load(path)?,
+      None => Camera::default(),
+    };
+
+    create_pipelines(&mut ctx)?;
+
+    let (width, height) = (ctx.config.width, ctx.config.height);
+    let crop = ctx.config.crop.unwrap_or(Rect::full(width, height));
+    ensure!(crop.fits_in(width, height), "crop {:?} lies outside of the {}x{} image", crop, width, height);
+
+    let accumulation = Accumulation::new(&mut ctx, width, height)?;
+    let (motion, motion_view) = create_motion_target(&mut ctx, width, height)?;
+
+    let sampler = Sampler::default(ctx.device.clone())?;
+
+    let blue_noise = BlueNoise::load(&mut ctx, "resources/textures/blue_noise.png")?;
+    let noise = ctx.config.sample_noise;
+    info!("Using {:?} sample jitter", noise);
+    let mode = ctx.config.render_mode;
+    info!("Render mode: {:?}", mode);
+    info!("Sampling strategy: {}", ctx.config.sampling);
+    if ctx.config.nee_roughness_threshold > 0.0 {
+      info!("Skipping shadow rays on materials with a roughness below {}", ctx.config.nee_roughness_threshold);
+    }
+    info!("Reconstruction filter: {}", ctx.config.filter);
+
+    let stereo = ctx.config.stereo;
+    if let Some(stereo) = stereo {
+      info!("Stereo rendering: {:?}", stereo);
+      ensure!(crop == Rect::full(width, height), "--crop can't be combined with stereo rendering");
+    }
+
+    let exposure = AutoExposure::new(&mut ctx, ctx.config.exposure)?;
+    let mut adaptive = ctx.config.adaptive;
+    // Anaglyph eyes write the same pixels, which have only one set of statistics.
+    if adaptive.is_some() && stereo.map_or(false, |stereo| stereo.mode == StereoMode::Anaglyph) {
+      warn!("Adaptive sampling is not supported with anaglyph stereo, disabling it");
+      adaptive = None;
+    }
+    if let Some(adaptive) = adaptive {
+      info!("Adaptive sampling: {:?}", adaptive);
+    }
+    let bloom = ctx.config.bloom.map(|settings| Bloom::new(&mut ctx, settings, width, height)).transpose()?;
+    let compositing = CompositingPasses::new(&mut ctx, width, height, ctx.config.compositing_passes)?;
+    if let Some(bloom) = &bloom {
+      info!("Bloom: {:?} over {} levels", bloom.settings, bloom.levels.len());
+    }
+    let pixel_stats = PixelStatsBuffer::new(&mut ctx, width, height)?;
+    let guides = GuideBuffers::new(&mut ctx, width, height)?;
+    let sample_params = ctx.create_buffer(std::mem::size_of::<RenderParams>() as u64, vk::BufferUsageFlags::UNIFORM_BUFFER, MemoryType::CpuToGpu)?;
+    ctx.memory.check();
+
+    Ok(Self {
+      scene,
+      camera,
+      accumulation,
+      motion,
+      motion_view,
+      sampler,
+      blue_noise,
+      noise,
+      mode,
+      sampling: ctx.config.sampling,
+      nee_roughness_threshold: ctx.config.nee_roughness_threshold,
+      filter: ctx.config.filter,
+      preview: Preview::new(ctx.config.preview_idle),
+      adaptive,
+      pixel_stats,
+      guides,
+      sample_params,
+      previous_view_projection: None,
+      sample_count_view: ctx.config.adaptive_view,
+      log_barriers: ctx.config.log_barriers,
+      spectral: ctx.config.spectral,
+      transmissive_shadows,
+      light_demo,
+      max_samples: ctx.config.max_samples,
+      seed: ctx.seed(),
+      t_min: ctx.config.t_min,
+      t_max: ctx.config.t_max,
+      firefly_clamp: ctx.config.firefly_clamp,
+      crop,
+      stereo,
+      pixel_debug: ctx.pixel_debug.clone(),
+      scratch: ctx.scratch.clone(),
+      exposure,
+      bloom,
+      compositing,
+      transparent_background: ctx.config.transparent_background,
+      color_space: ctx.config.color_space,
+      paper_white: ctx.config.paper_white,
+      width,
+      height,
+    })
+  }
+
+  fn frame(&mut self, ctx: Context, mut ifc: InFlightContext, time: FrameTime, _input: &InputState) -> Result<CommandBuffer<All>> {
+    if self.converged() {
+      if ctx.pixel_debug.begin_frame()?.is_some() {
+        warn!("The image has converged and no sample is traced, move the camera to debug a pixel");
+        ctx.pixel_debug.cancel();
+      }
+      let cmd = ctx
+        .exec
+        .on_domain::<All, _>(Some(ctx.pipelines.clone()), Some(ctx.descriptors.clone()))?;
+      let cmd = ctx.gpu_timer.begin(cmd);
+      return self.present_accumulation(&ctx, ifc, cmd);
+    }
+    let mut debug_pixel = ctx.pixel_debug.begin_frame()?;
+    if debug_pixel.is_some() && self.mode != RenderMode::PathTrace {
+      warn!("Pixel debug output is only written when path tracing");
+      ctx.pixel_debug.cancel();
+      debug_pixel = None;
+    }
+    let (params, _) = self.next_sample(debug_pixel);
+    let params_buffer = ctx.uniform(&mut ifc, &params)?;
+
+    // The luminance reduction and the bloom run on the compute queue between the trace and the display pass, so
+    // the trace is submitted on its own and the frame's command buffer only displays. The GPU time
+    // spans both, from the start of the trace to the end of the display.
+    let cmd = ctx
+      .exec
+      .on_domain::<All, _>(Some(ctx.pipelines.clone()), Some(ctx.descriptors.clone()))?;
+    let cmd = ctx.gpu_timer.begin(cmd);
+    let cmd = self.record_trace(cmd, &ctx, &mut ifc, params_buffer)?.finish()?;
+    ctx.exec.submit(cmd)?.wait()?;
+    self.exposure.reduce(&ctx, &self.accumulation.image, &self.accumulation.view, time.dt)?;
+    if let Some(bloom) = &self.bloom {
+      bloom.run(&ctx, &self.accumulation.image, &self.accumulation.view)?;
+    }
+
+    let cmd = ctx
+      .exec
+      .on_domain::<All, _>(Some(ctx.pipelines.clone()), Some(ctx.descriptors.clone()))?;
+    self.present_accumulation(&ctx, ifc, cmd)
+  }
+
+  fn on_resize(&mut self, mut ctx: Context, width: u32, height: u32) -> Result<()> {
+    self.accumulation.resize(&mut ctx, width, height)?;
+    // The view goes first, it references the image.
+    let (motion, motion_view) = create_motion_target(&mut ctx, width, height)?;
+    self.motion_view = motion_view;
+    self.motion = motion;
+    if let Some(bloom) = self.bloom.as_mut() {
+      bloom.resize(&mut ctx, width, height)?;
+    }
+    self.compositing = CompositingPasses::new(&mut ctx, width, height, self.compositing.enabled)?;
+    self.pixel_stats = PixelStatsBuffer::new(&mut ctx, width, height)?;
+    self.guides = GuideBuffers::new(&mut ctx, width, height)?;
+    if !self.crop.fits_in(width, height) {
+      warn!("Crop {:?} lies outside of the resized {}x{} image, rendering the full image", self.crop, width, height);
+      self.crop = Rect::full(width, height);
+    } else if self.crop == Rect::full(self.width, self.height) {
+      self.crop = Rect::full(width, height);
+    }
+    ctx.memory.check();
+    self.width = width;
+    self.height = height;
+    // The accumulated samples and the guides' history belong to the old image.
+    self.previous_view_projection = None;
+    Ok(())
+  }
+
+  fn sample_count(&self) -> u32 {
+    self.accumulation.sample_count()
+  }
+
+  // Preview samples never count, the full image starts over once the camera is still.
+  fn converged(&self) -> bool {
+    self.max_samples.map_or(false, |max_samples| self.accumulation.sample_count() >= max_samples) && !self.preview.is_active()
+  }
+
+  fn frame_scene(&mut self) {
+    let camera = self.camera.frame_bounds(&self.scene.bounds(), self.width as f32 / self.height as f32);
+    info!("Framing scene bounds {:?} from {}", self.scene.bounds(), camera.position);
+    self.set_camera(camera);
+  }
+
+  fn cycle_sampling(&mut self) {
+    self.set_sampling(self.sampling.next());
+  }
+
+  fn reload_scene(&mut self, mut ctx: Context) -> Result<()> {
+    let scene_path = ctx.config.scene.clone();
+    self.scene = build_scene(&mut ctx, scene_path.as_deref(), self.transmissive_shadows, self.light_demo).map_err(|e| e.context(format!("failed to build the scene, {}", ctx.memory_report())))?;
+    ctx.memory.check();
+    // Restart accumulation, the old samples belong to the previous scene.
+    self.accumulation.reset();
+    Ok(())
+  }
+
+  fn reload_shaders(&mut self, mut ctx: Context, _shaders: &[PathBuf]) -> Result<()> {
+    create_pipelines(&mut ctx)?;
+    if self.bloom.is_some() {
+      Bloom::create_pipelines(&mut ctx)?;
+    }
+    // The image so far was traced with the old shaders.
+    self.accumulation.reset();
+    Ok(())
+  }
+
+  // One more sample of the accumulation, shown in the imported image like in the window. The image is
+  // stretched when its extent differs from the render size.
+  fn render_to(&mut self, mut ctx: Context, mut thread: ThreadContext, target: &ImageView, _extent: vk::Extent2D) -> Result<()> {
+    self.accumulate(&mut ctx, &mut thread, 1, &CancellationToken::new(), |_, _| {})?;
+    self.tone_map_into(&mut ctx, &mut thread, target)
+  }
+
+  // Only the display pass, without a new sample, like the window shows it. An image without samples
+  // gets one first, so render_to_file has something to write even without frames before it.
+  fn screenshot_to(&mut self, mut ctx: Context, mut thread: ThreadContext, target: &ImageView, _extent: vk::Extent2D) -> Result<()> {
+    if self.accumulation.sample_count() == 0 {
+      self.accumulate(&mut ctx, &mut thread, 1, &CancellationToken::new(), |_, _| {})?;
+    }
+    self.tone_map_into(&mut ctx, &mut thread, target)
+  }
+
+  // One more sample of the accumulation per frame, see Runner::run_headless_frames.
+  fn headless_frame(&mut self, mut ctx: Context, thread: &mut ThreadContext, time: FrameTime) -> Result<()> {
+    self.trace_sample(&mut ctx, thread, time)
+  }
+
+  // Accumulates `spp` samples into the HDR attachment and writes the raw result to the configured output.
+  // With --bench, times the samples instead and writes the report.
+  fn run(&mut self, mut ctx: Context, mut thread: ThreadContext) -> Result<()> {
+    let output = ctx.config.output.clone().ok_or_else(|| anyhow!("headless rendering requires an output path"))?;
+    if let Some(frames) = ctx.config.bench {
+      let report = self.bench(&mut ctx, &mut thread, frames)?;
+      report.save(&output)?;
+      info!("Wrote benchmark report of {} frames to {}", frames, output.display());
+      return Ok(());
+    }
+    let samples = ctx.config.spp.unwrap_or(1);
+    let progress = |done: u32, total: u32| {
+      // Log roughly every 10%, and always the final sample.
+      if done == total || done % (total / 10).max(1) == 0 {
+        info!("Rendered {}/{} samples", done, total);
+      }
+    };
+    let tone_map = ctx.config.tone_map;
+    if let Some(frames) = ctx.config.frames {
+      // Nothing in the built-in scene moves yet, the frames only differ in their noise.
+      self.render_sequence(&mut ctx, &mut thread, &output, frames, samples, tone_map, &CancellationToken::new(), |_, _| {})?;
+      return Ok(());
+    }
+    self.render_to_png(&mut ctx, &mut thread, &output, samples, tone_map, &CancellationToken::new(), progress)?;
+    Ok(())
+  }
+}
+
+impl RaytracingSample {
+  fn render_flags(&self) -> u32 {
+    let mut flags = 0;
+    if self.noise == SampleNoise::BlueNoise {
+      flags |= FLAG_BLUE_NOISE;
+    }
+    if self.camera.is_orthographic() {
+      flags |= FLAG_ORTHOGRAPHIC;
+    }
+    if self.adaptive.is_some() {
+      flags |= FLAG_ADAPTIVE;
+    }
+    if self.spectral {
+      flags |= FLAG_SPECTRAL;
+    }
+    if self.transmissive_shadows {
+      flags |= FLAG_TRANSMISSIVE_SHADOWS;
+    }
+    if self.compositing.enabled {
+      flags |= FLAG_COMPOSITING;
+    }
+    // Coverage comes from the path tracer's first hit, ambient occlusion keeps its white misses.
+    if self.transparent_background && self.mode == RenderMode::PathTrace {
+      flags |= FLAG_TRANSPARENT_BACKGROUND;
+    }
+    // Guides come from the path tracer's first hit and reproject with a single camera.
+    if self.mode == RenderMode::PathTrace && self.stereo.is_none() {
+      flags |= FLAG_GUIDES;
+    }
+    flags
+  }
+
+  // One camera and pixel rectangle per ray launch: the crop, or one per eye in stereo. At most
+  // MAX_VIEWS.
+  fn views(&self) -> Vec<ViewParams> {
+    let full = [0, 0, self.width, self.height];
+    let Some(stereo) = self.stereo else {
+      return vec![ViewParams::new(&self.camera, full, [self.crop.x, self.crop.y], CHANNELS_ALL)];
+    };
+    let (left, right) = stereo.eyes(&self.camera);
+    match stereo.mode {
+      StereoMode::SideBySide => {
+        let half = self.width / 2;
+        let eye = |camera: &Camera, x: u32, width: u32| ViewParams::new(camera, [x, 0, width, self.height], [x, 0], CHANNELS_ALL);
+        vec![eye(&left, 0, half), eye(&right, half, self.width - half)]
+      }
+      StereoMode::Anaglyph => vec![ViewParams::new(&left, full, [0, 0], CHANNELS_RED), ViewParams::new(&right, full, [0, 0], CHANNELS_CYAN)],
+    }
+  }
+
+  // Moves the camera, showing the preview until it stops moving.
+  fn set_camera(&mut self, camera: Camera) {
+    self.camera = camera;
+    self.preview.camera_changed();
+    self.accumulation.reset();
+  }
+
+  // Strategies converge to the same image, but their samples must not be mixed in one accumulation.
+  fn set_sampling(&mut self, sampling: SamplingStrategy) {
+    if sampling != self.sampling {
+      info!("Sampling strategy: {}", sampling);
+      self.sampling = sampling;
+      self.accumulation.reset();
+    }
+  }
+
+  // Advances the sample counter and returns the per-sample shader parameters.
+  fn next_sample(&mut self, debug_pixel: Option<(u32, u32)>) -> (RenderParams, DisplayPushConstants) {
+    let (previewing, preview_changed) = self.preview.update();
+    if preview_changed {
+      self.accumulation.reset();
+    }
+    let view_projection = self.camera.projection_matrix(self.width as f32 / self.height as f32) * self.camera.view();
+    let mut views = [ViewParams::zeroed(); MAX_VIEWS];
+    for (slot, view) in views.iter_mut().zip(self.views()) {
+      *slot = view;
+    }
+    let params = RenderParams {
+      sample_index: self.accumulation.next_sample(),
+      flags: self.render_flags(),
+      seed: self.seed,
+      t_min: self.t_min.unwrap_or(self.scene.radius * T_MIN_SCALE),
+      t_max: self.t_max,
+      mode: self.mode.id(),
+      max_bounces: if previewing { PREVIEW_BOUNCES } else { MAX_BOUNCES },
+      adaptive_threshold: self.adaptive.map_or(0.0, |adaptive| adaptive.threshold),
+      adaptive_max_samples: self.adaptive.map_or(0, |adaptive| adaptive.max_samples),
+      // The shader treats 0 as disabled, infinity doesn't survive as a useful bound either.
+      firefly_clamp: if self.firefly_clamp.is_finite() { self.firefly_clamp } else { 0.0 },
+      debug_pixel_x: debug_pixel.map_or(NO_DEBUG_PIXEL, |(x, _)| x),
+      debug_pixel_y: debug_pixel.map_or(NO_DEBUG_PIXEL, |(_, y)| y),
+      sampling: if previewing { PREVIEW_SAMPLING.id() } else { self.sampling.id() },
+      guide_history: self.guides.has_history() as u32,
+      nee_roughness_threshold: self.nee_roughness_threshold,
+      _pad: 0,
+      previous_view_projection: self.previous_view_projection.unwrap_or(view_projection).to_cols_array(),
+      filter_kind: self.filter.id(),
+      filter_radius: self.filter.radius(),
+      filter_parameters: self.filter.parameters(),
+      light_count: self.scene.lights.count,
+      filter_weight_scale: self.filter.weight_scale(),
+      ao_samples: self.mode.ao_settings().map_or(0, |ao| ao.samples),
+      ao_radius: self.mode.ao_settings().map_or(0.0, |ao| ao.radius),
+      views,
+    };
+    self.previous_view_projection = Some(view_projection);
+    if !previewing && self.max_samples == Some(self.accumulation.sample_count()) {
+      info!("Converged after {} samples, pausing accumulation", self.accumulation.sample_count());
+    }
+    (params, self.display_constants())
+  }
+
+  fn display_constants(&self) -> DisplayPushConstants {
+    DisplayPushConstants {
+      sample_count_view: (self.sample_count_view && self.adaptive.is_some()) as u32,
+      max_samples: self.adaptive.map_or(0, |adaptive| adaptive.max_samples),
+      bloom_intensity: self.bloom.as_ref().map_or(0.0, |bloom| bloom.display_intensity()),
+      color_space: self.color_space.id(),
+      paper_white: self.paper_white,
+      ..self.exposure.display_constants()
+    }
+  }
+
+  // Accumulates up to `samples` samples and writes the result to `path`, returning the number of samples taken.
+  // `progress(done, total)` is called after every sample. When `cancel` is set the current sample is
+  // finished and the partial, already converging result is written instead of being discarded. Cancelled
+  // before the first sample there is no image yet, nothing is written and 0 is returned.
+  // With `tone_map` the image goes through the same exposure and sRGB encoding as the window and is
+  // written as 8 bit, otherwise the raw HDR values are written, see save_hdr_image.
+  #[allow(clippy::too_many_arguments)]
+  pub fn render_to_png(
+    &mut self,
+    ctx: &mut Context,
+    thread: &mut ThreadContext,
+    path: &Path,
+    samples: u32,
+    tone_map: bool,
+    cancel: &CancellationToken,
+    progress: impl Fn(u32, u32),
+  ) -> Result<u32> {
+    let done = self.accumulate(ctx, thread, samples, cancel, progress)?;
+    if done == 0 {
+      warn!("Render cancelled before the first sample, nothing written to {}", path.display());
+      return Ok(0);
+    }
+    if done < samples {
+      warn!("Render cancelled after {}/{} samples, writing partial result", done, samples);
+    }
+    let full = self.crop == Rect::full(self.width, self.height);
+    if tone_map {
+      let texels = self.tone_map_to_host(ctx, thread)?;
+      if full {
+        save_ldr_image(path, self.width, self.height, texels)?;
+      } else {
+        save_ldr_image(path, self.crop.width, self.crop.height, crop_texels(&texels, self.width, self.crop))?;
+      }
+    } else {
+      let texels = read_back_hdr(ctx, &self.accumulation.image, self.width, self.height)?;
+      if full {
+        save_hdr_image(path, self.width, self.height, texels)?;
+      } else {
+        save_hdr_image(path, self.crop.width, self.crop.height, crop_texels(&texels, self.width, self.crop))?;
+      }
+    }
+    info!("Wrote {} samples per pixel to {}", done, path.display());
+    if self.compositing.enabled {
+      let passes = [("depth", &self.compositing.depth), ("position", &self.compositing.position)];
+      for (name, image) in passes {
+        let pass_path = compositing_pass_path(path, name);
+        let texels = read_back_hdr(ctx, image, self.width, self.height)?;
+        if full {
+          save_hdr_image(&pass_path, self.width, self.height, texels)?;
+        } else {
+          save_hdr_image(&pass_path, self.crop.width, self.crop.height, crop_texels(&texels, self.width, self.crop))?;
+        }
+        info!("Wrote the {} pass to {}", name, pass_path.display());
+      }
+    }
+    Ok(done)
+  }
+
+  // Renders `frames` independent images for a video, written to `path` with the frame number appended,
+  // see sequence_frame_path. Unlike progressive rendering nothing carries over between frames: each
+  // one calls `advance(self, frame)` to move the camera or scene, restarts accumulation with a seed
+  // derived from the frame index and is written once all `samples_per_frame` samples are in.
+  // Returns how many frames were written, a cancelled frame is still written with its partial samples
+  // unless it had none yet.
+  #[allow(clippy::too_many_arguments)]
+  pub fn render_sequence(
+    &mut self,
+    ctx: &mut Context,
+    thread: &mut ThreadContext,
+    path: &Path,
+    frames: u32,
+    samples_per_frame: u32,
+    tone_map: bool,
+    cancel: &CancellationToken,
+    mut advance: impl FnMut(&mut Self, u32),
+  ) -> Result<u32> {
+    let base_seed = self.seed;
+    let mut written = 0;
+    for frame in 0..frames {
+      if cancel.is_cancelled() {
+        break;
+      }
+      advance(self, frame);
+      self.seed = frame_seed(base_seed, frame);
+      self.accumulation.reset();
+      let result = self.render_to_png(ctx, thread, &sequence_frame_path(path, frame), samples_per_frame, tone_map, cancel, |_, _| {});
+      self.seed = base_seed;
+      if result? == 0 {
+        break;
+      }
+      written += 1;
+      info!("Frame {}/{} done", frame + 1, frames);
+    }
+    Ok(written)
+  }
+
+  // Renders up to `samples` samples into the HDR attachment, waiting for each one. Returns how many
+  // were rendered before `cancel` was set.
+  fn accumulate(&mut self, ctx: &mut Context, thread: &mut ThreadContext, samples: u32, cancel: &CancellationToken, mut progress: impl FnMut(u32, u32)) -> Result<u32> {
+    let mut clock = Clock::new();
+
+    let mut done = 0;
+    while done < samples && !cancel.is_cancelled() {
+      self.trace_sample(ctx, thread, clock.tick())?;
+      ctx.pipelines.next_frame();
+      ctx.descriptors.next_frame();
+
+      done += 1;
+      progress(done, samples);
+    }
+    Ok(done)
+  }
+
+  // Records one sample into the HDR attachment, submits it and waits for it, then for the exposure
+  // reduction and the bloom over it.
+  fn trace_sample(&mut self, ctx: &mut Context, thread: &mut ThreadContext, time: FrameTime) -> Result<()> {
+    let (params, _) = self.next_sample(None);
+    self.sample_params.view_full().mapped_slice::<RenderParams>()?.copy_from_slice(std::slice::from_ref(&params));
+    let params_buffer = self.sample_params.view_full();
+    let mut ifc = thread.get_ifc();
+    let cmd = ctx
+      .exec
+      .on_domain::<All, _>(Some(ctx.pipelines.clone()), Some(ctx.descriptors.clone()))?;
+    let cmd = ctx.gpu_timer.begin(cmd);
+    let cmd = self.record_trace(cmd, ctx, &mut ifc, params_buffer)?;
+    let cmd = ctx.gpu_timer.end(cmd).finish()?;
+    ctx.exec.submit(cmd)?.wait()?;
+    self.exposure.reduce(ctx, &self.accumulation.image, &self.accumulation.view, time.dt)?;
+    if let Some(bloom) = &self.bloom {
+      bloom.run(ctx, &self.accumulation.image, &self.accumulation.view)?;
+    }
+    Ok(())
+  }
+
+  // Records the raytrace pass of one sample into `cmd`, leaving the HDR attachment for
+  // AutoExposure::reduce and the display pass.
+  fn record_trace<'q>(&mut self, cmd: IncompleteCommandBuffer<'q, All>, ctx: &Context, ifc: &mut InFlightContext, params: BufferView) -> Result<IncompleteCommandBuffer<'q, All>> {
+    let rt_image = image!("rt_out");
+    let motion_image = image!("motion_vectors");
+    let rt_pass = self.raytrace_pass(ctx, &rt_image, &motion_image, params);
+    if self.log_barriers {
+      log_resource_transitions("trace", [&rt_pass]);
+    }
+    let mut graph = PassGraph::new(None).add_pass(rt_pass)?.build()?;
+
+    let mut bindings = PhysicalResourceBindings::new();
+    bindings.bind_image("rt_out", &self.accumulation.view);
+    bindings.bind_image("motion_vectors", &self.motion_view);
+    let cmd = graph.record(cmd, &bindings, ifc, None, &mut ())?;
+    drop(graph);
+    self.guides.swap();
+    Ok(cmd)
+  }
+
+  // Times `frames` samples of the built-in scene after a short warmup.
+  fn bench(&mut self, ctx: &mut Context, thread: &mut ThreadContext, frames: u32) -> Result<BenchReport> {
+    let cancel = CancellationToken::new();
+    self.accumulate(ctx, thread, BENCH_WARMUP_FRAMES, &cancel, |_, _| {})?;
+    // Drops the time of the last warmup sample.
+    ctx.gpu_timer.read_ended();
+    let gpu_timer = ctx.gpu_timer.clone();
+    let mut times = Vec::with_capacity(frames as usize);
+    let mut trace_gpu_ms = Vec::with_capacity(frames as usize);
+    let mut last = Instant::now();
+    self.accumulate(ctx, thread, frames, &cancel, |_, _| {
+      let now = Instant::now();
+      times.push(now - last);
+      last = now;
+      // Every sample was waited for, so its timestamps are ready.
+      trace_gpu_ms.extend(gpu_timer.read_ended().map(f64::from));
+    })?;
+    Ok(BenchReport {
+      gpu: ctx.gpu.clone(),
+      width: self.width,
+      height: self.height,
+      seed: self.seed,
+      frames: times,
+      trace_gpu_ms,
+    })
+  }
+
+  // Runs the display pass into an offscreen sRGB image instead of the swapchain and reads it back.
+  fn tone_map_to_host(&self, ctx: &mut Context, thread: &mut ThreadContext) -> Result<Vec<u8>> {
+    let target = ctx.create_image(
+      self.width,
+      self.height,
+      vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+      // Same encoding as the sRGB swapchain, so the bytes can be written to a PNG as is.
+      vk::Format::R8G8B8A8_SRGB,
+    )?;
+    let target_view = target.view(vk::ImageAspectFlags::COLOR)?;
+    self.tone_map_into(ctx, thread, &target_view)?;
+    // The graph leaves its color attachments in COLOR_ATTACHMENT_OPTIMAL.
+    read_back_ldr(ctx, &target, self.width, self.height, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+  }
+
+  // Runs the display pass into `target`, any sRGB color attachment, and waits for it.
+  fn tone_map_into(&self, ctx: &mut Context, thread: &mut ThreadContext, target: &ImageView) -> Result<()> {
+    let rt_image = image!("rt_out");
+    let ldr_image = image!("ldr_out");
+    let bloom_image = image!("bloom_out");
+    let bloom_input = self.bloom.as_ref().map(|_| (&bloom_image, &bloom_image));
+    let constants = DisplayPushConstants {
+      color_space: OutputColorSpace::SRGB,
+      ..self.display_constants()
+    };
+    let pass = self.tonemap_pass(ctx, &rt_image, &rt_image, bloom_input, &ldr_image, constants)?;
+    if self.log_barriers {
+      log_resource_transitions("tone map", [&pass]);
+    }
+    let mut graph = PassGraph::new(None).add_pass(pass)?.build()?;
+
+    let mut bindings = PhysicalResourceBindings::new();
+    bindings.bind_image("rt_out", &self.accumulation.view);
+    bindings.bind_image("ldr_out", target);
+    if let Some(bloom) = &self.bloom {
+      bindings.bind_image("bloom_out", &bloom.levels[0].view);
+    }
+    let mut ifc = thread.get_ifc();
+    let cmd = ctx
+      .exec
+      .on_domain::<All, _>(Some(ctx.pipelines.clone()), Some(ctx.descriptors.clone()))?;
+    let cmd = graph.record(cmd, &bindings, &mut ifc, None, &mut ())?.finish()?;
+    ctx.exec.submit(cmd)?.wait()?;
+    Ok(())
+  }
+
+  // Exposes the accumulated image to the swapchain without tracing, recorded into `cmd`, after the
+  // trace of a frame or on its own for a converged image. Exposure isn't reduced here, converged
+  // frames keep the last traced frame's values.
+  fn present_accumulation(&self, ctx: &Context, mut ifc: InFlightContext, cmd: IncompleteCommandBuffer<All>) -> Result<CommandBuffer<All>> {
+    let swap = image!("swapchain");
+    let rt_image = image!("rt_out");
+    let bloom_image = image!("bloom_out");
+    let bloom_input = self.bloom.as_ref().map(|_| (&bloom_image, &bloom_image));
+    let render_pass = self.tonemap_pass(ctx, &rt_image, &rt_image, bloom_input, &swap, self.display_constants())?;
+
+    let present = PassBuilder::present("present", render_pass.output(&swap).unwrap());
+    if self.log_barriers {
+      log_resource_transitions("display", [&render_pass, &present]);
+    }
+    let mut graph = PassGraph::new(Some(&swap)).add_pass(render_pass)?.add_pass(present)?.build()?;
+
+    let mut bindings = PhysicalResourceBindings::new();
+    bindings.bind_image("swapchain", ifc.swapchain_image.as_ref().unwrap());
+    bindings.bind_image("rt_out", &self.accumulation.view);
+    if let Some(bloom) = &self.bloom {
+      bindings.bind_image("bloom_out", &bloom.levels[0].view);
+    }
+    let cmd = graph.record(cmd, &bindings, &mut ifc, None, &mut ())?;
+    ctx.gpu_timer.end(cmd).finish()
+  }
+
+  // Exposes the HDR image `rt_image` (in its version `input`) into `target`, the swapchain or any
+  // other color attachment bound to that resource. `bloom` is level 0 of the bloom chain and its
+  // version, written by Bloom::run after the last traced sample and added to the image before exposure.
+  fn tonemap_pass<'s>(
+    &'s self,
+    ctx: &'s Context,
+    rt_image: &'s VirtualResource,
+    input: &VirtualResource,
+    bloom: Option<(&'s VirtualResource, &VirtualResource)>,
+    target: &VirtualResource,
+    constants: DisplayPushConstants,
+  ) -> Result<Pass<'s, All, (), DefaultAllocator>> {
+    let mut pass = PassBuilder::render("copy")
+      .color_attachment(
+        target,
+        vk::AttachmentLoadOp::CLEAR,
+        Some(vk::ClearColorValue {
+          float32: [0.0, 0.0, 0.0, 0.0],
+        }),
+      )?
+      .sample_image(input, PipelineStage::FRAGMENT_SHADER);
+    if let Some((_, bloom_input)) = bloom {
+      pass = pass.sample_image(bloom_input, PipelineStage::FRAGMENT_SHADER);
+    }
+    // The shader always samples a bloom texture, without bloom its intensity is 0.
+    let bloom_image = bloom.map_or(rt_image, |(image, _)| image);
+    Ok(pass
+      .execute_fn(move |cmd, ifc, bindings, _| {
+        let vertices: Vec<f32> =
+          vec![-1.0, 1.0, 0.0, 1.0, -1.0, -1.0, 0.0, 0.0, 1.0, -1.0, 1.0, 0.0, -1.0, 1.0, 0.0, 1.0, 1.0, -1.0, 1.0, 0.0, 1.0, 1.0, 1.0, 1.0];
+        self.scratch.record(ScratchKind::Vertex, (vertices.len() * std::mem::size_of::<f32>()) as u64);
+        let mut vtx_buffer = ifc.allocate_scratch_vbo((vertices.len() * std::mem::size_of::<f32>()) as vk::DeviceSize)?;
+        let slice = vtx_buffer.mapped_slice::<f32>()?;
+        slice.copy_from_slice(vertices.as_slice());
+        let cmd = cmd
+          .full_viewport_scissor()
+          .bind_graphics_pipeline("sample")?
+          .bind_vertex_buffer(0, &vtx_buffer)
+          .resolve_and_bind_sampled_image(0, 0, rt_image, &self.sampler, bindings)?
+          .bind_storage_buffer(0, 1, &self.exposure.state.view_full())?
+          .bind_storage_buffer(0, 2, &self.pixel_stats.buffer.view_full())?
+          .resolve_and_bind_sampled_image(0, 3, bloom_image, &self.sampler, bindings)?;
+        ctx.push_constants(cmd, vk::ShaderStageFlags::FRAGMENT, 0, &constants)?.draw(6, 1, 0, 0)
+      })
+      .build())
+  }
+
+  fn raytrace_pass<'s>(
+    &'s self,
+    ctx: &'s Context,
+    rt_image: &'s VirtualResource,
+    motion_image: &'s VirtualResource,
+    params: BufferView,
+  ) -> Pass<'s, All, (), DefaultAllocator> {
+    PassBuilder::new("raytrace")
+      .write_storage_image(rt_image, PipelineStage::RAY_TRACING_SHADER_KHR)
+      .write_storage_image(motion_image, PipelineStage::RAY_TRACING_SHADER_KHR)
+      .execute_fn(move |cmd, _ifc, bindings, _| {
+        let mut cmd = cmd.bind_ray_tracing_pipeline("rt")?
+          .bind_acceleration_structure(0, 0, self.scene.tlas.accel())?
+          .resolve_and_bind_storage_image(0, 1, rt_image, bindings)?
+          .bind_uniform_buffer(0, 2, &params)?
+          .bind_storage_buffer(0, 3, &self.blue_noise.buffer.view_full())?
+          .bind_storage_buffer(0, 4, &self.scene.materials.buffer.view_full())?
+          .bind_storage_buffer(0, 5, &self.scene.geometries.buffer.view_full())?
+          .bind_storage_buffer(0, 6, &self.pixel_stats.buffer.view_full())?
+          .bind_storage_buffer(0, 7, &self.scene.textures.buffer.view_full())?
+          .bind_storage_buffer(0, 8, &self.pixel_debug.buffer.view_full())?
+          .bind_storage_buffer(0, 9, &self.guides.history().view_full())?
+          .bind_storage_buffer(0, 10, &self.guides.current().view_full())?
+          .resolve_and_bind_storage_image(0, 11, motion_image, bindings)?
+          .bind_storage_buffer(0, 12, &self.scene.lights.buffer.view_full())?
+          .bind_storage_image(0, 13, &self.compositing.depth_view)?
+          .bind_storage_image(0, 14, &self.compositing.position_view)?;
+        for (i, view) in self.views().into_iter().enumerate() {
+          // Anaglyph eyes read back the channels the previous launch stored.
+          if i > 0 {
+            cmd = cmd.memory_barrier(
+              PipelineStage::RAY_TRACING_SHADER_KHR,
+              vk::AccessFlags2::SHADER_STORAGE_WRITE,
+              PipelineStage::RAY_TRACING_SHADER_KHR,
+              vk::AccessFlags2::SHADER_STORAGE_READ | vk::AccessFlags2::SHADER_STORAGE_WRITE,
+            );
+          }
+          let [_, _, viewport_width, viewport_height] = view.viewport;
+          let (launch_width, launch_height) = if self.stereo.is_some() {
+            (viewport_width, viewport_height)
+          } else {
+            (self.crop.width, self.crop.height)
+          };
+          cmd = ctx.push_constants(cmd, vk::ShaderStageFlags::RAYGEN_KHR, 0, &(i as u32))?.trace_rays(launch_width, launch_height, 1)?;
+        }
+        // The graph only tracks rt_out, this covers the pixel stats for the display pass too.
+        Ok(cmd.memory_barrier(
+          PipelineStage::RAY_TRACING_SHADER_KHR,
+          vk::AccessFlags2::SHADER_STORAGE_WRITE,
+          PipelineStage::COMPUTE_SHADER | PipelineStage::FRAGMENT_SHADER,
+          vk::AccessFlags2::SHADER_STORAGE_READ,
+        ))
+      })
+      .build()
+  }
+}
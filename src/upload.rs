@@ -0,0 +1,280 @@
+use anyhow::{anyhow, Result};
+use ash::vk;
+
+use phobos::prelude::*;
+use phobos::sync::domain::{All, Transfer};
+
+use crate::app::Context;
+use crate::memory::{Buffer, Image};
+
+// Index of a buffer staged through an `Uploader`, resolved after `flush()`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct StagedBuffer(usize);
+
+// Index of an image staged through an `Uploader`, resolved after `flush()`.
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct StagedImage(usize);
+
+struct PendingImage {
+  image: Image,
+  view: ImageView,
+  staging: Buffer,
+}
+
+// Resources created by `Uploader::flush()`, in staging order.
+pub struct Uploaded {
+  buffers: Vec<Option<Buffer>>,
+  images: Vec<Option<(Image, ImageView)>>,
+}
+
+impl Uploaded {
+  // Returns None if the buffer was already taken.
+  #[allow(dead_code)]
+  pub fn take_buffer(&mut self, handle: StagedBuffer) -> Option<Buffer> {
+    self.buffers[handle.0].take()
+  }
+
+  // Returns None if the image was already taken.
+  #[allow(dead_code)]
+  pub fn take_image(&mut self, handle: StagedImage) -> Option<(Image, ImageView)> {
+    self.images[handle.0].take()
+  }
+}
+
+// Batches buffer and image uploads into a single transfer command buffer.
+//
+// Every `stage_*` call allocates a host-visible staging buffer and a device-local
+// destination, but nothing is recorded until `flush()`. `flush::<D>()` records all
+// copies on the dedicated transfer queue, submits once and waits on a single fence.
+// If the queue that executes domain `D` lives in a different queue family, ownership
+// of every resource is released by the transfer queue and acquired on `D`'s queue,
+// as required for exclusive-sharing resources.
+pub struct Uploader<'a> {
+  ctx: &'a mut Context,
+  buffers: Vec<(Buffer, Buffer)>,
+  images: Vec<PendingImage>,
+}
+
+impl<'a> Uploader<'a> {
+  pub fn new(ctx: &'a mut Context) -> Self {
+    Self {
+      ctx,
+      buffers: Vec::new(),
+      images: Vec::new(),
+    }
+  }
+
+  pub fn stage_buffer<T: Copy>(&mut self, data: &[T], usage: vk::BufferUsageFlags) -> Result<StagedBuffer> {
+    let size = (data.len() * std::mem::size_of::<T>()) as u64;
+    let staging = self.make_staging(size)?;
+    staging.view_full().mapped_slice::<T>()?.copy_from_slice(data);
+    let buffer = Buffer::new_device_local(
+      self.ctx.device.clone(),
+      &mut self.ctx.allocator,
+      size,
+      usage | vk::BufferUsageFlags::TRANSFER_DST,
+    )?;
+    self.buffers.push((staging, buffer));
+    Ok(StagedBuffer(self.buffers.len() - 1))
+  }
+
+  // The image is left in SHADER_READ_ONLY_OPTIMAL after the upload.
+  #[allow(dead_code)]
+  pub fn stage_image(&mut self, width: u32, height: u32, format: vk::Format, usage: vk::ImageUsageFlags, data: &[u8]) -> Result<StagedImage> {
+    let staging = self.make_staging(data.len() as u64)?;
+    staging.view_full().mapped_slice::<u8>()?.copy_from_slice(data);
+    let image = Image::new(
+      self.ctx.device.clone(),
+      &mut self.ctx.allocator,
+      width,
+      height,
+      usage | vk::ImageUsageFlags::TRANSFER_DST,
+      format,
+      vk::SampleCountFlags::TYPE_1,
+    )?;
+    let view = image.view(vk::ImageAspectFlags::COLOR)?;
+    self.images.push(PendingImage {
+      image,
+      view,
+      staging,
+    });
+    Ok(StagedImage(self.images.len() - 1))
+  }
+
+  // Records and submits all staged copies. `D` is the domain the resources will be used on afterwards.
+  pub fn flush<D: ExecutionDomain + 'static>(self) -> Result<Uploaded> {
+    let exec = self.ctx.exec.clone();
+    let src_family = exec.get_queue::<Transfer>().ok_or_else(|| anyhow!("no transfer queue available"))?.family_index();
+    let dst_family = exec.get_queue::<D>().ok_or_else(|| anyhow!("no queue available for upload destination"))?.family_index();
+    let ownership = if src_family != dst_family {
+      Some((src_family, dst_family))
+    } else {
+      None
+    };
+
+    let to_transfer_dst = self
+      .images
+      .iter()
+      .map(|pending| image_barrier(&pending.image, vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL, None)
+        .src_stage_mask(PipelineStage::NONE)
+        .src_access_mask(vk::AccessFlags2::NONE)
+        .dst_stage_mask(PipelineStage::TRANSFER)
+        .dst_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+        .build())
+      .collect::<Vec<_>>();
+    let (release_buffers, release_images) = self.release_barriers(ownership);
+
+    let mut cmd = exec
+      .on_domain::<Transfer, DefaultAllocator>(None, None)?
+      .pipeline_barrier(&vk::DependencyInfo::builder().image_memory_barriers(&to_transfer_dst).build())?;
+    for (staging, buffer) in &self.buffers {
+      cmd = cmd.copy_buffer(&staging.view_full(), &buffer.view_full())?;
+    }
+    for pending in &self.images {
+      cmd = cmd.copy_buffer_to_image(&pending.staging.view_full(), &pending.view)?;
+    }
+    let cmd = cmd
+      .pipeline_barrier(
+        &vk::DependencyInfo::builder()
+          .buffer_memory_barriers(&release_buffers)
+          .image_memory_barriers(&release_images)
+          .build(),
+      )?
+      .finish()?;
+    exec.submit(cmd)?.wait()?;
+
+    // The acquire half of a queue family ownership transfer has to run on the destination queue.
+    if let Some((src_family, dst_family)) = ownership {
+      let acquire_buffers = self
+        .buffers
+        .iter()
+        .map(|(_, buffer)| buffer_barrier(buffer, Some((src_family, dst_family)))
+          .dst_stage_mask(PipelineStage::ALL_COMMANDS)
+          .dst_access_mask(vk::AccessFlags2::MEMORY_READ)
+          .build())
+        .collect::<Vec<_>>();
+      let acquire_images = self
+        .images
+        .iter()
+        .map(|pending| image_barrier(&pending.image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, Some((src_family, dst_family)))
+          .dst_stage_mask(PipelineStage::ALL_COMMANDS)
+          .dst_access_mask(vk::AccessFlags2::SHADER_READ)
+          .build())
+        .collect::<Vec<_>>();
+      let cmd = exec
+        .on_domain::<D, DefaultAllocator>(None, None)?
+        .pipeline_barrier(
+          &vk::DependencyInfo::builder()
+            .buffer_memory_barriers(&acquire_buffers)
+            .image_memory_barriers(&acquire_images)
+            .build(),
+        )?
+        .finish()?;
+      exec.submit(cmd)?.wait()?;
+    }
+
+    Ok(Uploaded {
+      buffers: self.buffers.into_iter().map(|(_, buffer)| Some(buffer)).collect(),
+      images: self.images.into_iter().map(|pending| Some((pending.image, pending.view))).collect(),
+    })
+  }
+
+  // Without an ownership transfer these double as the final visibility barriers,
+  // with one the destination masks are ignored and the acquire barriers take over.
+  fn release_barriers(&self, ownership: Option<(u32, u32)>) -> (Vec<vk::BufferMemoryBarrier2>, Vec<vk::ImageMemoryBarrier2>) {
+    let buffers = self
+      .buffers
+      .iter()
+      .map(|(_, buffer)| buffer_barrier(buffer, ownership)
+        .src_stage_mask(PipelineStage::TRANSFER)
+        .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+        .dst_stage_mask(PipelineStage::ALL_COMMANDS)
+        .dst_access_mask(vk::AccessFlags2::MEMORY_READ)
+        .build())
+      .collect();
+    let images = self
+      .images
+      .iter()
+      .map(|pending| image_barrier(&pending.image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, ownership)
+        .src_stage_mask(PipelineStage::TRANSFER)
+        .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+        .dst_stage_mask(PipelineStage::ALL_COMMANDS)
+        .dst_access_mask(vk::AccessFlags2::SHADER_READ)
+        .build())
+      .collect();
+    (buffers, images)
+  }
+
+  fn make_staging(&mut self, size: u64) -> Result<Buffer> {
+    Ok(Buffer::new(
+      self.ctx.device.clone(),
+      &mut self.ctx.allocator,
+      size,
+      vk::BufferUsageFlags::TRANSFER_SRC,
+      MemoryType::CpuToGpu,
+    )?)
+  }
+}
+
+pub(crate) fn buffer_barrier(buffer: &Buffer, ownership: Option<(u32, u32)>) -> vk::BufferMemoryBarrier2Builder<'static> {
+  let (src_family, dst_family) = ownership.unwrap_or((vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED));
+  vk::BufferMemoryBarrier2::builder()
+    .buffer(unsafe { buffer.handle() })
+    .offset(0)
+    .size(vk::WHOLE_SIZE)
+    .src_queue_family_index(src_family)
+    .dst_queue_family_index(dst_family)
+}
+
+pub(crate) fn image_barrier(image: &Image, from: vk::ImageLayout, to: vk::ImageLayout, ownership: Option<(u32, u32)>) -> vk::ImageMemoryBarrier2Builder<'static> {
+  let (src_family, dst_family) = ownership.unwrap_or((vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED));
+  vk::ImageMemoryBarrier2::builder()
+    .image(unsafe { image.handle() })
+    .old_layout(from)
+    .new_layout(to)
+    .src_queue_family_index(src_family)
+    .dst_queue_family_index(dst_family)
+    .subresource_range(vk::ImageSubresourceRange {
+      aspect_mask: vk::ImageAspectFlags::COLOR,
+      base_mip_level: 0,
+      level_count: vk::REMAINING_MIP_LEVELS,
+      base_array_layer: 0,
+      layer_count: vk::REMAINING_ARRAY_LAYERS,
+    })
+}
+
+// Moves freshly created images from UNDEFINED to GENERAL, for storage images that are bound directly
+// instead of through the pass graph and so never get a layout transition from it. Waits for the GPU.
+pub fn transition_to_general(ctx: &Context, images: &[&Image], dst_stage: PipelineStage) -> Result<()> {
+  if images.is_empty() {
+    return Ok(());
+  }
+  let barriers = images
+    .iter()
+    .map(|image| {
+      vk::ImageMemoryBarrier2::builder()
+        .image(unsafe { image.handle() })
+        .old_layout(vk::ImageLayout::UNDEFINED)
+        .new_layout(vk::ImageLayout::GENERAL)
+        .src_stage_mask(PipelineStage::TOP_OF_PIPE)
+        .dst_stage_mask(dst_stage)
+        .dst_access_mask(vk::AccessFlags2::SHADER_STORAGE_READ | vk::AccessFlags2::SHADER_STORAGE_WRITE)
+        .subresource_range(vk::ImageSubresourceRange {
+          aspect_mask: vk::ImageAspectFlags::COLOR,
+          base_mip_level: 0,
+          level_count: 1,
+          base_array_layer: 0,
+          layer_count: 1,
+        })
+        .build()
+    })
+    .collect::<Vec<_>>();
+  let cmd = ctx
+    .exec
+    .on_domain::<All, DefaultAllocator>(None, None)?
+    .pipeline_barrier(&vk::DependencyInfo::builder().image_memory_barriers(&barriers).build())?
+    .finish()?;
+  ctx.exec.submit(cmd)?.wait()?;
+  Ok(())
+}
@@ -0,0 +1,126 @@
+use std::borrow::Cow;
+use std::ffi::{c_void, CStr};
+use std::os::raw::c_char;
+use std::sync::Mutex;
+
+use anyhow::Result;
+use ash::extensions::ext::DebugUtils;
+use ash::vk;
+use log::{debug, error, trace, warn};
+
+use phobos::prelude::*;
+
+struct MessengerState {
+  strict: bool,
+  // First error message since the last check, reported by ValidationMessenger::check.
+  first_error: Mutex<Option<String>>,
+}
+
+// VK_EXT_debug_utils messenger that logs validation messages through the log crate: VERBOSE as
+// trace, INFO as debug, WARNING as warn and ERROR as error, each with the message ID name and the
+// objects involved. Replaces the messenger phobos installs. With `strict`, check panics after an
+// error was reported; the callback itself can't, unwinding out of it into the driver would abort.
+pub struct ValidationMessenger {
+  loader: DebugUtils,
+  handle: vk::DebugUtilsMessengerEXT,
+  // Passed to the callback as user data, so it has to stay put until the messenger is destroyed.
+  state: Box<MessengerState>,
+  _entry: ash::Entry,
+}
+
+impl ValidationMessenger {
+  // `entry` is the loader the runner checked for the validation layer with.
+  pub fn new(entry: ash::Entry, instance: &VkInstance, strict: bool) -> Result<Self> {
+    let loader = DebugUtils::new(&entry, instance);
+    let state = Box::new(MessengerState {
+      strict,
+      first_error: Mutex::new(None),
+    });
+    let info = vk::DebugUtilsMessengerCreateInfoEXT::builder()
+      .message_severity(
+        vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE
+          | vk::DebugUtilsMessageSeverityFlagsEXT::INFO
+          | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
+          | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+      )
+      .message_type(vk::DebugUtilsMessageTypeFlagsEXT::GENERAL | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE)
+      .pfn_user_callback(Some(log_validation_message))
+      .user_data(&*state as *const MessengerState as *mut c_void);
+    let handle = unsafe { loader.create_debug_utils_messenger(&info, None)? };
+    Ok(Self {
+      loader,
+      handle,
+      state,
+      _entry: entry,
+    })
+  }
+
+  // Panics with the first validation error since the last call if the messenger is strict.
+  pub fn check(&self) {
+    if let Some(message) = self.take_error() {
+      panic!("Vulkan validation error (strict validation): {}", message);
+    }
+  }
+
+  // The first validation error since the last call if the messenger is strict, for callers that can't
+  // panic where they check, like the event loop.
+  pub fn take_error(&self) -> Option<String> {
+    if !self.state.strict {
+      return None;
+    }
+    self.state.first_error.lock().unwrap().take()
+  }
+}
+
+impl Drop for ValidationMessenger {
+  fn drop(&mut self) {
+    unsafe { self.loader.destroy_debug_utils_messenger(self.handle, None) };
+  }
+}
+
+fn c_str<'a>(ptr: *const c_char) -> Cow<'a, str> {
+  if ptr.is_null() {
+    Cow::Borrowed("")
+  } else {
+    unsafe { CStr::from_ptr(ptr) }.to_string_lossy()
+  }
+}
+
+unsafe extern "system" fn log_validation_message(
+  severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+  _types: vk::DebugUtilsMessageTypeFlagsEXT,
+  data: *const vk::DebugUtilsMessengerCallbackDataEXT,
+  user_data: *mut c_void,
+) -> vk::Bool32 {
+  let data = &*data;
+  let objects = if data.object_count == 0 || data.p_objects.is_null() {
+    &[]
+  } else {
+    std::slice::from_raw_parts(data.p_objects, data.object_count as usize)
+  };
+  let mut text = format!("[{}] {}", c_str(data.p_message_id_name), c_str(data.p_message));
+  for object in objects {
+    let name = c_str(object.p_object_name);
+    if name.is_empty() {
+      text += &format!("\n  {:?} {:#x}", object.object_type, object.object_handle);
+    } else {
+      text += &format!("\n  {:?} {:#x} \"{}\"", object.object_type, object.object_handle, name);
+    }
+  }
+  match severity {
+    vk::DebugUtilsMessageSeverityFlagsEXT::ERROR => {
+      error!(target: "vulkan", "{}", text);
+      let state = &*(user_data as *const MessengerState);
+      if state.strict {
+        if let Ok(mut first) = state.first_error.lock() {
+          first.get_or_insert(text);
+        }
+      }
+    }
+    vk::DebugUtilsMessageSeverityFlagsEXT::WARNING => warn!(target: "vulkan", "{}", text),
+    vk::DebugUtilsMessageSeverityFlagsEXT::INFO => debug!(target: "vulkan", "{}", text),
+    _ => trace!(target: "vulkan", "{}", text),
+  }
+  // VK_FALSE: the call that triggered the message goes ahead.
+  vk::FALSE
+}